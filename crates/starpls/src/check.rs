@@ -4,14 +4,19 @@ use anyhow::anyhow;
 use rustc_hash::FxHashMap;
 use starpls_bazel::client::{BazelCLI, BazelClient};
 use starpls_common::{FileInfo, Severity};
-use starpls_ide::{Analysis, Change};
+use starpls_ide::{Analysis, Change, InferenceOptions};
 
 use crate::{
     document::{self, DefaultFileLoader, PathInterner},
     server::{load_bazel_build_language, load_bazel_builtins},
+    workspace_repos::WorkspaceRepos,
 };
 
-pub(crate) fn run_check(paths: Vec<String>, output_base: Option<String>) -> anyhow::Result<()> {
+pub(crate) fn run_check(
+    paths: Vec<String>,
+    output_base: Option<String>,
+    report_unused_type_ignores: bool,
+) -> anyhow::Result<()> {
     let bazel_client = Arc::new(BazelCLI::default());
     let info = bazel_client.info()?;
     let external_output_base = output_base
@@ -47,7 +52,14 @@ pub(crate) fn run_check(paths: Vec<String>, output_base: Option<String>) -> anyh
         fetch_repo_sender,
         bzlmod_enabled,
     );
-    let mut analysis = Analysis::new(Arc::new(loader), Default::default());
+    loader.set_workspace_repos(WorkspaceRepos::scan(&info.workspace, bzlmod_enabled));
+    let mut analysis = Analysis::new(
+        Arc::new(loader),
+        InferenceOptions {
+            report_unused_type_ignores,
+            ..Default::default()
+        },
+    );
     let mut change = Change::default();
     let mut file_ids = Vec::new();
     let mut original_paths = FxHashMap::default();
@@ -91,19 +103,46 @@ pub(crate) fn run_check(paths: Vec<String>, output_base: Option<String>) -> anyh
             let start = line_index.line_col(diagnostic.range.range.start());
             writeln!(
                 &mut rendered_diagnostics,
-                "{}:{}:{} - {}: {}",
+                "{}:{}:{} - {}[{}]: {}",
                 original_paths.get(&file_id).unwrap(),
                 start.line + 1,
                 start.col + 1,
                 match diagnostic.severity {
+                    Severity::Hint => "hint",
+                    Severity::Information => "info",
                     Severity::Warning => "warn",
                     Severity::Error => {
                         has_error = true;
                         "error"
                     }
                 },
+                diagnostic.code,
                 diagnostic.message,
             )?;
+
+            // Render related locations underneath the diagnostic they belong to.
+            for related in diagnostic.related {
+                let related_line_index = match snap.line_index(related.range.file_id)? {
+                    Some(related_line_index) => related_line_index,
+                    None => continue,
+                };
+                let start = related_line_index.line_col(related.range.range.start());
+                let path = match original_paths.get(&related.range.file_id) {
+                    Some(path) => path.to_string(),
+                    None => interner
+                        .lookup_by_file_id(related.range.file_id)
+                        .display()
+                        .to_string(),
+                };
+                writeln!(
+                    &mut rendered_diagnostics,
+                    "    {}:{}:{} - note: {}",
+                    path,
+                    start.line + 1,
+                    start.col + 1,
+                    related.message,
+                )?;
+            }
         }
     }
 
@@ -31,4 +31,14 @@ impl ServerConfig {
                 .insert_replace_support
         )
     }
+
+    pub(crate) fn has_show_document_support(&self) -> bool {
+        try_or_default!(Some(
+            self.caps.window.as_ref()?.show_document.as_ref()?.support
+        ))
+    }
+
+    pub(crate) fn has_work_done_progress_support(&self) -> bool {
+        try_or_default!(self.caps.window.as_ref()?.work_done_progress)
+    }
 }
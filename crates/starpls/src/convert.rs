@@ -1,12 +1,12 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::anyhow;
 use line_index::{LineIndex, WideEncoding, WideLineCol};
-use starpls_common::{Diagnostic, FileId, Severity};
-use starpls_ide::{DocumentSymbol, SymbolKind, SymbolTag};
+use starpls_common::{Diagnostic, DiagnosticTag, FileId, FileRange, Severity};
+use starpls_ide::{DocumentSymbol, LocationLink, SymbolKind, SymbolTag};
 use starpls_syntax::{TextRange, TextSize};
 
-use crate::server::ServerSnapshot;
+use crate::{extensions::SHOW_LOCATION_COMMAND, server::ServerSnapshot};
 
 pub(crate) fn path_buf_from_url(url: &lsp_types::Url) -> anyhow::Result<PathBuf> {
     url.to_file_path()
@@ -14,22 +14,133 @@ pub(crate) fn path_buf_from_url(url: &lsp_types::Url) -> anyhow::Result<PathBuf>
 }
 
 pub(crate) fn lsp_diagnostic_from_native(
+    snapshot: &ServerSnapshot,
     diagnostic: Diagnostic,
     line_index: &LineIndex,
 ) -> Option<lsp_types::Diagnostic> {
+    let related_information = diagnostic
+        .related
+        .into_iter()
+        .flat_map(|related| {
+            Some(lsp_types::DiagnosticRelatedInformation {
+                location: lsp_location_from_file_range(snapshot, related.range)?,
+                message: related.message,
+            })
+        })
+        .collect::<Vec<_>>();
+    let tags = diagnostic
+        .tags
+        .into_iter()
+        .map(|tag| match tag {
+            DiagnosticTag::Unnecessary => lsp_types::DiagnosticTag::UNNECESSARY,
+            DiagnosticTag::Deprecated => lsp_types::DiagnosticTag::DEPRECATED,
+        })
+        .collect::<Vec<_>>();
+
     Some(lsp_types::Diagnostic {
         range: lsp_range_from_text_range(diagnostic.range.range, line_index)?,
         severity: Some(lsp_severity_from_native(diagnostic.severity)),
-        code: None,
+        code: Some(lsp_types::NumberOrString::String(
+            diagnostic.code.as_str().to_string(),
+        )),
         code_description: None,
         source: Some("starpls".to_string()),
         message: diagnostic.message,
-        related_information: None,
-        tags: None,
+        related_information: (!related_information.is_empty()).then_some(related_information),
+        tags: (!tags.is_empty()).then_some(tags),
         data: None,
     })
 }
 
+/// Converts the fixes attached to a diagnostic into quick fix code actions, and its navigations into
+/// code actions that show the target location. Navigations are only offered if the client supports
+/// `window/showDocument`, which is used to show the location.
+pub(crate) fn lsp_code_actions_from_native(
+    snapshot: &ServerSnapshot,
+    diagnostic: Diagnostic,
+    line_index: &LineIndex,
+) -> Vec<lsp_types::CodeAction> {
+    let fixes = diagnostic.fixes.clone();
+    let navigations = if snapshot.config.has_show_document_support() {
+        diagnostic.navigations.clone()
+    } else {
+        Vec::new()
+    };
+    let lsp_diagnostic = match lsp_diagnostic_from_native(snapshot, diagnostic, line_index) {
+        Some(lsp_diagnostic) => lsp_diagnostic,
+        None => return Vec::new(),
+    };
+
+    fixes
+        .into_iter()
+        .flat_map(|fix| {
+            let mut changes: HashMap<lsp_types::Url, Vec<lsp_types::TextEdit>> = HashMap::new();
+            for edit in fix.edits {
+                let edit_line_index = snapshot
+                    .analysis_snapshot
+                    .line_index(edit.range.file_id)
+                    .ok()??;
+                let uri = lsp_types::Url::from_file_path(
+                    snapshot
+                        .document_manager
+                        .read()
+                        .lookup_by_file_id(edit.range.file_id),
+                )
+                .ok()?;
+                changes.entry(uri).or_default().push(lsp_types::TextEdit {
+                    range: lsp_range_from_text_range(edit.range.range, edit_line_index)?,
+                    new_text: edit.new_text,
+                });
+            }
+
+            Some(lsp_types::CodeAction {
+                title: fix.label,
+                kind: Some(lsp_types::CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![lsp_diagnostic.clone()]),
+                edit: Some(lsp_types::WorkspaceEdit {
+                    changes: Some(changes),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        })
+        .chain(navigations.into_iter().flat_map(|navigation| {
+            let location = lsp_location_from_file_range(snapshot, navigation.target)?;
+            Some(lsp_types::CodeAction {
+                title: navigation.label.clone(),
+                kind: Some(lsp_types::CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![lsp_diagnostic.clone()]),
+                command: Some(lsp_types::Command {
+                    title: navigation.label,
+                    command: SHOW_LOCATION_COMMAND.to_string(),
+                    arguments: Some(vec![serde_json::to_value(location).ok()?]),
+                }),
+                ..Default::default()
+            })
+        }))
+        .collect()
+}
+
+fn lsp_location_from_file_range(
+    snapshot: &ServerSnapshot,
+    range: FileRange,
+) -> Option<lsp_types::Location> {
+    let line_index = snapshot
+        .analysis_snapshot
+        .line_index(range.file_id)
+        .ok()??;
+    Some(lsp_types::Location {
+        uri: lsp_types::Url::from_file_path(
+            snapshot
+                .document_manager
+                .read()
+                .lookup_by_file_id(range.file_id),
+        )
+        .ok()?,
+        range: lsp_range_from_text_range(range.range, line_index)?,
+    })
+}
+
 pub(crate) fn lsp_range_from_text_range(
     text_range: TextRange,
     line_index: &LineIndex,
@@ -48,6 +159,39 @@ pub(crate) fn lsp_range_from_text_range(
     })
 }
 
+/// Converts a location into a URL for a document link. Locations in BUILD files point at the line
+/// defining the target, using the `#L<line>` fragment supported by editors like VS Code.
+pub(crate) fn lsp_url_from_location_link(
+    snapshot: &ServerSnapshot,
+    location: LocationLink,
+) -> Option<lsp_types::Url> {
+    match location {
+        LocationLink::Local {
+            target_selection_range,
+            target_file_id,
+            ..
+        } => {
+            let line_index = snapshot
+                .analysis_snapshot
+                .line_index(target_file_id)
+                .ok()??;
+            let mut url = lsp_types::Url::from_file_path(
+                snapshot
+                    .document_manager
+                    .read()
+                    .lookup_by_file_id(target_file_id),
+            )
+            .ok()?;
+            let line = line_index.line_col(target_selection_range.start()).line;
+            url.set_fragment(Some(&format!("L{}", line + 1)));
+            Some(url)
+        }
+        LocationLink::External { target_path, .. } => {
+            lsp_types::Url::from_file_path(target_path).ok()
+        }
+    }
+}
+
 fn wide_line_col_from_lsp_position(pos: lsp_types::Position) -> WideLineCol {
     WideLineCol {
         line: pos.line,
@@ -76,6 +220,8 @@ fn lsp_severity_from_native(severity: Severity) -> lsp_types::DiagnosticSeverity
     match severity {
         Severity::Error => lsp_types::DiagnosticSeverity::ERROR,
         Severity::Warning => lsp_types::DiagnosticSeverity::WARNING,
+        Severity::Information => lsp_types::DiagnosticSeverity::INFORMATION,
+        Severity::Hint => lsp_types::DiagnosticSeverity::HINT,
     }
 }
 
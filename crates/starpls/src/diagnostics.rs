@@ -42,7 +42,10 @@ impl DiagnosticsManager {
 
 fn is_diagnostic_equal(left: &Diagnostic, right: &Diagnostic) -> bool {
     left.source == right.source
+        && left.code == right.code
         && left.severity == right.severity
         && left.range == right.range
         && left.message == right.message
+        && left.tags == right.tags
+        && left.related_information == right.related_information
 }
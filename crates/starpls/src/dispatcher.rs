@@ -9,11 +9,11 @@ use crate::{
 
 pub(crate) struct RequestDispatcher<'a> {
     req: Option<lsp_server::Request>,
-    server: &'a Server,
+    server: &'a mut Server,
 }
 
 impl<'a> RequestDispatcher<'a> {
-    pub(crate) fn new(req: lsp_server::Request, server: &'a Server) -> Self {
+    pub(crate) fn new(req: lsp_server::Request, server: &'a mut Server) -> Self {
         Self {
             req: Some(req),
             server,
@@ -70,6 +70,33 @@ impl<'a> RequestDispatcher<'a> {
         self
     }
 
+    /// Handles a request on the main thread with mutable access to the server, for requests that
+    /// change the server's state rather than query the analysis.
+    pub(crate) fn on_sync_mut<R>(
+        &mut self,
+        f: fn(&mut Server, R::Params) -> anyhow::Result<R::Result>,
+    ) -> &mut Self
+    where
+        R: lsp_types::request::Request + 'static,
+        R::Params: serde::de::DeserializeOwned,
+    {
+        let (req, params) = match self.parse::<R>() {
+            Some(res) => res,
+            None => return self,
+        };
+
+        let response = match f(self.server, params) {
+            Ok(res) => lsp_server::Response::new_ok(req.id, res),
+            Err(err) => lsp_server::Response::new_err(
+                req.id,
+                lsp_server::ErrorCode::RequestFailed as i32,
+                err.to_string(),
+            ),
+        };
+        self.server.respond(response);
+        self
+    }
+
     pub(crate) fn finish(&mut self) {
         let req = match self.req.take() {
             Some(req) => req,
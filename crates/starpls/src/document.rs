@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fs,
     hash::BuildHasherDefault,
     mem,
@@ -16,15 +16,19 @@ use rustc_hash::FxHasher;
 use starpls_bazel::{
     self,
     client::BazelClient,
+    glob::{self, GlobResult},
     label::{PartialParse, RepoKind},
     APIContext, Label, ParseError,
 };
 use starpls_common::{
-    Dialect, FileId, FileInfo, LoadItemCandidate, LoadItemCandidateKind, ResolvedPath,
+    AllowedFiles, Dialect, FileId, FileInfo, LoadItemCandidate, LoadItemCandidateKind, ResolvedPath,
 };
 use starpls_ide::{FileLoader, LoadFileResult};
 
-use crate::event_loop::{FetchExternalRepoRequest, Task};
+use crate::{
+    event_loop::{FetchExternalRepoRequest, Task},
+    workspace_repos::WorkspaceRepos,
+};
 
 macro_rules! try_opt {
     ($expr:expr) => {
@@ -156,6 +160,18 @@ impl DocumentManager {
     pub(crate) fn lookup_by_path_buf(&self, path: &PathBuf) -> Option<FileId> {
         self.path_interner.lookup_by_path_buf(path)
     }
+
+    pub(crate) fn workspace(&self) -> &Path {
+        &self.workspace
+    }
+
+    /// Returns the IDs of the documents that are currently open in an editor.
+    pub(crate) fn editor_file_ids(&self) -> impl Iterator<Item = FileId> + '_ {
+        self.documents
+            .iter()
+            .filter(|(_, document)| matches!(document.source, DocumentSource::Editor(_)))
+            .map(|(file_id, _)| *file_id)
+    }
 }
 
 #[derive(Default, Debug)]
@@ -194,8 +210,16 @@ pub(crate) struct DefaultFileLoader {
     cached_load_results: DashMap<String, PathBuf>,
     fetch_repo_sender: Sender<Task>,
     bzlmod_enabled: bool,
+    workspace_repos: RwLock<WorkspaceRepos>,
+    /// The errors from failed attempts to fetch external repositories, keyed by canonical name.
+    fetch_failures: DashMap<String, String>,
+    /// The results of evaluating globs, keyed by package directory and arguments.
+    cached_glob_results: DashMap<GlobKey, GlobResult>,
 }
 
+/// The package directory, `include`, `exclude` and `exclude_directories` arguments of a glob.
+type GlobKey = (PathBuf, Vec<String>, Vec<String>, bool);
+
 impl DefaultFileLoader {
     pub(crate) fn new(
         bazel_client: Arc<dyn BazelClient>,
@@ -215,9 +239,59 @@ impl DefaultFileLoader {
             cached_load_results: Default::default(),
             fetch_repo_sender,
             bzlmod_enabled,
+            workspace_repos: Default::default(),
+            fetch_failures: Default::default(),
+            cached_glob_results: Default::default(),
+        }
+    }
+
+    /// Sets the external repositories declared in the workspace's `WORKSPACE` file. Previously
+    /// resolved paths may refer to these repositories, so they are discarded.
+    pub(crate) fn set_workspace_repos(&self, repos: WorkspaceRepos) {
+        *self.workspace_repos.write() = repos;
+        self.cached_load_results.clear();
+    }
+
+    /// Records the outcome of fetching the given canonical repository, so that files failing to
+    /// load from it can report why.
+    pub(crate) fn set_fetch_failure(&self, repo: &str, err: Option<String>) {
+        match err {
+            Some(err) => {
+                self.fetch_failures.insert(repo.to_string(), err);
+            }
+            None => {
+                self.fetch_failures.remove(repo);
+            }
         }
     }
 
+    pub(crate) fn failed_repos(&self) -> Vec<String> {
+        let mut repos = self
+            .fetch_failures
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        repos.sort();
+        repos
+    }
+
+    pub(crate) fn clear_fetch_failures(&self) {
+        self.fetch_failures.clear();
+    }
+
+    /// Discards the cached glob results, e.g. because files may have been created or deleted.
+    pub(crate) fn clear_glob_results(&self) {
+        self.cached_glob_results.clear();
+    }
+
+    fn is_module_file_id(&self, file_id: FileId) -> bool {
+        self.interner
+            .lookup_by_file_id(file_id)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(is_module_file)
+    }
+
     fn make_cache_key(&self, repo_kind: &RepoKind, path: &str, from: FileId) -> String {
         format!("{:?}-{:?}-{:?}", repo_kind, path, from.0)
     }
@@ -286,6 +360,10 @@ impl DefaultFileLoader {
 
                 if self.workspace_name.as_deref() == Some(label.repo()) || label.repo().is_empty() {
                     (self.workspace.clone(), PathBuf::new())
+                } else if let Some(path) = self.local_repo_path(label.repo()) {
+                    // Local repositories can be resolved directly, without fetching them first.
+                    canonical_repo_res = None;
+                    (path, PathBuf::new())
                 } else {
                     (self.external_output_base.join(label.repo()), PathBuf::new())
                 }
@@ -340,9 +418,32 @@ impl DefaultFileLoader {
                                 let _ = self.fetch_repo_sender.send(
                                     Task::FetchExternalRepoRequest(FetchExternalRepoRequest {
                                         file_id: from,
-                                        repo: canonical_repo,
+                                        repo: canonical_repo.clone(),
                                     }),
                                 );
+
+                                if let Some(err) = self.fetch_failures.get(&canonical_repo) {
+                                    bail!(
+                                        "failed to fetch repository \"@@{}\": {}",
+                                        canonical_repo,
+                                        *err
+                                    );
+                                }
+
+                                // Point to the repository's declaration in `WORKSPACE`, if any.
+                                if let Some(repo) = self.workspace_repos.read().get(&canonical_repo)
+                                {
+                                    bail!(
+                                        "repository \"@{}\" hasn't been fetched yet (declared by `{}` at {}:{})",
+                                        canonical_repo,
+                                        repo.rule,
+                                        repo.file
+                                            .strip_prefix(&self.workspace)
+                                            .unwrap_or(&repo.file)
+                                            .display(),
+                                        repo.line
+                                    );
+                                }
                             }
                         }
                         return Err(err.into());
@@ -356,6 +457,13 @@ impl DefaultFileLoader {
         Ok((file_id, contents))
     }
 
+    fn local_repo_path(&self, repo: &str) -> Option<PathBuf> {
+        self.workspace_repos
+            .read()
+            .get(repo)
+            .and_then(|repo| repo.path.clone())
+    }
+
     fn repo_for_path<'a>(&'a self, path: &'a Path) -> Option<&str> {
         match path.strip_prefix(&self.external_output_base) {
             Ok(stripped) => stripped
@@ -372,6 +480,148 @@ impl DefaultFileLoader {
             }
         }
     }
+
+    /// Lists the candidates for completing a label, where `is_candidate` determines which of the
+    /// files in a package are included.
+    fn list_bazel_candidates(
+        &self,
+        path: &str,
+        from: FileId,
+        is_candidate: impl Fn(&str) -> bool,
+    ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>> {
+        let from_path = self.interner.lookup_by_file_id(from);
+
+        // Determine the loading file's workspace root and package.
+        let (mut root, package) = try_opt!(starpls_bazel::resolve_workspace(
+            self.interner.lookup_by_file_id(from),
+        )?);
+        let (label, err) = match Label::parse(path) {
+            Ok(label) => (label, None),
+            Err(PartialParse { partial, err }) => (partial, Some(err)),
+        };
+
+        if !label.has_leading_slashes()
+            && !label.is_relative()
+            && err != Some(ParseError::InvalidRepo)
+        {
+            return Ok(match label.kind() {
+                RepoKind::Apparent if self.bzlmod_enabled => Some(
+                    self.bazel_client
+                        .repo_mapping_keys("")?
+                        .into_iter()
+                        .map(|repo| LoadItemCandidate {
+                            kind: LoadItemCandidateKind::Directory,
+                            path: repo.to_string(),
+                            replace_trailing_slash: false,
+                        })
+                        .collect(),
+                ),
+                RepoKind::Canonical | RepoKind::Apparent => {
+                    // Offer both the repositories that have already been fetched and the
+                    // ones declared in `WORKSPACE`, which may not have been fetched yet.
+                    let repos = fs::read_dir(&self.external_output_base)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|entry| {
+                            let entry = entry.ok()?;
+                            entry
+                                .file_type()
+                                .ok()?
+                                .is_dir()
+                                .then(|| entry.file_name().to_string_lossy().to_string())
+                        })
+                        .chain(self.workspace_name.clone())
+                        .chain(self.workspace_repos.read().names().map(String::from))
+                        .collect::<BTreeSet<_>>();
+                    Some(
+                        repos
+                            .into_iter()
+                            .map(|repo| LoadItemCandidate {
+                                kind: LoadItemCandidateKind::Directory,
+                                path: repo,
+                                replace_trailing_slash: false,
+                            })
+                            .collect(),
+                    )
+                }
+                _ => None,
+            });
+        }
+
+        match label.kind() {
+            RepoKind::Apparent | RepoKind::Canonical => {
+                root = if self.bzlmod_enabled {
+                    let from_repo = try_opt!(self.repo_for_path(&from_path));
+                    let canonical_repo = try_opt!(self
+                        .bazel_client
+                        .resolve_repo_from_mapping(label.repo(), from_repo)?);
+                    if canonical_repo.is_empty() {
+                        self.workspace.clone()
+                    } else {
+                        self.external_output_base.join(canonical_repo)
+                    }
+                } else if self.workspace_name.as_deref() == Some(label.repo())
+                    || label.repo().is_empty()
+                {
+                    self.workspace.clone()
+                } else if let Some(path) = self.local_repo_path(label.repo()) {
+                    path
+                } else {
+                    self.external_output_base.join(label.repo())
+                };
+            }
+            RepoKind::Current => {}
+        }
+
+        match err {
+            Some(ParseError::EmptyPackage) => {
+                // An empty package usually indicates that the user is about to
+                // starting typing the package name.
+                read_dir_packages_and_targets(root, false, &is_candidate).map(Some)
+            }
+
+            Some(ParseError::EmptyTarget) => {
+                // Same logic as above, but for the target.
+                read_dir_targets(
+                    if label.is_relative() {
+                        package
+                    } else {
+                        root.join(label.package())
+                    },
+                    &is_candidate,
+                )
+                .map(Some)
+            }
+
+            Some(ParseError::InvalidPackageEndingSlash) | None => {
+                if label.is_relative() {
+                    // If the label is relative, check for target candidates in the current package.
+                    let (target_dir, _) = try_opt!(strip_slashes_or_pop_dir(label.target()));
+                    read_dir_targets(package.join(target_dir), &is_candidate).map(Some)
+                } else if !label.target().is_empty() && !label.has_target_shorthand() {
+                    // Check for target candidates in the label's package.
+                    let package_dir = root.join(label.package());
+                    let (target_dir, _) = try_opt!(strip_slashes_or_pop_dir(label.target()));
+                    read_dir_targets(package_dir.join(target_dir), &is_candidate).map(Some)
+                } else {
+                    // Otherwise, find package candidates.
+                    let (package_dir, has_trailing_slash) =
+                        try_opt!(strip_slashes_or_pop_dir(label.package()));
+                    read_dir_packages_and_targets(
+                        root.join(package_dir),
+                        has_trailing_slash,
+                        &is_candidate,
+                    )
+                    .map(Some)
+                }
+            }
+
+            _ => {
+                // Don't offer completions for any other parsing errors.
+                Ok(None)
+            }
+        }
+    }
 }
 
 impl FileLoader for DefaultFileLoader {
@@ -451,10 +701,15 @@ impl FileLoader for DefaultFileLoader {
                     Err(err) => return Err(anyhow!("error parsing label: {}", err.err)),
                 };
 
-                // Only .bzl files can be loaded.
-                if !label.target().ends_with(".bzl") {
+                // Only .bzl files can be loaded, with the exception of the `MODULE.bazel` segments
+                // loaded from other `MODULE.bazel` files, e.g. by `include()`.
+                let api_context = if label.target().ends_with(".bzl") {
+                    APIContext::Bzl
+                } else if is_module_file(label.target()) && self.is_module_file_id(from) {
+                    APIContext::Module
+                } else {
                     bail!("cannot load a non-bzl file");
-                }
+                };
 
                 let repo_kind = label.kind();
                 let (resolved_path, canonical_repo) = match self
@@ -472,7 +727,7 @@ impl FileLoader for DefaultFileLoader {
                 (
                     resolved_path,
                     Some(FileInfo::Bazel {
-                        api_context: APIContext::Bzl,
+                        api_context,
                         is_external,
                     }),
                     canonical_repo,
@@ -528,128 +783,70 @@ impl FileLoader for DefaultFileLoader {
                 Ok(Some(candidates))
             }
             Dialect::Bazel => {
-                // Determine the loading file's workspace root and package.
-                let (mut root, package) = try_opt!(starpls_bazel::resolve_workspace(
-                    self.interner.lookup_by_file_id(from),
-                )?);
-                let (label, err) = match Label::parse(path) {
-                    Ok(label) => (label, None),
-                    Err(PartialParse { partial, err }) => (partial, Some(err)),
-                };
-
-                if !label.has_leading_slashes()
-                    && !label.is_relative()
-                    && err != Some(ParseError::InvalidRepo)
-                {
-                    return Ok(match label.kind() {
-                        RepoKind::Apparent if self.bzlmod_enabled => Some(
-                            self.bazel_client
-                                .repo_mapping_keys("")?
-                                .into_iter()
-                                .map(|repo| LoadItemCandidate {
-                                    kind: LoadItemCandidateKind::Directory,
-                                    path: repo.to_string(),
-                                    replace_trailing_slash: false,
-                                })
-                                .collect(),
-                        ),
-                        RepoKind::Canonical | RepoKind::Apparent => Some(
-                            fs::read_dir(&self.external_output_base)?
-                                .filter_map(|entry| {
-                                    let entry = entry.ok()?;
-                                    entry.file_type().ok()?.is_dir().then(|| LoadItemCandidate {
-                                        kind: LoadItemCandidateKind::Directory,
-                                        path: entry.file_name().to_string_lossy().to_string(),
-                                        replace_trailing_slash: false,
-                                    })
-                                })
-                                .chain(self.workspace_name.as_ref().map(|name| LoadItemCandidate {
-                                    kind: LoadItemCandidateKind::Directory,
-                                    path: name.clone(),
-                                    replace_trailing_slash: false,
-                                }))
-                                .collect(),
-                        ),
-                        _ => None,
-                    });
-                }
-
-                match label.kind() {
-                    RepoKind::Apparent | RepoKind::Canonical => {
-                        root = if self.bzlmod_enabled {
-                            let from_repo = try_opt!(self.repo_for_path(&from_path));
-                            let canonical_repo = try_opt!(self
-                                .bazel_client
-                                .resolve_repo_from_mapping(label.repo(), from_repo)?);
-                            if canonical_repo.is_empty() {
-                                self.workspace.clone()
-                            } else {
-                                self.external_output_base.join(canonical_repo)
-                            }
-                        } else if self.workspace_name.as_deref() == Some(label.repo())
-                            || label.repo().is_empty()
-                        {
-                            self.workspace.clone()
-                        } else {
-                            self.external_output_base.join(label.repo())
-                        };
-                    }
-                    RepoKind::Current => {}
-                }
-
-                match err {
-                    Some(ParseError::EmptyPackage) => {
-                        // An empty package usually indicates that the user is about to
-                        // starting typing the package name.
-                        read_dir_packages_and_targets(root, false).map(Some)
-                    }
+                self.list_bazel_candidates(path, from, |file_name| file_name.ends_with(".bzl"))
+            }
+        }
+    }
 
-                    Some(ParseError::EmptyTarget) => {
-                        // Same logic as above, but for the target.
-                        read_dir_targets(if label.is_relative() {
-                            package
-                        } else {
-                            root.join(label.package())
-                        })
-                        .map(Some)
-                    }
+    fn list_label_candidates(
+        &self,
+        path: &str,
+        dialect: Dialect,
+        from: FileId,
+        allowed_files: &AllowedFiles,
+    ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>> {
+        if dialect != Dialect::Bazel {
+            return Ok(None);
+        }
 
-                    Some(ParseError::InvalidPackageEndingSlash) | None => {
-                        // TODO(withered-magic): Handle targets like in `//foo:bar/baz.bzl`.
-                        if label.is_relative() {
-                            // If the label is relative, check for target candidates in the current package.
-                            read_dir_targets(package).map(Some)
-                        } else if !label.target().is_empty() && !label.has_target_shorthand() {
-                            // Check for target candidates in the label's package.
-                            let package_dir = root.join(label.package());
-                            let (target_dir, _) =
-                                try_opt!(strip_slashes_or_pop_dir(label.target()));
-                            read_dir_targets(package_dir.join(target_dir)).map(Some)
-                        } else {
-                            // Otherwise, find package candidates.
-                            let (package_dir, has_trailing_slash) =
-                                try_opt!(strip_slashes_or_pop_dir(label.package()));
-                            read_dir_packages_and_targets(
-                                root.join(package_dir),
-                                has_trailing_slash,
-                            )
-                            .map(Some)
-                        }
-                    }
+        // Labels without a repository or package, e.g. `"foo.cc"`, are relative to the current
+        // package, just like `":foo.cc"`.
+        let path = if path.starts_with(['@', '/', ':']) {
+            path.to_string()
+        } else {
+            format!(":{}", path)
+        };
+        self.list_bazel_candidates(&path, from, |file_name| allowed_files.allows(file_name))
+    }
 
-                    _ => {
-                        // Don't offer completions for any other parsing errors.
-                        Ok(None)
-                    }
-                }
-            }
+    fn glob(
+        &self,
+        from: FileId,
+        include: &[String],
+        exclude: &[String],
+        exclude_directories: bool,
+    ) -> anyhow::Result<Option<GlobResult>> {
+        let from_path = self.interner.lookup_by_file_id(from);
+        let package_dir = try_opt!(from_path.parent());
+        let key = (
+            package_dir.to_path_buf(),
+            include.to_vec(),
+            exclude.to_vec(),
+            exclude_directories,
+        );
+        if let Some(result) = self.cached_glob_results.get(&key) {
+            return Ok(Some(result.clone()));
         }
+        let result = glob::glob(package_dir, include, exclude, exclude_directories)?;
+        self.cached_glob_results.insert(key, result.clone());
+        Ok(Some(result))
+    }
+
+    fn package_name(&self, file_id: FileId) -> Option<String> {
+        let path = self.interner.lookup_by_file_id(file_id);
+        let package_dir = path.parent()?.strip_prefix(&self.workspace).ok()?;
+        package_dir
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()
+            .map(|components| components.join("/"))
     }
 }
 
 fn read_dir_packages_and_targets(
     path: impl AsRef<Path>,
     has_trailing_slash: bool,
+    is_candidate: impl Fn(&str) -> bool,
 ) -> anyhow::Result<Vec<LoadItemCandidate>> {
     Ok(fs::read_dir(path)?
         .flatten()
@@ -667,7 +864,7 @@ fn read_dir_packages_and_targets(
                         file_name.to_string(),
                         false,
                     )
-                } else if file_name.ends_with(".bzl") {
+                } else if is_candidate(file_name) {
                     (
                         LoadItemCandidateKind::File,
                         format!(":{}", file_name),
@@ -686,7 +883,10 @@ fn read_dir_packages_and_targets(
         .collect())
 }
 
-fn read_dir_targets(path: impl AsRef<Path>) -> anyhow::Result<Vec<LoadItemCandidate>> {
+fn read_dir_targets(
+    path: impl AsRef<Path>,
+    is_candidate: impl Fn(&str) -> bool,
+) -> anyhow::Result<Vec<LoadItemCandidate>> {
     Ok(fs::read_dir(path)?
         .flatten()
         .filter_map(|entry| {
@@ -700,7 +900,7 @@ fn read_dir_targets(path: impl AsRef<Path>) -> anyhow::Result<Vec<LoadItemCandid
                 Some(LoadItemCandidate {
                     kind: if file_type.is_dir() {
                         LoadItemCandidateKind::Directory
-                    } else if file_name.ends_with(".bzl") {
+                    } else if is_candidate(file_name) {
                         LoadItemCandidateKind::File
                     } else {
                         return None;
@@ -725,6 +925,12 @@ fn strip_slashes_or_pop_dir(input: &str) -> Option<(PathBuf, bool)> {
     })
 }
 
+/// Whether the given file name is that of a `MODULE.bazel` file or of a segment included by one,
+/// e.g. `deps.MODULE.bazel`.
+fn is_module_file(name: &str) -> bool {
+    name == "MODULE.bazel" || name.ends_with(".MODULE.bazel")
+}
+
 pub(crate) fn dialect_and_api_context_for_workspace_path(
     workspace: impl AsRef<Path>,
     path: impl AsRef<Path>,
@@ -734,8 +940,7 @@ pub(crate) fn dialect_and_api_context_for_workspace_path(
     Some(match basename {
         "BUILD" | "BUILD.bazel" => (Dialect::Bazel, Some(APIContext::Build)),
         "REPO.bazel" => (Dialect::Bazel, Some(APIContext::Repo)),
-        "MODULE.bazel" => (Dialect::Bazel, Some(APIContext::Module)),
-        path if path.ends_with(".MODULE.bazel") => (Dialect::Bazel, Some(APIContext::Module)),
+        name if is_module_file(name) => (Dialect::Bazel, Some(APIContext::Module)),
         "WORKSPACE" | "WORKSPACE.bazel" | "WORKSPACE.bzlmod" => {
             (Dialect::Bazel, Some(APIContext::Workspace))
         }
@@ -1,7 +1,10 @@
 use crossbeam_channel::select;
 use lsp_server::Connection;
-use lsp_types::{InitializeParams, WorkDoneProgressCreateParams};
-use rustc_hash::FxHashSet;
+use lsp_types::InitializeParams;
+use starpls_bazel::{
+    lockfile::{ModuleExtensionRepos, ReferencedRepos, ResolvedModules},
+    registry::BazelRegistry,
+};
 use starpls_common::FileId;
 
 use crate::{
@@ -10,8 +13,10 @@ use crate::{
     dispatcher::RequestDispatcher,
     document::DocumentSource,
     extensions,
+    fetch::FetchOutcome,
     handlers::{notifications, requests},
     server::{Server, ServerSnapshot},
+    workspace_repos::WorkspaceRepos,
     ServerArgs,
 };
 
@@ -28,12 +33,6 @@ macro_rules! match_notification {
     }};
 }
 
-#[derive(Debug)]
-pub(crate) enum FetchExternalReposProgress {
-    Begin(FxHashSet<String>),
-    End(FxHashSet<FileId>, Vec<String>),
-}
-
 #[derive(Debug)]
 pub(crate) struct FetchExternalRepoRequest {
     pub(crate) file_id: FileId,
@@ -49,10 +48,20 @@ pub(crate) enum Task {
     ResponseReady(lsp_server::Response),
     /// Retry a previously failed request (e.g. due to Salsa cancellation).
     Retry(lsp_server::Request),
-    /// Events from fetching external repositories.
-    FetchExternalRepos(FetchExternalReposProgress),
+    /// An external repository has been fetched, or the fetch failed or was cancelled.
+    ExternalRepoFetched(String, FetchOutcome),
     /// A request to fetch an external repository.
     FetchExternalRepoRequest(FetchExternalRepoRequest),
+    /// The repositories generated by module extensions have been loaded from `MODULE.bazel.lock`.
+    ModuleExtensionReposLoaded(ModuleExtensionRepos),
+    /// The workspace has been scanned for the repositories referenced by its labels.
+    ReferencedReposLoaded(ReferencedRepos),
+    /// The modules selected during module resolution have been loaded from `MODULE.bazel.lock`.
+    ResolvedModulesLoaded(ResolvedModules),
+    /// The modules available in a local Bazel registry have been loaded.
+    BazelRegistryLoaded(BazelRegistry),
+    /// The external repositories declared in `WORKSPACE` have been scanned.
+    WorkspaceReposLoaded(WorkspaceRepos),
 }
 
 #[derive(Debug)]
@@ -117,9 +126,7 @@ impl Server {
             }
         };
 
-        if !self.pending_repos.is_empty() && !self.is_fetching_repos {
-            self.fetch_bazel_external_repos();
-        }
+        self.fetch_next_external_repo();
 
         // Update our diagnostics if a triggering event (e.g. document open/close/change) occured.
         // This is done asynchronously, so any new diagnostics resulting from this won't be seen until the next turn
@@ -201,7 +208,12 @@ impl Server {
         RequestDispatcher::new(req, self)
             .on::<extensions::ShowSyntaxTree>(requests::show_syntax_tree)
             .on::<extensions::ShowHir>(requests::show_hir)
+            .on_sync_mut::<extensions::FetchRepos>(requests::fetch_repos)
+            .on_sync_mut::<lsp_types::request::ExecuteCommand>(requests::execute_command)
+            .on::<lsp_types::request::CodeActionRequest>(requests::code_action)
             .on::<lsp_types::request::Completion>(requests::completion)
+            .on::<lsp_types::request::DocumentLinkRequest>(requests::document_links)
+            .on::<lsp_types::request::DocumentLinkResolve>(requests::document_link_resolve)
             .on::<lsp_types::request::DocumentSymbolRequest>(requests::document_symbols)
             .on::<lsp_types::request::GotoDefinition>(requests::goto_definition)
             .on::<lsp_types::request::HoverRequest>(requests::hover)
@@ -216,6 +228,7 @@ impl Server {
                 if lsp_types::notification::DidCloseTextDocument as params => notifications::did_close_text_document(self, params),
                 if lsp_types::notification::DidChangeTextDocument as params => notifications::did_change_text_document(self, params),
                 if lsp_types::notification::DidSaveTextDocument as params => notifications::did_save_text_document(self, params),
+                if lsp_types::notification::WorkDoneProgressCancel as params => notifications::work_done_progress_cancel(self, params),
                 _ => Ok(())
             }
         }
@@ -234,67 +247,56 @@ impl Server {
                 self.respond(resp);
             }
             Task::Retry(req) => self.handle_request(req),
-            Task::FetchExternalRepos(progress) => {
-                let token = "FetchExternalRepos".to_string();
-                let work_done = match progress {
-                    FetchExternalReposProgress::Begin(repos) => {
-                        self.send_request::<lsp_types::request::WorkDoneProgressCreate>(
-                            WorkDoneProgressCreateParams {
-                                token: lsp_types::NumberOrString::String(token.clone()),
-                            },
-                        );
-
-                        let mut repos = repos.into_iter().collect::<Vec<_>>();
-                        repos.sort();
-
-                        let mut title = "Fetching external repositories: ".to_string();
-                        for (i, repo) in repos.into_iter().enumerate() {
-                            if i > 0 {
-                                title.push_str(", ");
-                            }
-                            title.push('"');
-                            title.push_str(&repo);
-                            title.push('"');
-                        }
-
-                        lsp_types::WorkDoneProgress::Begin(lsp_types::WorkDoneProgressBegin {
-                            title,
-                            ..Default::default()
-                        })
-                    }
-                    FetchExternalReposProgress::End(files, failed_repos) => {
-                        self.is_fetching_repos = false;
-                        self.force_analysis_for_files.extend(files);
-                        if !failed_repos.is_empty() {
-                            self.send_error_message(&format!(
-                                "Failed to fetch external repositories: {}. Please check the server logs for more details.",
-                                failed_repos.join(", ")
-                            ));
-                        }
-
-                        lsp_types::WorkDoneProgress::End(lsp_types::WorkDoneProgressEnd {
-                            message: None,
-                        })
+            Task::ExternalRepoFetched(repo, outcome) => {
+                match &outcome {
+                    FetchOutcome::Fetched => self.loader.set_fetch_failure(&repo, None),
+                    FetchOutcome::Failed(err) => {
+                        self.loader.set_fetch_failure(&repo, Some(err.clone()))
                     }
-                };
+                    FetchOutcome::Cancelled => {}
+                }
 
-                self.send_notification::<lsp_types::notification::Progress>(
-                    lsp_types::ProgressParams {
-                        token: lsp_types::NumberOrString::String(token),
-                        value: lsp_types::ProgressParamsValue::WorkDone(work_done),
-                    },
-                );
+                let (files, batch) = self.fetch_queue.finish(&repo, &outcome);
+                self.force_analysis_for_files.extend(files);
+                if let Some(batch) = batch {
+                    // Repositories fetched on request may be needed by any open file.
+                    if batch.has_fetched {
+                        self.force_analysis_for_files
+                            .extend(self.document_manager.read().editor_file_ids());
+                    }
+                    if !batch.failed_repos.is_empty() {
+                        self.send_error_message(&format!(
+                            "Failed to fetch external repositories: {}. Please check the server logs for more details, and use the `starpls/fetchRepos` request to retry.",
+                            batch.failed_repos.join(", ")
+                        ));
+                    }
+                    self.send_fetch_progress(lsp_types::WorkDoneProgress::End(
+                        lsp_types::WorkDoneProgressEnd { message: None },
+                    ));
+                }
             }
             Task::FetchExternalRepoRequest(FetchExternalRepoRequest { file_id, repo }) => {
-                if !self.fetched_repos.contains(&repo) {
-                    self.pending_repos.insert(repo);
-                    self.pending_files.insert(file_id);
-                }
+                self.fetch_queue.request(repo, file_id);
+            }
+            Task::ModuleExtensionReposLoaded(repos) => {
+                self.module_extension_repos = Some(repos);
+                self.set_module_extension_repos();
+            }
+            Task::ReferencedReposLoaded(repos) => {
+                self.referenced_repos = repos;
+                self.set_module_extension_repos();
+            }
+            Task::ResolvedModulesLoaded(modules) => self.analysis.set_resolved_modules(modules),
+            Task::BazelRegistryLoaded(registry) => self.analysis.set_bazel_registry(registry),
+            Task::WorkspaceReposLoaded(repos) => {
+                self.loader.set_workspace_repos(repos);
+                self.force_analysis_for_files
+                    .extend(self.document_manager.read().editor_file_ids());
             }
         }
     }
 
-    fn respond(&mut self, resp: lsp_server::Response) {
+    pub(crate) fn respond(&mut self, resp: lsp_server::Response) {
         if self.req_queue.incoming.complete(resp.id.clone()).is_some() {
             self.connection.sender.send(resp.into()).unwrap();
         }
@@ -327,7 +329,9 @@ fn collect_diagnostics(
     Some(
         diagnostics
             .into_iter()
-            .flat_map(|diagnostic| convert::lsp_diagnostic_from_native(diagnostic, line_index))
+            .flat_map(|diagnostic| {
+                convert::lsp_diagnostic_from_native(snapshot, diagnostic, line_index)
+            })
             .collect::<Vec<_>>(),
     )
 }
@@ -1,6 +1,10 @@
 use lsp_types::{request::Request, TextDocumentIdentifier};
 use serde::{Deserialize, Serialize};
 
+/// The command that shows a location in the editor, e.g. the declaration that a code action
+/// navigates to. Its only argument is an [`lsp_types::Location`].
+pub const SHOW_LOCATION_COMMAND: &str = "starpls.showLocation";
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ShowSyntaxTreeParams {
@@ -30,3 +34,22 @@ impl Request for ShowHir {
     type Result = String;
     const METHOD: &'static str = "starpls/showHir";
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchReposParams {
+    /// The canonical names of the repositories to fetch. If empty, the repositories that failed to
+    /// be fetched are retried.
+    #[serde(default)]
+    pub repos: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum FetchRepos {}
+
+impl Request for FetchRepos {
+    type Params = FetchReposParams;
+    /// The repositories that were queued for fetching.
+    type Result = Vec<String>;
+    const METHOD: &'static str = "starpls/fetchRepos";
+}
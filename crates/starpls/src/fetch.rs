@@ -0,0 +1,314 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use starpls_common::FileId;
+
+/// The number of times a repository is fetched automatically, i.e. because a file failed to load
+/// from it. Afterwards, the repository is only fetched when explicitly requested, e.g. through the
+/// `starpls/fetchRepos` request.
+const MAX_AUTOMATIC_ATTEMPTS: usize = 2;
+
+/// The result of fetching an external repository.
+#[derive(Debug)]
+pub(crate) enum FetchOutcome {
+    Fetched,
+    Failed(String),
+    Cancelled,
+}
+
+/// A fetch that has been started by the [`FetchQueue`].
+pub(crate) struct ActiveFetch {
+    pub(crate) repo: String,
+    pub(crate) cancelled: Arc<AtomicBool>,
+    /// Whether this is the first fetch of a batch, i.e. whether progress reporting should begin.
+    pub(crate) is_first: bool,
+    /// The number of repositories still waiting to be fetched after this one.
+    pub(crate) remaining: usize,
+}
+
+/// The summary of a batch of fetches, i.e. the fetches performed between the queue becoming
+/// non-empty and becoming empty again.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct FinishedBatch {
+    pub(crate) failed_repos: Vec<String>,
+    /// Whether any repository was fetched successfully during the batch.
+    pub(crate) has_fetched: bool,
+}
+
+/// Schedules the fetching of external repositories, one repository at a time. Repositories needed
+/// by the focused file are fetched first, and the active fetch can be cancelled.
+#[derive(Default)]
+pub(crate) struct FetchQueue {
+    /// The repositories waiting to be fetched, in the order in which they were requested.
+    pending: Vec<String>,
+    /// The files that failed to load from each repository.
+    waiting_files: FxHashMap<String, FxHashSet<FileId>>,
+    active: Option<(String, Arc<AtomicBool>)>,
+    attempts: FxHashMap<String, usize>,
+    fetched: FxHashSet<String>,
+    /// The repositories whose fetches were cancelled by the user. These are no longer fetched
+    /// automatically, until they're explicitly requested again.
+    cancelled: FxHashSet<String>,
+    /// The summary of the current batch, if a batch is in progress.
+    batch: Option<FinishedBatch>,
+}
+
+impl FetchQueue {
+    /// Requests that `repo` be fetched because `file_id` failed to load a file from it.
+    pub(crate) fn request(&mut self, repo: String, file_id: FileId) {
+        if self.fetched.contains(&repo)
+            || self.cancelled.contains(&repo)
+            || self.attempts.get(&repo).copied().unwrap_or_default() >= MAX_AUTOMATIC_ATTEMPTS
+        {
+            return;
+        }
+        self.waiting_files
+            .entry(repo.clone())
+            .or_default()
+            .insert(file_id);
+        self.enqueue(repo);
+    }
+
+    /// Requests that `repo` be fetched regardless of whether it has been fetched before.
+    pub(crate) fn request_explicit(&mut self, repo: String) {
+        self.fetched.remove(&repo);
+        self.cancelled.remove(&repo);
+        self.attempts.remove(&repo);
+        self.enqueue(repo);
+    }
+
+    fn enqueue(&mut self, repo: String) {
+        let is_active = self
+            .active
+            .as_ref()
+            .is_some_and(|(active, _)| *active == repo);
+        if !is_active && !self.pending.contains(&repo) {
+            self.pending.push(repo);
+        }
+    }
+
+    /// Starts the next fetch, preferring the repositories that `focused_file` is waiting for.
+    pub(crate) fn start_next(&mut self, focused_file: Option<FileId>) -> Option<ActiveFetch> {
+        if self.active.is_some() || self.pending.is_empty() {
+            return None;
+        }
+
+        let index = focused_file
+            .and_then(|file_id| {
+                self.pending.iter().position(|repo| {
+                    self.waiting_files
+                        .get(repo)
+                        .is_some_and(|files| files.contains(&file_id))
+                })
+            })
+            .unwrap_or_default();
+        let repo = self.pending.remove(index);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        *self.attempts.entry(repo.clone()).or_default() += 1;
+        self.active = Some((repo.clone(), cancelled.clone()));
+
+        let is_first = self.batch.is_none();
+        self.batch.get_or_insert_with(Default::default);
+        Some(ActiveFetch {
+            repo,
+            cancelled,
+            is_first,
+            remaining: self.pending.len(),
+        })
+    }
+
+    /// Records the outcome of the active fetch. Returns the files that were waiting for the
+    /// repository, along with the summary of the current batch if it's now finished.
+    pub(crate) fn finish(
+        &mut self,
+        repo: &str,
+        outcome: &FetchOutcome,
+    ) -> (FxHashSet<FileId>, Option<FinishedBatch>) {
+        if self
+            .active
+            .as_ref()
+            .is_some_and(|(active, _)| active == repo)
+        {
+            self.active = None;
+        }
+
+        let mut files = self.waiting_files.remove(repo).unwrap_or_default();
+        match outcome {
+            FetchOutcome::Fetched => {
+                self.fetched.insert(repo.to_string());
+                if let Some(batch) = &mut self.batch {
+                    batch.has_fetched = true;
+                }
+            }
+            FetchOutcome::Failed(_) => {
+                if let Some(batch) = &mut self.batch {
+                    batch.failed_repos.push(repo.to_string());
+                }
+            }
+            // A cancelled fetch doesn't count against the repository's automatic attempts. The
+            // waiting files don't need to be analyzed again, since the repository is still missing.
+            FetchOutcome::Cancelled => {
+                if let Some(attempts) = self.attempts.get_mut(repo) {
+                    *attempts = attempts.saturating_sub(1);
+                }
+                files.clear();
+            }
+        }
+
+        let batch = if self.pending.is_empty() {
+            self.batch.take()
+        } else {
+            None
+        };
+        (files, batch)
+    }
+
+    /// Cancels the active fetch, as well as all pending fetches. The cancelled repositories aren't
+    /// fetched automatically again until they're explicitly requested.
+    pub(crate) fn cancel(&mut self) {
+        if let Some((repo, cancelled)) = &self.active {
+            cancelled.store(true, Ordering::Relaxed);
+            self.cancelled.insert(repo.clone());
+        }
+        for repo in self.pending.drain(..) {
+            self.waiting_files.remove(&repo);
+            self.cancelled.insert(repo);
+        }
+    }
+
+    /// Forgets which repositories have been fetched, e.g. because the workspace's external
+    /// dependencies have changed.
+    pub(crate) fn reset(&mut self) {
+        self.fetched.clear();
+        self.attempts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(file_ids: &[u32]) -> FxHashSet<FileId> {
+        file_ids.iter().map(|file_id| FileId(*file_id)).collect()
+    }
+
+    fn fetch_next(queue: &mut FetchQueue, focused_file: Option<FileId>) -> String {
+        queue.start_next(focused_file).unwrap().repo
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let mut queue = FetchQueue::default();
+        queue.request("foo".to_string(), FileId(0));
+        queue.request("foo".to_string(), FileId(1));
+        queue.request("bar".to_string(), FileId(1));
+
+        let fetch = queue.start_next(None).unwrap();
+        assert_eq!(fetch.repo, "foo");
+        assert!(fetch.is_first);
+        assert_eq!(fetch.remaining, 1);
+
+        // The active repository isn't queued again.
+        queue.request("foo".to_string(), FileId(2));
+        assert!(queue.start_next(None).is_none());
+        let (waiting, batch) = queue.finish("foo", &FetchOutcome::Fetched);
+        assert_eq!(waiting, files(&[0, 1, 2]));
+        assert!(batch.is_none());
+
+        let fetch = queue.start_next(None).unwrap();
+        assert_eq!(fetch.repo, "bar");
+        assert!(!fetch.is_first);
+        let (_, batch) = queue.finish("bar", &FetchOutcome::Fetched);
+        assert_eq!(
+            batch,
+            Some(FinishedBatch {
+                failed_repos: vec![],
+                has_fetched: true,
+            })
+        );
+
+        // Fetched repositories aren't fetched automatically again.
+        queue.request("foo".to_string(), FileId(0));
+        assert!(queue.start_next(None).is_none());
+    }
+
+    #[test]
+    fn test_focused_file_first() {
+        let mut queue = FetchQueue::default();
+        queue.request("foo".to_string(), FileId(0));
+        queue.request("bar".to_string(), FileId(1));
+        assert_eq!(fetch_next(&mut queue, Some(FileId(1))), "bar");
+    }
+
+    #[test]
+    fn test_attempts() {
+        let mut queue = FetchQueue::default();
+        for _ in 0..MAX_AUTOMATIC_ATTEMPTS {
+            queue.request("foo".to_string(), FileId(0));
+            assert_eq!(fetch_next(&mut queue, None), "foo");
+            let (_, batch) = queue.finish("foo", &FetchOutcome::Failed("error".to_string()));
+            assert_eq!(
+                batch,
+                Some(FinishedBatch {
+                    failed_repos: vec!["foo".to_string()],
+                    has_fetched: false,
+                })
+            );
+        }
+
+        // The repository has used up its automatic attempts.
+        queue.request("foo".to_string(), FileId(0));
+        assert!(queue.start_next(None).is_none());
+
+        // Explicit requests are always honored.
+        queue.request_explicit("foo".to_string());
+        assert_eq!(fetch_next(&mut queue, None), "foo");
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut queue = FetchQueue::default();
+        queue.request("foo".to_string(), FileId(0));
+        queue.request("bar".to_string(), FileId(1));
+        let fetch = queue.start_next(None).unwrap();
+        queue.cancel();
+        assert!(fetch.cancelled.load(Ordering::Relaxed));
+
+        // Files waiting for cancelled repositories aren't analyzed again.
+        let (waiting, batch) = queue.finish("foo", &FetchOutcome::Cancelled);
+        assert!(waiting.is_empty());
+        assert_eq!(
+            batch,
+            Some(FinishedBatch {
+                failed_repos: vec![],
+                has_fetched: false,
+            })
+        );
+
+        // Cancelled repositories, whether active or pending, aren't fetched automatically again.
+        queue.request("foo".to_string(), FileId(0));
+        queue.request("bar".to_string(), FileId(1));
+        assert!(queue.start_next(None).is_none());
+    }
+
+    #[test]
+    fn test_retry_after_cancel() {
+        let mut queue = FetchQueue::default();
+        queue.request("foo".to_string(), FileId(0));
+        assert_eq!(fetch_next(&mut queue, None), "foo");
+        queue.cancel();
+        queue.finish("foo", &FetchOutcome::Cancelled);
+
+        queue.request_explicit("foo".to_string());
+        assert_eq!(fetch_next(&mut queue, None), "foo");
+        queue.finish("foo", &FetchOutcome::Fetched);
+
+        // Once explicitly requested, the repository is no longer considered cancelled.
+        queue.reset();
+        queue.request("foo".to_string(), FileId(0));
+        assert_eq!(fetch_next(&mut queue, None), "foo");
+    }
+}
@@ -1,15 +1,23 @@
-use crate::{convert, server::Server, utils::apply_document_content_changes};
+use crate::{
+    convert,
+    server::{fetch_progress_token, is_referencing_file_name, Server},
+    utils::apply_document_content_changes,
+};
 
 pub(crate) fn did_open_text_document(
     server: &mut Server,
     params: lsp_types::DidOpenTextDocumentParams,
 ) -> anyhow::Result<()> {
     let path = convert::path_buf_from_url(&params.text_document.uri)?;
-    server.document_manager.write().open(
-        path,
+    // Opening a document may mean that a new file was created.
+    server.loader.clear_glob_results();
+    let mut document_manager = server.document_manager.write();
+    document_manager.open(
+        path.clone(),
         params.text_document.version,
         params.text_document.text,
     );
+    server.focused_file = document_manager.lookup_by_path_buf(&path);
     Ok(())
 }
 
@@ -18,7 +26,13 @@ pub(crate) fn did_close_text_document(
     params: lsp_types::DidCloseTextDocumentParams,
 ) -> anyhow::Result<()> {
     let path = convert::path_buf_from_url(&params.text_document.uri)?;
-    server.document_manager.write().close(&path);
+    let mut document_manager = server.document_manager.write();
+    if server.focused_file.is_some()
+        && server.focused_file == document_manager.lookup_by_path_buf(&path)
+    {
+        server.focused_file = None;
+    }
+    document_manager.close(&path);
     Ok(())
 }
 
@@ -34,7 +48,8 @@ pub(crate) fn did_change_text_document(
             .map(|document| document.contents.clone())
             .expect("lookup contents of non-existent file");
         let contents = apply_document_content_changes(contents, params.content_changes);
-        document_manager.modify(file_id, contents, Some(params.text_document.version))
+        document_manager.modify(file_id, contents, Some(params.text_document.version));
+        server.focused_file = Some(file_id);
     }
     Ok(())
 }
@@ -44,19 +59,42 @@ pub(crate) fn did_save_text_document(
     params: lsp_types::DidSaveTextDocumentParams,
 ) -> anyhow::Result<()> {
     let path = convert::path_buf_from_url(&params.text_document.uri)?;
-    if server
-        .document_manager
-        .read()
-        .lookup_by_path_buf(&path)
-        .is_some()
-    {
-        match path.file_name().and_then(|file_name| file_name.to_str()) {
-            Some("MODULE.bazel" | "WORKSPACE" | "WORKSPACE.bazel" | "WORKSPACE.bzlmod") => {}
-            Some(file_name) if file_name.ends_with(".MODULE.bazel") => {}
-            _ => return Ok(()),
+    server.loader.clear_glob_results();
+    let file_id = match server.document_manager.read().lookup_by_path_buf(&path) {
+        Some(file_id) => file_id,
+        None => return Ok(()),
+    };
+    match path.file_name().and_then(|file_name| file_name.to_str()) {
+        Some("MODULE.bazel" | "WORKSPACE" | "WORKSPACE.bazel" | "WORKSPACE.bzlmod") => {}
+        Some(file_name) if file_name.ends_with(".MODULE.bazel") => {}
+        Some(file_name) if server.bzlmod_enabled && is_referencing_file_name(file_name) => {
+            let contents = server
+                .document_manager
+                .read()
+                .get(file_id)
+                .map(|document| document.contents.clone());
+            if let Some(contents) = contents {
+                server.update_referenced_repos(path, &contents);
+            }
+            return Ok(());
         }
-        server.bazel_client.clear_repo_mappings();
-        server.fetched_repos.clear();
+        _ => return Ok(()),
+    }
+    server.bazel_client.clear_repo_mappings();
+    server.fetch_queue.reset();
+    server.loader.clear_fetch_failures();
+    server.load_module_lockfile();
+    server.load_workspace_repos();
+    Ok(())
+}
+
+pub(crate) fn work_done_progress_cancel(
+    server: &mut Server,
+    params: lsp_types::WorkDoneProgressCancelParams,
+) -> anyhow::Result<()> {
+    if params.token == fetch_progress_token() {
+        eprintln!("server: cancelling fetching of external repositories");
+        server.fetch_queue.cancel();
     }
     Ok(())
 }
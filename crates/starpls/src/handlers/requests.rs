@@ -1,14 +1,16 @@
-use anyhow::Ok;
+use anyhow::{anyhow, Ok};
+use serde::{Deserialize, Serialize};
 use starpls_ide::{
     CompletionItemKind,
     CompletionMode::{InsertText, TextEdit},
     Edit, FilePosition,
 };
+use starpls_syntax::TextRange;
 
 use crate::{
     convert::{self, path_buf_from_url},
-    extensions::{ShowHirParams, ShowSyntaxTreeParams},
-    server::ServerSnapshot,
+    extensions::{FetchReposParams, ShowHirParams, ShowSyntaxTreeParams, SHOW_LOCATION_COMMAND},
+    server::{Server, ServerSnapshot},
     utils::response_from_locations,
 };
 
@@ -32,6 +34,57 @@ pub(crate) fn show_hir(snapshot: &ServerSnapshot, params: ShowHirParams) -> anyh
     Ok(rendered_hir.unwrap_or_else(|| "".to_string()))
 }
 
+/// Queues the given external repositories for fetching, even if they've been fetched before. If no
+/// repositories are given, the repositories that failed to be fetched are retried.
+pub(crate) fn fetch_repos(
+    server: &mut Server,
+    params: FetchReposParams,
+) -> anyhow::Result<Vec<String>> {
+    let repos = if params.repos.is_empty() {
+        server.loader.failed_repos()
+    } else {
+        params
+            .repos
+            .iter()
+            .map(|repo| repo.trim_start_matches('@').to_string())
+            .collect()
+    };
+    for repo in &repos {
+        server.fetch_queue.request_explicit(repo.clone());
+    }
+    Ok(repos)
+}
+
+pub(crate) fn execute_command(
+    server: &mut Server,
+    params: lsp_types::ExecuteCommandParams,
+) -> anyhow::Result<Option<serde_json::Value>> {
+    match params.command.as_str() {
+        SHOW_LOCATION_COMMAND => {
+            if !server.config.has_show_document_support() {
+                return Err(anyhow!("client doesn't support showing documents"));
+            }
+            let location: lsp_types::Location = serde_json::from_value(
+                params
+                    .arguments
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("missing location argument"))?,
+            )?;
+            server.send_request::<lsp_types::request::ShowDocument>(
+                lsp_types::ShowDocumentParams {
+                    uri: location.uri,
+                    external: None,
+                    take_focus: Some(true),
+                    selection: Some(location.range),
+                },
+            );
+            Ok(None)
+        }
+        command => Err(anyhow!("unknown command: {}", command)),
+    }
+}
+
 pub(crate) fn show_syntax_tree(
     snapshot: &ServerSnapshot,
     params: ShowSyntaxTreeParams,
@@ -68,6 +121,47 @@ pub(crate) fn goto_definition(
     Ok(Some(resp))
 }
 
+pub(crate) fn code_action(
+    snapshot: &ServerSnapshot,
+    params: lsp_types::CodeActionParams,
+) -> anyhow::Result<Option<lsp_types::CodeActionResponse>> {
+    let path = path_buf_from_url(&params.text_document.uri)?;
+    let file_id = try_opt!(snapshot.document_manager.read().lookup_by_path_buf(&path));
+    let line_index = try_opt!(snapshot.analysis_snapshot.line_index(file_id)?);
+    let start = try_opt!(convert::text_size_from_lsp_position(
+        snapshot,
+        file_id,
+        params.range.start,
+    )?);
+    let end = try_opt!(convert::text_size_from_lsp_position(
+        snapshot,
+        file_id,
+        params.range.end,
+    )?);
+    let range = TextRange::new(start, end);
+
+    // Offer the fixes and navigations attached to any diagnostic that overlaps with the requested
+    // range.
+    Ok(Some(
+        snapshot
+            .analysis_snapshot
+            .diagnostics(file_id)?
+            .into_iter()
+            .filter(|diagnostic| {
+                (!diagnostic.fixes.is_empty()
+                    || (!diagnostic.navigations.is_empty()
+                        && snapshot.config.has_show_document_support()))
+                    && diagnostic.range.file_id == file_id
+                    && diagnostic.range.range.intersect(range).is_some()
+            })
+            .flat_map(|diagnostic| {
+                convert::lsp_code_actions_from_native(snapshot, diagnostic, line_index)
+            })
+            .map(lsp_types::CodeActionOrCommand::CodeAction)
+            .collect(),
+    ))
+}
+
 pub(crate) fn completion(
     snapshot: &ServerSnapshot,
     params: lsp_types::CompletionParams,
@@ -142,6 +236,7 @@ pub(crate) fn completion(
                         CompletionItemKind::File => lsp_types::CompletionItemKind::FILE,
                         CompletionItemKind::Folder => lsp_types::CompletionItemKind::FOLDER,
                         CompletionItemKind::Constant => lsp_types::CompletionItemKind::CONSTANT,
+                        CompletionItemKind::Target => lsp_types::CompletionItemKind::VALUE,
                     }),
                     sort_text,
                     insert_text,
@@ -216,6 +311,63 @@ pub(crate) fn signature_help(
         }))
 }
 
+/// The data attached to the document links for labels, which are resolved lazily to avoid loading
+/// BUILD files or fetching external repositories when a file is opened.
+#[derive(Serialize, Deserialize)]
+struct DocumentLinkData {
+    uri: lsp_types::Url,
+    label: String,
+}
+
+pub(crate) fn document_links(
+    snapshot: &ServerSnapshot,
+    params: lsp_types::DocumentLinkParams,
+) -> anyhow::Result<Option<Vec<lsp_types::DocumentLink>>> {
+    let path = path_buf_from_url(&params.text_document.uri)?;
+    let file_id = try_opt!(snapshot.document_manager.read().lookup_by_path_buf(&path));
+    let line_index = try_opt!(snapshot.analysis_snapshot.line_index(file_id)?);
+    Ok(snapshot
+        .analysis_snapshot
+        .document_links(file_id)?
+        .map(|links| {
+            links
+                .into_iter()
+                .filter_map(|link| {
+                    Some(lsp_types::DocumentLink {
+                        range: convert::lsp_range_from_text_range(link.range, line_index)?,
+                        target: None,
+                        data: serde_json::to_value(DocumentLinkData {
+                            uri: params.text_document.uri.clone(),
+                            label: link.label.clone(),
+                        })
+                        .ok(),
+                        tooltip: Some(link.label),
+                    })
+                })
+                .collect()
+        }))
+}
+
+pub(crate) fn document_link_resolve(
+    snapshot: &ServerSnapshot,
+    mut link: lsp_types::DocumentLink,
+) -> anyhow::Result<lsp_types::DocumentLink> {
+    let data: DocumentLinkData = match link.data.take() {
+        Some(data) => serde_json::from_value(data)?,
+        None => return Ok(link),
+    };
+    let path = path_buf_from_url(&data.uri)?;
+    let file_id = match snapshot.document_manager.read().lookup_by_path_buf(&path) {
+        Some(file_id) => file_id,
+        None => return Ok(link),
+    };
+    link.target = snapshot
+        .analysis_snapshot
+        .resolve_document_link(file_id, &data.label)?
+        .and_then(|location| convert::lsp_url_from_location_link(snapshot, location));
+    Ok(link)
+}
+
 pub(crate) fn document_symbols(
     snapshot: &ServerSnapshot,
     params: lsp_types::DocumentSymbolParams,
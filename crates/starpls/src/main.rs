@@ -2,10 +2,13 @@ use check::run_check;
 use clap::{Args, Parser, Subcommand};
 use lsp_server::Connection;
 use lsp_types::{
-    CompletionOptions, HoverProviderCapability, OneOf, ServerCapabilities, SignatureHelpOptions,
+    CodeActionProviderCapability, CompletionOptions, DocumentLinkOptions, ExecuteCommandOptions,
+    HoverProviderCapability, OneOf, ServerCapabilities, SignatureHelpOptions,
     TextDocumentSyncCapability, TextDocumentSyncKind,
 };
 
+use crate::extensions::SHOW_LOCATION_COMMAND;
+
 mod check;
 mod config;
 mod convert;
@@ -15,10 +18,12 @@ mod dispatcher;
 mod document;
 mod event_loop;
 mod extensions;
+mod fetch;
 mod handlers;
 mod server;
 mod task_pool;
 mod utils;
+mod workspace_repos;
 
 const COMPLETION_TRIGGER_CHARACTERS: &[char] = &['.', '"', '\'', '/', ':', '@'];
 const SIGNATURE_HELP_TRIGGER_CHARACTERS: &[char] = &['(', ',', ')'];
@@ -39,6 +44,9 @@ enum Commands {
         /// Path to the Bazel output base.
         #[clap(long = "output_base")]
         output_base: Option<String>,
+        /// Report `# type: ignore` comments that don't suppress any diagnostics.
+        #[clap(long = "report_unused_type_ignores", default_value_t = false)]
+        report_unused_type_ignores: bool,
     },
     /// Start the language server.
     Server(ServerArgs),
@@ -57,13 +65,26 @@ pub(crate) struct ServerArgs {
     /// Enable code-flow analysis during typechecking.
     #[clap(long = "experimental_use_code_flow_analysis", default_value_t = false)]
     use_code_flow_analysis: bool,
+    /// Report `# type: ignore` comments that don't suppress any diagnostics.
+    #[clap(long = "report_unused_type_ignores", default_value_t = false)]
+    report_unused_type_ignores: bool,
+    /// Allow rebinding globals in `.bzl` files, for Starlark interpreters that permit it.
+    #[clap(long = "allow_global_reassignment", default_value_t = false)]
+    allow_global_reassignment: bool,
+    /// Path to a local Bazel registry, used to complete module names and versions in `MODULE.bazel`.
+    #[clap(long = "registry")]
+    registry: Option<String>,
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Some(Commands::Check { paths, output_base }) => run_check(paths, output_base),
+        Some(Commands::Check {
+            paths,
+            output_base,
+            report_unused_type_ignores,
+        }) => run_check(paths, output_base, report_unused_type_ignores),
         Some(Commands::Server(args)) => run_server(args),
         Some(Commands::Version) => run_version(),
         None => run_server(Default::default()),
@@ -79,12 +100,21 @@ fn run_server(args: ServerArgs) -> anyhow::Result<()> {
     // Initialize the connection with server capabilities. For now, this consists
     // only of `TextDocumentSyncKind.Full`.
     let server_capabilities = serde_json::to_value(ServerCapabilities {
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
         completion_provider: Some(CompletionOptions {
             trigger_characters: Some(make_trigger_characters(COMPLETION_TRIGGER_CHARACTERS)),
             ..Default::default()
         }),
         definition_provider: Some(OneOf::Left(true)),
+        document_link_provider: Some(DocumentLinkOptions {
+            resolve_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        }),
         document_symbol_provider: Some(OneOf::Left(true)),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: vec![SHOW_LOCATION_COMMAND.to_string()],
+            work_done_progress_options: Default::default(),
+        }),
         hover_provider: Some(HoverProviderCapability::Simple(true)),
         signature_help_provider: Some(SignatureHelpOptions {
             trigger_characters: Some(make_trigger_characters(SIGNATURE_HELP_TRIGGER_CHARACTERS)),
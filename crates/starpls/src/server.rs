@@ -1,7 +1,8 @@
 use std::{
-    fs, mem, panic,
+    fs, io, panic,
     path::{Path, PathBuf},
-    sync::Arc,
+    str,
+    sync::{atomic::Ordering, Arc},
     time::Duration,
 };
 
@@ -11,7 +12,10 @@ use rustc_hash::FxHashSet;
 use starpls_bazel::{
     build_language::decode_rules,
     client::{BazelCLI, BazelClient},
-    decode_builtins, APIContext, Builtins,
+    decode_builtins,
+    lockfile::{ModuleExtensionRepos, ReferencedRepos, ResolvedModules},
+    registry::BazelRegistry,
+    APIContext, Builtins,
 };
 use starpls_common::{Dialect, FileId, FileInfo};
 use starpls_ide::{Analysis, AnalysisSnapshot, Change, InferenceOptions};
@@ -21,12 +25,16 @@ use crate::{
     debouncer::AnalysisDebouncer,
     diagnostics::DiagnosticsManager,
     document::{DefaultFileLoader, DocumentChangeKind, DocumentManager, PathInterner},
-    event_loop::{FetchExternalReposProgress, Task},
+    event_loop::Task,
+    fetch::{FetchOutcome, FetchQueue},
     task_pool::{TaskPool, TaskPoolHandle},
+    workspace_repos::WorkspaceRepos,
 };
 
 const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(250);
 
+const FETCH_REPOS_PROGRESS_TOKEN: &str = "FetchExternalRepos";
+
 const BAZEL_INIT_ERR_MESSAGE: &str = "Failed to fetch Bazel configuration! Please check the language server logs for more details. Certain features may not work correctly until the underlying issue is fixed.";
 
 pub(crate) struct Server {
@@ -40,11 +48,17 @@ pub(crate) struct Server {
     pub(crate) analysis_debouncer: AnalysisDebouncer,
     pub(crate) analysis_requested_for_files: Option<Vec<FileId>>,
     pub(crate) bazel_client: Arc<dyn BazelClient>,
-    pub(crate) pending_repos: FxHashSet<String>,
-    pub(crate) pending_files: FxHashSet<FileId>,
     pub(crate) force_analysis_for_files: FxHashSet<FileId>,
-    pub(crate) fetched_repos: FxHashSet<String>,
-    pub(crate) is_fetching_repos: bool,
+    pub(crate) fetch_queue: FetchQueue,
+    /// The most recently opened or edited file, whose repositories are fetched first.
+    pub(crate) focused_file: Option<FileId>,
+    pub(crate) supports_fetch_repo: bool,
+    pub(crate) bzlmod_enabled: bool,
+    /// The repositories generated by module extensions, as last loaded from `MODULE.bazel.lock`.
+    pub(crate) module_extension_repos: Option<ModuleExtensionRepos>,
+    /// The repositories referenced by labels in the workspace's `BUILD` and `.bzl` files.
+    pub(crate) referenced_repos: ReferencedRepos,
+    pub(crate) loader: Arc<DefaultFileLoader>,
 }
 
 pub(crate) struct ServerSnapshot {
@@ -146,6 +160,9 @@ impl Server {
 
         eprintln!("server: bzlmod_enabled = {}", bzlmod_enabled);
 
+        let supports_fetch_repo = supports_fetch_repo(&info.release);
+        eprintln!("server: supports_fetch_repo = {}", supports_fetch_repo);
+
         // Load builtin rules from `bazel info build-language`.
         eprintln!("server: fetching builtin rules via `bazel info build-language`");
         let rules = match load_bazel_build_language(&*bazel_client) {
@@ -161,7 +178,7 @@ impl Server {
         };
 
         let path_interner = Arc::new(PathInterner::default());
-        let loader = DefaultFileLoader::new(
+        let loader = Arc::new(DefaultFileLoader::new(
             bazel_client.clone(),
             path_interner.clone(),
             info.workspace.clone(),
@@ -169,12 +186,14 @@ impl Server {
             external_output_base.clone(),
             task_pool_sender.clone(),
             bzlmod_enabled,
-        );
+        ));
         let mut analysis = Analysis::new(
-            Arc::new(loader),
+            loader.clone(),
             InferenceOptions {
                 infer_ctx_attributes: config.args.infer_ctx_attributes,
                 use_code_flow_analysis: config.args.use_code_flow_analysis,
+                report_unused_type_ignores: config.args.report_unused_type_ignores,
+                allow_global_reassignment: config.args.allow_global_reassignment,
             },
         );
 
@@ -213,17 +232,25 @@ impl Server {
             analysis_debouncer: AnalysisDebouncer::new(DEBOUNCE_INTERVAL, task_pool_sender),
             analysis_requested_for_files: None,
             bazel_client,
-            pending_repos: Default::default(),
-            pending_files: Default::default(),
             force_analysis_for_files: Default::default(),
-            fetched_repos: Default::default(),
-            is_fetching_repos: false,
+            fetch_queue: Default::default(),
+            focused_file: None,
+            supports_fetch_repo,
+            bzlmod_enabled,
+            module_extension_repos: None,
+            referenced_repos: Default::default(),
+            loader,
         };
 
         if has_bazel_init_err {
             server.send_error_message(BAZEL_INIT_ERR_MESSAGE);
         }
 
+        server.load_module_lockfile();
+        server.load_referenced_repos();
+        server.load_bazel_registry();
+        server.load_workspace_repos();
+
         Ok(server)
     }
 
@@ -321,40 +348,232 @@ impl Server {
         )
     }
 
-    pub(crate) fn fetch_bazel_external_repos(&mut self) {
-        let repos = mem::take(&mut self.pending_repos);
-        let files = mem::take(&mut self.pending_files);
-        let bazel_client = self.bazel_client.clone();
-        self.is_fetching_repos = true;
-        self.fetched_repos.extend(repos.clone());
-        self.task_pool_handle.spawn_with_sender(move |sender| {
-            sender
-                .send(Task::FetchExternalRepos(FetchExternalReposProgress::Begin(
-                    repos.clone(),
-                )))
-                .unwrap();
+    /// Starts fetching the next queued external repository, unless a fetch is already in progress.
+    /// Repositories are fetched one at a time so that the fetch can be cancelled, and so that the
+    /// repositories needed by the focused file can be fetched first.
+    pub(crate) fn fetch_next_external_repo(&mut self) {
+        let fetch = match self.fetch_queue.start_next(self.focused_file) {
+            Some(fetch) => fetch,
+            None => return,
+        };
 
-            let mut failed_repos = vec![];
+        let message = if fetch.remaining > 0 {
+            format!("@@{} ({} more)", fetch.repo, fetch.remaining)
+        } else {
+            format!("@@{}", fetch.repo)
+        };
+        let work_done = if fetch.is_first {
+            if self.config.has_work_done_progress_support() {
+                self.send_request::<lsp_types::request::WorkDoneProgressCreate>(
+                    lsp_types::WorkDoneProgressCreateParams {
+                        token: fetch_progress_token(),
+                    },
+                );
+            }
+            lsp_types::WorkDoneProgress::Begin(lsp_types::WorkDoneProgressBegin {
+                title: "Fetching external repositories".to_string(),
+                cancellable: Some(true),
+                message: Some(message),
+                ..Default::default()
+            })
+        } else {
+            lsp_types::WorkDoneProgress::Report(lsp_types::WorkDoneProgressReport {
+                cancellable: Some(true),
+                message: Some(message),
+                ..Default::default()
+            })
+        };
+        self.send_fetch_progress(work_done);
 
-            for repo in &repos {
-                eprintln!("server: fetching external repository \"@@{}\"", repo);
-                if let Err(err) = bazel_client.null_query_external_repo_targets(repo) {
-                    failed_repos.push(repo.clone());
+        let bazel_client = self.bazel_client.clone();
+        let supports_fetch_repo = self.supports_fetch_repo;
+        self.task_pool_handle.spawn(move || {
+            eprintln!("server: fetching external repository \"@@{}\"", fetch.repo);
+            let res = if supports_fetch_repo {
+                bazel_client.fetch_repo(&fetch.repo, &fetch.cancelled)
+            } else {
+                bazel_client.null_query_external_repo_targets(&fetch.repo, &fetch.cancelled)
+            };
+            let outcome = match res {
+                Ok(()) => FetchOutcome::Fetched,
+                Err(_) if fetch.cancelled.load(Ordering::Relaxed) => {
+                    eprintln!(
+                        "server: cancelled fetching external repository \"@@{}\"",
+                        fetch.repo
+                    );
+                    FetchOutcome::Cancelled
+                }
+                Err(err) => {
                     eprintln!(
                         "server: failed to fetch external repository \"@@{}\": {}",
-                        repo, err
+                        fetch.repo, err
                     );
+                    FetchOutcome::Failed(err.to_string())
                 }
+            };
+            Task::ExternalRepoFetched(fetch.repo, outcome)
+        });
+    }
+
+    /// Reports the progress of fetching external repositories. Clients that don't support work done
+    /// progress can't show or cancel the fetch, so the progress is only logged for them; fetches
+    /// can still be retried with the `starpls/fetchRepos` request.
+    pub(crate) fn send_fetch_progress(&self, work_done: lsp_types::WorkDoneProgress) {
+        if !self.config.has_work_done_progress_support() {
+            match work_done {
+                lsp_types::WorkDoneProgress::Begin(lsp_types::WorkDoneProgressBegin {
+                    message: Some(message),
+                    ..
+                })
+                | lsp_types::WorkDoneProgress::Report(lsp_types::WorkDoneProgressReport {
+                    message: Some(message),
+                    ..
+                }) => eprintln!("server: fetching external repositories: {}", message),
+                lsp_types::WorkDoneProgress::End(_) => {
+                    eprintln!("server: finished fetching external repositories")
+                }
+                _ => {}
+            }
+            return;
+        }
+        self.send_notification::<lsp_types::notification::Progress>(lsp_types::ProgressParams {
+            token: fetch_progress_token(),
+            value: lsp_types::ProgressParamsValue::WorkDone(work_done),
+        });
+    }
+
+    /// Loads the results of module resolution from `MODULE.bazel.lock`. This includes the selected
+    /// version of each module, as well as the repositories generated by module extensions, which are
+    /// used to validate `use_repo()` calls in `MODULE.bazel`.
+    pub(crate) fn load_module_lockfile(&self) {
+        if !self.bzlmod_enabled {
+            return;
+        }
+
+        let workspace = self.document_manager.read().workspace().to_path_buf();
+        let bazel_client = self.bazel_client.clone();
+        self.task_pool_handle.spawn_with_sender(move |sender| {
+            let contents = fs::read_to_string(workspace.join("MODULE.bazel.lock"));
+
+            // Starting with Bazel 7.2, the lockfile no longer records the module dependency graph, so
+            // fall back to querying it from Bazel instead.
+            let modules = match contents.as_deref().map(ResolvedModules::from_lockfile) {
+                Ok(Ok(modules)) => Ok(modules),
+                _ => load_resolved_modules_from_mod_graph(&*bazel_client),
+            };
+            match modules {
+                Ok(modules) => sender.send(Task::ResolvedModulesLoaded(modules)).unwrap(),
+                Err(err) => eprintln!("server: failed to load resolved modules: {}", err),
             }
 
+            let contents = match contents {
+                Ok(contents) => contents,
+                Err(err) => {
+                    eprintln!("server: failed to read MODULE.bazel.lock: {}", err);
+                    return;
+                }
+            };
+            match ModuleExtensionRepos::from_lockfile(&contents) {
+                Ok(repos) => sender
+                    .send(Task::ModuleExtensionReposLoaded(repos))
+                    .unwrap(),
+                Err(err) => eprintln!(
+                    "server: failed to load module extension repositories: {}",
+                    err
+                ),
+            }
+        });
+    }
+
+    /// Scans the workspace's `BUILD` and `.bzl` files for the repositories that they reference, which
+    /// are offered as quick fixes for `use_repo()` calls. The scan only runs once on startup, and the
+    /// results are afterwards kept up to date as files are saved.
+    fn load_referenced_repos(&self) {
+        if !self.bzlmod_enabled {
+            return;
+        }
+
+        let workspace = self.document_manager.read().workspace().to_path_buf();
+        self.task_pool_handle.spawn_with_sender(move |sender| {
+            let ignored = read_bazelignore(&workspace);
+            let mut referenced = ReferencedRepos::default();
+            if let Err(err) =
+                collect_workspace_referenced_repos(&workspace, &ignored, &mut referenced)
+            {
+                eprintln!("server: failed to scan referenced repositories: {}", err);
+            }
             sender
-                .send(Task::FetchExternalRepos(FetchExternalReposProgress::End(
-                    files,
-                    failed_repos,
-                )))
+                .send(Task::ReferencedReposLoaded(referenced))
                 .unwrap();
         });
     }
+
+    /// Updates the repositories referenced by a saved `BUILD` or `.bzl` file.
+    pub(crate) fn update_referenced_repos(&mut self, path: PathBuf, contents: &str) {
+        if self.referenced_repos.update_file(path, contents) {
+            self.set_module_extension_repos();
+        }
+    }
+
+    /// Combines the repositories generated by module extensions with those referenced in the workspace,
+    /// and passes them on to the analysis.
+    pub(crate) fn set_module_extension_repos(&mut self) {
+        let mut repos = match self.module_extension_repos.clone() {
+            Some(repos) => repos,
+            None => return,
+        };
+        repos.set_referenced_repos(self.referenced_repos.repos());
+        self.analysis.set_module_extension_repos(repos);
+        self.force_analysis_for_files
+            .extend(self.document_manager.read().editor_file_ids());
+    }
+
+    /// Statically scans `WORKSPACE` for the external repositories that it declares, so that they can
+    /// be resolved and completed without having to be fetched first.
+    pub(crate) fn load_workspace_repos(&self) {
+        let workspace = self.document_manager.read().workspace().to_path_buf();
+        let bzlmod_enabled = self.bzlmod_enabled;
+        self.task_pool_handle.spawn_with_sender(move |sender| {
+            let repos = WorkspaceRepos::scan(&workspace, bzlmod_enabled);
+            sender.send(Task::WorkspaceReposLoaded(repos)).unwrap();
+        });
+    }
+
+    /// Loads the modules available in a local Bazel registry, which are used to complete module names
+    /// and versions in `MODULE.bazel`. The registry is taken from the `--registry` server argument if
+    /// specified, and otherwise from a `--registry=file://...` flag in the workspace's `.bazelrc`.
+    pub(crate) fn load_bazel_registry(&self) {
+        let workspace = self.document_manager.read().workspace().to_path_buf();
+        let registry = self.config.args.registry.clone();
+        self.task_pool_handle.spawn_with_sender(move |sender| {
+            let registry = match registry.or_else(|| find_registry_in_bazelrc(&workspace)) {
+                Some(registry) => registry,
+                None => return,
+            };
+
+            if registry.starts_with("http://") || registry.starts_with("https://") {
+                eprintln!(
+                    "server: ignoring Bazel registry {:?}, only local registries are supported",
+                    registry
+                );
+                return;
+            }
+
+            eprintln!("server: loading Bazel registry at {:?}", registry);
+            match BazelRegistry::from_path(registry.strip_prefix("file://").unwrap_or(&registry)) {
+                Ok(registry) => {
+                    for (name, err) in registry.invalid_modules() {
+                        eprintln!(
+                            "server: skipping module {:?} in Bazel registry, failed to parse its metadata: {}",
+                            name, err
+                        );
+                    }
+                    sender.send(Task::BazelRegistryLoaded(registry)).unwrap()
+                }
+                Err(err) => eprintln!("server: failed to load Bazel registry: {}", err),
+            }
+        });
+    }
 }
 
 impl panic::RefUnwindSafe for ServerSnapshot {}
@@ -370,8 +589,103 @@ pub(crate) fn load_bazel_build_language(client: &dyn BazelClient) -> anyhow::Res
     decode_rules(&build_language_output)
 }
 
+fn load_resolved_modules_from_mod_graph(
+    client: &dyn BazelClient,
+) -> anyhow::Result<ResolvedModules> {
+    let output = client.mod_graph()?;
+    ResolvedModules::from_mod_graph(str::from_utf8(&output)?)
+}
+
 fn load_bazel_prelude(workspace: impl AsRef<Path>) -> anyhow::Result<(PathBuf, String)> {
     let prelude = workspace.as_ref().join("tools/build_rules/prelude_bazel");
     let contents = fs::read_to_string(&prelude)?;
     Ok((prelude, contents))
 }
+
+fn collect_workspace_referenced_repos(
+    dir: &Path,
+    ignored: &[PathBuf],
+    repos: &mut ReferencedRepos,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            // Skip hidden directories, e.g. `.git`, as well as directories listed in `.bazelignore`.
+            // Bazel's convenience symlinks, e.g. `bazel-bin`, aren't directories and are skipped as well.
+            if !file_name.starts_with('.') && !ignored.contains(&path) {
+                collect_workspace_referenced_repos(&path, ignored, repos)?;
+            }
+        } else if is_referencing_file_name(file_name) {
+            if let Ok(contents) = fs::read_to_string(&path) {
+                repos.update_file(path, &contents);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether labels in the given file are considered when determining the repositories referenced
+/// in the workspace.
+pub(crate) fn is_referencing_file_name(file_name: &str) -> bool {
+    matches!(file_name, "BUILD" | "BUILD.bazel") || file_name.ends_with(".bzl")
+}
+
+/// Reads the directories that Bazel ignores from the workspace's `.bazelignore` file.
+fn read_bazelignore(workspace: &Path) -> Vec<PathBuf> {
+    fs::read_to_string(workspace.join(".bazelignore"))
+        .map(|contents| {
+            contents
+                .lines()
+                .map(|line| line.trim().trim_end_matches('/'))
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| workspace.join(line))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn fetch_progress_token() -> lsp_types::NumberOrString {
+    lsp_types::NumberOrString::String(FETCH_REPOS_PROGRESS_TOKEN.to_string())
+}
+
+/// Whether the given Bazel release, e.g. `release 7.1.0`, supports `bazel fetch --repo`.
+fn supports_fetch_repo(release: &str) -> bool {
+    let mut version = release
+        .trim_start_matches("release ")
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().ok());
+    match (version.next().flatten(), version.next().flatten()) {
+        (Some(major), Some(minor)) => (major, minor) >= (7, 1),
+        (Some(major), None) => major > 7,
+        _ => false,
+    }
+}
+
+/// Finds the first `file://` registry passed with `--registry` in the workspace's `.bazelrc`.
+/// Remote registries, e.g. `https://` mirrors of the Bazel Central Registry, are ignored.
+fn find_registry_in_bazelrc(workspace: &Path) -> Option<String> {
+    let contents = fs::read_to_string(workspace.join(".bazelrc")).ok()?;
+    let mut registry = None;
+    for flag in contents.lines().flat_map(|line| line.split_whitespace()) {
+        let value = match flag.strip_prefix("--registry=") {
+            Some(value) => value,
+            None => continue,
+        };
+        match value.strip_prefix("file://") {
+            Some(path) => {
+                registry.get_or_insert_with(|| path.to_string());
+            }
+            None => eprintln!(
+                "server: ignoring registry {:?} from .bazelrc, only file:// registries are supported",
+                value
+            ),
+        }
+    }
+    registry
+}
@@ -0,0 +1,523 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use starpls_bazel::{label::RepoKind, Label};
+use starpls_syntax::{
+    ast::{self, AstNode},
+    parse_module,
+};
+
+/// The repository rules whose instantiations are recognized when scanning `WORKSPACE` files.
+const REPO_RULES: &[&str] = &["http_archive", "local_repository", "new_local_repository"];
+
+/// The maximum depth of nested macro calls that are followed when scanning `WORKSPACE` files.
+const MAX_MACRO_DEPTH: usize = 8;
+
+/// An external repository declared in a `WORKSPACE` file, either directly or by a macro that the
+/// `WORKSPACE` file loads and calls.
+#[derive(Clone, Debug)]
+pub(crate) struct WorkspaceRepo {
+    /// The repository rule that declares the repository, e.g. `http_archive`.
+    pub(crate) rule: String,
+    /// The directory of the repository, for `local_repository`. This isn't recorded for
+    /// `new_local_repository`, whose BUILD file comes from its `build_file` or
+    /// `build_file_content` argument rather than from the directory.
+    pub(crate) path: Option<PathBuf>,
+    /// The file containing the repository rule call.
+    pub(crate) file: PathBuf,
+    /// The 1-based line of the repository rule call.
+    pub(crate) line: usize,
+}
+
+/// The external repositories declared in a workspace, as determined by statically scanning its
+/// `WORKSPACE` file. This lets repositories be resolved and completed before Bazel has fetched them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WorkspaceRepos {
+    repos: BTreeMap<String, WorkspaceRepo>,
+}
+
+impl WorkspaceRepos {
+    /// Scans the `WORKSPACE` file at the given workspace root, along with the macros that it loads
+    /// from the main repository or from local repositories. When bzlmod is enabled, Bazel reads
+    /// `WORKSPACE.bzlmod` instead.
+    pub(crate) fn scan(workspace: &Path, bzlmod_enabled: bool) -> Self {
+        let candidates: &[&str] = if bzlmod_enabled {
+            &["WORKSPACE.bzlmod"]
+        } else {
+            &["WORKSPACE.bazel", "WORKSPACE"]
+        };
+        let mut scanner = Scanner {
+            workspace,
+            repos: Default::default(),
+            visited: Default::default(),
+        };
+        if let Some(path) = candidates
+            .iter()
+            .map(|name| workspace.join(name))
+            .find(|path| path.is_file())
+        {
+            scanner.scan_file(&path, workspace, None, 0);
+        }
+        Self {
+            repos: scanner.repos,
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&WorkspaceRepo> {
+        self.repos.get(name)
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.repos.keys().map(|name| name.as_str())
+    }
+}
+
+struct Scanner<'a> {
+    workspace: &'a Path,
+    repos: BTreeMap<String, WorkspaceRepo>,
+    /// The files and macros that have already been scanned, to guard against recursive macros.
+    visited: HashSet<(PathBuf, Option<String>)>,
+}
+
+impl Scanner<'_> {
+    /// Scans either the top-level statements of the given file, or the body of the macro with the
+    /// given name in that file. `root` is the root of the repository containing the file, which
+    /// labels starting with `//` are resolved against.
+    fn scan_file(&mut self, path: &Path, root: &Path, macro_name: Option<&str>, depth: usize) {
+        if depth > MAX_MACRO_DEPTH
+            || !self
+                .visited
+                .insert((path.to_path_buf(), macro_name.map(String::from)))
+        {
+            return;
+        }
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return,
+        };
+        let module = parse_module(&contents, &mut |_| {}).tree();
+
+        // Record the functions defined in this file, as well as the symbols it loads from other
+        // files, so that calls to macros can be followed. Repository rules may be loaded under an
+        // alias, e.g. `load(":repos.bzl", my_http_archive = "http_archive")`.
+        let mut defs = HashMap::new();
+        let mut loads = HashMap::new();
+        let mut rule_aliases = HashMap::new();
+        for stmt in module.statements() {
+            match stmt {
+                ast::Statement::Def(def) => {
+                    if let Some(name) = def.name().and_then(|name| name.name()) {
+                        defs.insert(name.text().to_string(), def);
+                    }
+                }
+                ast::Statement::Load(load) => {
+                    // Loads are resolved lazily, since a `WORKSPACE` file may load from a local
+                    // repository that it declares earlier on.
+                    let load_module = match load
+                        .module()
+                        .and_then(|module| module.name())
+                        .and_then(ast::String::cast)
+                        .and_then(|module| module.value())
+                    {
+                        Some(module) => module.to_string(),
+                        None => continue,
+                    };
+                    for item in load.items() {
+                        let (alias, name) = match item {
+                            ast::LoadItem::Direct(item) => {
+                                let name = item.name().and_then(ast::String::cast);
+                                let name = match name.and_then(|name| name.value()) {
+                                    Some(name) => name.to_string(),
+                                    None => continue,
+                                };
+                                (name.clone(), name)
+                            }
+                            ast::LoadItem::Aliased(item) => {
+                                let alias = item.alias().and_then(|alias| alias.name());
+                                let name = item.name().and_then(ast::String::cast);
+                                match (alias, name.and_then(|name| name.value())) {
+                                    (Some(alias), Some(name)) => {
+                                        (alias.text().to_string(), name.to_string())
+                                    }
+                                    _ => continue,
+                                }
+                            }
+                        };
+                        if REPO_RULES.contains(&name.as_str()) {
+                            rule_aliases.insert(alias, name);
+                        } else {
+                            loads.insert(alias, (load_module.clone(), name));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let statements = match macro_name {
+            Some(name) => match defs.get(name).and_then(|def| def.suite()) {
+                Some(suite) => suite.statements().collect::<Vec<_>>(),
+                None => return,
+            },
+            None => module.statements().collect(),
+        };
+        let calls = statements
+            .iter()
+            .filter(|stmt| !matches!(stmt, ast::Statement::Def(_)))
+            .flat_map(|stmt| stmt.syntax().descendants().filter_map(ast::CallExpr::cast));
+
+        for call in calls {
+            let callee = match call.callee() {
+                Some(ast::Expression::Name(name_ref)) => match name_ref.name() {
+                    Some(name) => name.text().to_string(),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            if let Some(rule) = repo_rule(&call, &callee, &rule_aliases) {
+                self.add_repo(path, &contents, &call, rule);
+            } else if defs.contains_key(&callee) {
+                self.scan_file(path, root, Some(&callee), depth + 1);
+            } else if let Some((load_module, name)) = loads.get(&callee) {
+                if let Some((loaded_path, loaded_root)) = self.resolve_load(path, root, load_module)
+                {
+                    self.scan_file(&loaded_path, &loaded_root, Some(name), depth + 1);
+                }
+            }
+        }
+    }
+
+    fn add_repo(&mut self, path: &Path, contents: &str, call: &ast::CallExpr, rule: String) {
+        let name = match string_kwarg(call, "name") {
+            Some(name) => name,
+            None => return,
+        };
+        let offset = usize::from(call.syntax().text_range().start());
+        let repo_path = match rule.as_str() {
+            "local_repository" => {
+                string_kwarg(call, "path").map(|repo_path| self.workspace.join(repo_path))
+            }
+            _ => None,
+        };
+
+        // As with `maybe()`, the first declaration of a repository takes precedence.
+        self.repos.entry(name).or_insert_with(|| WorkspaceRepo {
+            rule,
+            path: repo_path,
+            file: path.to_path_buf(),
+            line: contents[..offset].matches('\n').count() + 1,
+        });
+    }
+
+    /// Resolves the path of a file loaded from `from`, which belongs to the repository rooted at
+    /// `root`, along with the root of the repository containing the loaded file. Only files in the
+    /// main repository or in local repositories can be resolved, since other repositories may not
+    /// have been fetched.
+    fn resolve_load(&self, from: &Path, root: &Path, module: &str) -> Option<(PathBuf, PathBuf)> {
+        let label = Label::parse(module).ok()?;
+        if label.is_relative() {
+            return Some((from.parent()?.join(label.target()), root.to_path_buf()));
+        }
+        let root = match (label.kind(), label.repo()) {
+            (RepoKind::Current, _) => root.to_path_buf(),
+            (_, "") => self.workspace.to_path_buf(),
+            (_, repo) => self.repos.get(repo)?.path.clone()?,
+        };
+        Some((root.join(label.package()).join(label.target()), root))
+    }
+}
+
+/// Returns the repository rule instantiated by a call, handling both direct calls, e.g.
+/// `http_archive(name = "foo")`, and calls wrapped in `maybe()`, e.g. `maybe(http_archive, name = "foo")`.
+/// Aliases of repository rules are resolved through `rule_aliases`.
+fn repo_rule(
+    call: &ast::CallExpr,
+    callee: &str,
+    rule_aliases: &HashMap<String, String>,
+) -> Option<String> {
+    let resolve = |name: &str| {
+        if REPO_RULES.contains(&name) {
+            Some(name.to_string())
+        } else {
+            rule_aliases.get(name).cloned()
+        }
+    };
+    if callee != "maybe" {
+        return resolve(callee);
+    }
+    match call.arguments()?.arguments().next()? {
+        ast::Argument::Simple(arg) => match arg.expr()? {
+            ast::Expression::Name(name_ref) => resolve(name_ref.name()?.text()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn string_kwarg(call: &ast::CallExpr, name: &str) -> Option<String> {
+    call.arguments()?.arguments().find_map(|arg| match arg {
+        ast::Argument::Keyword(kwarg) if kwarg.name()?.name()?.text() == name => {
+            match kwarg.expr()? {
+                ast::Expression::Literal(lit) => match lit.kind() {
+                    ast::LiteralKind::String(s) => s.value().map(String::from),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "starpls-workspace-repos-{}-{}",
+                name,
+                std::process::id()
+            ));
+            for (file, contents) in files {
+                let path = dir.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, contents).unwrap();
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn check(name: &str, files: &[(&str, &str)], bzlmod_enabled: bool, expected: &[(&str, &str)]) {
+        let dir = TempDir::new(name, files);
+        let repos = WorkspaceRepos::scan(&dir.0, bzlmod_enabled);
+        let actual = repos
+            .names()
+            .map(|name| (name, repos.get(name).unwrap().rule.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_direct_calls() {
+        check(
+            "direct",
+            &[(
+                "WORKSPACE",
+                r#"
+load("@bazel_tools//tools/build_defs/repo:http.bzl", "http_archive")
+
+http_archive(name = "foo", urls = ["https://example.com/foo.tar.gz"])
+http_archive(urls = ["https://example.com/unnamed.tar.gz"])
+"#,
+            )],
+            false,
+            &[("foo", "http_archive")],
+        );
+    }
+
+    #[test]
+    fn test_bzlmod_reads_workspace_bzlmod() {
+        check(
+            "bzlmod",
+            &[
+                ("WORKSPACE", r#"http_archive(name = "foo")"#),
+                ("WORKSPACE.bzlmod", r#"http_archive(name = "bar")"#),
+            ],
+            true,
+            &[("bar", "http_archive")],
+        );
+    }
+
+    #[test]
+    fn test_maybe() {
+        check(
+            "maybe",
+            &[(
+                "WORKSPACE",
+                r#"
+maybe(http_archive, name = "foo")
+maybe(local_repository, name = "foo", path = "third_party/foo")
+maybe(some_other_rule, name = "bar")
+"#,
+            )],
+            false,
+            &[("foo", "http_archive")],
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        check(
+            "macros",
+            &[
+                (
+                    "WORKSPACE",
+                    r#"
+load("//tools:deps.bzl", "deps")
+
+def local_deps():
+    http_archive(name = "local")
+
+local_deps()
+deps()
+"#,
+                ),
+                (
+                    "tools/deps.bzl",
+                    r#"
+load(":more_deps.bzl", more = "more_deps")
+
+def deps():
+    http_archive(name = "loaded")
+    more()
+
+def unused():
+    http_archive(name = "unused")
+"#,
+                ),
+                (
+                    "tools/more_deps.bzl",
+                    r#"
+def more_deps():
+    http_archive(name = "nested")
+    more_deps()
+"#,
+                ),
+            ],
+            false,
+            &[
+                ("loaded", "http_archive"),
+                ("local", "http_archive"),
+                ("nested", "http_archive"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_local_repositories() {
+        let dir = TempDir::new(
+            "local",
+            &[
+                (
+                    "WORKSPACE",
+                    r#"
+local_repository(name = "foo", path = "third_party/foo")
+new_local_repository(name = "bar", path = "third_party/bar", build_file = "//:bar.BUILD")
+
+load("@foo//:deps.bzl", "foo_deps")
+
+foo_deps()
+"#,
+                ),
+                (
+                    "third_party/foo/deps.bzl",
+                    r#"
+def foo_deps():
+    http_archive(name = "foo_dep")
+"#,
+                ),
+            ],
+        );
+        let repos = WorkspaceRepos::scan(&dir.0, false);
+        assert_eq!(
+            repos.names().collect::<Vec<_>>(),
+            vec!["bar", "foo", "foo_dep"]
+        );
+
+        let foo = repos.get("foo").unwrap();
+        assert_eq!(foo.rule, "local_repository");
+        assert_eq!(foo.path, Some(dir.0.join("third_party/foo")));
+        assert_eq!(foo.line, 2);
+
+        let bar = repos.get("bar").unwrap();
+        assert_eq!(bar.rule, "new_local_repository");
+        assert_eq!(bar.path, None);
+
+        let foo_dep = repos.get("foo_dep").unwrap();
+        assert_eq!(foo_dep.file, dir.0.join("third_party/foo/deps.bzl"));
+        assert_eq!(foo_dep.line, 3);
+    }
+
+    #[test]
+    fn test_loads_from_local_repository_root() {
+        check(
+            "local-root",
+            &[
+                (
+                    "WORKSPACE",
+                    r#"
+local_repository(name = "foo", path = "third_party/foo")
+
+load("@foo//:deps.bzl", "foo_deps")
+
+foo_deps()
+"#,
+                ),
+                (
+                    "third_party/foo/deps.bzl",
+                    r#"
+load("//tools:more.bzl", "more_deps")
+load("@//tools:more.bzl", main_deps = "more_deps")
+
+def foo_deps():
+    more_deps()
+    main_deps()
+"#,
+                ),
+                (
+                    "third_party/foo/tools/more.bzl",
+                    r#"
+def more_deps():
+    http_archive(name = "foo_more")
+"#,
+                ),
+                (
+                    "tools/more.bzl",
+                    r#"
+def more_deps():
+    http_archive(name = "main_more")
+"#,
+                ),
+            ],
+            false,
+            &[
+                ("foo", "local_repository"),
+                ("foo_more", "http_archive"),
+                ("main_more", "http_archive"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_load_aliases() {
+        check(
+            "aliases",
+            &[(
+                "WORKSPACE",
+                r#"
+load("@bazel_tools//tools/build_defs/repo:http.bzl", my_http = "http_archive")
+load("@bazel_tools//tools/build_defs/repo:utils.bzl", "maybe")
+
+my_http(name = "foo")
+maybe(my_http, name = "bar")
+"#,
+            )],
+            false,
+            &[("bar", "http_archive"), ("foo", "http_archive")],
+        );
+    }
+}
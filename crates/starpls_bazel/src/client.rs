@@ -1,8 +1,12 @@
 use std::{
     collections::HashMap,
+    io::Read,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
     str,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
 };
 
 use anyhow::{anyhow, bail};
@@ -29,8 +33,19 @@ pub trait BazelClient: Send + Sync + 'static {
         from_repo: &str,
     ) -> anyhow::Result<Option<String>>;
     fn clear_repo_mappings(&self);
-    fn null_query_external_repo_targets(&self, repo: &str) -> anyhow::Result<()>;
+    /// Fetches the given canonical repository by querying its targets. The query is aborted once
+    /// `cancelled` is set.
+    fn null_query_external_repo_targets(
+        &self,
+        repo: &str,
+        cancelled: &AtomicBool,
+    ) -> anyhow::Result<()>;
+    /// Fetches the given canonical repository with `bazel fetch --repo`, which is available starting
+    /// with Bazel 7.1. The fetch is aborted once `cancelled` is set.
+    fn fetch_repo(&self, repo: &str, cancelled: &AtomicBool) -> anyhow::Result<()>;
     fn repo_mapping_keys(&self, from_repo: &str) -> anyhow::Result<Vec<String>>;
+    /// Returns the resolved module dependency graph, as output by `bazel mod graph --output=json`.
+    fn mod_graph(&self) -> anyhow::Result<Vec<u8>>;
 }
 
 pub struct BazelCLI {
@@ -58,6 +73,52 @@ impl BazelCLI {
         Ok(output.stdout)
     }
 
+    /// Runs a Bazel command, killing it if `cancelled` is set before it completes. Only the errors
+    /// printed by Bazel are included in the returned error, since its progress output is noisy.
+    fn run_cancellable_command(&self, args: &[&str], cancelled: &AtomicBool) -> anyhow::Result<()> {
+        let mut child = Command::new(&self.executable)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stderr = child.stderr.take().expect("stderr should be piped");
+        let stderr_reader = thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output);
+            output
+        });
+
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if cancelled.load(Ordering::Relaxed) {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("Bazel command was cancelled");
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+
+        let stderr = stderr_reader.join().unwrap_or_default();
+        if !status.success() {
+            let errors = stderr
+                .lines()
+                .filter(|line| line.starts_with("ERROR:"))
+                .collect::<Vec<_>>();
+            bail!(
+                "failed to run Bazel command with exit status {}: {}",
+                status,
+                if errors.is_empty() {
+                    stderr.trim().to_string()
+                } else {
+                    errors.join("\n")
+                }
+            );
+        }
+        Ok(())
+    }
+
     pub fn dump_repo_mapping(&self, repo: &str) -> anyhow::Result<HashMap<String, String>> {
         let output = self.run_command(&["mod", "--enable_bzlmod", "dump_repo_mapping", repo])?;
         let json = String::from_utf8(output)?;
@@ -154,9 +215,19 @@ impl BazelClient for BazelCLI {
         self.repo_mappings.write().clear();
     }
 
-    fn null_query_external_repo_targets(&self, repo: &str) -> anyhow::Result<()> {
-        self.run_command(&["query", "--keep_going", &format!("@@{}//...", repo)])?;
-        Ok(())
+    fn null_query_external_repo_targets(
+        &self,
+        repo: &str,
+        cancelled: &AtomicBool,
+    ) -> anyhow::Result<()> {
+        self.run_cancellable_command(
+            &["query", "--keep_going", &format!("@@{}//...", repo)],
+            cancelled,
+        )
+    }
+
+    fn fetch_repo(&self, repo: &str, cancelled: &AtomicBool) -> anyhow::Result<()> {
+        self.run_cancellable_command(&["fetch", &format!("--repo=@@{}", repo)], cancelled)
     }
 
     fn repo_mapping_keys(&self, from_repo: &str) -> anyhow::Result<Vec<String>> {
@@ -174,6 +245,10 @@ impl BazelClient for BazelCLI {
             .insert(from_repo.to_string(), mapping);
         Ok(keys)
     }
+
+    fn mod_graph(&self) -> anyhow::Result<Vec<u8>> {
+        self.run_command(&["mod", "--enable_bzlmod", "graph", "--output=json"])
+    }
 }
 
 impl Default for BazelCLI {
@@ -75,7 +75,7 @@ struct ParamJson {
     is_star_star_arg: bool,
 }
 
-/// The builtin.pb file is missing `module_extension`, `repository_rule` and `tag_class`.
+/// The builtin.pb file is missing `macro`, `module_extension`, `repository_rule` and `tag_class`.
 pub fn make_bzl_builtins() -> Builtins {
     serde_json::from_str::<BuiltinsJson>(include_str!("../data/bzl.builtins.json"))
         .expect("bug: invalid bzl.builtins.json")
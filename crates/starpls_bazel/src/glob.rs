@@ -0,0 +1,428 @@
+//! An implementation of the `glob()` function available in BUILD files. As in Bazel, patterns
+//! support the `*` and `?` wildcards within a path segment and the `**` recursive wildcard as its
+//! own segment, and globs never descend into subpackages, i.e. directories with a BUILD file.
+//! Symlinks to directories are followed, except where they would form a cycle.
+
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GlobError {
+    pub pattern: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for GlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid glob pattern \"{}\": {}",
+            self.pattern, self.reason
+        )
+    }
+}
+
+impl std::error::Error for GlobError {}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlobResult {
+    /// The matched paths, relative to the package directory, in sorted order.
+    pub matches: Vec<String>,
+    /// The subpackages that contain files matched by the glob's patterns. These files aren't
+    /// included in `matches`, since globs don't cross package boundaries.
+    pub subpackages: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Segment<'a> {
+    /// The `**` wildcard, which matches zero or more path segments.
+    Recursive,
+    /// A path segment, which may contain the `*` and `?` wildcards.
+    Pattern(&'a str),
+}
+
+/// Evaluates a glob in the package rooted at `package_dir`, with the same semantics as the `glob()`
+/// function's `include`, `exclude` and `exclude_directories` arguments.
+pub fn glob(
+    package_dir: &Path,
+    include: &[String],
+    exclude: &[String],
+    exclude_directories: bool,
+) -> Result<GlobResult, GlobError> {
+    let include = parse_patterns(include)?;
+    let exclude = parse_patterns(exclude)?;
+    let mut walker = Walker {
+        include: &include,
+        exclude: &exclude,
+        exclude_directories,
+        ancestors: HashSet::new(),
+        result: GlobResult::default(),
+    };
+    walker.enter_dir(package_dir, |walker| {
+        walker.walk(package_dir, &mut Vec::new())
+    });
+    walker.result.matches.sort();
+    Ok(walker.result)
+}
+
+fn parse_patterns(patterns: &[String]) -> Result<Vec<Vec<Segment<'_>>>, GlobError> {
+    patterns
+        .iter()
+        .map(|pattern| parse_pattern(pattern))
+        .collect()
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment<'_>>, GlobError> {
+    let err = |reason| {
+        Err(GlobError {
+            pattern: pattern.to_string(),
+            reason,
+        })
+    };
+    if pattern.is_empty() {
+        return err("pattern cannot be empty");
+    }
+    if pattern.starts_with('/') {
+        return err("pattern cannot be absolute");
+    }
+
+    let mut segments = Vec::new();
+    for segment in pattern.split('/') {
+        segments.push(match segment {
+            "" => return err("empty segment not permitted"),
+            "." => return err("segment '.' not permitted"),
+            ".." => return err("segment '..' not permitted"),
+            "**" => Segment::Recursive,
+            segment if segment.contains("**") => {
+                return err("recursive wildcard must be its own segment")
+            }
+            segment => Segment::Pattern(segment),
+        });
+    }
+    Ok(segments)
+}
+
+struct Walker<'a> {
+    include: &'a [Vec<Segment<'a>>],
+    exclude: &'a [Vec<Segment<'a>>],
+    exclude_directories: bool,
+    /// The canonicalized paths of the directories being walked, used to detect symlink cycles.
+    ancestors: HashSet<PathBuf>,
+    result: GlobResult,
+}
+
+impl Walker<'_> {
+    fn walk(&mut self, dir: &Path, path: &mut Vec<String>) {
+        for (name, entry_path, is_dir) in read_dir_sorted(dir) {
+            path.push(name);
+            if is_dir && is_package(&entry_path) {
+                if self.may_match_descendant(path)
+                    && self
+                        .enter_dir(&entry_path, |walker| {
+                            walker.contains_match(&entry_path, path)
+                        })
+                        .unwrap_or(false)
+                {
+                    self.result.subpackages.push(path.join("/"));
+                }
+            } else {
+                if (!is_dir || !self.exclude_directories) && self.is_match(path) {
+                    self.result.matches.push(path.join("/"));
+                }
+                if is_dir && self.may_match_descendant(path) {
+                    self.enter_dir(&entry_path, |walker| walker.walk(&entry_path, path));
+                }
+            }
+            path.pop();
+        }
+    }
+
+    /// Determines whether any path in the given subpackage would be matched if it weren't a
+    /// package. Nested subpackages are skipped, since their files belong to other packages.
+    fn contains_match(&mut self, dir: &Path, path: &mut Vec<String>) -> bool {
+        for (name, entry_path, is_dir) in read_dir_sorted(dir) {
+            if is_dir && is_package(&entry_path) {
+                continue;
+            }
+            path.push(name);
+            let found = ((!is_dir || !self.exclude_directories) && self.is_match(path))
+                || (is_dir
+                    && self.may_match_descendant(path)
+                    && self
+                        .enter_dir(&entry_path, |walker| {
+                            walker.contains_match(&entry_path, path)
+                        })
+                        .unwrap_or(false));
+            path.pop();
+            if found {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Runs `f` with the given directory marked as being walked. Returns `None` without running `f`
+    /// if the directory is already being walked, i.e. it was reached again through a symlink cycle.
+    fn enter_dir<T>(&mut self, dir: &Path, f: impl FnOnce(&mut Self) -> T) -> Option<T> {
+        let dir = fs::canonicalize(dir).ok()?;
+        if !self.ancestors.insert(dir.clone()) {
+            return None;
+        }
+        let res = f(self);
+        self.ancestors.remove(&dir);
+        Some(res)
+    }
+
+    fn is_match(&self, path: &[String]) -> bool {
+        self.include
+            .iter()
+            .any(|segments| matches_path(segments, path))
+            && !self
+                .exclude
+                .iter()
+                .any(|segments| matches_path(segments, path))
+    }
+
+    fn may_match_descendant(&self, path: &[String]) -> bool {
+        self.include
+            .iter()
+            .any(|segments| matches_path_prefix(segments, path))
+    }
+}
+
+/// Lists the entries of a directory as `(name, path, is_dir)` tuples, skipping entries that can't
+/// be read or whose names aren't valid UTF-8. Symlinks are resolved when checking for directories.
+fn read_dir_sorted(dir: &Path) -> Vec<(String, PathBuf, bool)> {
+    let mut entries = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let file_type = entry.file_type().ok()?;
+            let path = entry.path();
+            let is_dir = if file_type.is_symlink() {
+                path.is_dir()
+            } else {
+                file_type.is_dir()
+            };
+            Some((name, path, is_dir))
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+fn is_package(dir: &Path) -> bool {
+    ["BUILD", "BUILD.bazel"]
+        .iter()
+        .any(|name| dir.join(name).is_file())
+}
+
+fn matches_path(segments: &[Segment], path: &[String]) -> bool {
+    match segments.split_first() {
+        None => path.is_empty(),
+        Some((Segment::Recursive, rest)) => {
+            matches_path(rest, path) || (!path.is_empty() && matches_path(segments, &path[1..]))
+        }
+        Some((Segment::Pattern(pattern), rest)) => match path.split_first() {
+            Some((name, path)) => matches_segment(pattern, name) && matches_path(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// Determines whether the descendants of the given path could be matched by the pattern.
+fn matches_path_prefix(segments: &[Segment], path: &[String]) -> bool {
+    match (segments.split_first(), path.split_first()) {
+        (_, None) => !segments.is_empty(),
+        (None, _) => false,
+        (Some((Segment::Recursive, _)), _) => true,
+        (Some((Segment::Pattern(pattern), rest)), Some((name, path))) => {
+            matches_segment(pattern, name) && matches_path_prefix(rest, path)
+        }
+    }
+}
+
+fn matches_segment(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+
+    // The position of the last `*` wildcard, and the position in `name` that it was matched up to.
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_matches(pattern: &str, path: &str, expected: bool) {
+        let segments = parse_pattern(pattern).unwrap();
+        let path = path.split('/').map(String::from).collect::<Vec<_>>();
+        assert_eq!(matches_path(&segments, &path), expected);
+    }
+
+    fn check_err(pattern: &str, reason: &str) {
+        assert_eq!(parse_pattern(pattern).unwrap_err().reason, reason);
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("starpls-glob-{}-{}", name, std::process::id()));
+            for file in files {
+                let path = dir.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "").unwrap();
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_matches_segment() {
+        check_matches("foo.cc", "foo.cc", true);
+        check_matches("foo.cc", "foo.h", false);
+        check_matches("*.cc", "foo.cc", true);
+        check_matches("*.cc", ".cc", true);
+        check_matches("*.cc", "foo.cc.bak", false);
+        check_matches("f?o.*", "foo.cc", true);
+        check_matches("*_test*.py", "foo_test_util.py", true);
+        check_matches("*", "foo/bar", false);
+    }
+
+    #[test]
+    fn test_matches_recursive() {
+        check_matches("**/*.cc", "foo.cc", true);
+        check_matches("**/*.cc", "a/b/foo.cc", true);
+        check_matches("a/**/foo.cc", "a/foo.cc", true);
+        check_matches("a/**/foo.cc", "a/b/c/foo.cc", true);
+        check_matches("a/**/foo.cc", "b/foo.cc", false);
+        check_matches("**", "a/b/c", true);
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        check_err("", "pattern cannot be empty");
+        check_err("/foo", "pattern cannot be absolute");
+        check_err("foo//bar", "empty segment not permitted");
+        check_err("./foo", "segment '.' not permitted");
+        check_err("../foo", "segment '..' not permitted");
+        check_err("foo/**.cc", "recursive wildcard must be its own segment");
+    }
+
+    #[test]
+    fn test_glob() {
+        let dir = TempDir::new(
+            "test_glob",
+            &[
+                "BUILD",
+                "a.cc",
+                "a_test.cc",
+                "b.h",
+                "sub/c.cc",
+                "sub/deep/d.cc",
+                "pkg/BUILD",
+                "pkg/e.cc",
+                "other/BUILD.bazel",
+                "other/f.h",
+                "other/nested/BUILD",
+                "other/nested/g.cc",
+            ],
+        );
+
+        assert_eq!(
+            glob(
+                &dir.0,
+                &strings(&["**/*.cc"]),
+                &strings(&["*_test.cc"]),
+                true
+            ),
+            Ok(GlobResult {
+                matches: strings(&["a.cc", "sub/c.cc", "sub/deep/d.cc"]),
+                // `other` only contains matches in its own subpackage, `other/nested`.
+                subpackages: strings(&["pkg"]),
+            })
+        );
+        assert_eq!(
+            glob(&dir.0, &strings(&["*"]), &strings(&["BUILD"]), false),
+            Ok(GlobResult {
+                matches: strings(&["a.cc", "a_test.cc", "b.h", "sub"]),
+                subpackages: strings(&[]),
+            })
+        );
+        assert_eq!(
+            glob(&dir.0, &strings(&["*.py"]), &[], true),
+            Ok(GlobResult::default())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_glob_symlinks() {
+        let dir = TempDir::new("test_glob_symlinks", &["BUILD", "a.cc", "sub/c.cc"]);
+        std::os::unix::fs::symlink(dir.0.join("sub"), dir.0.join("link")).unwrap();
+        // A cycle, which is listed but not descended into.
+        std::os::unix::fs::symlink(dir.0.join("sub"), dir.0.join("sub/loop")).unwrap();
+
+        assert_eq!(
+            glob(&dir.0, &strings(&["**/*.cc"]), &[], true),
+            Ok(GlobResult {
+                matches: strings(&["a.cc", "link/c.cc", "sub/c.cc"]),
+                subpackages: strings(&[]),
+            })
+        );
+        assert_eq!(
+            glob(&dir.0, &strings(&["**"]), &strings(&["BUILD"]), false),
+            Ok(GlobResult {
+                matches: strings(&[
+                    "a.cc",
+                    "link",
+                    "link/c.cc",
+                    "link/loop",
+                    "sub",
+                    "sub/c.cc",
+                    "sub/loop",
+                ]),
+                subpackages: strings(&[]),
+            })
+        );
+    }
+}
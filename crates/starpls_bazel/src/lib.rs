@@ -13,7 +13,11 @@ pub mod attr;
 pub mod build_language;
 pub mod client;
 pub mod env;
+pub mod glob;
 pub mod label;
+pub mod lockfile;
+pub mod registry;
+pub mod visibility;
 
 #[cfg(bazel)]
 pub mod builtin {
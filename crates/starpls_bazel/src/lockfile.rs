@@ -0,0 +1,445 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use serde_json::Value;
+
+/// The repositories generated by module extensions, as recorded in a `MODULE.bazel.lock` file, along
+/// with the apparent repository names referenced throughout the workspace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleExtensionRepos {
+    generated: HashMap<String, Vec<String>>,
+    referenced: HashSet<String>,
+}
+
+impl ModuleExtensionRepos {
+    pub fn from_lockfile(contents: &str) -> anyhow::Result<Self> {
+        let lockfile: Value = serde_json::from_str(contents)?;
+        let extensions = lockfile
+            .get("moduleExtensions")
+            .and_then(|extensions| extensions.as_object())
+            .ok_or_else(|| anyhow!("missing \"moduleExtensions\" in lockfile"))?;
+        let mut generated = HashMap::new();
+
+        for (id, entry) in extensions {
+            let (bzl_file, name) = match id.rsplit_once('%') {
+                Some(parts) => parts,
+                None => continue,
+            };
+
+            // Depending on the lockfile version, the repo specs are either stored directly on the entry, or
+            // on a set of nested entries keyed by factors like the host OS and architecture (e.g. "general").
+            let mut repos = Vec::new();
+            let mut add_repo_specs = |entry: &Value| {
+                if let Some(specs) = entry
+                    .get("generatedRepoSpecs")
+                    .and_then(|specs| specs.as_object())
+                {
+                    repos.extend(specs.keys().cloned());
+                }
+            };
+            add_repo_specs(entry);
+            if let Some(entries) = entry.as_object() {
+                entries.values().for_each(add_repo_specs);
+            }
+
+            repos.sort();
+            repos.dedup();
+            generated.insert(extension_key(bzl_file, name), repos);
+        }
+
+        Ok(Self {
+            generated,
+            referenced: Default::default(),
+        })
+    }
+
+    /// Returns the repositories generated by the extension `name` defined in `bzl_file`, or `None` if the
+    /// extension isn't recorded in the lockfile.
+    pub fn generated_repos(&self, bzl_file: &str, name: &str) -> Option<&[String]> {
+        self.generated
+            .get(&extension_key(bzl_file, name))
+            .map(|repos| &repos[..])
+    }
+
+    pub fn set_referenced_repos(&mut self, referenced: HashSet<String>) {
+        self.referenced = referenced;
+    }
+
+    /// Whether the apparent repository name `repo` is referenced by a label, e.g. `@repo//pkg:target`,
+    /// somewhere in the workspace.
+    pub fn is_referenced(&self, repo: &str) -> bool {
+        self.referenced.contains(repo)
+    }
+}
+
+/// A module selected during Bazel module resolution, as recorded in the `moduleDepGraph` section of a
+/// `MODULE.bazel.lock` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolvedModule {
+    pub version: String,
+    /// The keys of the module's dependencies, e.g. `platforms@0.0.7`.
+    pub deps: Vec<String>,
+}
+
+/// The modules selected during Bazel module resolution, keyed by module name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolvedModules {
+    modules: HashMap<String, ResolvedModule>,
+}
+
+impl ResolvedModules {
+    pub fn from_lockfile(contents: &str) -> anyhow::Result<Self> {
+        let lockfile: Value = serde_json::from_str(contents)?;
+        let graph = lockfile
+            .get("moduleDepGraph")
+            .and_then(|graph| graph.as_object())
+            .ok_or_else(|| anyhow!("missing \"moduleDepGraph\" in lockfile"))?;
+        let mut modules = HashMap::new();
+
+        for (key, entry) in graph {
+            if key == "<root>" {
+                continue;
+            }
+            let name = match entry.get("name").and_then(|name| name.as_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            let version = entry
+                .get("version")
+                .and_then(|version| version.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let mut deps = entry
+                .get("deps")
+                .and_then(|deps| deps.as_object())
+                .map(|deps| {
+                    deps.values()
+                        .filter_map(|dep| dep.as_str().map(|dep| dep.to_string()))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            deps.sort();
+            modules.insert(name.to_string(), ResolvedModule { version, deps });
+        }
+
+        Ok(Self { modules })
+    }
+
+    /// Loads the resolved modules from the output of `bazel mod graph --output=json`. This is needed
+    /// starting with Bazel 7.2, whose lockfiles no longer record the module dependency graph.
+    pub fn from_mod_graph(contents: &str) -> anyhow::Result<Self> {
+        let root: Value = serde_json::from_str(contents)?;
+        let mut modules = HashMap::new();
+        collect_mod_graph_modules(&root, &mut modules);
+        Ok(Self { modules })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ResolvedModule> {
+        self.modules.get(name)
+    }
+}
+
+/// Collects the modules in a `bazel mod graph --output=json` node and its descendants. Modules that
+/// appear more than once are only expanded on their first occurrence.
+fn collect_mod_graph_modules(node: &Value, modules: &mut HashMap<String, ResolvedModule>) {
+    let deps = node
+        .get("dependencies")
+        .and_then(|deps| deps.as_array())
+        .map(|deps| &deps[..])
+        .unwrap_or_default();
+    let is_expanded = !node
+        .get("unexpanded")
+        .and_then(|unexpanded| unexpanded.as_bool())
+        .unwrap_or_default();
+    let key = node.get("key").and_then(|key| key.as_str());
+    let name = node.get("name").and_then(|name| name.as_str());
+    if let (Some(key), Some(name)) = (key, name) {
+        if key != "<root>" && is_expanded {
+            let version = node
+                .get("version")
+                .and_then(|version| version.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let mut dep_keys = deps
+                .iter()
+                .filter_map(|dep| dep.get("key").and_then(|key| key.as_str()))
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>();
+            dep_keys.sort();
+            modules.insert(
+                name.to_string(),
+                ResolvedModule {
+                    version,
+                    deps: dep_keys,
+                },
+            );
+        }
+    }
+    for dep in deps {
+        collect_mod_graph_modules(dep, modules);
+    }
+}
+
+/// The apparent repository names referenced by labels in each `BUILD` and `.bzl` file of the workspace.
+/// Names are reference-counted across files, so that the set can be updated as individual files change
+/// without rescanning the workspace.
+#[derive(Clone, Debug, Default)]
+pub struct ReferencedRepos {
+    files: HashMap<PathBuf, HashSet<String>>,
+    counts: HashMap<String, usize>,
+}
+
+impl ReferencedRepos {
+    /// Records the repositories referenced by the file at `path`, replacing those previously recorded
+    /// for it. Returns whether the set of referenced repositories changed.
+    pub fn update_file(&mut self, path: PathBuf, contents: &str) -> bool {
+        let mut repos = HashSet::new();
+        collect_referenced_repos(contents, &mut repos);
+        let mut changed = self.remove_file(&path);
+        for repo in repos.iter() {
+            let count = self.counts.entry(repo.clone()).or_default();
+            changed |= *count == 0;
+            *count += 1;
+        }
+        if !repos.is_empty() {
+            self.files.insert(path, repos);
+        }
+        changed
+    }
+
+    /// Forgets the repositories referenced by the file at `path`. Returns whether the set of
+    /// referenced repositories changed.
+    pub fn remove_file(&mut self, path: &Path) -> bool {
+        let mut changed = false;
+        for repo in self.files.remove(path).into_iter().flatten() {
+            if let Some(count) = self.counts.get_mut(&repo) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&repo);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    pub fn repos(&self) -> HashSet<String> {
+        self.counts.keys().cloned().collect()
+    }
+}
+
+/// Collects the apparent repository names referenced by labels, e.g. `@repo//pkg:target`, in the given
+/// file contents. Canonical repository names, e.g. `@@repo//pkg:target`, are skipped.
+pub fn collect_referenced_repos(contents: &str, repos: &mut HashSet<String>) {
+    let mut rest = contents;
+    while let Some(pos) = rest.find('@') {
+        let after = rest[pos..].trim_start_matches('@');
+        let is_canonical = rest.len() - pos - after.len() > 1;
+        let end = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')))
+            .unwrap_or(after.len());
+        if !is_canonical && end > 0 && after[end..].starts_with("//") {
+            repos.insert(after[..end].to_string());
+        }
+        rest = &after[end..];
+    }
+}
+
+/// Normalizes an extension identifier so that the form used in `MODULE.bazel`, e.g.
+/// `@rules_python//python/extensions:pip.bzl`, matches the form recorded in the lockfile, e.g.
+/// `@@rules_python~//python/extensions:pip.bzl`. Canonical repository names are reduced to their module
+/// name, and the main repository is represented by the empty string.
+fn extension_key(bzl_file: &str, name: &str) -> String {
+    let bzl_file = bzl_file.trim_start_matches('@');
+    let (repo, rest) = match bzl_file.find("//") {
+        Some(pos) => bzl_file.split_at(pos),
+        None => ("", bzl_file),
+    };
+    let repo = repo.split(['~', '+']).next().unwrap_or_default();
+    format!("{}{}%{}", repo, rest, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCKFILE: &str = r#"{
+  "lockFileVersion": 6,
+  "moduleExtensions": {
+    "@@rules_python~//python/extensions:pip.bzl%pip": {
+      "general": {
+        "bzlTransitiveDigest": "abc",
+        "generatedRepoSpecs": {
+          "pypi": {},
+          "pypi_requests": {}
+        }
+      }
+    },
+    "//:extensions.bzl%deps": {
+      "os:linux,arch:x86_64": {
+        "generatedRepoSpecs": {
+          "tool_linux": {}
+        }
+      },
+      "os:osx,arch:aarch64": {
+        "generatedRepoSpecs": {
+          "tool_macos": {}
+        }
+      }
+    },
+    "@@rules_go+//go:extensions.bzl%go_sdk": {
+      "generatedRepoSpecs": {
+        "go_default_sdk": {}
+      }
+    }
+  }
+}"#;
+
+    #[test]
+    fn test_generated_repos() {
+        let repos = ModuleExtensionRepos::from_lockfile(LOCKFILE).unwrap();
+        assert_eq!(
+            repos.generated_repos("@rules_python//python/extensions:pip.bzl", "pip"),
+            Some(&["pypi".to_string(), "pypi_requests".to_string()][..])
+        );
+        assert_eq!(
+            repos.generated_repos("//:extensions.bzl", "deps"),
+            Some(&["tool_linux".to_string(), "tool_macos".to_string()][..])
+        );
+        assert_eq!(
+            repos.generated_repos("@//:extensions.bzl", "deps"),
+            Some(&["tool_linux".to_string(), "tool_macos".to_string()][..])
+        );
+        assert_eq!(
+            repos.generated_repos("@rules_go//go:extensions.bzl", "go_sdk"),
+            Some(&["go_default_sdk".to_string()][..])
+        );
+        assert_eq!(
+            repos.generated_repos("@rules_go//go:extensions.bzl", "go_deps"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_resolved_modules() {
+        let modules = ResolvedModules::from_lockfile(
+            r#"{
+  "lockFileVersion": 6,
+  "moduleDepGraph": {
+    "<root>": {
+      "name": "",
+      "version": "",
+      "deps": {
+        "rules_cc": "rules_cc@0.0.9"
+      }
+    },
+    "rules_cc@0.0.9": {
+      "name": "rules_cc",
+      "version": "0.0.9",
+      "deps": {
+        "platforms": "platforms@0.0.7",
+        "bazel_tools": "bazel_tools@_"
+      }
+    }
+  }
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            modules.get("rules_cc"),
+            Some(&ResolvedModule {
+                version: "0.0.9".to_string(),
+                deps: vec!["bazel_tools@_".to_string(), "platforms@0.0.7".to_string()],
+            })
+        );
+        assert_eq!(modules.get(""), None);
+        assert_eq!(modules.get("platforms"), None);
+    }
+
+    #[test]
+    fn test_invalid_lockfile() {
+        assert!(ModuleExtensionRepos::from_lockfile("{}").is_err());
+        assert!(ModuleExtensionRepos::from_lockfile("not json").is_err());
+        assert!(ResolvedModules::from_lockfile("{}").is_err());
+    }
+
+    #[test]
+    fn test_collect_referenced_repos() {
+        let mut repos = HashSet::new();
+        collect_referenced_repos(
+            r#"
+deps = ["@pypi_requests//:pkg", "@@canonical~//:pkg", "@bar", "@go_sdk//go:bin"]
+"#,
+            &mut repos,
+        );
+        let mut repos = repos.into_iter().collect::<Vec<_>>();
+        repos.sort();
+        assert_eq!(repos, vec!["go_sdk", "pypi_requests"]);
+    }
+
+    #[test]
+    fn test_referenced_repos_update_file() {
+        let mut referenced = ReferencedRepos::default();
+        assert!(referenced.update_file("a/BUILD".into(), r#"deps = ["@foo//:a", "@bar//:b"]"#));
+        assert!(!referenced.update_file("b/BUILD".into(), r#"deps = ["@foo//:c"]"#));
+
+        // Removing a reference from one file keeps repositories still referenced by others.
+        assert!(referenced.update_file("a/BUILD".into(), r#"deps = ["@foo//:a"]"#));
+        assert!(!referenced.remove_file(Path::new("a/BUILD")));
+        assert_eq!(referenced.repos(), HashSet::from(["foo".to_string()]));
+
+        assert!(referenced.remove_file(Path::new("b/BUILD")));
+        assert!(referenced.repos().is_empty());
+    }
+
+    #[test]
+    fn test_resolved_modules_from_mod_graph() {
+        let modules = ResolvedModules::from_mod_graph(
+            r#"{
+  "key": "<root>",
+  "name": "main",
+  "version": "",
+  "dependencies": [
+    {
+      "key": "rules_cc@0.0.9",
+      "name": "rules_cc",
+      "version": "0.0.9",
+      "dependencies": [
+        {
+          "key": "platforms@0.0.7",
+          "name": "platforms",
+          "version": "0.0.7",
+          "dependencies": []
+        }
+      ]
+    },
+    {
+      "key": "platforms@0.0.7",
+      "name": "platforms",
+      "version": "0.0.7",
+      "unexpanded": true
+    }
+  ]
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            modules.get("rules_cc"),
+            Some(&ResolvedModule {
+                version: "0.0.9".to_string(),
+                deps: vec!["platforms@0.0.7".to_string()],
+            })
+        );
+        assert_eq!(
+            modules.get("platforms"),
+            Some(&ResolvedModule {
+                version: "0.0.7".to_string(),
+                deps: vec![],
+            })
+        );
+        assert_eq!(modules.get("main"), None);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    versions: Vec<String>,
+}
+
+/// The modules and versions available in a Bazel registry stored on the local filesystem, e.g. a
+/// checkout of the Bazel Central Registry or a `file://` registry mirror.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BazelRegistry {
+    modules: BTreeMap<String, Vec<String>>,
+    invalid_modules: Vec<(String, String)>,
+}
+
+impl BazelRegistry {
+    /// Reads the registry rooted at the given path. Each module is expected to have a
+    /// `modules/<name>/metadata.json` file listing its versions. Modules whose metadata can't be
+    /// parsed are skipped, and are listed by `BazelRegistry::invalid_modules`.
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut modules = BTreeMap::new();
+        let mut invalid_modules = Vec::new();
+        for entry in fs::read_dir(path.as_ref().join("modules"))? {
+            let entry = entry?;
+            let name = match entry.file_name().to_str() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let versions = match fs::read_to_string(entry.path().join("metadata.json")) {
+                Ok(contents) => match versions_from_metadata(&contents) {
+                    Ok(versions) => versions,
+                    Err(err) => {
+                        invalid_modules.push((name, err.to_string()));
+                        continue;
+                    }
+                },
+                Err(_) => continue,
+            };
+            modules.insert(name, versions);
+        }
+        invalid_modules.sort();
+        Ok(Self {
+            modules,
+            invalid_modules,
+        })
+    }
+
+    pub fn module_names(&self) -> impl Iterator<Item = &str> {
+        self.modules.keys().map(|name| name.as_str())
+    }
+
+    pub fn versions(&self, name: &str) -> &[String] {
+        self.modules
+            .get(name)
+            .map(|versions| &versions[..])
+            .unwrap_or_default()
+    }
+    /// The modules that were skipped because their `metadata.json` couldn't be parsed, along
+    /// with the parse errors.
+    pub fn invalid_modules(&self) -> &[(String, String)] {
+        &self.invalid_modules
+    }
+}
+
+fn versions_from_metadata(contents: &str) -> anyhow::Result<Vec<String>> {
+    let metadata: Metadata = serde_json::from_str(contents)?;
+    Ok(metadata.versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_from_metadata() {
+        let versions = versions_from_metadata(
+            r#"{
+  "homepage": "https://github.com/bazelbuild/rules_cc",
+  "maintainers": [],
+  "versions": ["0.0.1", "0.0.2", "0.0.9"],
+  "yanked_versions": {}
+}"#,
+        )
+        .unwrap();
+        assert_eq!(versions, vec!["0.0.1", "0.0.2", "0.0.9"]);
+        assert_eq!(versions_from_metadata("{}").unwrap(), Vec::<String>::new());
+        assert!(versions_from_metadata("[]").is_err());
+    }
+
+    #[test]
+    fn test_from_path_skips_invalid_metadata() {
+        let root = std::env::temp_dir().join(format!(
+            "starpls-registry-invalid-metadata-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        for (name, metadata) in [("rules_cc", r#"{"versions": ["0.0.9"]}"#), ("broken", "{")] {
+            let dir = root.join("modules").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("metadata.json"), metadata).unwrap();
+        }
+
+        let registry = BazelRegistry::from_path(&root);
+        fs::remove_dir_all(&root).unwrap();
+        let registry = registry.unwrap();
+        assert_eq!(
+            registry.module_names().collect::<Vec<_>>(),
+            vec!["rules_cc"]
+        );
+        assert_eq!(registry.versions("rules_cc"), ["0.0.9"]);
+        assert_eq!(
+            registry
+                .invalid_modules()
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["broken"]
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Evaluation of Bazel's visibility rules, i.e. whether a target can be depended on by the
+//! targets of another package. Only packages in the main repository are supported, so package
+//! names are relative to the workspace root, e.g. `foo/bar` for `//foo/bar`.
+
+use crate::Label;
+
+pub const PUBLIC: &str = "//visibility:public";
+pub const PRIVATE: &str = "//visibility:private";
+
+/// The `packages` and `includes` arguments of a `package_group()` declaration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackageGroup {
+    pub packages: Vec<String>,
+    pub includes: Vec<String>,
+}
+
+/// Determines whether a target declared in `target_package` with the given visibility labels is
+/// visible to the targets in `from_package`. `resolve_group` looks up the `package_group` with the
+/// given absolute label, e.g. `//foo:friends`.
+///
+/// Visibility that can't be evaluated, e.g. references to package groups that can't be found or to
+/// other repositories, is assumed to be granted, so that callers don't report false positives.
+pub fn is_visible(
+    visibility: &[String],
+    target_package: &str,
+    from_package: &str,
+    mut resolve_group: impl FnMut(&str) -> Option<PackageGroup>,
+) -> bool {
+    if target_package == from_package {
+        return true;
+    }
+
+    let mut evaluator = Evaluator {
+        from_package,
+        resolve_group: &mut resolve_group,
+        visited: Vec::new(),
+    };
+    visibility
+        .iter()
+        .any(|label| evaluator.grants_visibility(label, target_package))
+}
+
+struct Evaluator<'a> {
+    from_package: &'a str,
+    resolve_group: &'a mut dyn FnMut(&str) -> Option<PackageGroup>,
+    /// The package groups that have already been evaluated, to guard against cycles.
+    visited: Vec<String>,
+}
+
+impl Evaluator<'_> {
+    fn grants_visibility(&mut self, label: &str, target_package: &str) -> bool {
+        let label = match Label::parse(label) {
+            Ok(label) if label.repo().is_empty() => label,
+            _ => return true,
+        };
+        let package = if label.is_relative() {
+            target_package
+        } else {
+            label.package()
+        };
+
+        match (package, label.target()) {
+            ("visibility", "public") => true,
+            ("visibility", "private") => false,
+            (package, "__pkg__") => self.from_package == package,
+            (package, "__subpackages__") => is_subpackage(self.from_package, package),
+            (package, name) => self.in_package_group(package, name),
+        }
+    }
+
+    fn in_package_group(&mut self, package: &str, name: &str) -> bool {
+        let label = format!("//{}:{}", package, name);
+        if self.visited.contains(&label) {
+            return false;
+        }
+        let group = match (self.resolve_group)(&label) {
+            Some(group) => group,
+            None => return true,
+        };
+        self.visited.push(label);
+
+        // Negative specifications take precedence over both positive specifications and the
+        // included package groups.
+        let mut matched = false;
+        for spec in group.packages.iter() {
+            match spec.strip_prefix('-') {
+                Some(spec) if matches_package_spec(spec, self.from_package) => return false,
+                Some(_) => {}
+                None => matched |= matches_package_spec(spec, self.from_package),
+            }
+        }
+        matched
+            || group
+                .includes
+                .iter()
+                .any(|include| self.grants_visibility(include, package))
+    }
+}
+
+/// Matches a package against a `package_group` specification, e.g. `//foo`, `//foo/...` or
+/// `public`.
+fn matches_package_spec(spec: &str, package: &str) -> bool {
+    match spec {
+        "public" => return true,
+        "private" => return false,
+        _ => {}
+    }
+    let spec = match spec.strip_prefix("//") {
+        Some(spec) => spec,
+        None => return false,
+    };
+    match spec.strip_suffix("...") {
+        Some("") => true,
+        Some(parent) => match parent.strip_suffix('/') {
+            Some(parent) => is_subpackage(package, parent),
+            None => false,
+        },
+        None => package == spec,
+    }
+}
+
+/// Determines whether `package` is `parent` or one of its subpackages.
+fn is_subpackage(package: &str, parent: &str) -> bool {
+    parent.is_empty()
+        || package
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn check(visibility: &[&str], from_package: &str, expected: bool) {
+        let groups = [
+            (
+                "//foo:friends",
+                PackageGroup {
+                    packages: strings(&["//bar", "//baz/...", "-//baz/secret"]),
+                    includes: strings(&[":more_friends"]),
+                },
+            ),
+            (
+                "//foo:more_friends",
+                PackageGroup {
+                    packages: strings(&["//qux"]),
+                    includes: strings(&["//foo:friends"]),
+                },
+            ),
+        ];
+        let resolve_group = |label: &str| {
+            groups
+                .iter()
+                .find(|(name, _)| *name == label)
+                .map(|(_, group)| group.clone())
+        };
+        assert_eq!(
+            is_visible(&strings(visibility), "foo", from_package, resolve_group),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_public_and_private() {
+        check(&[PUBLIC], "bar", true);
+        check(&[PRIVATE], "bar", false);
+        check(&[PRIVATE], "foo", true);
+        check(&[], "bar", false);
+    }
+
+    #[test]
+    fn test_packages() {
+        check(&["//bar:__pkg__"], "bar", true);
+        check(&["//bar:__pkg__"], "bar/sub", false);
+        check(&["//bar:__subpackages__"], "bar/sub", true);
+        check(&["//bar:__subpackages__"], "barn", false);
+        check(&[":__subpackages__"], "foo/sub", true);
+        check(&["//:__subpackages__"], "anything", true);
+    }
+
+    #[test]
+    fn test_package_groups() {
+        check(&["//foo:friends"], "bar", true);
+        check(&[":friends"], "baz/sub", true);
+        check(&["//foo:friends"], "baz/secret", false);
+        check(&["//foo:friends"], "qux", true);
+        check(&["//foo:friends"], "other", false);
+        check(&["//foo:unknown"], "other", true);
+        check(&["@other//foo:friends"], "other", true);
+    }
+
+    #[test]
+    fn test_package_specs() {
+        assert!(matches_package_spec("//...", "foo"));
+        assert!(matches_package_spec("public", "foo"));
+        assert!(!matches_package_spec("private", "foo"));
+        assert!(matches_package_spec("//foo/...", "foo"));
+        assert!(!matches_package_spec("//foo...", "foo"));
+        assert!(matches_package_spec("//", ""));
+    }
+}
@@ -1,27 +1,175 @@
+use std::fmt;
+
 use starpls_syntax::TextRange;
 
 use crate::FileId;
 
 /// An IDE diagnostic. This is the common data structure used to report errors to the user.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Diagnostic {
     pub message: String,
     pub severity: Severity,
     pub range: FileRange,
+    pub code: DiagnosticCode,
+    pub tags: Vec<DiagnosticTag>,
+    pub related: Vec<RelatedInformation>,
+    pub fixes: Vec<Fix>,
+    pub navigations: Vec<Navigation>,
+}
+
+impl Diagnostic {
+    pub fn new(
+        code: DiagnosticCode,
+        severity: Severity,
+        range: FileRange,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            message: message.into(),
+            severity,
+            range,
+            code,
+            tags: Vec::new(),
+            related: Vec::new(),
+            fixes: Vec::new(),
+            navigations: Vec::new(),
+        }
+    }
+
+    pub fn with_tag(mut self, tag: DiagnosticTag) -> Self {
+        self.tags.push(tag);
+        self
+    }
+
+    pub fn with_related(mut self, related: RelatedInformation) -> Self {
+        self.related.push(related);
+        self
+    }
+
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fixes.push(fix);
+        self
+    }
+
+    pub fn with_navigation(mut self, navigation: Navigation) -> Self {
+        self.navigations.push(navigation);
+        self
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FileRange {
     pub file_id: FileId,
     pub range: TextRange,
 }
 
 /// A severity level for diagnostic messages.
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Severity {
+    Hint,
+    Information,
     Warning,
     Error,
 }
 
+/// Additional metadata that editors can use to render a diagnostic, e.g. by fading out
+/// unnecessary code or striking through deprecated symbols.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticTag {
+    Unnecessary,
+    Deprecated,
+}
+
+/// A secondary location relevant to a diagnostic, e.g. the original definition of a symbol that
+/// has been redefined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelatedInformation {
+    pub message: String,
+    pub range: FileRange,
+}
+
+/// A set of edits that can be applied to resolve a diagnostic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fix {
+    pub label: String,
+    pub edits: Vec<SourceEdit>,
+}
+
+/// A location that needs to be changed to resolve a diagnostic but can't be edited automatically,
+/// e.g. the declaration of a target in another BUILD file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Navigation {
+    pub label: String,
+    pub target: FileRange,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceEdit {
+    pub range: FileRange,
+    pub new_text: String,
+}
+
+macro_rules! diagnostic_codes {
+    ($($variant:ident => $code:literal,)*) => {
+        /// A stable identifier for a class of diagnostics. The string form of a code is what
+        /// users see in editors and in `starpls check` output.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum DiagnosticCode {
+            $($variant,)*
+        }
+
+        impl DiagnosticCode {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(DiagnosticCode::$variant => $code,)*
+                }
+            }
+
+            pub fn lookup(code: &str) -> Option<DiagnosticCode> {
+                Some(match code {
+                    $($code => DiagnosticCode::$variant,)*
+                    _ => return None,
+                })
+            }
+        }
+    };
+}
+
+diagnostic_codes! {
+    Syntax => "syntax",
+    TopLevelStatement => "top-level-statement",
+    InvalidAssignmentTarget => "invalid-assignment-target",
+    UndefinedName => "undefined-name",
+    PossiblyUnbound => "possibly-unbound",
+    AttrAccess => "attr-access",
+    Index => "index",
+    CallArg => "call-arg",
+    ArgType => "arg-type",
+    NotCallable => "not-callable",
+    Operator => "operator",
+    Assignment => "assignment",
+    NotIterable => "not-iterable",
+    TypeComment => "type-comment",
+    Load => "load",
+    CircularImport => "circular-import",
+    UnusedIgnore => "unused-ignore",
+    UnreachableCode => "unreachable-code",
+    Recursion => "recursion",
+    GlobalReassignment => "global-reassignment",
+    FrozenMutation => "frozen-mutation",
+    UseRepo => "use-repo",
+    Glob => "glob",
+    Visibility => "visibility",
+    ProviderReturn => "provider-return",
+    AttrSchema => "attr-schema",
+    Subrule => "subrule",
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[salsa::accumulator]
 pub struct Diagnostics(Diagnostic);
@@ -5,7 +5,10 @@ use starpls_syntax::{
     line_index as syntax_line_index, parse_module, LineIndex, Module, ParseTree, SyntaxNode,
 };
 
-pub use crate::diagnostics::{Diagnostic, Diagnostics, FileRange, Severity};
+pub use crate::diagnostics::{
+    Diagnostic, DiagnosticCode, DiagnosticTag, Diagnostics, FileRange, Fix, Navigation,
+    RelatedInformation, Severity, SourceEdit,
+};
 
 mod diagnostics;
 mod util;
@@ -39,6 +42,30 @@ pub struct LoadItemCandidate {
     pub replace_trailing_slash: bool,
 }
 
+/// The source files accepted by a label-valued attribute, as declared by its `allow_files` or
+/// `allow_single_file` argument.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AllowedFiles {
+    /// The attribute only accepts targets.
+    #[default]
+    None,
+    Any,
+    /// The attribute accepts source files with any of the given extensions, e.g. `.cc`.
+    Extensions(Box<[Box<str>]>),
+}
+
+impl AllowedFiles {
+    pub fn allows(&self, file_name: &str) -> bool {
+        match self {
+            AllowedFiles::None => false,
+            AllowedFiles::Any => true,
+            AllowedFiles::Extensions(extensions) => extensions
+                .iter()
+                .any(|extension| file_name.ends_with(&**extension)),
+        }
+    }
+}
+
 /// A Key corresponding to an interned file path. Use these instead of `Path`s to refer to files.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct FileId(pub u32);
@@ -83,6 +110,15 @@ pub trait Db: salsa::DbWithJar<Jar> {
         from: FileId,
     ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>>;
 
+    /// Returns the packages and source files that can complete the given label in a label-valued
+    /// attribute. Only the source files allowed by `allowed_files` are included.
+    fn list_label_candidates(
+        &self,
+        path: &str,
+        from: FileId,
+        allowed_files: &AllowedFiles,
+    ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>>;
+
     fn resolve_path(
         &self,
         path: &str,
@@ -143,14 +179,15 @@ pub fn parse(db: &dyn Db, file: File) -> Parse {
     let parse = parse_module(file.contents(db), &mut |err| {
         Diagnostics::push(
             db,
-            Diagnostic {
-                message: err.message,
-                range: FileRange {
+            Diagnostic::new(
+                DiagnosticCode::Syntax,
+                Severity::Error,
+                FileRange {
                     file_id: file.id(db),
                     range: err.range,
                 },
-                severity: Severity::Error,
-            },
+                err.message,
+            ),
         )
     });
     Parse::new(db, file, parse)
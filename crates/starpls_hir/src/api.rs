@@ -1,25 +1,32 @@
 use std::sync::Arc;
 
+use rustc_hash::{FxHashMap, FxHashSet};
 use smallvec::SmallVec;
-use starpls_common::{parse, Diagnostic, Diagnostics, File, InFile};
+use starpls_bazel::visibility::{PackageGroup, PRIVATE, PUBLIC};
+use starpls_common::{
+    line_index, parse, Diagnostic, DiagnosticCode, DiagnosticTag, Diagnostics, File, FileRange,
+    Fix, InFile, Severity, SourceEdit,
+};
 use starpls_syntax::{
     ast::{self, AstNode, AstPtr, SyntaxNodePtr},
-    TextSize, T,
+    TextRange, TextSize, T,
 };
 
 pub use crate::typeck::{Field, Param};
 use crate::{
     def::{
         self,
+        checks::{check_module, frozen_mutation_diagnostics},
         resolver::Resolver,
         scope::{self, module_scopes, FunctionDef, ParameterDef},
+        targets::{eval_string, file_targets, TargetDef, VisibilityDef},
         LoadItemId, Stmt,
     },
     module, source_map,
     typeck::{
         self, builtins::BuiltinFunction, intrinsics::IntrinsicFunction, resolve_type_ref, with_tcx,
-        FieldInner, ParamInner, Provider, Struct as DefStruct, Substitution, TagClass, Tuple, Ty,
-        TypeRef,
+        FieldInner, Macro, ParamInner, Provider, Struct as DefStruct, Substitution, TagClass,
+        Tuple, Ty, TypeRef,
     },
     Db, ExprId, Name, TyKind,
 };
@@ -27,7 +34,114 @@ use crate::{
 const TARGET_DOC: &str = "The BUILD target for a dependency. Appears in the fields of `ctx.attr` corresponding to dependency attributes (`label` or `label_list`).";
 
 pub fn diagnostics_for_file(db: &dyn Db, file: File) -> impl Iterator<Item = Diagnostic> {
-    module_scopes::accumulated::<Diagnostics>(db, file).into_iter()
+    module_scopes::accumulated::<Diagnostics>(db, file)
+        .into_iter()
+        .chain(check_module(db, file).diagnostics(db).clone())
+        .chain(frozen_mutation_diagnostics(db, file))
+}
+
+/// Applies the `# type: ignore` comments of a file to the diagnostics reported for it, while
+/// keeping track of the comments that didn't suppress anything.
+pub struct TypeIgnores<'a> {
+    db: &'a dyn Db,
+    file: File,
+    used_lines: FxHashSet<u32>,
+    is_file_ignore_used: bool,
+}
+
+impl<'a> TypeIgnores<'a> {
+    pub fn new(db: &'a dyn Db, file: File) -> Self {
+        Self {
+            db,
+            file,
+            used_lines: FxHashSet::default(),
+            is_file_ignore_used: false,
+        }
+    }
+
+    /// Determines whether a diagnostic is suppressed by a file-level `# type: ignore` comment or
+    /// by one on any of the lines that the diagnostic spans.
+    pub fn is_suppressed(&mut self, diagnostic: &Diagnostic) -> bool {
+        if diagnostic.range.file_id != self.file.id(self.db) {
+            return false;
+        }
+        let module = module(self.db, self.file);
+        if module
+            .file_type_ignore
+            .as_ref()
+            .is_some_and(|ignore| ignore.suppresses(diagnostic.code))
+        {
+            self.is_file_ignore_used = true;
+            return true;
+        }
+
+        let line_index = line_index(self.db, self.file);
+        let start_line = line_index.line_col(diagnostic.range.range.start()).line;
+        let end_line = line_index.line_col(diagnostic.range.range.end()).line;
+        for line in start_line..=end_line {
+            if let Some(ignore) = module.type_ignores.get(&line) {
+                if ignore.suppresses(diagnostic.code) {
+                    self.used_lines.insert(line);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Reports the `# type: ignore` comments that haven't suppressed any diagnostics.
+    pub fn unused_ignores(&self) -> Vec<Diagnostic> {
+        let module = module(self.db, self.file);
+        let mut unused_ignores = module
+            .type_ignores
+            .iter()
+            .filter(|(line, _)| !self.used_lines.contains(*line))
+            .map(|(_, ignore)| ignore)
+            .chain(
+                module
+                    .file_type_ignore
+                    .as_ref()
+                    .filter(|_| !self.is_file_ignore_used),
+            )
+            .collect::<Vec<_>>();
+        unused_ignores.sort_by_key(|ignore| ignore.range.start());
+
+        let file_id = self.file.id(self.db);
+        let contents = self.file.contents(self.db);
+        unused_ignores
+            .into_iter()
+            .map(|ignore| {
+                // Remove the comment along with any whitespace preceding it.
+                let comment_start = usize::from(ignore.range.start());
+                let edit_start = contents[..comment_start]
+                    .trim_end_matches(|c| c == ' ' || c == '\t')
+                    .len();
+                Diagnostic::new(
+                    DiagnosticCode::UnusedIgnore,
+                    Severity::Warning,
+                    FileRange {
+                        file_id,
+                        range: ignore.range,
+                    },
+                    "Unused \"type: ignore\" comment",
+                )
+                .with_tag(DiagnosticTag::Unnecessary)
+                .with_fix(Fix {
+                    label: "Remove unused \"type: ignore\" comment".to_string(),
+                    edits: vec![SourceEdit {
+                        range: FileRange {
+                            file_id,
+                            range: TextRange::new(
+                                TextSize::new(edit_start as u32),
+                                ignore.range.end(),
+                            ),
+                        },
+                        new_text: String::new(),
+                    }],
+                })
+            })
+            .collect()
+    }
 }
 
 pub struct Semantics<'a> {
@@ -106,6 +220,7 @@ impl<'a> Semantics<'a> {
             }
             TyKind::BuiltinFunction(func) => (*func).into(),
             TyKind::Rule(_) => Callable(CallableInner::Rule(ty.ty.clone())),
+            TyKind::Macro(macro_) => Callable(CallableInner::Macro(macro_.clone())),
             TyKind::Provider(provider) => Callable(CallableInner::Provider(provider.clone())),
             TyKind::ProviderRawConstructor(name, provider) => Callable(
                 CallableInner::ProviderRawConstructor(name.clone(), provider.clone()),
@@ -128,6 +243,26 @@ impl<'a> Semantics<'a> {
         Some(with_tcx(self.db, |tcx| tcx.infer_param(file, *param)).into())
     }
 
+    /// Returns the fields of a provider or provider instance type. The types of the fields of
+    /// custom providers are inferred from the provider's construction sites.
+    pub fn provider_fields(&self, file: File, ty: &Type) -> Vec<(Field, Type)> {
+        let provider = match ty.ty.kind() {
+            TyKind::Provider(provider) | TyKind::ProviderInstance(provider) => provider.clone(),
+            _ => return Vec::new(),
+        };
+        let mut fields =
+            Type::from(TyKind::ProviderInstance(provider.clone()).intern()).fields(self.db);
+        if let Provider::Custom(_) = provider {
+            let field_tys = with_tcx(self.db, move |tcx| {
+                tcx.infer_custom_provider_field_tys(file, &provider)
+            });
+            for ((_, ty), field_ty) in fields.iter_mut().zip(field_tys.iter()) {
+                *ty = field_ty.clone().into();
+            }
+        }
+        fields
+    }
+
     pub fn resolve_load_stmt(&self, file: File, load_stmt: &ast::LoadStmt) -> Option<File> {
         let ptr = AstPtr::new(&ast::Statement::Load(load_stmt.clone()));
         let stmt = source_map(self.db, file).stmt_map.get(&ptr)?;
@@ -138,6 +273,13 @@ impl<'a> Semantics<'a> {
         with_tcx(self.db, |tcx| tcx.resolve_load_stmt(file, load_stmt))
     }
 
+    /// Resolves the `MODULE.bazel` segment included by an `include()` call.
+    pub fn resolve_include(&self, file: File, expr: &ast::CallExpr) -> Option<File> {
+        let ptr = AstPtr::new(&ast::Expression::Call(expr.clone()));
+        let expr = *source_map(self.db, file).expr_map.get(&ptr)?;
+        with_tcx(self.db, |tcx| tcx.resolve_include(file, expr))
+    }
+
     pub fn resolve_load_item(&self, file: File, load_item: &ast::LoadItem) -> Option<LoadItem> {
         let ptr = AstPtr::new(load_item);
         let load_item = source_map(self.db, file).load_item_map.get(&ptr)?;
@@ -177,6 +319,71 @@ impl<'a> Semantics<'a> {
         })
     }
 
+    /// Returns the targets declared by a BUILD file. Calls to macros are expanded into the targets
+    /// generated by the macros where possible.
+    pub fn targets(&self, file: File) -> &'a [Target] {
+        expanded_targets(self.db, file).targets(self.db)
+    }
+
+    pub fn target(&self, file: File, name: &str) -> Option<Target> {
+        let targets = expanded_targets(self.db, file);
+        let index = *targets.by_name(self.db).get(name)?;
+        targets.targets(self.db).get(index).cloned()
+    }
+
+    /// Returns the `packages` and `includes` arguments of the `package_group()` with the given
+    /// name in a BUILD file.
+    pub fn package_group(&self, file: File, name: &str) -> Option<PackageGroup> {
+        let group = file_targets(self.db, file)
+            .package_groups(self.db)
+            .get(name)?;
+        let strings = |values: &[Box<str>]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+        Some(PackageGroup {
+            packages: strings(&group.packages),
+            includes: strings(&group.includes),
+        })
+    }
+
+    fn expand_macro(&self, file: File, scope: &SemanticsScope, def: &TargetDef) -> Vec<Target> {
+        let func = match scope.resolve_name(&def.kind).into_iter().next() {
+            Some(ScopeDef::Callable(Callable(CallableInner::HirDef(it)))) => it.func,
+            Some(ScopeDef::LoadItem(load_item)) => match self.def_for_load_item(&load_item) {
+                Some(InFile {
+                    value: ScopeDef::Callable(Callable(CallableInner::HirDef(it))),
+                    ..
+                }) => it.func,
+                _ => return Vec::new(),
+            },
+            _ => return Vec::new(),
+        };
+
+        let macro_file = func.file(self.db);
+        let calls = match file_targets(self.db, macro_file)
+            .macros(self.db)
+            .get(&func.name(self.db))
+        {
+            Some(calls) => calls,
+            None => return Vec::new(),
+        };
+        let env = def.string_args.iter().cloned().collect::<FxHashMap<_, _>>();
+        let macro_module = module(self.db, macro_file);
+        calls
+            .iter()
+            .filter_map(|call| {
+                Some(Target {
+                    file,
+                    call: def.call,
+                    name_expr: def.name_expr,
+                    name: eval_string(self.db, macro_module, call.name_expr, &env)?.into(),
+                    kind: call.kind.clone(),
+                    generator: Some(def.kind.clone()),
+                })
+            })
+            .collect()
+    }
+
     pub fn def_for_load_item(&self, load_item: &LoadItem) -> Option<InFile<ScopeDef>> {
         let load_stmt = load_item.load_stmt(self.db)?;
         let loaded_file = self.resolve_load_stmt(load_item.file, &load_stmt)?;
@@ -191,6 +398,152 @@ impl<'a> Semantics<'a> {
     }
 }
 
+/// The targets declared by a BUILD file, with calls to macros expanded where possible.
+#[salsa::tracked]
+pub(crate) struct ExpandedTargets {
+    #[return_ref]
+    pub(crate) targets: Vec<Target>,
+    /// The index of each target in `targets`, keyed by name.
+    #[return_ref]
+    pub(crate) by_name: FxHashMap<Box<str>, usize>,
+}
+
+#[salsa::tracked]
+pub(crate) fn expanded_targets(db: &dyn Db, file: File) -> ExpandedTargets {
+    let sema = Semantics::new(db);
+    let scope = sema.scope_for_module(file);
+    let mut targets = Vec::new();
+    for def in file_targets(db, file).targets(db).iter() {
+        let start = targets.len();
+        targets.extend(sema.expand_macro(file, &scope, def));
+        if targets.len() == start {
+            targets.push(Target {
+                file,
+                call: def.call,
+                name_expr: def.name_expr,
+                name: def.name.clone(),
+                kind: def.kind.clone(),
+                generator: None,
+            });
+        }
+    }
+
+    // If a name is declared more than once, the first declaration wins.
+    let mut by_name = FxHashMap::default();
+    for (index, target) in targets.iter().enumerate() {
+        by_name.entry(target.name.clone()).or_insert(index);
+    }
+    ExpandedTargets::new(db, targets, by_name)
+}
+
+/// A target declared in a BUILD file, either directly or by a macro.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    file: File,
+    call: ExprId,
+    name_expr: ExprId,
+    name: Box<str>,
+    kind: Name,
+    generator: Option<Name>,
+}
+
+impl Target {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The rule that declares the target, e.g. `cc_library`. For targets generated by unexpanded
+    /// macros, this is the name of the macro.
+    pub fn kind(&self) -> &str {
+        self.kind.as_str()
+    }
+
+    /// The macro that generated the target, if any.
+    pub fn generator_function(&self) -> Option<&str> {
+        self.generator.as_ref().map(|name| name.as_str())
+    }
+
+    pub fn file(&self) -> File {
+        self.file
+    }
+
+    /// The top-level call that declares the target.
+    pub fn call_expr(&self, db: &dyn Db) -> Option<ast::CallExpr> {
+        source_map(db, self.file)
+            .expr_map_back
+            .get(&self.call)?
+            .clone()
+            .cast::<ast::CallExpr>()?
+            .try_to_node(&parse(db, self.file).syntax(db))
+    }
+
+    /// The range of the `name` argument's value in the top-level call that declares the target.
+    pub fn name_range(&self, db: &dyn Db) -> Option<TextRange> {
+        source_map(db, self.file)
+            .expr_map_back
+            .get(&self.name_expr)
+            .map(|ptr| ptr.syntax_node_ptr().text_range())
+    }
+
+    /// The visibility of the target, as declared by its `visibility` argument or by the
+    /// `default_visibility` of its package. Returns `None` if the visibility can't be determined,
+    /// e.g. for targets generated by macros.
+    pub fn visibility(&self, db: &dyn Db) -> Option<Visibility> {
+        if self.generator.is_some() {
+            return None;
+        }
+        let targets = file_targets(db, self.file);
+        let def = targets
+            .targets(db)
+            .iter()
+            .find(|def| def.call == self.call && def.name_expr == self.name_expr)?;
+
+        // Files exported without a `visibility` argument are public, regardless of the package's
+        // default visibility.
+        let (labels, expr) = match (&def.visibility, targets.default_visibility(db)) {
+            (None, _) if def.kind.as_str() == "exports_files" => (vec![PUBLIC.to_string()], None),
+            (Some(VisibilityDef { labels, expr }), _)
+            | (None, Some(VisibilityDef { labels, expr })) => (
+                labels
+                    .as_ref()?
+                    .iter()
+                    .map(|label| label.to_string())
+                    .collect(),
+                Some(*expr),
+            ),
+            (None, None) => (vec![PRIVATE.to_string()], None),
+        };
+        Some(Visibility {
+            file: self.file,
+            labels,
+            expr,
+        })
+    }
+}
+
+/// The visibility labels of a target, e.g. `["//visibility:public"]`.
+#[derive(Clone, Debug)]
+pub struct Visibility {
+    file: File,
+    labels: Vec<String>,
+    expr: Option<ExprId>,
+}
+
+impl Visibility {
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// The range of the list that declares the visibility, if any. Targets without a declared
+    /// visibility are private to their package.
+    pub fn range(&self, db: &dyn Db) -> Option<TextRange> {
+        source_map(db, self.file)
+            .expr_map_back
+            .get(&self.expr?)
+            .map(|ptr| ptr.syntax_node_ptr().text_range())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Variable {
     id: Option<(File, ExprId)>,
@@ -361,6 +714,8 @@ impl Type {
             || matches!(
                 self.ty.kind(),
                 TyKind::Rule(_)
+                    | TyKind::Macro(_)
+                    | TyKind::Subrule(_)
                     | TyKind::Provider(_)
                     | TyKind::ProviderRawConstructor(_, _)
                     | TyKind::Tag(_)
@@ -371,6 +726,10 @@ impl Type {
         self.ty.kind() == &TyKind::Unknown
     }
 
+    pub fn is_provider(&self) -> bool {
+        matches!(self.ty.kind(), TyKind::Provider(_))
+    }
+
     pub fn is_user_defined_function(&self) -> bool {
         matches!(self.ty.kind(), TyKind::Function(_))
     }
@@ -389,6 +748,8 @@ impl Type {
             TyKind::Function(def) => def.func.doc(db).map(|doc| doc.to_string()),
             TyKind::IntrinsicFunction(func, _) => Some(func.doc(db).clone()),
             TyKind::Rule(rule) => rule.doc.as_ref().map(Box::to_string),
+            TyKind::Aspect(aspect) => aspect.doc.as_ref().map(Box::to_string),
+            TyKind::Macro(macro_) => macro_.doc.as_ref().map(Box::to_string),
             TyKind::Provider(provider) | TyKind::ProviderInstance(provider) => provider.doc(db),
             TyKind::ModuleExtension(module_extension)
             | TyKind::ModuleExtensionProxy(module_extension) => {
@@ -501,6 +862,7 @@ impl Callable {
             CallableInner::IntrinsicFunction(func, _) => func.name(db),
             CallableInner::BuiltinFunction(func) => func.name(db),
             CallableInner::Rule(_) => Name::new_inline("rule"),
+            CallableInner::Macro(_) => Name::new_inline("macro"),
             CallableInner::Provider(ref provider) => provider
                 .name(db)
                 .cloned()
@@ -526,6 +888,7 @@ impl Callable {
             .intern(),
             CallableInner::BuiltinFunction(func) => TyKind::BuiltinFunction(func).intern(),
             CallableInner::Rule(ref ty) => ty.clone(),
+            CallableInner::Macro(ref macro_) => TyKind::Macro(macro_.clone()).intern(),
             CallableInner::Provider(ref provider) => TyKind::Provider(provider.clone()).intern(),
             CallableInner::ProviderRawConstructor(ref name, ref provider) => {
                 TyKind::ProviderRawConstructor(name.clone(), provider.clone()).intern()
@@ -552,6 +915,7 @@ impl Callable {
                 TyKind::Rule(rule) => rule.doc.as_ref().map(Box::to_string),
                 _ => None,
             },
+            CallableInner::Macro(ref macro_) => macro_.doc.as_ref().map(Box::to_string),
             CallableInner::Provider(ref provider)
             | CallableInner::ProviderRawConstructor(_, ref provider) => match provider {
                 Provider::Builtin(provider) => Some(provider.doc(db).clone()),
@@ -569,6 +933,10 @@ impl Callable {
         matches!(self.0, CallableInner::Rule(_))
     }
 
+    pub fn is_macro(&self) -> bool {
+        matches!(self.0, CallableInner::Macro(_))
+    }
+
     pub fn is_tag(&self) -> bool {
         matches!(self.0, CallableInner::Tag(_))
     }
@@ -592,6 +960,7 @@ enum CallableInner {
     IntrinsicFunction(IntrinsicFunction, Option<Substitution>),
     BuiltinFunction(BuiltinFunction),
     Rule(Ty),
+    Macro(Macro),
     Provider(Provider),
     ProviderRawConstructor(Name, Provider),
     Tag(Arc<TagClass>),
@@ -1,10 +1,10 @@
-use std::{collections::HashSet, fmt, ops::Index};
+use std::{fmt, ops::Index};
 
 use either::Either;
 use id_arena::{Arena, Id};
 use rustc_hash::FxHashMap;
 use smol_str::SmolStr;
-use starpls_common::File;
+use starpls_common::{DiagnosticCode, File};
 use starpls_syntax::{
     ast::{self, AssignOp, AstPtr, BinaryOp, SyntaxNodePtr, UnaryOp},
     TextRange,
@@ -12,10 +12,12 @@ use starpls_syntax::{
 
 use crate::{typeck::TypeRef, Db};
 
+pub(crate) mod checks;
 pub(crate) mod codeflow;
 mod lower;
 pub(crate) mod resolver;
 pub(crate) mod scope;
+pub(crate) mod targets;
 
 #[cfg(test)]
 mod tests;
@@ -41,11 +43,30 @@ pub(crate) struct Module {
     pub(crate) params: Arena<Param>,
     pub(crate) load_items: Arena<LoadItem>,
     pub(crate) top_level: Box<[StmtId]>,
-    pub(crate) type_ignore_comment_lines: HashSet<u32>,
+    pub(crate) type_ignores: FxHashMap<u32, TypeIgnore>,
+    pub(crate) file_type_ignore: Option<TypeIgnore>,
     pub(crate) call_expr_with_impl_fn: FxHashMap<Name, ExprId>,
+    pub(crate) call_expr_with_map_each_fn: FxHashMap<Name, ExprId>,
     pub(crate) param_to_def_stmt: FxHashMap<ParamId, StmtId>,
 }
 
+/// A `# type: ignore` comment. If `codes` is present, only diagnostics with one of the listed codes
+/// are suppressed, e.g. `# type: ignore[arg-type, undefined-name]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TypeIgnore {
+    pub(crate) range: TextRange,
+    pub(crate) codes: Option<Box<[SmolStr]>>,
+}
+
+impl TypeIgnore {
+    pub(crate) fn suppresses(&self, code: DiagnosticCode) -> bool {
+        match &self.codes {
+            Some(codes) => codes.iter().any(|c| c == code.as_str()),
+            None => true,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct ModuleSourceMap {
     pub root: ModulePtr,
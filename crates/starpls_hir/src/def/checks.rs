@@ -0,0 +1,384 @@
+use std::collections::hash_map::Entry;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use starpls_bazel::APIContext;
+use starpls_common::{
+    parse, Diagnostic, DiagnosticCode, File, FileRange, RelatedInformation, Severity,
+};
+use starpls_syntax::{
+    ast::{self, AstNode},
+    TextRange,
+};
+
+use crate::{
+    def::{
+        resolver::Resolver, scope::ScopeDef, Expr, ExprId, LoadItem, Module, ModuleSourceMap, Stmt,
+        StmtId,
+    },
+    module, source_map,
+    typeck::with_tcx,
+    Db, Name, TyKind,
+};
+
+/// Methods that mutate their receiver. Calling any of these on a frozen value fails at runtime.
+const MUTATING_METHODS: &[&str] = &[
+    "append",
+    "clear",
+    "extend",
+    "insert",
+    "pop",
+    "popitem",
+    "remove",
+    "setdefault",
+    "update",
+];
+
+#[salsa::tracked]
+pub(crate) struct ModuleChecks {
+    #[return_ref]
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// Mutations of values loaded from other modules, along with the receivers being mutated.
+    /// These are only errors if the receiver is a list or dict, which depends on type inference
+    /// and so is determined by `frozen_mutation_diagnostics`.
+    #[return_ref]
+    pub(crate) loaded_value_mutations: Vec<(ExprId, Diagnostic)>,
+}
+
+/// Checks a file for violations of Starlark's static rules that don't depend on type inference,
+/// i.e. recursion, reassignment of globals and mutation of values loaded from other modules.
+#[salsa::tracked]
+pub(crate) fn check_module(db: &dyn Db, file: File) -> ModuleChecks {
+    let mut cx = CheckCtx {
+        db,
+        file,
+        module: module(db, file),
+        source_map: source_map(db, file),
+        diagnostics: Vec::new(),
+        loaded_value_mutations: Vec::new(),
+    };
+    cx.check_recursion();
+
+    // Bazel rejects rebinding a global in the same `.bzl` module, but allows top-level rebinding in
+    // BUILD, WORKSPACE and `MODULE.bazel` files. Other interpreters can be configured to allow it
+    // everywhere, which is mirrored by the `allow_global_reassignment` option.
+    if file.api_context(db) == Some(APIContext::Bzl)
+        && !db.gcx().options().allow_global_reassignment
+    {
+        cx.check_global_reassignment();
+    }
+
+    cx.check_frozen_mutation();
+    ModuleChecks::new(db, cx.diagnostics, cx.loaded_value_mutations)
+}
+
+/// Reports the mutations of loaded values found by `check_module` whose receivers are lists or
+/// dicts. Other values, e.g. structs returned by `struct()`, may have methods with the same names
+/// that don't mutate anything.
+pub(crate) fn frozen_mutation_diagnostics(db: &dyn Db, file: File) -> Vec<Diagnostic> {
+    check_module(db, file)
+        .loaded_value_mutations(db)
+        .iter()
+        .filter(|(receiver, _)| {
+            matches!(
+                with_tcx(db, |tcx| tcx.infer_expr(file, *receiver)).kind(),
+                TyKind::List(_) | TyKind::Dict(..)
+            )
+        })
+        .map(|(_, diagnostic)| diagnostic.clone())
+        .collect()
+}
+
+struct CheckCtx<'a> {
+    db: &'a dyn Db,
+    file: File,
+    module: &'a Module,
+    source_map: &'a ModuleSourceMap,
+    diagnostics: Vec<Diagnostic>,
+    loaded_value_mutations: Vec<(ExprId, Diagnostic)>,
+}
+
+impl CheckCtx<'_> {
+    fn check_recursion(&mut self) {
+        // Build the call graph for the functions defined in this file. Each function maps to the
+        // calls in its body, along with the functions that those calls resolve to.
+        let module = self.module;
+        let mut calls: FxHashMap<StmtId, Vec<(ExprId, StmtId)>> = FxHashMap::default();
+        for (stmt, data) in module.stmts.iter() {
+            if let Stmt::Def { stmts, .. } = data {
+                let mut callees = Vec::new();
+                self.collect_calls_in_stmts(stmts, &mut callees);
+                calls.insert(stmt, callees);
+            }
+        }
+
+        // Report each call that leads back to the calling function.
+        let mut callers = calls.keys().copied().collect::<Vec<_>>();
+        callers.sort_by_key(|stmt| stmt.index());
+        for caller in callers {
+            for (call, callee) in calls[&caller].iter() {
+                let message = if *callee == caller {
+                    format!(
+                        "Function \"{}\" calls itself recursively",
+                        self.function_name(caller).as_str()
+                    )
+                } else if reaches(&calls, *callee, caller) {
+                    format!(
+                        "Call to \"{}\" results in a recursive call to \"{}\"",
+                        self.function_name(*callee).as_str(),
+                        self.function_name(caller).as_str()
+                    )
+                } else {
+                    continue;
+                };
+                if let Some(range) = self.expr_range(*call) {
+                    self.push_error(DiagnosticCode::Recursion, range, message);
+                }
+            }
+        }
+    }
+
+    fn check_global_reassignment(&mut self) {
+        let module = self.module;
+        let mut bindings: FxHashMap<Name, TextRange> = FxHashMap::default();
+        for stmt in module.top_level.iter().copied() {
+            let mut names = Vec::new();
+            match &module[stmt] {
+                Stmt::Def { func, .. } => {
+                    if let Some(range) = self.def_name_range(stmt) {
+                        names.push((func.name(self.db), range));
+                    }
+                }
+                Stmt::Assign { lhs, .. } => self.collect_assigned_names(*lhs, &mut names),
+                Stmt::Load { items, .. } => {
+                    for item in items.iter() {
+                        let name: &str = match &module[*item] {
+                            LoadItem::Direct { name, .. } => name,
+                            LoadItem::Aliased { alias, .. } => alias.as_str(),
+                        };
+                        if let Some(ptr) = self.source_map.load_item_map_back.get(item) {
+                            names.push((Name::from_str(name), ptr.syntax_node_ptr().text_range()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            for (name, range) in names {
+                match bindings.entry(name) {
+                    Entry::Occupied(entry) => {
+                        let diagnostic = Diagnostic::new(
+                            DiagnosticCode::GlobalReassignment,
+                            Severity::Error,
+                            self.file_range(range),
+                            format!("Cannot reassign global \"{}\"", entry.key().as_str()),
+                        )
+                        .with_related(RelatedInformation {
+                            message: format!("Previous binding of \"{}\"", entry.key().as_str()),
+                            range: self.file_range(*entry.get()),
+                        });
+                        self.diagnostics.push(diagnostic);
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(range);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_frozen_mutation(&mut self) {
+        let module = self.module;
+
+        // Check for calls like `x.append(...)`.
+        for (expr, data) in module.exprs.iter() {
+            let receiver = match data {
+                Expr::Call { callee, .. } => match &module[*callee] {
+                    Expr::Dot { expr, field } if MUTATING_METHODS.contains(&field.as_str()) => {
+                        *expr
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            self.check_loaded_value_mutation(expr, receiver);
+        }
+
+        // Check for assignments like `x[0] = ...`.
+        for (_, data) in module.stmts.iter() {
+            if let Stmt::Assign { lhs, .. } = data {
+                if let Expr::Index { lhs: receiver, .. } = &module[*lhs] {
+                    self.check_loaded_value_mutation(*lhs, *receiver);
+                }
+            }
+        }
+    }
+
+    fn check_loaded_value_mutation(&mut self, expr: ExprId, receiver: ExprId) {
+        let name = match &self.module[receiver] {
+            Expr::Name { name } => name,
+            _ => return,
+        };
+        let resolver = Resolver::new_for_expr(self.db, self.file, receiver);
+        let is_loaded = resolver
+            .resolve_name(name)
+            .and_then(|(_, defs)| defs.last())
+            .is_some_and(|def| matches!(def.def, ScopeDef::LoadItem(_)));
+        if !is_loaded {
+            return;
+        }
+
+        if let Some(range) = self.expr_range(expr) {
+            let diagnostic = Diagnostic::new(
+                DiagnosticCode::FrozenMutation,
+                Severity::Error,
+                self.file_range(range),
+                format!(
+                    "Cannot mutate \"{}\", which is frozen because it was loaded from another module",
+                    name.as_str()
+                ),
+            );
+            self.loaded_value_mutations.push((receiver, diagnostic));
+        }
+    }
+
+    fn collect_calls_in_stmts(&self, stmts: &[StmtId], calls: &mut Vec<(ExprId, StmtId)>) {
+        for stmt in stmts.iter().copied() {
+            match &self.module[stmt] {
+                // Calls in nested functions are attributed to those functions instead.
+                Stmt::Def { .. } => {}
+                Stmt::If {
+                    test,
+                    if_stmts,
+                    elif_or_else_stmts,
+                } => {
+                    self.collect_calls_in_expr(*test, calls);
+                    self.collect_calls_in_stmts(if_stmts, calls);
+                    match elif_or_else_stmts {
+                        Some(either::Either::Left(elif_stmt)) => {
+                            self.collect_calls_in_stmts(&[*elif_stmt], calls)
+                        }
+                        Some(either::Either::Right(else_stmts)) => {
+                            self.collect_calls_in_stmts(else_stmts, calls)
+                        }
+                        None => {}
+                    }
+                }
+                Stmt::For {
+                    iterable, stmts, ..
+                } => {
+                    self.collect_calls_in_expr(*iterable, calls);
+                    self.collect_calls_in_stmts(stmts, calls);
+                }
+                Stmt::Return { expr: Some(expr) } | Stmt::Expr { expr } => {
+                    self.collect_calls_in_expr(*expr, calls);
+                }
+                Stmt::Assign { lhs, rhs, .. } => {
+                    self.collect_calls_in_expr(*lhs, calls);
+                    self.collect_calls_in_expr(*rhs, calls);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn collect_calls_in_expr(&self, expr: ExprId, calls: &mut Vec<(ExprId, StmtId)>) {
+        match &self.module[expr] {
+            // Lambda bodies aren't evaluated until the lambda is called.
+            Expr::Lambda { .. } => return,
+            Expr::Call { callee, .. } => {
+                if let Some(func) = self.resolve_function(*callee) {
+                    calls.push((expr, func));
+                }
+            }
+            _ => {}
+        }
+        self.module[expr].walk_child_exprs(|expr| self.collect_calls_in_expr(expr, calls));
+    }
+
+    fn resolve_function(&self, callee: ExprId) -> Option<StmtId> {
+        let name = match &self.module[callee] {
+            Expr::Name { name } => name,
+            _ => return None,
+        };
+        let resolver = Resolver::new_for_expr(self.db, self.file, callee);
+        let (_, defs) = resolver.resolve_name(name)?;
+        match defs.last()?.def {
+            ScopeDef::Function(def) if def.stmt.file == self.file => Some(def.stmt.value),
+            _ => None,
+        }
+    }
+
+    fn collect_assigned_names(&self, expr: ExprId, names: &mut Vec<(Name, TextRange)>) {
+        match &self.module[expr] {
+            Expr::Name { name } => {
+                if let Some(range) = self.expr_range(expr) {
+                    names.push((name.clone(), range));
+                }
+            }
+            Expr::Tuple { exprs } | Expr::List { exprs } => {
+                for expr in exprs.iter() {
+                    self.collect_assigned_names(*expr, names);
+                }
+            }
+            Expr::Paren { expr } => self.collect_assigned_names(*expr, names),
+            _ => {}
+        }
+    }
+
+    fn function_name(&self, stmt: StmtId) -> Name {
+        match &self.module[stmt] {
+            Stmt::Def { func, .. } => func.name(self.db),
+            _ => Name::missing(),
+        }
+    }
+
+    fn def_name_range(&self, stmt: StmtId) -> Option<TextRange> {
+        let root = parse(self.db, self.file).syntax(self.db);
+        let ptr = self.source_map.stmt_map_back.get(&stmt)?;
+        match ptr.try_to_node(&root)? {
+            ast::Statement::Def(def_stmt) => Some(def_stmt.name()?.syntax().text_range()),
+            _ => None,
+        }
+    }
+
+    fn expr_range(&self, expr: ExprId) -> Option<TextRange> {
+        self.source_map
+            .expr_map_back
+            .get(&expr)
+            .map(|ptr| ptr.syntax_node_ptr().text_range())
+    }
+
+    fn file_range(&self, range: TextRange) -> FileRange {
+        FileRange {
+            file_id: self.file.id(self.db),
+            range,
+        }
+    }
+
+    fn push_error(&mut self, code: DiagnosticCode, range: TextRange, message: String) {
+        self.diagnostics.push(Diagnostic::new(
+            code,
+            Severity::Error,
+            self.file_range(range),
+            message,
+        ));
+    }
+}
+
+/// Determines whether `to` can be reached from `from` in the given call graph.
+fn reaches(calls: &FxHashMap<StmtId, Vec<(ExprId, StmtId)>>, from: StmtId, to: StmtId) -> bool {
+    let mut visited = FxHashSet::default();
+    let mut stack = vec![from];
+    while let Some(func) = stack.pop() {
+        if func == to {
+            return true;
+        }
+        if !visited.insert(func) {
+            continue;
+        }
+        if let Some(callees) = calls.get(&func) {
+            stack.extend(callees.iter().map(|(_, callee)| *callee));
+        }
+    }
+    false
+}
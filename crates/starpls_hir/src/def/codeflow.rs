@@ -1,14 +1,20 @@
+use std::mem;
+
 use either::Either;
 use id_arena::{Arena, Id};
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use starpls_common::File;
+use starpls_syntax::ast::{BinaryOp, CmpOp, LogicOp, UnaryOp};
 
 use crate::{
     def::{
-        scope::{module_scopes, ExecutionScopeId, ScopeHirId, Scopes},
-        CompClause, Expr, Stmt, StmtId,
+        resolver::Resolver,
+        scope::{module_scopes, ExecutionScopeId, ScopeDef, ScopeHirId, Scopes},
+        Argument, CompClause, Expr, Literal, LiteralString, Stmt, StmtId,
     },
-    lower, Db, ExprId, Module, Name,
+    lower,
+    typeck::{resolve_builtin_type_ref, TyKind},
+    Db, ExprId, Module, Name,
 };
 
 #[allow(unused)]
@@ -32,17 +38,111 @@ pub(crate) enum FlowNode {
     Loop {
         antecedents: Vec<FlowNodeId>,
     },
+    /// Marks the point after a condition has been evaluated, where the condition is known to
+    /// have been either truthy or falsy. Used to narrow the types of the names referenced by the
+    /// condition.
+    Condition {
+        expr: ExprId,
+        is_true: bool,
+        execution_scope: ExecutionScopeId,
+        antecedent: FlowNodeId,
+    },
     Unreachable,
 }
 
+/// A condition that narrows the type of a name when it is known to be truthy or falsy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Narrowing<'a> {
+    /// The name expression whose type is narrowed.
+    pub(crate) subject: ExprId,
+    pub(crate) name: &'a Name,
+    pub(crate) kind: NarrowingKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum NarrowingKind {
+    /// `x`
+    Truthiness,
+    /// `x == None`, or `x != None` if `negated` is set.
+    IsNone { negated: bool },
+    /// `type(x) == "string"`, or `type(x) != "string"` if `negated` is set. The narrowing only
+    /// applies if `callee` resolves to the builtin `type()` function.
+    TypeIs {
+        callee: ExprId,
+        type_name: LiteralString,
+        negated: bool,
+    },
+}
+
+/// Determines whether the given expression is a condition that can be used to narrow the type of
+/// a name, e.g. `x != None`.
+pub(crate) fn narrowing_for_condition(module: &Module, expr: ExprId) -> Option<Narrowing<'_>> {
+    let narrowing = move |subject: ExprId, kind| match &module[subject] {
+        Expr::Name { name } => Some(Narrowing {
+            subject,
+            name,
+            kind,
+        }),
+        _ => None,
+    };
+
+    match &module[expr] {
+        Expr::Name { .. } => narrowing(expr, NarrowingKind::Truthiness),
+        Expr::Paren { expr } => narrowing_for_condition(module, *expr),
+        Expr::Binary {
+            lhs,
+            rhs,
+            op: Some(BinaryOp::Cmp(op @ (CmpOp::Eq | CmpOp::Ne))),
+        } => {
+            let negated = *op == CmpOp::Ne;
+            let (lhs, rhs) = (*lhs, *rhs);
+            [(lhs, rhs), (rhs, lhs)]
+                .into_iter()
+                .find_map(|(subject, other)| match &module[other] {
+                    Expr::Literal {
+                        literal: Literal::None,
+                    } => narrowing(subject, NarrowingKind::IsNone { negated }),
+                    Expr::Literal {
+                        literal: Literal::String(type_name),
+                    } => match &module[subject] {
+                        Expr::Call { callee, args } => match (&module[*callee], &args[..]) {
+                            (Expr::Name { name }, [Argument::Simple { expr }])
+                                if name.as_str() == "type" =>
+                            {
+                                narrowing(
+                                    *expr,
+                                    NarrowingKind::TypeIs {
+                                        callee: *callee,
+                                        type_name: *type_name,
+                                        negated,
+                                    },
+                                )
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    },
+                    _ => None,
+                })
+        }
+        _ => None,
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct CodeFlowGraph {
     pub(crate) flow_nodes: Arena<FlowNode>,
     pub(crate) hir_to_flow_node: FxHashMap<ScopeHirId, FlowNodeId>,
+    /// The first and last statements of each run of unreachable statements.
+    pub(crate) unreachable_stmts: Vec<(StmtId, StmtId)>,
+    /// The statements defining functions whose calls never return.
+    pub(crate) no_return_functions: FxHashSet<StmtId>,
 }
 
 #[allow(unused)]
 struct CodeFlowLowerCtx<'a> {
+    db: &'a dyn Db,
+    file: File,
     module: &'a Module,
     scopes: &'a Scopes,
     result: CodeFlowGraph,
@@ -50,18 +150,27 @@ struct CodeFlowLowerCtx<'a> {
     unreachable_node: FlowNodeId,
     curr_break_target: Option<FlowNodeId>,
     curr_continue_target: Option<FlowNodeId>,
+    /// Joins the flow from all reachable `return` statements in the function currently being
+    /// lowered. Allocated when the first such statement is seen.
+    curr_return_target: Option<FlowNodeId>,
+    /// Whether calls to the function defined by the given statement never return.
+    no_return_functions: FxHashMap<StmtId, bool>,
 }
 
 impl<'a> CodeFlowLowerCtx<'a> {
-    fn new(module: &'a Module, scopes: &'a Scopes) -> Self {
+    fn new(db: &'a dyn Db, file: File, module: &'a Module, scopes: &'a Scopes) -> Self {
         let mut flow_nodes = Arena::new();
         let unreachable_node = flow_nodes.alloc(FlowNode::Unreachable);
         let curr_node = flow_nodes.alloc(FlowNode::Start);
         let cfg = CodeFlowGraph {
             flow_nodes,
             hir_to_flow_node: Default::default(),
+            unreachable_stmts: Vec::new(),
+            no_return_functions: Default::default(),
         };
         CodeFlowLowerCtx {
+            db,
+            file,
             module,
             scopes,
             result: cfg,
@@ -69,20 +178,25 @@ impl<'a> CodeFlowLowerCtx<'a> {
             unreachable_node,
             curr_break_target: None,
             curr_continue_target: None,
+            curr_return_target: None,
+            no_return_functions: Default::default(),
         }
     }
 
     fn lower_stmts(&mut self, stmts: &[StmtId]) {
         // Lower each statement in the list, stopping if we see unreachable code.
         for stmt in stmts {
-            self.lower_stmt(*stmt);
-
             // If we find ourselves at an unreachable flow node, all remaining statements
             // are unreachable. Unreachable statements in general are not represented
-            // in the code flow graph, so we can simply exit here.
+            // in the code flow graph, so we can simply record them and exit here.
             if self.curr_node == self.unreachable_node {
+                self.result
+                    .unreachable_stmts
+                    .push((*stmt, *stmts.last().unwrap()));
                 break;
             }
+
+            self.lower_stmt(*stmt);
         }
     }
 
@@ -96,10 +210,19 @@ impl<'a> CodeFlowLowerCtx<'a> {
             }
 
             Stmt::Def { stmts, .. } => {
+                let saved_return_target = self.curr_return_target.take();
                 self.with_new_start_node(|this| {
                     this.lower_stmts(stmts);
+                    let no_return = this.curr_node == this.unreachable_node
+                        && this.curr_return_target.is_none();
+                    this.no_return_functions.entry(stmt).or_insert(no_return);
+                    if let Some(return_target) = this.curr_return_target {
+                        this.push_antecedent(return_target, this.curr_node);
+                        this.curr_node = return_target;
+                    }
                     stmt
                 });
+                self.curr_return_target = saved_return_target;
                 self.result
                     .hir_to_flow_node
                     .insert(stmt.into(), self.curr_node);
@@ -110,35 +233,57 @@ impl<'a> CodeFlowLowerCtx<'a> {
                 if_stmts,
                 elif_or_else_stmts,
             } => {
-                self.lower_expr(*test);
-
-                let pre_if_node = self.curr_node;
+                let (true_node, false_node) = self.lower_condition(*test);
                 let post_if_node = self.new_flow_node(FlowNode::Branch {
                     antecedents: Vec::new(),
                 });
+                self.curr_node = true_node;
                 self.lower_stmts(if_stmts);
                 self.push_antecedent(post_if_node, self.curr_node);
                 match elif_or_else_stmts {
                     Some(Either::Left(elif_stmt)) => {
-                        self.curr_node = pre_if_node;
+                        self.curr_node = false_node;
                         self.lower_stmt(*elif_stmt);
                         self.push_antecedent(post_if_node, self.curr_node);
                     }
                     Some(Either::Right(else_stmts)) => {
-                        self.curr_node = pre_if_node;
+                        self.curr_node = false_node;
                         self.lower_stmts(else_stmts);
                         self.push_antecedent(post_if_node, self.curr_node);
                     }
                     _ => {
-                        self.push_antecedent(post_if_node, pre_if_node);
+                        self.push_antecedent(post_if_node, false_node);
                     }
                 }
 
-                self.curr_node = post_if_node;
+                // If none of the branches fall through, then the code following the `if`
+                // statement is unreachable.
+                self.curr_node = match &self.result.flow_nodes[post_if_node] {
+                    FlowNode::Branch { antecedents } if antecedents.is_empty() => {
+                        self.unreachable_node
+                    }
+                    _ => post_if_node,
+                };
             }
 
-            Stmt::Return { expr: Some(expr) } => {
-                self.lower_expr(*expr);
+            Stmt::Return { expr } => {
+                if let Some(expr) = expr {
+                    self.lower_expr(*expr);
+                }
+                if self.curr_node != self.unreachable_node {
+                    let return_target = match self.curr_return_target {
+                        Some(return_target) => return_target,
+                        None => {
+                            let return_target = self.new_flow_node(FlowNode::Branch {
+                                antecedents: Vec::new(),
+                            });
+                            self.curr_return_target = Some(return_target);
+                            return_target
+                        }
+                    };
+                    self.push_antecedent(return_target, self.curr_node);
+                }
+                self.curr_node = self.unreachable_node;
             }
 
             Stmt::Expr { expr } => {
@@ -220,14 +365,128 @@ impl<'a> CodeFlowLowerCtx<'a> {
                 self.lower_comp_clauses(comp_clauses);
                 self.lower_expr(*expr);
             }
+            Expr::Call { callee, args } => {
+                self.lower_expr(*callee);
+                for arg in args.iter() {
+                    match arg {
+                        Argument::Simple { expr }
+                        | Argument::Keyword { expr, .. }
+                        | Argument::UnpackedList { expr }
+                        | Argument::UnpackedDict { expr } => self.lower_expr(*expr),
+                    }
+                }
+
+                // Calls to functions that never return, e.g. `fail()`, terminate the current flow.
+                if self.is_no_return_callee(*callee) {
+                    self.curr_node = self.unreachable_node;
+                }
+            }
+            Expr::Lambda { body, .. } => {
+                // The body of a lambda is only evaluated when the lambda is called, so it
+                // doesn't affect the flow of the enclosing scope.
+                let saved_curr_node = self.curr_node;
+                self.lower_expr(*body);
+                self.curr_node = saved_curr_node;
+            }
+            Expr::If {
+                if_expr,
+                test,
+                else_expr,
+            } => {
+                let (true_node, false_node) = self.lower_condition(*test);
+                self.curr_node = true_node;
+                self.lower_expr(*if_expr);
+                let post_if_expr_node = self.curr_node;
+                self.curr_node = false_node;
+                self.lower_expr(*else_expr);
+                self.curr_node = self.join(post_if_expr_node, self.curr_node);
+            }
+            Expr::Binary {
+                lhs,
+                rhs,
+                op: Some(BinaryOp::Logic(op)),
+            } => {
+                // The right-hand side of `and` is only evaluated if the left-hand side is truthy,
+                // and the right-hand side of `or` is only evaluated if it is falsy.
+                let (true_node, false_node) = self.lower_condition(*lhs);
+                let (rhs_node, short_circuit_node) = match op {
+                    LogicOp::And => (true_node, false_node),
+                    LogicOp::Or => (false_node, true_node),
+                };
+                self.curr_node = rhs_node;
+                self.lower_expr(*rhs);
+                self.curr_node = self.join(self.curr_node, short_circuit_node);
+            }
             expr => expr.walk_child_exprs(|expr| {
                 self.lower_expr(expr);
             }),
         }
     }
 
+    /// Lowers a condition, e.g. the test of an `if` statement. Returns the flow nodes to continue
+    /// from when the condition is truthy and when it is falsy, respectively.
+    fn lower_condition(&mut self, expr: ExprId) -> (FlowNodeId, FlowNodeId) {
+        match &self.module[expr] {
+            Expr::Paren { expr } => self.lower_condition(*expr),
+            Expr::Unary {
+                op: Some(UnaryOp::Not),
+                expr,
+            } => {
+                let (true_node, false_node) = self.lower_condition(*expr);
+                (false_node, true_node)
+            }
+            Expr::Binary {
+                lhs,
+                rhs,
+                op: Some(BinaryOp::Logic(LogicOp::And)),
+            } => {
+                let (lhs_true_node, lhs_false_node) = self.lower_condition(*lhs);
+                self.curr_node = lhs_true_node;
+                let (rhs_true_node, rhs_false_node) = self.lower_condition(*rhs);
+                (rhs_true_node, self.join(lhs_false_node, rhs_false_node))
+            }
+            Expr::Binary {
+                lhs,
+                rhs,
+                op: Some(BinaryOp::Logic(LogicOp::Or)),
+            } => {
+                let (lhs_true_node, lhs_false_node) = self.lower_condition(*lhs);
+                self.curr_node = lhs_false_node;
+                let (rhs_true_node, rhs_false_node) = self.lower_condition(*rhs);
+                (self.join(lhs_true_node, rhs_true_node), rhs_false_node)
+            }
+            _ => {
+                self.lower_expr(expr);
+
+                // Only allocate condition nodes for conditions that can actually narrow types.
+                // Conditions are also irrelevant if we're already in unreachable code.
+                let narrowing = match narrowing_for_condition(self.module, expr) {
+                    Some(narrowing) if self.curr_node != self.unreachable_node => narrowing,
+                    _ => return (self.curr_node, self.curr_node),
+                };
+
+                let execution_scope = self
+                    .scopes
+                    .execution_scope_for_hir_id(narrowing.subject)
+                    .unwrap();
+                let mut new_condition_node = |is_true| {
+                    self.new_flow_node(FlowNode::Condition {
+                        expr,
+                        is_true,
+                        execution_scope,
+                        antecedent: self.curr_node,
+                    })
+                };
+                (new_condition_node(true), new_condition_node(false))
+            }
+        }
+    }
+
     fn lower_assignment_target(&mut self, expr: ExprId, source: ExprId) {
         self.lower_expr(source);
+        if self.curr_node == self.unreachable_node {
+            return;
+        }
         match &self.module[expr] {
             Expr::Name { ref name } => {
                 let assign_node = self.new_flow_node(FlowNode::Assign {
@@ -266,10 +525,70 @@ impl<'a> CodeFlowLowerCtx<'a> {
                     }
                 }
                 CompClause::If { test } => {
-                    self.lower_expr(*test);
+                    self.curr_node = self.lower_condition(*test).0;
+                }
+            }
+        }
+    }
+
+    fn is_no_return_callee(&mut self, callee: ExprId) -> bool {
+        let name = match &self.module[callee] {
+            Expr::Name { name } => name,
+            _ => return false,
+        };
+
+        let resolver = Resolver::new_for_expr(self.db, self.file, callee);
+        if let Some((_, defs)) = resolver.resolve_name(name) {
+            return match defs.last().map(|def| def.def) {
+                Some(ScopeDef::Function(def)) if def.stmt.file == self.file => {
+                    self.is_no_return_function(def.stmt.value)
                 }
+                _ => false,
+            };
+        }
+
+        match resolver
+            .resolve_name_in_prelude_or_builtins(name)
+            .as_deref()
+        {
+            Some([ScopeDef::IntrinsicFunction(func), ..]) => {
+                func.ret_ty(self.db).kind() == &TyKind::Never
+            }
+            Some([ScopeDef::BuiltinFunction(func), ..]) => {
+                resolve_builtin_type_ref(self.db, func.ret_type_ref(self.db))
+                    .0
+                    .kind()
+                    == &TyKind::Never
             }
+            _ => false,
+        }
+    }
+
+    /// Determines whether the function defined by the given statement never returns, i.e. if
+    /// every path through its body ends in a call to another function that never returns.
+    fn is_no_return_function(&mut self, stmt: StmtId) -> bool {
+        if let Some(no_return) = self.no_return_functions.get(&stmt) {
+            return *no_return;
         }
+
+        let stmts = match &self.module[stmt] {
+            Stmt::Def { stmts, .. } => stmts,
+            _ => return false,
+        };
+
+        // Assume that the function returns while its body is being analyzed, so that
+        // recursive calls don't loop forever.
+        self.no_return_functions.insert(stmt, false);
+
+        // Lower the function body into a separate graph, since we only care about whether the
+        // end of the body is reachable.
+        let mut cx = CodeFlowLowerCtx::new(self.db, self.file, self.module, self.scopes);
+        cx.no_return_functions = mem::take(&mut self.no_return_functions);
+        cx.lower_stmts(stmts);
+        let no_return = cx.curr_node == cx.unreachable_node && cx.curr_return_target.is_none();
+        self.no_return_functions = cx.no_return_functions;
+        self.no_return_functions.insert(stmt, no_return);
+        no_return
     }
 
     fn new_flow_node(&mut self, data: FlowNode) -> FlowNodeId {
@@ -292,6 +611,19 @@ impl<'a> CodeFlowLowerCtx<'a> {
         }
     }
 
+    /// Joins two flow nodes with a new branch node, unless they are the same node.
+    fn join(&mut self, first: FlowNodeId, second: FlowNodeId) -> FlowNodeId {
+        if first == second {
+            return first;
+        }
+        let branch_node = self.new_flow_node(FlowNode::Branch {
+            antecedents: Vec::new(),
+        });
+        self.push_antecedent(branch_node, first);
+        self.push_antecedent(branch_node, second);
+        branch_node
+    }
+
     fn with_new_start_node<F, T>(&mut self, mut f: F)
     where
         F: FnMut(&mut Self) -> T,
@@ -305,12 +637,22 @@ impl<'a> CodeFlowLowerCtx<'a> {
     }
 }
 
-pub(crate) fn lower_to_code_flow_graph(module: &Module, scopes: &Scopes) -> CodeFlowGraph {
-    let mut cx = CodeFlowLowerCtx::new(module, scopes);
+pub(crate) fn lower_to_code_flow_graph(
+    db: &dyn Db,
+    file: File,
+    module: &Module,
+    scopes: &Scopes,
+) -> CodeFlowGraph {
+    let mut cx = CodeFlowLowerCtx::new(db, file, module, scopes);
     cx.lower_stmts(&module.top_level);
     cx.result
         .hir_to_flow_node
         .insert(ScopeHirId::Module, cx.curr_node);
+    cx.result.no_return_functions = cx
+        .no_return_functions
+        .into_iter()
+        .filter_map(|(stmt, no_return)| no_return.then_some(stmt))
+        .collect();
     cx.result
 }
 
@@ -325,7 +667,7 @@ pub(crate) struct CodeFlowGraphResult {
 pub(crate) fn code_flow_graph(db: &dyn Db, file: File) -> CodeFlowGraphResult {
     let info = lower(db, file);
     let scopes = module_scopes(db, file);
-    let cfg = lower_to_code_flow_graph(info.module(db), scopes.scopes(db));
+    let cfg = lower_to_code_flow_graph(db, file, info.module(db), scopes.scopes(db));
     CodeFlowGraphResult::new(db, cfg)
 }
 
@@ -432,6 +774,105 @@ if x > 0:
         );
     }
 
+    #[test]
+    fn test_if_condition() {
+        check(
+            r#"
+x = None
+if x != None:
+    y = x
+"#,
+            expect![[r#"
+                def main():
+                    'bb0: {
+                        data: Unreachable
+                        antecedents: []
+                    }
+
+                    'bb1: {
+                        data: Start
+                        antecedents: []
+                    }
+
+                    'bb2: {
+                        data: Assign { expr: Id { idx: 0 }, name: Name("x"), execution_scope: Module, source: Id { idx: 1 }, antecedent: Id { idx: 1 } }
+                        antecedents: ['bb1]
+                    }
+
+                    'bb3: {
+                        data: Condition { expr: Id { idx: 4 }, is_true: true, execution_scope: Module, antecedent: Id { idx: 2 } }
+                        antecedents: ['bb2]
+                    }
+
+                    'bb4: {
+                        data: Condition { expr: Id { idx: 4 }, is_true: false, execution_scope: Module, antecedent: Id { idx: 2 } }
+                        antecedents: ['bb2]
+                    }
+
+                    'bb5: {
+                        data: Branch { antecedents: [Id { idx: 6 }, Id { idx: 4 }] }
+                        antecedents: ['bb6, 'bb4]
+                    }
+
+                    'bb6: {
+                        data: Assign { expr: Id { idx: 5 }, name: Name("y"), execution_scope: Module, source: Id { idx: 6 }, antecedent: Id { idx: 3 } }
+                        antecedents: ['bb3]
+                    }
+
+            "#]],
+        );
+    }
+
+    #[test]
+    fn test_fail_call() {
+        check(
+            r#"
+x = None
+if not x:
+    fail("x is not set")
+y = x
+"#,
+            expect![[r#"
+                def main():
+                    'bb0: {
+                        data: Unreachable
+                        antecedents: []
+                    }
+
+                    'bb1: {
+                        data: Start
+                        antecedents: []
+                    }
+
+                    'bb2: {
+                        data: Assign { expr: Id { idx: 0 }, name: Name("x"), execution_scope: Module, source: Id { idx: 1 }, antecedent: Id { idx: 1 } }
+                        antecedents: ['bb1]
+                    }
+
+                    'bb3: {
+                        data: Condition { expr: Id { idx: 2 }, is_true: true, execution_scope: Module, antecedent: Id { idx: 2 } }
+                        antecedents: ['bb2]
+                    }
+
+                    'bb4: {
+                        data: Condition { expr: Id { idx: 2 }, is_true: false, execution_scope: Module, antecedent: Id { idx: 2 } }
+                        antecedents: ['bb2]
+                    }
+
+                    'bb5: {
+                        data: Branch { antecedents: [Id { idx: 3 }] }
+                        antecedents: ['bb3]
+                    }
+
+                    'bb6: {
+                        data: Assign { expr: Id { idx: 7 }, name: Name("y"), execution_scope: Module, source: Id { idx: 8 }, antecedent: Id { idx: 5 } }
+                        antecedents: ['bb5]
+                    }
+
+            "#]],
+        );
+    }
+
     #[test]
     fn test_separate_execution_scope() {
         check(
@@ -1,15 +1,18 @@
 use either::Either;
-use starpls_common::{line_index, Diagnostic, Diagnostics, File, FileRange, Severity};
+use smol_str::SmolStr;
+use starpls_common::{
+    line_index, Diagnostic, DiagnosticCode, Diagnostics, File, FileRange, Severity,
+};
 use starpls_syntax::{
     ast::{self, AstNode, AstPtr, AstToken, SyntaxNodePtr},
-    SyntaxToken, TextRange,
+    SyntaxKind, SyntaxToken, TextRange,
 };
 
 use crate::{
     def::{
         Argument, CompClause, DictEntry, Expr, ExprId, ExprPtr, Function, Literal, LoadItem,
         LoadItemId, LoadItemPtr, LoadStmt, Module, ModuleSourceMap, Name, Param, ParamId, ParamPtr,
-        Stmt, StmtId, StmtPtr,
+        Stmt, StmtId, StmtPtr, TypeIgnore,
     },
     typeck::FunctionTypeRef,
     Db, TypeRef,
@@ -50,10 +53,37 @@ struct LoweringContext<'a> {
 impl<'a> LoweringContext<'a> {
     fn lower(mut self, syntax: ast::Module) -> (Module, ModuleSourceMap) {
         let line_index = line_index(self.db, self.file);
-        self.module.type_ignore_comment_lines = syntax
-            .type_ignore_comment_positions()
-            .map(|pos| line_index.line_col(pos).line)
-            .collect();
+        // A `# type: ignore` comment that appears before the first statement of the module applies
+        // to the entire file. Otherwise, the comment applies only to the line it appears on.
+        let first_stmt_line = syntax
+            .statements()
+            .next()
+            .map(|stmt| line_index.line_col(stmt.syntax().text_range().start()).line);
+        for ignore in syntax.type_ignore_comments() {
+            let range = ignore
+                .syntax()
+                .ancestors()
+                .find(|node| node.kind() == SyntaxKind::TYPE_COMMENT)
+                .map(|node| node.text_range())
+                .unwrap_or_else(|| ignore.syntax().text_range());
+            let type_ignore = TypeIgnore {
+                range,
+                codes: ignore.has_codes().then(|| {
+                    ignore
+                        .codes()
+                        .map(|code| SmolStr::from(code.text()))
+                        .collect()
+                }),
+            };
+            let line = line_index.line_col(range.start()).line;
+            if first_stmt_line.map_or(true, |first_stmt_line| line < first_stmt_line) {
+                if self.module.file_type_ignore.is_none() {
+                    self.module.file_type_ignore = Some(type_ignore);
+                }
+            } else {
+                self.module.type_ignores.insert(line, type_ignore);
+            }
+        }
 
         let mut top_level = Vec::new();
         for statement in syntax.statements() {
@@ -62,25 +92,27 @@ impl<'a> LoweringContext<'a> {
             match &self.module.stmts[stmt] {
                 Stmt::If { .. } => Diagnostics::push(
                     self.db,
-                    Diagnostic {
-                        message: "Starlark does not allow top-level if statements".to_string(),
-                        severity: Severity::Error,
-                        range: FileRange {
+                    Diagnostic::new(
+                        DiagnosticCode::TopLevelStatement,
+                        Severity::Error,
+                        FileRange {
                             file_id: self.file.id(self.db),
                             range: statement.syntax().text_range(),
                         },
-                    },
+                        "Starlark does not allow top-level if statements",
+                    ),
                 ),
                 Stmt::For { .. } => Diagnostics::push(
                     self.db,
-                    Diagnostic {
-                        message: "Starlark does not allow top-level for statements".to_string(),
-                        severity: Severity::Error,
-                        range: FileRange {
+                    Diagnostic::new(
+                        DiagnosticCode::TopLevelStatement,
+                        Severity::Error,
+                        FileRange {
                             file_id: self.file.id(self.db),
                             range: statement.syntax().text_range(),
                         },
-                    },
+                        "Starlark does not allow top-level for statements",
+                    ),
                 ),
                 _ => {}
             }
@@ -286,19 +318,26 @@ impl<'a> LoweringContext<'a> {
             ast::Expression::Call(node) => {
                 let callee = self.lower_expr_opt(node.callee());
                 let args = self.lower_args_opt(node.arguments());
-                let impl_fn_name = args.iter().find_map(|arg| match arg {
-                    Argument::Keyword { name, expr } if name.as_str() == "implementation" => {
-                        match &self.module.exprs[*expr] {
-                            Expr::Name { name } => Some(name.clone()),
-                            _ => None,
+                let fn_name_for_keyword = |keyword| {
+                    args.iter().find_map(|arg| match arg {
+                        Argument::Keyword { name, expr } if name.as_str() == keyword => {
+                            match &self.module.exprs[*expr] {
+                                Expr::Name { name } => Some(name.clone()),
+                                _ => None,
+                            }
                         }
-                    }
-                    _ => None,
-                });
+                        _ => None,
+                    })
+                };
+                let impl_fn_name = fn_name_for_keyword("implementation");
+                let map_each_fn_name = fn_name_for_keyword("map_each");
                 let expr = self.alloc_expr(Expr::Call { callee, args }, ptr);
                 if let Some(name) = impl_fn_name {
                     self.module.call_expr_with_impl_fn.insert(name, expr);
                 }
+                if let Some(name) = map_each_fn_name {
+                    self.module.call_expr_with_map_each_fn.insert(name, expr);
+                }
                 return expr;
             }
             ast::Expression::Index(node) => {
@@ -3,7 +3,7 @@ use std::collections::{hash_map::Entry, VecDeque};
 use either::Either;
 use id_arena::{Arena, Id};
 use rustc_hash::FxHashMap;
-use starpls_common::{Diagnostic, Diagnostics, File, FileRange, InFile, Severity};
+use starpls_common::{Diagnostic, DiagnosticCode, Diagnostics, File, FileRange, InFile, Severity};
 
 use crate::{
     def::{CompClause, Expr, ExprId, Function, LoadItem, LoadItemId, Param, ParamId, Stmt, StmtId},
@@ -398,10 +398,10 @@ impl ScopeCollector<'_> {
                 Expr::Missing => {}
                 _ => Diagnostics::push(
                     self.db,
-                    Diagnostic {
-                        message: "Expression is not assignable".to_string(),
-                        severity: Severity::Error,
-                        range: FileRange {
+                    Diagnostic::new(
+                        DiagnosticCode::InvalidAssignmentTarget,
+                        Severity::Error,
+                        FileRange {
                             file_id: self.file.id(self.db),
                             range: self
                                 .source_map
@@ -411,7 +411,8 @@ impl ScopeCollector<'_> {
                                 .syntax_node_ptr()
                                 .text_range(),
                         },
-                    },
+                        "Expression is not assignable",
+                    ),
                 ),
             }
         } else {
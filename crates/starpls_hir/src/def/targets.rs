@@ -0,0 +1,380 @@
+//! A static index of the targets declared by a BUILD file. Targets are found by evaluating the
+//! `name` arguments of top-level calls, including calls made by list comprehensions over lists of
+//! strings. The calls made by macros are recorded as well, so that the targets generated by macros
+//! can be determined once a macro call has been resolved to its definition. The visibility
+//! declarations of the BUILD file, i.e. `package(default_visibility = ...)`, `visibility`
+//! arguments, `package_group()` declarations and `exports_files()` calls, are recorded as well.
+
+use either::Either;
+use rustc_hash::FxHashMap;
+use starpls_common::File;
+use starpls_syntax::ast::{ArithOp, BinaryOp};
+
+use crate::{
+    def::{Argument, CompClause, Expr, ExprId, Literal, Stmt, StmtId},
+    lower, Db, Module, ModuleInfo, Name,
+};
+
+/// A target declared by a top-level call in a BUILD file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TargetDef {
+    pub(crate) name: Box<str>,
+    /// The name of the rule or macro being called, e.g. `cc_library`.
+    pub(crate) kind: Name,
+    pub(crate) call: ExprId,
+    pub(crate) name_expr: ExprId,
+    /// The keyword arguments of the call that evaluate to strings. These are used to evaluate the
+    /// names of the targets generated by the macro being called, if any.
+    pub(crate) string_args: Box<[(Name, Box<str>)]>,
+    pub(crate) visibility: Option<VisibilityDef>,
+}
+
+/// The value of a `visibility` or `default_visibility` argument.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct VisibilityDef {
+    /// The labels in the list, or `None` if the list couldn't be evaluated.
+    pub(crate) labels: Option<Box<[Box<str>]>>,
+    pub(crate) expr: ExprId,
+}
+
+/// The `packages` and `includes` arguments of a `package_group()` declaration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PackageGroupDef {
+    pub(crate) packages: Box<[Box<str>]>,
+    pub(crate) includes: Box<[Box<str>]>,
+}
+
+/// A call made by a macro, e.g. `native.cc_library(name = name + "_lib")`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MacroCall {
+    pub(crate) kind: Name,
+    pub(crate) name_expr: ExprId,
+}
+
+#[salsa::tracked]
+pub(crate) struct FileTargets {
+    #[return_ref]
+    pub(crate) targets: Vec<TargetDef>,
+    /// The calls made by each top-level function, keyed by the function's name.
+    #[return_ref]
+    pub(crate) macros: FxHashMap<Name, Vec<MacroCall>>,
+    /// The `default_visibility` argument of the `package()` call, if any.
+    #[return_ref]
+    pub(crate) default_visibility: Option<VisibilityDef>,
+    /// The package groups declared by the file, keyed by name.
+    #[return_ref]
+    pub(crate) package_groups: FxHashMap<Box<str>, PackageGroupDef>,
+}
+
+#[salsa::tracked]
+pub(crate) fn file_targets_query(db: &dyn Db, info: ModuleInfo) -> FileTargets {
+    let module = info.module(db);
+    let mut collector = TargetsCollector {
+        db,
+        module,
+        strings: Default::default(),
+        lists: Default::default(),
+        targets: Vec::new(),
+        default_visibility: None,
+        package_groups: FxHashMap::default(),
+    };
+    let mut macros = FxHashMap::default();
+
+    for stmt in module.top_level.iter() {
+        match &module[*stmt] {
+            Stmt::Expr { expr } => collector.collect_expr(*expr),
+            Stmt::Assign {
+                lhs, rhs, op: None, ..
+            } => collector.collect_assign(*lhs, *rhs),
+            Stmt::Def { func, stmts } => {
+                let mut calls = Vec::new();
+                collect_macro_calls(module, stmts, &mut calls);
+                macros.insert(func.name(db), calls);
+            }
+            _ => {}
+        }
+    }
+
+    FileTargets::new(
+        db,
+        collector.targets,
+        macros,
+        collector.default_visibility,
+        collector.package_groups,
+    )
+}
+
+#[salsa::tracked]
+pub(crate) fn file_targets(db: &dyn Db, file: File) -> FileTargets {
+    let info = lower(db, file);
+    file_targets_query(db, info)
+}
+
+struct TargetsCollector<'a> {
+    db: &'a dyn Db,
+    module: &'a Module,
+    /// The top-level variables assigned to strings, e.g. `NAME = "foo"`.
+    strings: FxHashMap<Name, Box<str>>,
+    /// The top-level variables assigned to lists of strings, e.g. `NAMES = ["foo", "bar"]`.
+    lists: FxHashMap<Name, Vec<Box<str>>>,
+    targets: Vec<TargetDef>,
+    default_visibility: Option<VisibilityDef>,
+    package_groups: FxHashMap<Box<str>, PackageGroupDef>,
+}
+
+impl TargetsCollector<'_> {
+    fn collect_assign(&mut self, lhs: ExprId, rhs: ExprId) {
+        let name = match &self.module[lhs] {
+            Expr::Name { name } => name.clone(),
+            _ => return,
+        };
+        if let Some(value) = eval_string(self.db, self.module, rhs, &self.strings) {
+            self.strings.insert(name, value.into());
+        } else if let Some(values) = self.eval_list(rhs) {
+            self.lists.insert(name, values);
+        }
+    }
+
+    fn collect_expr(&mut self, expr: ExprId) {
+        match &self.module[expr] {
+            Expr::Call { .. } => {
+                let env = self.strings.clone();
+                self.collect_call(expr, &env);
+            }
+            // Handle comprehensions like `[cc_test(name = src + "_test") for src in SRCS]` by evaluating
+            // the call once for each element, as long as the element is bound to a single name.
+            Expr::ListComp { expr, comp_clauses } => {
+                let (iterable, target) = match &comp_clauses[..] {
+                    [CompClause::For { iterable, targets }] if targets.len() == 1 => {
+                        (*iterable, targets[0])
+                    }
+                    _ => return,
+                };
+                let var = match &self.module[target] {
+                    Expr::Name { name } => name.clone(),
+                    _ => return,
+                };
+                let values = match self.eval_list(iterable) {
+                    Some(values) => values,
+                    None => return,
+                };
+                for value in values {
+                    let mut env = self.strings.clone();
+                    env.insert(var.clone(), value);
+                    self.collect_call(*expr, &env);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn collect_call(&mut self, call: ExprId, env: &FxHashMap<Name, Box<str>>) {
+        let (callee, args) = match &self.module[call] {
+            Expr::Call { callee, args } => (*callee, args),
+            _ => return,
+        };
+        let kind = match callee_name(self.module, callee) {
+            Some(kind) => kind,
+            None => return,
+        };
+        match kind.as_str() {
+            "package" => {
+                if let Some(expr) = keyword_arg(args, "default_visibility") {
+                    self.default_visibility = Some(self.eval_visibility(expr));
+                }
+                return;
+            }
+            "exports_files" => {
+                self.collect_exports_files(call, kind.clone(), args);
+                return;
+            }
+            _ => {}
+        }
+
+        let mut name = None;
+        let mut string_args = Vec::new();
+        let mut visibility = None;
+        for arg in args.iter() {
+            if let Argument::Keyword {
+                name: keyword,
+                expr,
+            } = arg
+            {
+                if keyword.as_str() == "visibility" {
+                    visibility = Some(self.eval_visibility(*expr));
+                }
+                if let Some(value) = eval_string(self.db, self.module, *expr, env) {
+                    if keyword.as_str() == "name" {
+                        name = Some((value.clone().into_boxed_str(), *expr));
+                    }
+                    string_args.push((keyword.clone(), value.into_boxed_str()));
+                }
+            }
+        }
+
+        if let Some((name, name_expr)) = name {
+            if kind.as_str() == "package_group" {
+                self.collect_package_group(&name, args);
+            }
+            self.targets.push(TargetDef {
+                name,
+                kind,
+                call,
+                name_expr,
+                string_args: string_args.into_boxed_slice(),
+                visibility,
+            });
+        }
+    }
+
+    /// Records each file exported by a call like `exports_files(["foo.txt"])` as a target.
+    fn collect_exports_files(&mut self, call: ExprId, kind: Name, args: &[Argument]) {
+        let srcs = match args.first() {
+            Some(Argument::Simple { expr }) => *expr,
+            _ => match keyword_arg(args, "srcs") {
+                Some(expr) => expr,
+                None => return,
+            },
+        };
+        let exprs = match &self.module[srcs] {
+            Expr::List { exprs } => exprs,
+            _ => return,
+        };
+        let visibility = keyword_arg(args, "visibility").map(|expr| self.eval_visibility(expr));
+        for expr in exprs.iter() {
+            if let Some(name) = eval_string(self.db, self.module, *expr, &self.strings) {
+                self.targets.push(TargetDef {
+                    name: name.into_boxed_str(),
+                    kind: kind.clone(),
+                    call,
+                    name_expr: *expr,
+                    string_args: Box::new([]),
+                    visibility: visibility.clone(),
+                });
+            }
+        }
+    }
+
+    fn collect_package_group(&mut self, name: &str, args: &[Argument]) {
+        let eval_arg = |keyword| match keyword_arg(args, keyword) {
+            Some(expr) => self.eval_list(expr).map(Vec::into_boxed_slice),
+            None => Some(Box::new([]) as Box<[_]>),
+        };
+        if let (Some(packages), Some(includes)) = (eval_arg("packages"), eval_arg("includes")) {
+            self.package_groups
+                .insert(name.into(), PackageGroupDef { packages, includes });
+        }
+    }
+
+    fn eval_visibility(&self, expr: ExprId) -> VisibilityDef {
+        VisibilityDef {
+            labels: self.eval_list(expr).map(Vec::into_boxed_slice),
+            expr,
+        }
+    }
+
+    fn eval_list(&self, expr: ExprId) -> Option<Vec<Box<str>>> {
+        match &self.module[expr] {
+            Expr::List { exprs } => exprs
+                .iter()
+                .map(|expr| {
+                    eval_string(self.db, self.module, *expr, &self.strings).map(String::into)
+                })
+                .collect(),
+            Expr::Name { name } => self.lists.get(name).cloned(),
+            Expr::Paren { expr } => self.eval_list(*expr),
+            _ => None,
+        }
+    }
+}
+
+/// Collects the calls with a `name` argument made by a macro's body, including the bodies of its
+/// `if` and `for` statements.
+fn collect_macro_calls(module: &Module, stmts: &[StmtId], calls: &mut Vec<MacroCall>) {
+    for stmt in stmts.iter() {
+        match &module[*stmt] {
+            Stmt::Expr { expr } => {
+                let (callee, args) = match &module[*expr] {
+                    Expr::Call { callee, args } => (*callee, args),
+                    _ => continue,
+                };
+                let name_expr = args.iter().find_map(|arg| match arg {
+                    Argument::Keyword { name, expr } if name.as_str() == "name" => Some(*expr),
+                    _ => None,
+                });
+                if let (Some(kind), Some(name_expr)) = (callee_name(module, callee), name_expr) {
+                    calls.push(MacroCall { kind, name_expr });
+                }
+            }
+            Stmt::If {
+                if_stmts,
+                elif_or_else_stmts,
+                ..
+            } => {
+                collect_macro_calls(module, if_stmts, calls);
+                match elif_or_else_stmts {
+                    Some(Either::Left(stmt)) => collect_macro_calls(module, &[*stmt], calls),
+                    Some(Either::Right(stmts)) => collect_macro_calls(module, stmts, calls),
+                    None => {}
+                }
+            }
+            Stmt::For { stmts, .. } => collect_macro_calls(module, stmts, calls),
+            _ => {}
+        }
+    }
+}
+
+fn keyword_arg(args: &[Argument], keyword: &str) -> Option<ExprId> {
+    args.iter().find_map(|arg| match arg {
+        Argument::Keyword { name, expr } if name.as_str() == keyword => Some(*expr),
+        _ => None,
+    })
+}
+
+/// Returns the name of the rule or macro being called, e.g. `cc_library` for both
+/// `cc_library(...)` and `native.cc_library(...)`.
+fn callee_name(module: &Module, callee: ExprId) -> Option<Name> {
+    match &module[callee] {
+        Expr::Name { name } => Some(name.clone()),
+        Expr::Dot { field, .. } => Some(field.clone()),
+        _ => None,
+    }
+}
+
+/// Evaluates an expression that computes a string, e.g. `name + "_test"` or `"%s_lib" % name`,
+/// where the variables in `env` are bound to strings.
+pub(crate) fn eval_string(
+    db: &dyn Db,
+    module: &Module,
+    expr: ExprId,
+    env: &FxHashMap<Name, Box<str>>,
+) -> Option<String> {
+    match &module[expr] {
+        Expr::Literal {
+            literal: Literal::String(s),
+        } => Some(s.value(db).to_string()),
+        Expr::Name { name } => env.get(name).map(|value| value.to_string()),
+        Expr::Paren { expr } => eval_string(db, module, *expr, env),
+        Expr::Binary {
+            lhs,
+            rhs,
+            op: Some(BinaryOp::Arith(ArithOp::Add)),
+        } => {
+            let mut value = eval_string(db, module, *lhs, env)?;
+            value.push_str(&eval_string(db, module, *rhs, env)?);
+            Some(value)
+        }
+        // Only handle format strings with a single `%s` directive.
+        Expr::Binary {
+            lhs,
+            rhs,
+            op: Some(BinaryOp::Arith(ArithOp::Mod)),
+        } => {
+            let format = eval_string(db, module, *lhs, env)?;
+            let arg = eval_string(db, module, *rhs, env)?;
+            (format.matches('%').count() == 1 && format.contains("%s"))
+                .then(|| format.replacen("%s", &arg, 1))
+        }
+        _ => None,
+    }
+}
@@ -1,15 +1,19 @@
 use std::collections::HashSet;
 
+use expect_test::{expect, Expect};
 use starpls_bazel::{
     env::{make_build_builtins, make_bzl_builtins},
     APIContext,
 };
-use starpls_common::{Db as _, Dialect, FileId, FileInfo};
+use starpls_common::{Db as _, Dialect, File, FileId, FileInfo};
 use starpls_test_util::parse_fixture;
 
 use crate::{
-    def::resolver::Resolver, test_database::TestDatabase, typeck::intrinsics::intrinsic_functions,
-    Db as _,
+    def::resolver::Resolver,
+    diagnostics_for_file,
+    test_database::{TestDatabase, TestDatabaseBuilder},
+    typeck::intrinsics::intrinsic_functions,
+    Db as _, InferenceOptions, Semantics, TypeIgnores,
 };
 
 fn check_scope(fixture: &str, expected: &[&str]) {
@@ -75,6 +79,113 @@ fn check_scope_full(fixture: &str, expected: &[&str], prelude: Option<&str>) {
     assert_eq!(expected, &actual[..]);
 }
 
+fn check_diagnostics(fixture: &str, expect: Expect) {
+    check_diagnostics_with_files(fixture, &[], expect)
+}
+
+fn check_diagnostics_with_files(fixture: &str, files: &[(&str, &str)], expect: Expect) {
+    let mut builder = TestDatabaseBuilder::default();
+    for (path, contents) in files {
+        builder.add_file(*path, *contents);
+    }
+    check_diagnostics_with_builder(fixture, APIContext::Bzl, builder, expect)
+}
+
+fn check_diagnostics_with_builder(
+    fixture: &str,
+    api_context: APIContext,
+    builder: TestDatabaseBuilder,
+    expect: Expect,
+) {
+    let mut test_db = builder.build();
+    let file = test_db.create_file(
+        FileId(0),
+        Dialect::Bazel,
+        Some(FileInfo::Bazel {
+            api_context,
+            is_external: false,
+        }),
+        fixture.to_string(),
+    );
+
+    let mut type_ignores = TypeIgnores::new(&test_db, file);
+    let mut diagnostics = diagnostics_for_file(&test_db, file)
+        .filter(|diagnostic| !type_ignores.is_suppressed(diagnostic))
+        .collect::<Vec<_>>();
+    diagnostics.sort_by_key(|diagnostic| diagnostic.range.range.start());
+    let mut actual = String::new();
+    for diagnostic in diagnostics {
+        actual.push_str(&format!(
+            "{:?} {}\n",
+            diagnostic.range.range, diagnostic.message
+        ));
+    }
+    expect.assert_eq(&actual);
+}
+
+fn create_build_file(fixture: &str, files: &[(&str, &str)]) -> (TestDatabase, File) {
+    let mut builder = TestDatabaseBuilder::default();
+    for (path, contents) in files {
+        builder.add_file(*path, *contents);
+    }
+    let mut test_db = builder.build();
+    let file = test_db.create_file(
+        FileId(0),
+        Dialect::Bazel,
+        Some(FileInfo::Bazel {
+            api_context: APIContext::Build,
+            is_external: false,
+        }),
+        fixture.to_string(),
+    );
+    (test_db, file)
+}
+
+fn check_targets(fixture: &str, files: &[(&str, &str)], expect: Expect) {
+    let (test_db, file) = create_build_file(fixture, files);
+    let mut actual = String::new();
+    for target in Semantics::new(&test_db).targets(file) {
+        actual.push_str(&format!(
+            "{:?} {} {}",
+            target.name_range(&test_db).unwrap(),
+            target.name(),
+            target.kind()
+        ));
+        if let Some(generator) = target.generator_function() {
+            actual.push_str(&format!(" (generated by {})", generator));
+        }
+        actual.push('\n');
+    }
+    expect.assert_eq(&actual);
+}
+
+fn check_visibility(fixture: &str, expect: Expect) {
+    let (test_db, file) = create_build_file(fixture, &[]);
+    let sema = Semantics::new(&test_db);
+    let mut actual = String::new();
+    for target in sema.targets(file) {
+        actual.push_str(target.name());
+        match target.visibility(&test_db) {
+            Some(visibility) => {
+                actual.push_str(&format!(" [{}]", visibility.labels().join(", ")));
+                if let Some(range) = visibility.range(&test_db) {
+                    actual.push_str(&format!(" {:?}", range));
+                }
+            }
+            None => actual.push_str(" unknown"),
+        }
+        actual.push('\n');
+        if let Some(group) = sema.package_group(file, target.name()) {
+            actual.push_str(&format!(
+                "    packages: [{}], includes: [{}]\n",
+                group.packages.join(", "),
+                group.includes.join(", ")
+            ));
+        }
+    }
+    expect.assert_eq(&actual);
+}
+
 #[test]
 fn smoke_test() {
     check_scope(
@@ -233,3 +344,233 @@ def f():
         ),
     )
 }
+
+#[test]
+fn test_recursion() {
+    check_diagnostics(
+        r#"
+def f(n):
+    return f(n - 1)
+
+def g():
+    h()
+
+def h():
+    g()
+"#,
+        expect![[r#"
+            22..30 Function "f" calls itself recursively
+            45..48 Call to "h" results in a recursive call to "g"
+            63..66 Call to "g" results in a recursive call to "h"
+        "#]],
+    )
+}
+
+#[test]
+fn test_recursion_type_ignore() {
+    check_diagnostics(
+        r#"
+def f(n):
+    return f(n - 1) # type: ignore[recursion]
+
+def g(n):
+    return g(n - 1) # type: ignore[undefined-name]
+"#,
+        expect![[r#"
+            79..87 Function "g" calls itself recursively
+        "#]],
+    )
+}
+
+#[test]
+fn test_global_reassignment() {
+    check_diagnostics(
+        r#"
+load("//:foo.bzl", "foo")
+
+x = 1
+x = 2
+foo = 3
+
+def x():
+    pass
+"#,
+        expect![[r#"
+            34..35 Cannot reassign global "x"
+            40..43 Cannot reassign global "foo"
+            53..54 Cannot reassign global "x"
+        "#]],
+    )
+}
+
+#[test]
+fn test_global_reassignment_in_build_file() {
+    // Unlike `.bzl` files, BUILD files allow rebinding globals.
+    check_diagnostics_with_builder(
+        r#"
+SRCS = ["a.cc"]
+SRCS = SRCS + ["b.cc"]
+"#,
+        APIContext::Build,
+        TestDatabaseBuilder::default(),
+        expect![""],
+    )
+}
+
+#[test]
+fn test_global_reassignment_allowed() {
+    let mut builder = TestDatabaseBuilder::default();
+    builder.set_inference_options(InferenceOptions {
+        allow_global_reassignment: true,
+        ..Default::default()
+    });
+    check_diagnostics_with_builder(
+        r#"
+x = 1
+x = 2
+"#,
+        APIContext::Bzl,
+        builder,
+        expect![""],
+    )
+}
+
+#[test]
+fn test_frozen_mutation() {
+    check_diagnostics_with_files(
+        r#"
+load("//:foo.bzl", "foo", "sets", bar = "baz")
+
+foo.append(1)
+bar["key"] = 2
+sets.insert(3)
+baz = []
+baz.append(4)
+
+def f():
+    foo.extend([])
+"#,
+        &[(
+            "//:foo.bzl",
+            r#"
+foo = []
+baz = {}
+
+def _insert(s, e):
+    pass
+
+sets = struct(insert = _insert)
+"#,
+        )],
+        expect![[r#"
+            49..62 Cannot mutate "foo", which is frozen because it was loaded from another module
+            63..73 Cannot mutate "bar", which is frozen because it was loaded from another module
+            130..144 Cannot mutate "foo", which is frozen because it was loaded from another module
+        "#]],
+    )
+}
+
+#[test]
+fn test_targets() {
+    check_targets(
+        r#"
+NAME = "bar"
+SRCS = ["a", "b"]
+
+cc_library(
+    name = "foo",
+    srcs = ["foo.cc"],
+)
+
+cc_binary(name = NAME + "_bin")
+
+[cc_test(name = "%s_test" % src) for src in SRCS]
+
+filegroup(srcs = ["missing_name.txt"])
+"#,
+        &[],
+        expect![[r#"
+            56..61 foo cc_library
+            106..119 bar_bin cc_binary
+            138..153 a_test cc_test
+            138..153 b_test cc_test
+        "#]],
+    )
+}
+
+#[test]
+fn test_targets_from_macro() {
+    check_targets(
+        r#"
+load("//:macros.bzl", "go_test_suite", "unknown")
+
+go_test_suite(
+    name = "suite",
+    srcs = ["suite_test.go"],
+)
+
+unknown(name = "other")
+"#,
+        &[(
+            "//:macros.bzl",
+            r#"
+def go_test_suite(name, srcs):
+    native.go_library(name = name + "_lib", srcs = srcs)
+    if srcs:
+        native.go_test(name = name, embed = [":" + name + "_lib"])
+
+def unknown(**kwargs):
+    pass
+"#,
+        )],
+        expect![[r#"
+            78..85 suite_lib go_library (generated by go_test_suite)
+            78..85 suite go_test (generated by go_test_suite)
+            135..142 other unknown
+        "#]],
+    )
+}
+
+#[test]
+fn test_targets_visibility() {
+    check_visibility(
+        r#"
+package(default_visibility = ["//foo:__subpackages__"])
+
+package_group(
+    name = "friends",
+    packages = ["//bar/..."],
+    includes = [":others"],
+)
+
+cc_library(name = "lib")
+
+cc_library(
+    name = "public_lib",
+    visibility = ["//visibility:public"],
+)
+
+cc_library(
+    name = "unknown",
+    visibility = VISIBILITY,
+)
+
+exports_files(["a.txt"])
+
+exports_files(
+    ["b.txt", "c.txt"],
+    visibility = [":friends"],
+)
+"#,
+        expect![[r#"
+            friends [//foo:__subpackages__] 30..55
+                packages: [//bar/...], includes: [:others]
+            lib [//foo:__subpackages__] 30..55
+            public_lib [//visibility:public] 236..259
+            unknown unknown
+            a.txt [//visibility:public]
+            b.txt [:friends] 412..424
+            c.txt [:friends] 412..424
+        "#]],
+    )
+}
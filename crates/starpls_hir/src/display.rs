@@ -107,6 +107,16 @@ impl DisplayWithDb for TyKind {
                 ty.fmt(db, f)?;
                 return f.write_char(']');
             }
+            TyKind::Depset(ty, _) => {
+                f.write_str("depset[")?;
+                ty.fmt(db, f)?;
+                return f.write_char(']');
+            }
+            TyKind::Select(ty) => {
+                f.write_str("Select[")?;
+                ty.fmt(db, f)?;
+                return f.write_char(']');
+            }
             TyKind::Tuple(tuple) => {
                 f.write_str("tuple[")?;
                 match tuple {
@@ -279,6 +289,9 @@ impl DisplayWithDb for TyKind {
                 RuleKind::Build => "rule",
                 RuleKind::Repository => "repository_rule",
             },
+            TyKind::Aspect(_) => "Aspect",
+            TyKind::Macro(_) => "macro",
+            TyKind::Subrule(_) => "Subrule",
             TyKind::Provider(provider) => {
                 return write!(
                     f,
@@ -1,4 +1,6 @@
-use starpls_bazel::Builtins;
+use std::sync::Arc;
+
+use starpls_bazel::{lockfile::ModuleExtensionRepos, Builtins};
 use starpls_common::{parse, Dialect, File, FileId, Parse};
 
 pub use crate::{
@@ -32,14 +34,21 @@ pub struct Jar(
     lower,
     lower_query,
     ModuleInfo,
+    api::ExpandedTargets,
+    api::expanded_targets,
     def::Function,
     def::LoadStmt,
     def::LiteralString,
     def::codeflow::CodeFlowGraphResult,
     def::codeflow::code_flow_graph,
+    def::checks::ModuleChecks,
+    def::checks::check_module,
     def::scope::ModuleScopes,
     def::scope::module_scopes,
     def::scope::module_scopes_query,
+    def::targets::FileTargets,
+    def::targets::file_targets,
+    def::targets::file_targets_query,
     typeck::builtins::BuiltinDefs,
     typeck::builtins::BuiltinFunction,
     typeck::builtins::BuiltinGlobals,
@@ -68,6 +77,8 @@ pub trait Db: salsa::DbWithJar<Jar> + starpls_common::Db {
     fn get_builtin_defs(&self, dialect: &Dialect) -> BuiltinDefs;
     fn set_bazel_prelude_file(&mut self, file_id: FileId);
     fn get_bazel_prelude_file(&self) -> Option<FileId>;
+    fn set_module_extension_repos(&mut self, repos: Arc<ModuleExtensionRepos>);
+    fn get_module_extension_repos(&self) -> Option<Arc<ModuleExtensionRepos>>;
 }
 
 #[salsa::tracked]
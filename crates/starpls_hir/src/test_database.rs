@@ -1,8 +1,10 @@
 use std::sync::Arc;
 
 use dashmap::{mapref::entry::Entry, DashMap};
-use starpls_bazel::Builtins;
-use starpls_common::{File, FileId, FileInfo, LoadItemCandidate, ResolvedPath};
+use starpls_bazel::{label::Label, lockfile::ModuleExtensionRepos, APIContext, Builtins};
+use starpls_common::{
+    AllowedFiles, Db as _, File, FileId, FileInfo, LoadItemCandidate, ResolvedPath,
+};
 use starpls_test_util::{make_test_builtins, FixtureType};
 
 use crate::{BuiltinDefs, Db, Dialect, GlobalContext, InferenceOptions};
@@ -13,7 +15,9 @@ pub(crate) struct TestDatabase {
     builtin_defs: Arc<DashMap<Dialect, BuiltinDefs>>,
     storage: salsa::Storage<Self>,
     files: Arc<DashMap<FileId, File>>,
+    file_paths: Arc<DashMap<String, FileId>>,
     prelude_file: Option<FileId>,
+    module_extension_repos: Option<Arc<ModuleExtensionRepos>>,
     pub(crate) gcx: Arc<GlobalContext>,
 }
 
@@ -52,10 +56,13 @@ impl starpls_common::Db for TestDatabase {
 
     fn load_file(
         &self,
-        _path: &str,
+        path: &str,
         _dialect: Dialect,
         _from: FileId,
     ) -> anyhow::Result<Option<File>> {
+        if let Some(file_id) = self.file_paths.get(path) {
+            return Ok(self.files.get(&*file_id).map(|file| *file));
+        }
         Ok(Some(File::new(
             self,
             FileId(0),
@@ -77,14 +84,48 @@ impl starpls_common::Db for TestDatabase {
         Ok(None)
     }
 
-    fn resolve_path(
+    fn list_label_candidates(
         &self,
         _path: &str,
-        _dialect: Dialect,
         _from: FileId,
-    ) -> anyhow::Result<Option<ResolvedPath>> {
+        _allowed_files: &AllowedFiles,
+    ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>> {
         Ok(None)
     }
+
+    fn resolve_path(
+        &self,
+        path: &str,
+        _dialect: Dialect,
+        from: FileId,
+    ) -> anyhow::Result<Option<ResolvedPath>> {
+        // Labels resolve to targets in the BUILD file of their package, if one was added.
+        let label = match Label::parse(path) {
+            Ok(label) => label,
+            Err(_) => return Ok(None),
+        };
+        let package = if label.is_relative() {
+            match self
+                .file_paths
+                .iter()
+                .find(|entry| *entry.value() == from)
+                .and_then(|entry| Some(entry.key().split_once(':')?.0.to_string()))
+            {
+                Some(package) => package,
+                None => return Ok(None),
+            }
+        } else {
+            format!("//{}", label.package())
+        };
+        Ok(["BUILD.bazel", "BUILD"].iter().find_map(|name| {
+            let build_file = *self.file_paths.get(&format!("{}:{}", package, name))?;
+            Some(ResolvedPath::BuildTarget {
+                build_file,
+                target: label.target().to_string(),
+                contents: None,
+            })
+        }))
+    }
 }
 
 impl crate::Db for TestDatabase {
@@ -118,6 +159,14 @@ impl crate::Db for TestDatabase {
         self.prelude_file
     }
 
+    fn set_module_extension_repos(&mut self, repos: Arc<ModuleExtensionRepos>) {
+        self.module_extension_repos = Some(repos)
+    }
+
+    fn get_module_extension_repos(&self) -> Option<Arc<ModuleExtensionRepos>> {
+        self.module_extension_repos.clone()
+    }
+
     fn gcx(&self) -> &GlobalContext {
         &self.gcx
     }
@@ -130,6 +179,9 @@ pub(crate) struct TestDatabaseBuilder {
     functions: Vec<String>,
     globals: Vec<(String, String)>,
     types: Vec<FixtureType>,
+    module_extension_repos: Option<ModuleExtensionRepos>,
+    file_path: Option<String>,
+    files: Vec<(String, String)>,
 }
 
 #[allow(unused)]
@@ -150,6 +202,20 @@ impl TestDatabaseBuilder {
         self.options = options;
     }
 
+    pub fn set_module_extension_repos(&mut self, repos: ModuleExtensionRepos) {
+        self.module_extension_repos = Some(repos);
+    }
+
+    /// Sets the path from which the file under test can be loaded.
+    pub fn set_file_path(&mut self, path: impl Into<String>) {
+        self.file_path = Some(path.into());
+    }
+
+    /// Adds a file that can be loaded from the given path.
+    pub fn add_file(&mut self, path: impl Into<String>, contents: impl Into<String>) {
+        self.files.push((path.into(), contents.into()));
+    }
+
     pub fn build(self) -> TestDatabase {
         let mut db = TestDatabase {
             gcx: Arc::new(GlobalContext::new(self.options)),
@@ -160,6 +226,32 @@ impl TestDatabaseBuilder {
             make_test_builtins(self.functions, self.globals, self.types),
             Builtins::default(),
         );
+        if let Some(repos) = self.module_extension_repos {
+            db.set_module_extension_repos(Arc::new(repos));
+        }
+        if let Some(path) = self.file_path {
+            db.file_paths.insert(path, FileId(0));
+        }
+        for (index, (path, contents)) in self.files.into_iter().enumerate() {
+            let file_id = FileId(index as u32 + 1);
+            let api_context = if path.ends_with("MODULE.bazel") {
+                APIContext::Module
+            } else if path.ends_with(":BUILD.bazel") || path.ends_with(":BUILD") {
+                APIContext::Build
+            } else {
+                APIContext::Bzl
+            };
+            db.create_file(
+                file_id,
+                Dialect::Bazel,
+                Some(FileInfo::Bazel {
+                    api_context,
+                    is_external: false,
+                }),
+                contents,
+            );
+            db.file_paths.insert(path, file_id);
+        }
         db
     }
 }
@@ -10,7 +10,8 @@ use either::Either;
 use parking_lot::Mutex;
 use rustc_hash::{FxHashMap, FxHashSet};
 use smallvec::{smallvec, SmallVec};
-use starpls_common::{parse, Diagnostic, Dialect, File, InFile};
+use starpls_bazel::label::{Label, RepoKind};
+use starpls_common::{parse, AllowedFiles, Diagnostic, Dialect, File, InFile};
 use starpls_intern::{impl_internable, Interned};
 use starpls_syntax::ast::SyntaxNodePtr;
 
@@ -37,6 +38,7 @@ use crate::{
 
 mod call;
 mod infer;
+mod narrowing;
 
 #[cfg(test)]
 mod tests;
@@ -148,6 +150,9 @@ impl std::error::Error for Cancelled {}
 pub struct InferenceOptions {
     pub infer_ctx_attributes: bool,
     pub use_code_flow_analysis: bool,
+    pub report_unused_type_ignores: bool,
+    /// Allow rebinding globals in `.bzl` files, which Bazel rejects by default.
+    pub allow_global_reassignment: bool,
 }
 
 #[derive(Default)]
@@ -252,35 +257,12 @@ impl Ty {
         }
 
         let fields = match kind {
-            TyKind::BuiltinType(ty, data) => Fields::Builtin(
-                ty.fields(db)
-                    .iter()
-                    .enumerate()
-                    .map(move |(index, field)| {
-                        let resolved = resolve_builtin_type_ref(db, &field.type_ref).0;
-                        let resolved = match (resolved.kind(), data) {
-                            // If `TyData` is set, this means the current type is either `ctx` or `repository_ctx`.
-                            // Override the `attr` field for both of these types.
-                            (TyKind::Struct(_), Some(TyData::Attributes(attrs)))
-                                if field.name.as_str() == "attr" =>
-                            {
-                                TyKind::Struct(Some(Struct::Attributes {
-                                    attrs: attrs.clone(),
-                                }))
-                                .intern()
-                            }
-                            _ => resolved,
-                        };
-                        let field = Field(FieldInner::BuiltinField { parent: *ty, index });
-                        (field, resolved)
-                    })
-                    .chain(ty.methods(db).iter().map(|func| {
-                        (
-                            Field(FieldInner::BuiltinMethod { func: *func }),
-                            TyKind::BuiltinFunction(*func).intern(),
-                        )
-                    })),
-            ),
+            TyKind::BuiltinType(ty, data) => {
+                Fields::Builtin(builtin_type_fields(db, *ty, data.as_ref()))
+            }
+            // Depsets use the fields and methods of the `depset` type defined in `builtin.pb`.
+            TyKind::Depset(_, Some(ty)) => Fields::Builtin(builtin_type_fields(db, *ty, None)),
+            TyKind::Depset(_, None) => return None,
             TyKind::Union(tys) => {
                 // TODO(withered-magic): Can probably do better than a Vec here?
                 let mut acc = Vec::new();
@@ -313,6 +295,15 @@ impl Ty {
                             }),
                             ty.clone(),
                         )
+                    })
+                    .chain(match strukt {
+                        Some(Struct::RuleFiles {
+                            kind,
+                            attrs,
+                            outputs,
+                            ..
+                        }) => kind.fields(db, attrs, outputs),
+                        _ => Vec::new(),
                     }),
             ),
             TyKind::ProviderInstance(provider) => Fields::Provider(match provider {
@@ -500,6 +491,50 @@ impl Ty {
                         ))),
                 )
             }
+            TyKind::Macro(macro_) => {
+                let mut common_attrs = macro_.common_attrs(db).map(|(index, _, attr)| {
+                    (
+                        Param(RuleParam::BuiltinKeyword(RuleKind::Build, index).into()),
+                        attr.expected_ty(),
+                    )
+                });
+
+                // As with rules, the `name` attribute comes first. Macros don't accept arbitrary
+                // keyword arguments, so there is no `**kwargs` parameter.
+                Params::Macro(
+                    common_attrs
+                        .next()
+                        .into_iter()
+                        .chain(macro_.attrs.iter().map(|(name, attr)| {
+                            (
+                                Param(
+                                    RuleParam::Keyword {
+                                        name: name.clone(),
+                                        attr: attr.clone(),
+                                    }
+                                    .into(),
+                                ),
+                                attr.expected_ty(),
+                            )
+                        }))
+                        .chain(common_attrs),
+                )
+            }
+            TyKind::Subrule(subrule) => {
+                Params::Subrule(subrule.params(db).map(move |(index, param)| {
+                    let def = subrule
+                        .implementation
+                        .as_ref()
+                        .expect("expected implementation");
+                    let file = def.func.file(db);
+                    let ty = with_tcx(db, |tcx| tcx.infer_param(file, param));
+                    let param = Param(ParamInner::Param {
+                        parent: Some(def.func),
+                        index,
+                    });
+                    (param, ty)
+                }))
+            }
             TyKind::Provider(provider) | TyKind::ProviderRawConstructor(_, provider) => {
                 Params::Provider(match provider {
                     Provider::Builtin(builtin_provider) => {
@@ -561,7 +596,11 @@ impl Ty {
             TyKind::Function(def) => resolve_builtin_type_ref_opt(db, def.func.ret_type_ref(db)),
             TyKind::IntrinsicFunction(func, subst) => func.ret_ty(db).substitute(&subst.args),
             TyKind::BuiltinFunction(func) => resolve_builtin_type_ref(db, func.ret_type_ref(db)).0,
-            TyKind::Rule(_) => Ty::none(),
+            TyKind::Rule(_) | TyKind::Macro(_) => Ty::none(),
+            TyKind::Subrule(subrule) => match subrule.implementation {
+                Some(ref def) => resolve_builtin_type_ref_opt(db, def.func.ret_type_ref(db)),
+                None => Ty::unknown(),
+            },
             TyKind::Provider(provider) | TyKind::ProviderRawConstructor(_, provider) => {
                 TyKind::ProviderInstance(provider.clone()).intern()
             }
@@ -602,6 +641,25 @@ impl Ty {
         TyKind::List(ty).intern()
     }
 
+    pub(crate) fn depset(ty: Ty, class: Option<BuiltinType>) -> Ty {
+        TyKind::Depset(ty, class).intern()
+    }
+
+    pub(crate) fn select(ty: Ty) -> Ty {
+        TyKind::Select(ty).intern()
+    }
+
+    /// Returns the types of the branches of a `select()`, or this type itself if it isn't one.
+    pub(crate) fn select_branch_tys(&self) -> SmallVec<[Ty; 2]> {
+        match self.kind() {
+            TyKind::Select(ty) => match ty.kind() {
+                TyKind::Union(tys) => tys.clone(),
+                _ => smallvec![ty.clone()],
+            },
+            _ => smallvec![self.clone()],
+        }
+    }
+
     pub(crate) fn dict(key_ty: Ty, value_ty: Ty, known_keys: Option<Arc<DictLiteral>>) -> Ty {
         TyKind::Dict(key_ty, value_ty, known_keys).intern()
     }
@@ -648,7 +706,6 @@ impl Ty {
         self.kind() == &TyKind::Any
     }
 
-    #[allow(unused)]
     fn is_unknown(&self) -> bool {
         self.kind() == &TyKind::Unknown || self.kind() == &TyKind::Unbound
     }
@@ -668,6 +725,7 @@ impl Ty {
     pub(crate) fn substitute(&self, args: &[Ty]) -> Ty {
         match self.kind() {
             TyKind::List(ty) => Ty::list(ty.substitute(args)),
+            TyKind::Depset(ty, class) => Ty::depset(ty.substitute(args), *class),
             TyKind::Tuple(tup) => match tup {
                 Tuple::Simple(tys) => TyKind::Tuple(Tuple::Simple(
                     tys.iter().map(|ty| ty.substitute(args)).collect(),
@@ -985,6 +1043,43 @@ impl Param {
             })
         })
     }
+
+    /// Returns the source files accepted by the parameter if it is a label-valued attribute, e.g.
+    /// the `srcs` or `deps` of a rule.
+    pub fn allowed_files(&self, db: &dyn Db) -> Option<AllowedFiles> {
+        let common = common_attributes_query(db);
+        let attr: &Attribute = match &self.0 {
+            // The rules provided by `bazel info build-language` don't declare which files their
+            // attributes accept, so allow all of them.
+            ParamInner::BuiltinParam { parent, index } => {
+                let type_ref = parent.params(db)[*index].type_ref()?;
+                return is_label_type_ref(&type_ref).then_some(AllowedFiles::Any);
+            }
+            ParamInner::RuleParam(RuleParam::Keyword { attr, .. })
+            | ParamInner::TagParam(TagParam::Keyword { attr, .. }) => attr,
+            ParamInner::RuleParam(RuleParam::BuiltinKeyword(kind, index)) => {
+                common.get(db, kind.clone(), *index).1
+            }
+            _ => return None,
+        };
+
+        matches!(
+            attr.kind,
+            AttributeKind::Label | AttributeKind::LabelList | AttributeKind::LabelKeyedStringDict
+        )
+        .then(|| attr.allowed_files.clone())
+    }
+}
+
+/// Whether the type is `Label` or a list of `Label`s.
+fn is_label_type_ref(type_ref: &TypeRef) -> bool {
+    match type_ref {
+        TypeRef::Name(name, None) => name.as_str() == "Label",
+        TypeRef::Name(name, Some(args)) if name.as_str() == "list" => {
+            matches!(&args[..], [arg] if is_label_type_ref(arg))
+        }
+        _ => false,
+    }
 }
 
 enum ProviderParams<I1, I2> {
@@ -1007,16 +1102,18 @@ where
     }
 }
 
-enum Params<I1, I2, I3, I4, I5, I6, I7> {
+enum Params<I1, I2, I3, I4, I5, I6, I7, I8, I9> {
     Simple(I1),
     Intrinsic(I2),
     Builtin(I3),
     Rule(I4),
     Provider(ProviderParams<I5, I6>),
     Tag(I7),
+    Macro(I8),
+    Subrule(I9),
 }
 
-impl<I1, I2, I3, I4, I5, I6, I7> Iterator for Params<I1, I2, I3, I4, I5, I6, I7>
+impl<I1, I2, I3, I4, I5, I6, I7, I8, I9> Iterator for Params<I1, I2, I3, I4, I5, I6, I7, I8, I9>
 where
     I1: Iterator<Item = (Param, Ty)>,
     I2: Iterator<Item = (Param, Ty)>,
@@ -1025,6 +1122,8 @@ where
     I5: Iterator<Item = (Param, Ty)>,
     I6: Iterator<Item = (Param, Ty)>,
     I7: Iterator<Item = (Param, Ty)>,
+    I8: Iterator<Item = (Param, Ty)>,
+    I9: Iterator<Item = (Param, Ty)>,
 {
     type Item = (Param, Ty);
 
@@ -1036,6 +1135,8 @@ where
             Params::Rule(it) => it.next(),
             Params::Provider(it) => it.next(),
             Params::Tag(it) => it.next(),
+            Params::Macro(it) => it.next(),
+            Params::Subrule(it) => it.next(),
         }
     }
 }
@@ -1201,7 +1302,76 @@ where
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) enum TyData {
-    Attributes(Arc<Vec<(Name, Arc<Attribute>)>>),
+    /// The rule whose implementation function receives a value of this type.
+    Rule(Rule),
+    /// The aspect whose implementation function receives a value of this type.
+    Aspect(Aspect),
+    /// The subrule whose implementation function receives a value of this type.
+    Subrule(Arc<Subrule>),
+    /// The toolchain types that can be requested from a `ToolchainContext` or `ExecGroupContext`.
+    Toolchains(Arc<ToolchainTypes>),
+    /// The execution groups that can be requested from an `ExecGroupCollection`.
+    ExecGroups(Arc<Vec<(Name, Arc<ToolchainTypes>)>>),
+}
+
+impl TyData {
+    /// Returns the type of the given field of `ctx` or `repository_ctx` if that field depends on
+    /// the rule or aspect being implemented.
+    pub(crate) fn ctx_field_ty(&self, field: &str) -> Option<Ty> {
+        let (attrs, attrs_known, outputs) = match self {
+            TyData::Rule(rule) => {
+                let mut outputs = rule.outputs.to_vec();
+                if rule.executable {
+                    outputs.push(Name::new_inline("executable"));
+                }
+                (&rule.attrs, rule.attrs_known, Arc::new(outputs))
+            }
+            // Aspects don't have outputs of their own.
+            TyData::Aspect(_) if field == "outputs" => return Some(Ty::unknown()),
+            TyData::Aspect(aspect) => (&aspect.attrs, aspect.attrs_known, Default::default()),
+            TyData::Subrule(_) | TyData::Toolchains(_) | TyData::ExecGroups(_) => return None,
+        };
+        let kind = match field {
+            "attr" => {
+                return Some(
+                    TyKind::Struct(Some(Struct::Attributes {
+                        attrs: attrs.clone(),
+                    }))
+                    .intern(),
+                )
+            }
+            "executable" => RuleFilesKind::Executable,
+            "file" => RuleFilesKind::File,
+            "files" => RuleFilesKind::Files,
+            "outputs" => RuleFilesKind::Outputs,
+            _ => return None,
+        };
+        Some(
+            TyKind::Struct(Some(Struct::RuleFiles {
+                kind,
+                attrs: attrs.clone(),
+                attrs_known,
+                outputs,
+            }))
+            .intern(),
+        )
+    }
+
+    /// Returns the data to attach to the given builtin-typed field, which lets `ctx.toolchains`
+    /// and `ctx.exec_groups` know about the toolchains and execution groups declared by the rule.
+    fn field_data(&self, field: &str) -> Option<TyData> {
+        Some(match (self, field) {
+            (TyData::Rule(rule), "toolchains") => TyData::Toolchains(rule.toolchains.clone()),
+            (TyData::Rule(rule), "exec_groups") => TyData::ExecGroups(rule.exec_groups.clone()),
+            (TyData::Subrule(subrule), "toolchains") => {
+                TyData::Toolchains(subrule.toolchains.clone())
+            }
+            (TyData::Toolchains(toolchains), "toolchains") => {
+                TyData::Toolchains(toolchains.clone())
+            }
+            _ => return None,
+        })
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -1263,11 +1433,24 @@ pub(crate) enum TyKind {
     /// A Bazel struct (https://bazel.build/rules/lib/builtins/struct).
     /// Use this instead of the `struct` type defined in `builtin.pb`.
     Struct(Option<Struct>),
+    /// A Bazel depset (https://bazel.build/rules/lib/builtins/depset) whose elements have the
+    /// given type. Use this instead of the `depset` type defined in `builtin.pb`, which is kept
+    /// alongside the element type to provide the depset's fields and methods.
+    Depset(Ty, Option<BuiltinType>),
+    /// A configurable value created with `select()`. The type is the union of the types of the
+    /// select's branches.
+    Select(Ty),
     /// A Bazel attribute (https://bazel.build/rules/lib/builtins/Attribute.html).
     /// Use this instead of the `Attribute` type defined in `builtin.pb`.
     Attribute(Arc<Attribute>),
     /// A Bazel rule (https://bazel.build/rules/lib/builtins/rule).
     Rule(Rule),
+    /// A Bazel aspect (https://bazel.build/rules/lib/builtins/Aspect).
+    Aspect(Aspect),
+    /// A Bazel symbolic macro (https://bazel.build/rules/lib/builtins/macro).
+    Macro(Macro),
+    /// A Bazel subrule (https://bazel.build/rules/lib/builtins/Subrule).
+    Subrule(Arc<Subrule>),
     /// A Bazel provider (https://bazel.build/rules/lib/builtins/Provider.html).
     /// This is a callable that yields "provider instances".
     Provider(Provider),
@@ -1317,6 +1500,12 @@ pub struct Attribute {
     pub doc: Option<Box<str>>,
     pub mandatory: bool,
     pub default_text_range: Option<Either<(File, SyntaxNodePtr), String>>,
+    /// Whether the attribute was declared with `allow_single_file`, which exposes it in `ctx.file`.
+    pub allow_single_file: bool,
+    /// Whether the attribute was declared with `executable = True`, which exposes it in `ctx.executable`.
+    pub executable: bool,
+    /// The source files accepted by the attribute, from its `allow_files` or `allow_single_file` argument.
+    pub allowed_files: AllowedFiles,
 }
 
 impl Attribute {
@@ -1331,6 +1520,9 @@ impl Attribute {
             doc,
             mandatory,
             default_text_range,
+            allow_single_file: false,
+            executable: false,
+            allowed_files: AllowedFiles::None,
         }
     }
 
@@ -1379,6 +1571,80 @@ pub(crate) struct Rule {
     pub(crate) kind: RuleKind,
     pub(crate) doc: Option<Box<str>>,
     pub(crate) attrs: Arc<Vec<(Name, Arc<Attribute>)>>,
+    /// Whether `attrs` contains all of the attributes declared by the rule.
+    pub(crate) attrs_known: bool,
+    /// The names of the rule's predeclared outputs, i.e. the keys of its `outputs` dict.
+    pub(crate) outputs: Arc<Vec<Name>>,
+    /// Whether the rule was declared with `executable = True` or `test = True`.
+    pub(crate) executable: bool,
+    /// The toolchain types required by the rule's default execution group.
+    pub(crate) toolchains: Arc<ToolchainTypes>,
+    /// The rule's additional execution groups, along with the toolchain types each of them requires.
+    pub(crate) exec_groups: Arc<Vec<(Name, Arc<ToolchainTypes>)>>,
+    /// The providers that the rule's implementation function must return.
+    pub(crate) provides: Arc<Vec<Provider>>,
+    /// The subrules that the rule's implementation function is allowed to call, or `None` if the
+    /// `subrules` argument couldn't be evaluated.
+    pub(crate) subrules: Option<Arc<Vec<Arc<Subrule>>>>,
+}
+
+/// The toolchain types required by a rule, subrule or execution group.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct ToolchainTypes {
+    /// The labels of the toolchain types, normalized with `ToolchainTypes::normalize_label`.
+    pub(crate) labels: Vec<Box<str>>,
+    /// Whether `labels` contains all of the required toolchain types, i.e. whether the
+    /// `toolchains` argument could be fully evaluated.
+    pub(crate) known: bool,
+}
+
+impl ToolchainTypes {
+    /// The toolchain types of a rule, subrule or execution group that doesn't require any.
+    pub(crate) fn none() -> Self {
+        Self {
+            labels: Vec::new(),
+            known: true,
+        }
+    }
+
+    /// Returns whether the given toolchain type, referenced from `file`, might be required.
+    pub(crate) fn may_contain(&self, db: &dyn Db, file: File, label: &str) -> bool {
+        if !self.known {
+            return true;
+        }
+        let label = Self::normalize_label(db, file, label);
+        self.labels.iter().any(|other| *other == label)
+    }
+
+    /// Normalizes a toolchain type label referenced from `file`, so that different spellings of
+    /// the same label, e.g. `//cc:toolchain_type` and `@//cc:toolchain_type`, compare equal.
+    /// Relative labels are kept relative, since they're resolved against the package of `file`.
+    pub(crate) fn normalize_label(db: &dyn Db, file: File, label: &str) -> Box<str> {
+        let parsed = match Label::parse(label) {
+            Ok(parsed) => parsed,
+            Err(_) => return label.into(),
+        };
+        if parsed.is_relative() {
+            return format!(":{}", parsed.target()).into_boxed_str();
+        }
+
+        // `@//` and `@@//` refer to the main repository, which is also the current repository
+        // unless `file` belongs to an external repository.
+        let repo = match (parsed.kind(), parsed.repo()) {
+            (RepoKind::Current, _) if file.is_external(db) == Some(true) => "",
+            (RepoKind::Current, _) | (_, "") => "@@",
+            (RepoKind::Apparent, _) => "@",
+            (RepoKind::Canonical, _) => "@@",
+        };
+        format!(
+            "{}{}//{}:{}",
+            repo,
+            parsed.repo(),
+            parsed.package(),
+            parsed.target()
+        )
+        .into_boxed_str()
+    }
 }
 
 impl Rule {
@@ -1400,6 +1666,99 @@ impl Rule {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Aspect {
+    pub(crate) doc: Option<Box<str>>,
+    pub(crate) attrs: Arc<Vec<(Name, Arc<Attribute>)>>,
+    /// Whether `attrs` contains all of the attributes declared by the aspect.
+    pub(crate) attrs_known: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Macro {
+    pub(crate) doc: Option<Box<str>>,
+    /// The macro's own attributes, followed by any attributes inherited through `inherit_attrs`
+    /// that the macro doesn't override.
+    pub(crate) attrs: Arc<Vec<(Name, Arc<Attribute>)>>,
+    /// Whether the macro inherits the common attributes of build rules, e.g. `tags`.
+    pub(crate) inherit_common_attrs: bool,
+    /// Inherited attributes that were removed by setting them to `None` in `attrs`.
+    pub(crate) removed_attrs: Arc<Vec<Name>>,
+}
+
+impl Macro {
+    pub(crate) fn attrs<'a>(&'a self, db: &'a dyn Db) -> impl Iterator<Item = (&Name, &Attribute)> {
+        // This chaining is done to put the `name` attribute first.
+        let mut common_attrs = self.common_attrs(db).map(|(_, name, attr)| (name, attr));
+        common_attrs
+            .next()
+            .into_iter()
+            .chain(self.attrs.iter().map(|(name, attr)| (name, &**attr)))
+            .chain(common_attrs)
+    }
+
+    /// Returns the common build attributes accepted by this macro, along with their indices.
+    /// Every macro accepts `name` and `visibility`, while the remaining common attributes must be
+    /// inherited.
+    pub(crate) fn common_attrs<'a>(
+        &'a self,
+        db: &'a dyn Db,
+    ) -> impl Iterator<Item = (usize, &Name, &Attribute)> {
+        common_attributes_query(db)
+            .build(db)
+            .iter()
+            .enumerate()
+            .filter(move |(_, (name, _))| match name.as_str() {
+                "name" | "visibility" => true,
+                _ => {
+                    self.inherit_common_attrs
+                        && !self.removed_attrs.contains(name)
+                        && !self.attrs.iter().any(|(other, _)| other == name)
+                }
+            })
+            .map(|(index, (name, attr))| (index, name, attr))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Subrule {
+    /// The `subrule()` call that declared the subrule.
+    pub(crate) call_expr: InFile<ExprId>,
+    pub(crate) implementation: Option<FunctionDef>,
+    /// The subrule's implicit attributes, which Bazel passes to the implementation function as
+    /// keyword arguments.
+    pub(crate) attrs: Arc<Vec<(Name, Arc<Attribute>)>>,
+    /// The toolchain types required by the subrule.
+    pub(crate) toolchains: Arc<ToolchainTypes>,
+    /// The subrules that the subrule's implementation function is allowed to call, or `None` if
+    /// the `subrules` argument couldn't be evaluated.
+    pub(crate) subrules: Option<Arc<Vec<Arc<Subrule>>>>,
+}
+
+impl Subrule {
+    /// Returns the parameters of the implementation function that callers pass arguments to,
+    /// along with their indices. This skips the leading `subrule_ctx` parameter and the
+    /// parameters that receive the subrule's attributes.
+    pub(crate) fn params<'a>(
+        &'a self,
+        db: &'a dyn Db,
+    ) -> impl Iterator<Item = (usize, ParamId)> + 'a {
+        self.implementation.iter().flat_map(move |def| {
+            let module = module(db, def.func.file(db));
+            def.func
+                .params(db)
+                .iter()
+                .copied()
+                .enumerate()
+                .skip(1)
+                .filter(move |(_, param)| {
+                    let name = module[*param].name();
+                    !self.attrs.iter().any(|(other, _)| other == name)
+                })
+        })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct CustomProviderFields {
     pub(crate) fields: Box<[ProviderField]>,
@@ -1411,6 +1770,10 @@ pub(crate) struct CustomProvider {
     pub(crate) name: Option<Name>,
     pub(crate) doc: Option<LiteralString>,
     pub(crate) fields: Option<CustomProviderFields>,
+    /// The `provider()` call that declared the provider.
+    pub(crate) call_expr: InFile<ExprId>,
+    /// The `init` callback passed to `provider()`, in the same file as `call_expr`.
+    pub(crate) init: Option<ExprId>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -1447,6 +1810,121 @@ pub(crate) enum Struct {
     Attributes {
         attrs: Arc<Vec<(Name, Arc<Attribute>)>>,
     },
+    /// One of `ctx.executable`, `ctx.file`, `ctx.files` or `ctx.outputs`.
+    RuleFiles {
+        kind: RuleFilesKind,
+        attrs: Arc<Vec<(Name, Arc<Attribute>)>>,
+        attrs_known: bool,
+        outputs: Arc<Vec<Name>>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum RuleFilesKind {
+    Executable,
+    File,
+    Files,
+    Outputs,
+}
+
+impl RuleFilesKind {
+    pub(crate) fn ctx_field_name(&self) -> &'static str {
+        match self {
+            RuleFilesKind::Executable => "executable",
+            RuleFilesKind::File => "file",
+            RuleFilesKind::Files => "files",
+            RuleFilesKind::Outputs => "outputs",
+        }
+    }
+
+    pub(crate) fn description(&self) -> &'static str {
+        match self {
+            RuleFilesKind::Executable => "attributes declared with \"executable = True\"",
+            RuleFilesKind::File => "attributes declared with \"allow_single_file\"",
+            RuleFilesKind::Files => "label attributes",
+            RuleFilesKind::Outputs => "output attributes and predeclared outputs",
+        }
+    }
+
+    /// Returns the type of the given implicit field of this struct, i.e. one that Bazel adds for
+    /// the label attributes common to all rules, rather than for a declared attribute.
+    pub(crate) fn implicit_field_ty(&self, db: &dyn Db, name: &str) -> Option<Ty> {
+        // Only `ctx.files` exposes the common attributes, since none of them are declared with
+        // `allow_single_file` or `executable`.
+        const COMMON_LABEL_ATTRS: &[&str] = &[
+            "applicable_licenses",
+            "compatible_with",
+            "exec_compatible_with",
+            "package_metadata",
+            "restricted_to",
+            "target_compatible_with",
+            "toolchains",
+        ];
+
+        match self {
+            RuleFilesKind::Files if COMMON_LABEL_ATTRS.contains(&name) => {
+                let file_ty = resolve_builtin_type_ref(db, &TypeRef::from_str_opt("File")).0;
+                Some(Ty::list(file_ty))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the fields of this struct. Only the attributes that Bazel exposes through the
+    /// struct are included.
+    pub(crate) fn fields(
+        self,
+        db: &dyn Db,
+        attrs: &[(Name, Arc<Attribute>)],
+        outputs: &[Name],
+    ) -> Vec<(Field, Ty)> {
+        let file_ty = resolve_builtin_type_ref(db, &TypeRef::from_str_opt("File")).0;
+        let field = |name: &Name, doc: Option<&str>, ty: Ty| {
+            (
+                Field(FieldInner::StructField {
+                    name: name.clone(),
+                    doc: doc.map(|doc| doc.to_string()),
+                }),
+                ty,
+            )
+        };
+
+        let mut fields = attrs
+            .iter()
+            .filter_map(|(name, attr)| {
+                let ty = match (self, &attr.kind) {
+                    (RuleFilesKind::Executable, AttributeKind::Label) if attr.executable => {
+                        file_ty.clone()
+                    }
+                    (RuleFilesKind::File, AttributeKind::Label) if attr.allow_single_file => {
+                        file_ty.clone()
+                    }
+                    (
+                        RuleFilesKind::Files,
+                        AttributeKind::Label
+                        | AttributeKind::LabelList
+                        | AttributeKind::LabelKeyedStringDict,
+                    ) => Ty::list(file_ty.clone()),
+                    (RuleFilesKind::Outputs, AttributeKind::Output) => file_ty.clone(),
+                    (RuleFilesKind::Outputs, AttributeKind::OutputList) => {
+                        Ty::list(file_ty.clone())
+                    }
+                    _ => return None,
+                };
+                Some(field(name, attr.doc.as_deref(), ty))
+            })
+            .collect::<Vec<_>>();
+
+        if self == RuleFilesKind::Outputs {
+            fields.extend(
+                outputs
+                    .iter()
+                    .map(|name| field(name, None, file_ty.clone())),
+            );
+        }
+
+        fields
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -1570,6 +2048,10 @@ impl GlobalContext {
         }
     }
 
+    pub fn options(&self) -> &InferenceOptions {
+        &self.shared_state.options
+    }
+
     pub fn cancel(&self) -> CancelGuard {
         CancelGuard::new(self)
     }
@@ -1615,6 +2097,58 @@ pub(crate) struct InferenceContext {
     pub(crate) type_of_param: FxHashMap<FileParamId, Ty>,
     pub(crate) source_assign_done: FxHashSet<FileExprId>,
     pub(crate) flow_node_type_cache: FxHashMap<CodeFlowCacheKey, Option<Ty>>,
+    pub(crate) custom_provider_field_tys: FxHashMap<(File, Provider), Arc<Vec<Ty>>>,
+    pub(crate) toolchain_info_tys: FxHashMap<(File, Box<str>), Ty>,
+    pub(crate) validated_attr_exprs: FxHashSet<FileExprId>,
+    /// The custom providers whose field types are currently being inferred.
+    pub(crate) custom_providers_in_progress: FxHashSet<(File, Provider)>,
+    /// The number of times the placeholder field types of a custom provider in
+    /// `custom_providers_in_progress` were read.
+    pub(crate) custom_provider_placeholder_reads: usize,
+}
+
+impl InferenceContext {
+    /// Takes a snapshot of the inferred types and diagnostics, which can later be restored with
+    /// `InferenceContext::rollback`.
+    pub(crate) fn snapshot(&self) -> InferenceSnapshot {
+        InferenceSnapshot {
+            diagnostics_len: self.diagnostics.len(),
+            type_of_expr: self.type_of_expr.clone(),
+            type_of_load_item: self.type_of_load_item.clone(),
+            type_of_param: self.type_of_param.clone(),
+            source_assign_done: self.source_assign_done.clone(),
+            flow_node_type_cache: self.flow_node_type_cache.clone(),
+            custom_provider_field_tys: self.custom_provider_field_tys.clone(),
+            toolchain_info_tys: self.toolchain_info_tys.clone(),
+            validated_attr_exprs: self.validated_attr_exprs.clone(),
+        }
+    }
+
+    /// Discards everything that was inferred since the given snapshot was taken, so that it is
+    /// inferred again when next needed.
+    pub(crate) fn rollback(&mut self, snapshot: InferenceSnapshot) {
+        self.diagnostics.truncate(snapshot.diagnostics_len);
+        self.type_of_expr = snapshot.type_of_expr;
+        self.type_of_load_item = snapshot.type_of_load_item;
+        self.type_of_param = snapshot.type_of_param;
+        self.source_assign_done = snapshot.source_assign_done;
+        self.flow_node_type_cache = snapshot.flow_node_type_cache;
+        self.custom_provider_field_tys = snapshot.custom_provider_field_tys;
+        self.toolchain_info_tys = snapshot.toolchain_info_tys;
+        self.validated_attr_exprs = snapshot.validated_attr_exprs;
+    }
+}
+
+pub(crate) struct InferenceSnapshot {
+    diagnostics_len: usize,
+    type_of_expr: FxHashMap<FileExprId, Ty>,
+    type_of_load_item: FxHashMap<FileLoadItemId, Ty>,
+    type_of_param: FxHashMap<FileParamId, Ty>,
+    source_assign_done: FxHashSet<FileExprId>,
+    flow_node_type_cache: FxHashMap<CodeFlowCacheKey, Option<Ty>>,
+    custom_provider_field_tys: FxHashMap<(File, Provider), Arc<Vec<Ty>>>,
+    toolchain_info_tys: FxHashMap<(File, Box<str>), Ty>,
+    validated_attr_exprs: FxHashSet<FileExprId>,
 }
 
 pub struct CancelGuard<'a> {
@@ -1773,6 +2307,10 @@ impl<'a, 'b> TypeRefResolver<'a, 'b> {
                 TyKind::Struct(Some(Struct::FieldSignature { ty }))
             }),
             "Target" => TyKind::Target.intern(),
+            "depset" => {
+                let class = builtin_depset_class(self.db, Dialect::Bazel);
+                self.resolve_single_arg_type_constructor(args, |ty| TyKind::Depset(ty, class))
+            }
             "tuple" => match args.as_ref() {
                 Some(args) => {
                     // Handle variable tuples directly. The ellipsis type `...` is valid only when
@@ -1834,6 +2372,42 @@ impl<'a, 'b> TypeRefResolver<'a, 'b> {
     }
 }
 
+fn builtin_type_fields<'a>(
+    db: &'a dyn Db,
+    ty: BuiltinType,
+    data: Option<&'a TyData>,
+) -> impl Iterator<Item = (Field, Ty)> + 'a {
+    ty.fields(db)
+        .iter()
+        .enumerate()
+        .map(move |(index, field)| {
+            let resolved = resolve_builtin_type_ref(db, &field.type_ref).0;
+            let resolved = match (resolved.kind(), data) {
+                // If `TyData` is set, this means the current type is either `ctx` or `repository_ctx`.
+                // Override the `attr` field for both of these types, as well as the fields of `ctx`
+                // that expose the files corresponding to the rule's attributes.
+                (TyKind::Struct(_), Some(data)) => {
+                    data.ctx_field_ty(field.name.as_str()).unwrap_or(resolved)
+                }
+                (TyKind::BuiltinType(field_ty, None), Some(data)) => {
+                    match data.field_data(field.name.as_str()) {
+                        Some(data) => TyKind::BuiltinType(*field_ty, Some(data)).intern(),
+                        None => resolved,
+                    }
+                }
+                _ => resolved,
+            };
+            let field = Field(FieldInner::BuiltinField { parent: ty, index });
+            (field, resolved)
+        })
+        .chain(ty.methods(db).iter().map(|func| {
+            (
+                Field(FieldInner::BuiltinMethod { func: *func }),
+                TyKind::BuiltinFunction(*func).intern(),
+            )
+        }))
+}
+
 pub(crate) fn resolve_type_ref(
     tcx: &mut TyContext,
     type_ref: &TypeRef,
@@ -1858,6 +2432,14 @@ pub(crate) fn resolve_type_ref_opt(
         .unwrap_or_else(Ty::unknown)
 }
 
+/// Returns the `depset` type defined in the builtins of the given dialect, if any.
+pub(crate) fn builtin_depset_class(db: &dyn Db, dialect: Dialect) -> Option<BuiltinType> {
+    match builtin_types(db, dialect).types(db).get("depset")?.kind() {
+        TyKind::BuiltinType(ty, _) => Some(*ty),
+        _ => None,
+    }
+}
+
 pub(crate) fn resolve_builtin_type_ref(db: &dyn Db, type_ref: &TypeRef) -> (Ty, Vec<String>) {
     TypeRefResolver {
         db,
@@ -1882,6 +2464,13 @@ pub(crate) fn assign_tys(db: &dyn Db, source: &Ty, target: &Ty) -> bool {
     // are always valid to avoid confusion.
     match (source.kind(), target.kind()) {
         (TyKind::Any | TyKind::Unknown, _) | (_, TyKind::Any | TyKind::Unknown) => true,
+        // `Never` is the bottom type, and so is assignable to everything.
+        (TyKind::Never, _) => true,
+        // A `select()` is assignable only if each of its branches is.
+        (TyKind::Select(_), _) => source
+            .select_branch_tys()
+            .iter()
+            .all(|source| assign_tys(db, source, target)),
         (
             TyKind::List(source),
             TyKind::List(target) | TyKind::Protocol(Iterable(target) | Sequence(target)),
@@ -1919,6 +2508,13 @@ pub(crate) fn assign_tys(db: &dyn Db, source: &Ty, target: &Ty) -> bool {
         {
             true
         }
+        (TyKind::Depset(source, _), TyKind::Depset(target, _)) => assign_tys(db, source, target),
+        (TyKind::Depset(..), TyKind::BuiltinType(ty, _))
+        | (TyKind::BuiltinType(ty, _), TyKind::Depset(..))
+            if ty.name(db).as_str() == "depset" =>
+        {
+            true
+        }
         (TyKind::Union(source_tys), TyKind::Union(target_tys)) => {
             source_tys.iter().all(|source_ty| {
                 target_tys
@@ -9,19 +9,19 @@ use starpls_bazel::{
     env::{self, make_workspace_builtins},
     Builtins, BUILTINS_TYPES_DENY_LIST, BUILTINS_VALUES_DENY_LIST, KNOWN_PROVIDER_TYPES,
 };
-use starpls_common::{parse, Dialect, File, InFile};
+use starpls_common::{parse, AllowedFiles, Dialect, File, InFile};
 use starpls_syntax::ast::{self, AstNode};
 
 use crate::{
     def::{
         resolver::{Export, Resolver},
-        Argument,
+        Argument, Expr, Literal,
     },
-    source_map,
+    module, source_map,
     typeck::{
-        Attribute, AttributeData, AttributeKind, CustomProvider, CustomProviderFields,
-        ModuleExtension, Provider, ProviderField, Rule as TyRule, RuleKind, Struct, TagClass,
-        TagClassData, Tuple,
+        Aspect, Attribute, AttributeData, AttributeKind, CustomProvider, CustomProviderFields,
+        Macro, ModuleExtension, Provider, ProviderField, Rule as TyRule, RuleKind, Struct, Subrule,
+        TagClass, TagClassData, ToolchainTypes, Tuple,
     },
     Db, ExprId, Name, Ty, TyContext, TyKind, TypeRef,
 };
@@ -185,9 +185,9 @@ impl BuiltinFunction {
             (None, "provider") => {
                 let mut fields = None;
                 let mut doc = None;
-                let mut has_init = false;
+                let mut init = None;
                 for (arg, ty) in args {
-                    if let Argument::Keyword { name, .. } = arg {
+                    if let Argument::Keyword { name, expr } = arg {
                         match name.as_str() {
                             "doc" => {
                                 if let TyKind::String(Some(s)) = ty.kind() {
@@ -221,10 +221,28 @@ impl BuiltinFunction {
                                             })
                                             .collect(),
                                     });
+                                } else if let Expr::List { exprs } = &module(db, file)[*expr] {
+                                    fields = Some(CustomProviderFields {
+                                        expr: None,
+                                        fields: exprs
+                                            .iter()
+                                            .filter_map(|expr| match &module(db, file)[*expr] {
+                                                Expr::Literal {
+                                                    literal: Literal::String(s),
+                                                } if !s.value(db).is_empty() => {
+                                                    Some(ProviderField {
+                                                        name: Name::from_str(s.value(db)),
+                                                        doc: None,
+                                                    })
+                                                }
+                                                _ => None,
+                                            })
+                                            .collect(),
+                                    });
                                 }
                             }
                             "init" => {
-                                has_init = true;
+                                init = Some(*expr);
                             }
                             _ => {}
                         }
@@ -256,7 +274,7 @@ impl BuiltinFunction {
                     })
                 };
 
-                if has_init {
+                if init.is_some() {
                     let (provider_name, ctor_name) = lhs
                         .and_then(|lhs| match lhs {
                             ast::Expression::Tuple(tuple_expr) => {
@@ -273,6 +291,11 @@ impl BuiltinFunction {
                         name: provider_name,
                         doc,
                         fields,
+                        call_expr: InFile {
+                            file,
+                            value: call_expr,
+                        },
+                        init,
                     }));
 
                     TyKind::Tuple(Tuple::Simple(smallvec![
@@ -296,22 +319,51 @@ impl BuiltinFunction {
                         name,
                         doc,
                         fields,
+                        call_expr: InFile {
+                            file,
+                            value: call_expr,
+                        },
+                        init,
                     })))
                 }
             }
 
             (None, name @ ("rule" | "repository_rule")) => {
                 let mut attrs = None;
+                let mut attrs_known = true;
+                let mut executable = false;
                 let mut doc = None;
+                let mut outputs = None;
+                let mut toolchains = None;
+                let mut exec_groups = None;
+                let mut provides = None;
+                let mut subrules = Some(Vec::new());
                 for (arg, ty) in args {
-                    if let Argument::Keyword { name, .. } = arg {
+                    if let Argument::Keyword { name, expr } = arg {
                         match name.as_str() {
                             "doc" => {
                                 if let TyKind::String(Some(s)) = ty.kind() {
                                     doc = Some(*s);
                                 }
                             }
+                            // Executable and test rules predeclare `ctx.outputs.executable`.
+                            "executable" | "test" => {
+                                if let TyKind::Bool(Some(true)) = ty.kind() {
+                                    executable = true;
+                                }
+                            }
+                            "outputs" => {
+                                if let TyKind::Dict(_, _, Some(lit)) = ty.kind() {
+                                    outputs = Some(
+                                        lit.known_keys
+                                            .iter()
+                                            .map(|(name, _)| Name::from_str(&name.value(db)))
+                                            .collect::<Vec<_>>(),
+                                    )
+                                }
+                            }
                             "attrs" => {
+                                attrs_known = are_attrs_known(db, ty);
                                 if let TyKind::Dict(_, _, Some(lit)) = ty.kind() {
                                     attrs = Some(
                                         lit.known_keys
@@ -327,6 +379,32 @@ impl BuiltinFunction {
                                     )
                                 }
                             }
+                            "toolchains" => {
+                                toolchains = Some(collect_toolchain_types(db, file, *expr));
+                            }
+                            "exec_groups" => {
+                                exec_groups = Some(collect_exec_groups(db, file, *expr));
+                            }
+                            "provides" => {
+                                if let Expr::List { exprs } = &module(db, file)[*expr] {
+                                    provides = Some(
+                                        exprs
+                                            .iter()
+                                            .filter_map(|expr| {
+                                                match tcx.infer_expr(file, *expr).kind() {
+                                                    TyKind::Provider(provider) => {
+                                                        Some(provider.clone())
+                                                    }
+                                                    _ => None,
+                                                }
+                                            })
+                                            .collect::<Vec<_>>(),
+                                    );
+                                }
+                            }
+                            "subrules" => {
+                                subrules = collect_subrules(tcx, file, *expr);
+                            }
                             _ => {}
                         }
                     }
@@ -340,6 +418,177 @@ impl BuiltinFunction {
                     },
                     doc: doc.map(|doc| doc.value(db).clone()),
                     attrs: Arc::new(attrs.unwrap_or_default()),
+                    attrs_known,
+                    outputs: Arc::new(outputs.unwrap_or_default()),
+                    executable,
+                    toolchains: Arc::new(toolchains.unwrap_or_else(ToolchainTypes::none)),
+                    exec_groups: Arc::new(exec_groups.unwrap_or_default()),
+                    provides: Arc::new(provides.unwrap_or_default()),
+                    subrules: subrules.map(Arc::new),
+                })
+            }
+
+            (None, "subrule") => {
+                let mut implementation = None;
+                let mut attrs = None;
+                let mut toolchains = None;
+                let mut subrules = Some(Vec::new());
+                for (arg, ty) in args {
+                    if let Argument::Keyword { name, expr } = arg {
+                        match name.as_str() {
+                            "implementation" => {
+                                if let TyKind::Function(def) = ty.kind() {
+                                    implementation = Some(def.clone());
+                                }
+                            }
+                            "attrs" => {
+                                if let TyKind::Dict(_, _, Some(lit)) = ty.kind() {
+                                    attrs = Some(
+                                        lit.known_keys
+                                            .iter()
+                                            .filter_map(|(name, ty)| match ty.kind() {
+                                                TyKind::Attribute(attr) => Some((
+                                                    Name::from_str(&name.value(db)),
+                                                    attr.clone(),
+                                                )),
+                                                _ => None,
+                                            })
+                                            .collect::<Vec<_>>(),
+                                    )
+                                }
+                            }
+                            "toolchains" => {
+                                toolchains = Some(collect_toolchain_types(db, file, *expr));
+                            }
+                            "subrules" => {
+                                subrules = collect_subrules(tcx, file, *expr);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                TyKind::Subrule(Arc::new(Subrule {
+                    call_expr: InFile {
+                        file,
+                        value: call_expr,
+                    },
+                    implementation,
+                    attrs: Arc::new(attrs.unwrap_or_default()),
+                    toolchains: Arc::new(toolchains.unwrap_or_else(ToolchainTypes::none)),
+                    subrules: subrules.map(Arc::new),
+                }))
+            }
+
+            (None, "aspect") => {
+                let mut attrs = None;
+                let mut attrs_known = true;
+                let mut doc = None;
+                for (arg, ty) in args {
+                    if let Argument::Keyword { name, .. } = arg {
+                        match name.as_str() {
+                            "doc" => {
+                                if let TyKind::String(Some(s)) = ty.kind() {
+                                    doc = Some(s.value(db).clone());
+                                }
+                            }
+                            "attrs" => {
+                                attrs_known = are_attrs_known(db, ty);
+                                if let TyKind::Dict(_, _, Some(lit)) = ty.kind() {
+                                    attrs = Some(
+                                        lit.known_keys
+                                            .iter()
+                                            .filter_map(|(name, ty)| match ty.kind() {
+                                                TyKind::Attribute(attr) => Some((
+                                                    Name::from_str(&name.value(db)),
+                                                    attr.clone(),
+                                                )),
+                                                _ => None,
+                                            })
+                                            .collect::<Vec<_>>(),
+                                    )
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                TyKind::Aspect(Aspect {
+                    doc,
+                    attrs: Arc::new(attrs.unwrap_or_default()),
+                    attrs_known,
+                })
+            }
+
+            (None, "macro") => {
+                let mut attrs = Vec::new();
+                let mut removed_attrs = Vec::new();
+                let mut doc = None;
+                let mut inherit_attrs = None;
+                for (arg, ty) in args {
+                    if let Argument::Keyword { name, .. } = arg {
+                        match name.as_str() {
+                            "doc" => {
+                                if let TyKind::String(Some(s)) = ty.kind() {
+                                    doc = Some(s.value(db).clone());
+                                }
+                            }
+                            "attrs" => {
+                                if let TyKind::Dict(_, _, Some(lit)) = ty.kind() {
+                                    for (name, ty) in lit.known_keys.iter() {
+                                        let name = Name::from_str(&name.value(db));
+                                        match ty.kind() {
+                                            TyKind::Attribute(attr) => {
+                                                attrs.push((name, attr.clone()))
+                                            }
+                                            // Setting an attribute to `None` removes it from
+                                            // the inherited attributes.
+                                            TyKind::None => removed_attrs.push(name),
+                                            _ => {}
+                                        }
+                                    }
+                                }
+                            }
+                            "inherit_attrs" => inherit_attrs = Some(ty.clone()),
+                            _ => {}
+                        }
+                    }
+                }
+
+                // Inherit the attributes of the referenced rule or macro, unless they have been
+                // overridden or removed. The special value "common" only inherits the common
+                // build attributes.
+                let mut inherit_common_attrs = false;
+                let inherited_attrs = match inherit_attrs.as_ref().map(|ty| ty.kind()) {
+                    Some(TyKind::Rule(rule)) if rule.kind == RuleKind::Build => {
+                        inherit_common_attrs = true;
+                        rule.attrs.iter().cloned().collect()
+                    }
+                    Some(TyKind::Macro(macro_)) => {
+                        inherit_common_attrs = macro_.inherit_common_attrs;
+                        removed_attrs.extend(macro_.removed_attrs.iter().cloned());
+                        macro_.attrs.iter().cloned().collect()
+                    }
+                    Some(TyKind::String(Some(s))) if s.value(db).as_ref() == "common" => {
+                        inherit_common_attrs = true;
+                        Vec::new()
+                    }
+                    _ => Vec::new(),
+                };
+                for (name, attr) in inherited_attrs {
+                    if !removed_attrs.contains(&name)
+                        && !attrs.iter().any(|(other, _)| *other == name)
+                    {
+                        attrs.push((name, attr));
+                    }
+                }
+
+                TyKind::Macro(Macro {
+                    doc,
+                    attrs: Arc::new(attrs),
+                    inherit_common_attrs,
+                    removed_attrs: Arc::new(removed_attrs),
                 })
             }
 
@@ -347,9 +596,26 @@ impl BuiltinFunction {
                 let mut doc: Option<Box<str>> = None;
                 let mut mandatory = false;
                 let mut default_ptr = None;
+                let mut allow_single_file = false;
+                let mut executable = false;
+                let mut allowed_files = AllowedFiles::None;
                 for (arg, ty) in args {
                     if let Argument::Keyword { name, expr } = arg {
                         match name.as_str() {
+                            // `allow_single_file` accepts either a boolean or a list of file extensions.
+                            "allow_single_file" => {
+                                allow_single_file =
+                                    !matches!(ty.kind(), TyKind::Bool(Some(false)) | TyKind::None);
+                                allowed_files = allowed_files_for_arg(db, file, *expr, ty);
+                            }
+                            "allow_files" => {
+                                allowed_files = allowed_files_for_arg(db, file, *expr, ty);
+                            }
+                            "executable" => {
+                                if let TyKind::Bool(Some(b)) = ty.kind() {
+                                    executable = *b;
+                                }
+                            }
                             "doc" => {
                                 if let TyKind::String(Some(s)) = ty.kind() {
                                     doc = Some(s.value(db).clone());
@@ -370,7 +636,7 @@ impl BuiltinFunction {
                     }
                 }
 
-                TyKind::Attribute(Arc::new(Attribute::new(
+                let mut attribute = Attribute::new(
                     match attr {
                         "bool" => AttributeKind::Bool,
                         "int" => AttributeKind::Int,
@@ -389,7 +655,11 @@ impl BuiltinFunction {
                     doc,
                     mandatory,
                     default_ptr.map(|text_range| Either::Left((file, text_range))),
-                )))
+                );
+                attribute.allow_single_file = allow_single_file;
+                attribute.executable = executable;
+                attribute.allowed_files = allowed_files;
+                TyKind::Attribute(Arc::new(attribute))
             }
 
             (None, "tag_class") => {
@@ -763,6 +1033,7 @@ pub(crate) fn builtin_types_query(db: &dyn Db, defs: BuiltinDefs) -> BuiltinType
 
         let indexable_by = match type_.name.as_str() {
             "ToolchainContext" => Some(("string", "ToolchainInfo")),
+            "ExecGroupCollection" => Some(("string", "ExecGroupContext")),
             // TODO(withered-magic): Audit Bazel docs for other indexable builtin types.
             _ => None,
         }
@@ -939,6 +1210,14 @@ pub(crate) fn common_attributes_query(db: &dyn Db) -> CommonAttributes {
                         doc: Some(normalize_doc_text(&attr.doc).into_boxed_str()),
                         mandatory: attr.is_mandatory,
                         default_text_range: Some(Either::Right(attr.default_value)),
+                        allow_single_file: false,
+                        executable: false,
+                        // Of the common attributes, only `data` accepts source files.
+                        allowed_files: if attr.name == "data" {
+                            AllowedFiles::Any
+                        } else {
+                            AllowedFiles::None
+                        },
                     },
                 )
             })
@@ -1017,6 +1296,7 @@ fn parse_type_ref(text: &str) -> TypeRef {
                     type_ref_with_single_arg("Sequence", element)
                 }
                 (Some("List" | "list"), element) => type_ref_with_single_arg("list", element),
+                (Some("depset"), element) => type_ref_with_single_arg("depset", element),
                 (Some("Dict" | "dict" | "Dictionary"), element) => TypeRef::Name(
                     Name::new_inline("dict"),
                     Some(
@@ -1056,12 +1336,14 @@ fn type_ref_with_single_arg(name: &str, element: Option<&str>) -> TypeRef {
 
 fn maybe_field_type_ref_override(typ: &str, field: &str) -> Option<TypeRef> {
     let type_ref = match (typ, field) {
-        ("ctx", "executable" | "file" | "outputs") => TypeRef::Name(
-            Name::new_inline("struct"),
-            Some(vec![TypeRef::Name(Name::new_inline("File"), None)].into_boxed_slice()),
-        ),
+        ("ctx", "executable" | "file" | "outputs") | ("rule_attributes", "executable" | "file") => {
+            TypeRef::Name(
+                Name::new_inline("struct"),
+                Some(vec![TypeRef::Name(Name::new_inline("File"), None)].into_boxed_slice()),
+            )
+        }
 
-        ("ctx", "files") => TypeRef::Name(
+        ("ctx" | "rule_attributes", "files") => TypeRef::Name(
             Name::new_inline("struct"),
             Some(
                 vec![TypeRef::Name(
@@ -1077,6 +1359,143 @@ fn maybe_field_type_ref_override(typ: &str, field: &str) -> Option<TypeRef> {
     Some(type_ref)
 }
 
+/// Determines whether the `attrs` argument of `rule()` or `aspect()` declares all of its attributes
+/// explicitly, i.e. it is a dict literal whose keys are all string literals. This isn't the case for
+/// e.g. `dict(_ATTRS, **_MORE_ATTRS)` or `_ATTRS | {...}`, whose attributes can't be listed.
+fn are_attrs_known(db: &dyn Db, ty: &Ty) -> bool {
+    let lit = match ty.kind() {
+        TyKind::Dict(_, _, Some(lit)) => lit,
+        _ => return false,
+    };
+    match lit.expr {
+        Some(InFile { file, value }) => match &module(db, file)[value] {
+            Expr::Dict { entries } => {
+                entries.len() == lit.known_keys.len()
+                    && lit
+                        .known_keys
+                        .iter()
+                        .all(|(_, ty)| matches!(ty.kind(), TyKind::Attribute(_)))
+            }
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+/// Collects the toolchain types from the `toolchains` argument of `rule()` or `exec_group()`. Each
+/// element is either a label string or a call like `config_common.toolchain_type()` or `Label()`
+/// whose first argument is one.
+fn collect_toolchain_types(db: &dyn Db, file: File, expr: ExprId) -> ToolchainTypes {
+    let module = module(db, file);
+    let exprs = match &module[expr] {
+        Expr::List { exprs } => exprs,
+        _ => return ToolchainTypes::default(),
+    };
+
+    let labels = exprs
+        .iter()
+        .filter_map(|expr| {
+            let expr = match &module[*expr] {
+                Expr::Call { args, .. } => match args.first()? {
+                    Argument::Simple { expr } => *expr,
+                    Argument::Keyword { name, expr } if name.as_str() == "name" => *expr,
+                    _ => return None,
+                },
+                _ => *expr,
+            };
+            match &module[expr] {
+                Expr::Literal {
+                    literal: Literal::String(s),
+                } => Some(ToolchainTypes::normalize_label(db, file, s.value(db))),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    ToolchainTypes {
+        known: labels.len() == exprs.len(),
+        labels,
+    }
+}
+
+/// Determines the source files accepted by an attribute from its `allow_files` or
+/// `allow_single_file` argument, which is either a boolean or a list of file extensions.
+fn allowed_files_for_arg(db: &dyn Db, file: File, expr: ExprId, ty: &Ty) -> AllowedFiles {
+    match ty.kind() {
+        TyKind::Bool(Some(false)) | TyKind::None => return AllowedFiles::None,
+        TyKind::Bool(_) => return AllowedFiles::Any,
+        _ => {}
+    }
+
+    let module = module(db, file);
+    let exprs = match &module[expr] {
+        Expr::List { exprs } => exprs,
+        _ => return AllowedFiles::Any,
+    };
+    exprs
+        .iter()
+        .map(|expr| match &module[*expr] {
+            Expr::Literal {
+                literal: Literal::String(s),
+            } => Some(s.value(db).clone()),
+            _ => None,
+        })
+        .collect::<Option<_>>()
+        .map(AllowedFiles::Extensions)
+        .unwrap_or(AllowedFiles::Any)
+}
+
+/// Collects the subrules listed in the `subrules` argument of `rule()` or `subrule()`. Returns
+/// `None` if the argument isn't a list literal of subrules.
+fn collect_subrules(tcx: &mut TyContext, file: File, expr: ExprId) -> Option<Vec<Arc<Subrule>>> {
+    let exprs = match &module(tcx.db, file)[expr] {
+        Expr::List { exprs } => exprs,
+        _ => return None,
+    };
+
+    exprs
+        .iter()
+        .map(|expr| match tcx.infer_expr(file, *expr).kind() {
+            TyKind::Subrule(subrule) => Some(subrule.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collects the execution groups declared by the `exec_groups` argument of `rule()`, along with
+/// the toolchain types passed to each group's `exec_group()` call.
+fn collect_exec_groups(db: &dyn Db, file: File, expr: ExprId) -> Vec<(Name, Arc<ToolchainTypes>)> {
+    let module = module(db, file);
+    let entries = match &module[expr] {
+        Expr::Dict { entries } => entries,
+        _ => return Vec::new(),
+    };
+
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let name = match &module[entry.key] {
+                Expr::Literal {
+                    literal: Literal::String(s),
+                } => Name::from_str(s.value(db)),
+                _ => return None,
+            };
+            let toolchains = match &module[entry.value] {
+                Expr::Call { args, .. } => args
+                    .iter()
+                    .find_map(|arg| match arg {
+                        Argument::Keyword { name, expr } if name.as_str() == "toolchains" => {
+                            Some(collect_toolchain_types(db, file, *expr))
+                        }
+                        _ => None,
+                    })
+                    .unwrap_or_else(ToolchainTypes::none),
+                _ => ToolchainTypes::default(),
+            };
+            Some((name, Arc::new(toolchains)))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
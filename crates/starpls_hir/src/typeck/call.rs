@@ -8,7 +8,7 @@ use smallvec::{smallvec, SmallVec};
 use crate::{
     def::{Argument, Param},
     typeck::{
-        builtins::BuiltinFunctionParam, intrinsics::IntrinsicFunctionParam, Provider, Rule,
+        builtins::BuiltinFunctionParam, intrinsics::IntrinsicFunctionParam, Macro, Provider, Rule,
         TagClass,
     },
     Db, ExprId, Name,
@@ -214,6 +214,21 @@ impl Slots {
         }
     }
 
+    pub(crate) fn from_macro(db: &dyn Db, macro_: &Macro) -> Self {
+        // Unlike rules, macros don't accept arbitrary keyword arguments.
+        Self {
+            slots: macro_
+                .attrs(db)
+                .map(|(name, _)| Slot::Keyword {
+                    name: name.clone(),
+                    provider: SlotProvider::Missing,
+                    positional: false,
+                })
+                .collect(),
+            disable_errors: false,
+        }
+    }
+
     pub(crate) fn from_tag_class(tag_class: &TagClass) -> Self {
         Self {
             slots: tag_class
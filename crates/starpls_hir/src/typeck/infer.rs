@@ -1,6 +1,12 @@
-use std::sync::Arc;
-
-use starpls_common::{line_index, parse, Diagnostic, File, FileRange, InFile, Severity};
+use std::{slice, sync::Arc};
+
+use either::Either;
+use rustc_hash::FxHashSet;
+use starpls_bazel::label::Label;
+use starpls_common::{
+    parse, Diagnostic, DiagnosticCode, DiagnosticTag, Dialect, File, FileRange, Fix, InFile,
+    RelatedInformation, ResolvedPath, Severity, SourceEdit,
+};
 use starpls_syntax::{
     ast::{self, ArithOp, AstNode, AstPtr, BinaryOp, BitwiseOp, LogicOp, UnaryOp},
     TextRange,
@@ -8,25 +14,32 @@ use starpls_syntax::{
 
 use crate::{
     def::{
-        codeflow::{code_flow_graph, CodeFlowGraph, FlowNode, FlowNodeId},
+        codeflow::{
+            code_flow_graph, narrowing_for_condition, CodeFlowGraph, FlowNode, FlowNodeId,
+            NarrowingKind,
+        },
         resolver::{Export, Resolver},
-        scope::{ExecutionScopeId, LoadItemDef, ParameterDef, ScopeDef, ScopeHirId, VariableDef},
-        Argument, Expr, ExprId, Literal, LiteralString, LoadItem, LoadItemId, LoadStmt, Param,
-        ParamId, Stmt, StmtId,
+        scope::{
+            ExecutionScopeId, FunctionDef, LoadItemDef, ParameterDef, ScopeDef, ScopeHirId,
+            VariableDef,
+        },
+        targets::{file_targets, TargetDef},
+        Argument, Expr, ExprId, Literal, LiteralString, LoadItem, LoadItemId, LoadStmt, Module,
+        Param, ParamId, Stmt, StmtId,
     },
     display::DisplayWithDb,
     module, source_map,
     typeck::{
-        assign_tys,
-        builtins::builtin_types,
+        assign_tys, builtin_depset_class,
+        builtins::{builtin_types, BuiltinFunction},
         call::{Slot, SlotProvider, Slots},
         intrinsics::{IntrinsicFunctionParam, IntrinsicTypes},
-        resolve_builtin_type_ref, resolve_type_ref, resolve_type_ref_opt, CodeFlowCacheKey,
-        DictLiteral, FileExprId, FileLoadItemId, FileLoadStmt, FileParamId, Protocol, Provider,
-        RuleKind, Struct, Substitution, Tuple, Ty, TyContext, TyData, TyKind, TypeRef,
-        TypecheckCancelled,
+        resolve_builtin_type_ref, resolve_type_ref, resolve_type_ref_opt, Attribute, AttributeKind,
+        CodeFlowCacheKey, DictLiteral, FileExprId, FileLoadItemId, FileLoadStmt, FileParamId,
+        Protocol, Provider, Rule, RuleKind, Struct, Subrule, Substitution, ToolchainTypes, Tuple,
+        Ty, TyContext, TyData, TyKind, TypeRef, TypecheckCancelled,
     },
-    Name,
+    Db, Name,
 };
 
 impl TyContext<'_> {
@@ -56,22 +69,43 @@ impl TyContext<'_> {
     }
 
     pub fn diagnostics_for_file(&self, file: File) -> Vec<Diagnostic> {
-        let line_index = line_index(self.db, file);
-        let module = module(self.db, file);
-        self.cx
+        let mut diagnostics = self
+            .cx
             .diagnostics
             .iter()
-            .filter(|diagnostic| {
-                if diagnostic.range.file_id != file.id(self.db) {
-                    return false;
-                }
-                let start_line = line_index.line_col(diagnostic.range.range.start()).line;
-                let end_line = line_index.line_col(diagnostic.range.range.end()).line;
-                (start_line..=end_line)
-                    .all(|line| !module.type_ignore_comment_lines.contains(&line))
-            })
+            .filter(|diagnostic| diagnostic.range.file_id == file.id(self.db))
             .cloned()
-            .collect()
+            .collect::<Vec<_>>();
+
+        // Report unreachable code, e.g. statements following a `return` or a call to `fail()`.
+        if self.shared_state.options.use_code_flow_analysis {
+            let cfg = code_flow_graph(self.db, file).cfg(self.db);
+            let source_map = source_map(self.db, file);
+            let stmt_range = |stmt| {
+                source_map
+                    .stmt_map_back
+                    .get(stmt)
+                    .map(|ptr| ptr.syntax_node_ptr().text_range())
+            };
+            for (first, last) in cfg.unreachable_stmts.iter() {
+                if let (Some(first), Some(last)) = (stmt_range(first), stmt_range(last)) {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            DiagnosticCode::UnreachableCode,
+                            Severity::Hint,
+                            FileRange {
+                                file_id: file.id(self.db),
+                                range: first.cover(last),
+                            },
+                            "Code is unreachable",
+                        )
+                        .with_tag(DiagnosticTag::Unnecessary),
+                    );
+                }
+            }
+        }
+
+        diagnostics
     }
 
     fn unwind_if_cancelled(&self) {
@@ -105,12 +139,14 @@ impl TyContext<'_> {
                     self.add_expr_diagnostic_error(
                         file,
                         expr,
+                        DiagnosticCode::UndefinedName,
                         format!("\"{}\" is not defined", name.as_str()),
                     );
                 } else if ty.is_possibly_unbound() {
                     self.add_expr_diagnostic_error(
                         file,
                         expr,
+                        DiagnosticCode::PossiblyUnbound,
                         format!("\"{}\" is possibly unbound", name.as_str()),
                     )
                 }
@@ -192,10 +228,17 @@ impl TyContext<'_> {
             } => {
                 let receiver_ty = self.infer_expr(file, *dot_expr);
                 match receiver_ty.kind() {
-                    TyKind::Unknown
-                    | TyKind::Unbound
-                    | TyKind::Any
-                    | TyKind::ProviderInstance(Provider::Custom(_)) => self.unknown_ty(),
+                    TyKind::Unknown | TyKind::Unbound | TyKind::Any => self.unknown_ty(),
+                    TyKind::ProviderInstance(provider @ Provider::Custom(custom_provider)) => {
+                        match custom_provider.fields.as_ref().and_then(|fields| {
+                            fields.fields.iter().position(|other| &other.name == field)
+                        }) {
+                            Some(index) => {
+                                self.infer_custom_provider_field_tys(file, provider)[index].clone()
+                            }
+                            None => self.unknown_ty(),
+                        }
+                    }
                     _ => {
                         if field.is_missing() {
                             return self.unknown_ty();
@@ -229,6 +272,51 @@ impl TyContext<'_> {
                                             })
                                             .unwrap_or_else(|| self.unknown_ty());
                                     }
+                                    TyKind::Struct(Some(Struct::RuleFiles {
+                                        kind,
+                                        attrs,
+                                        attrs_known,
+                                        ..
+                                    })) => {
+                                        // If the rule's attributes aren't all known, the field
+                                        // might belong to one of the unknown attributes.
+                                        if !attrs_known {
+                                            return self.unknown_ty();
+                                        }
+
+                                        // Fields that don't correspond to any declared attribute
+                                        // may still be provided by Bazel for the label attributes
+                                        // common to all rules.
+                                        let is_declared =
+                                            attrs.iter().any(|(name, _)| name == field);
+                                        if !is_declared {
+                                            if let Some(ty) =
+                                                kind.implicit_field_ty(db, field.as_str())
+                                            {
+                                                return ty;
+                                            }
+                                        }
+
+                                        return self.add_expr_diagnostic_error_ty(
+                                            file,
+                                            expr,
+                                            DiagnosticCode::AttrAccess,
+                                            if is_declared {
+                                                format!(
+                                                    "Cannot access field \"{}\" of \"ctx.{}\", which only contains {}",
+                                                    field.as_str(),
+                                                    kind.ctx_field_name(),
+                                                    kind.description()
+                                                )
+                                            } else {
+                                                format!(
+                                                    "Cannot access field \"{}\" of \"ctx.{}\", the rule has no attribute with that name",
+                                                    field.as_str(),
+                                                    kind.ctx_field_name(),
+                                                )
+                                            },
+                                        );
+                                    }
                                     TyKind::Struct(_) | TyKind::ProviderInstance(_) => {
                                         return self.unknown_ty()
                                     }
@@ -238,6 +326,7 @@ impl TyContext<'_> {
                                 self.add_expr_diagnostic_warning_ty(
                                     file,
                                     expr,
+                                    DiagnosticCode::AttrAccess,
                                     format!(
                                         "Cannot access field \"{}\" for type \"{}\"",
                                         field.as_str(),
@@ -265,6 +354,7 @@ impl TyContext<'_> {
                                 None => self.add_expr_diagnostic_error_ty(
                                     file,
                                     expr,
+                                    DiagnosticCode::Index,
                                     format!(
                                         "Index {} is out of range for type {}",
                                         x,
@@ -276,6 +366,7 @@ impl TyContext<'_> {
                             _ => self.add_expr_diagnostic_error_ty(
                                 file,
                                 expr,
+                                DiagnosticCode::Index,
                                 format!(
                                     "Cannot index tuple with type \"{}\"",
                                     index_ty.display(db).alt()
@@ -297,6 +388,18 @@ impl TyContext<'_> {
                                 TyKind::Provider(provider),
                             ) => Some(TyKind::ProviderInstance(provider.clone()).intern()),
                             (TyKind::Any | TyKind::Unknown, _) => Some(Ty::unknown()),
+                            (
+                                TyKind::BuiltinType(ty, Some(TyData::Toolchains(toolchains))),
+                                TyKind::String(Some(label)),
+                            ) if ty.name(db).as_str() == "ToolchainContext" => Some(
+                                self.infer_toolchain_index(file, expr, toolchains, label.value(db)),
+                            ),
+                            (
+                                TyKind::BuiltinType(ty, Some(TyData::ExecGroups(exec_groups))),
+                                TyKind::String(Some(name)),
+                            ) if ty.name(db).as_str() == "ExecGroupCollection" => {
+                                self.infer_exec_group_index(file, expr, exec_groups, name.value(db))
+                            }
                             (TyKind::BuiltinType(ty, _), _) => match ty.indexable_by(db) {
                                 Some((expected_index_ty, return_ty)) => {
                                     let expected_index_ty =
@@ -316,6 +419,7 @@ impl TyContext<'_> {
                             self.add_expr_diagnostic_warning_ty(
                                 file,
                                 expr,
+                                DiagnosticCode::Index,
                                 format!("Type \"{}\" is not indexable", lhs_ty.display(db).alt()),
                             )
                         });
@@ -330,6 +434,7 @@ impl TyContext<'_> {
                     self.add_expr_diagnostic_warning_ty(
                         file,
                         *lhs,
+                        DiagnosticCode::Index,
                         format!(
                             "Cannot index {} with type \"{}\"",
                             name,
@@ -350,19 +455,19 @@ impl TyContext<'_> {
                                 self.add_expr_diagnostic_error(
                                     file,
                                     *expr,
+                                    DiagnosticCode::CallArg,
                                     String::from(
                                         "Positional argument cannot follow keyword arguments",
-                                    ),
-                                );
+                                    ));
                             }
                             if saw_unpacked_dict {
                                 self.add_expr_diagnostic_error(
                                     file,
                                     *expr,
+                                    DiagnosticCode::CallArg,
                                     String::from(
                                         "Positional argument cannot follow keyword argument unpacking",
-                                    ),
-                                );
+                                    ));
                             }
                             self.infer_expr(file, *expr)
                         }
@@ -375,19 +480,19 @@ impl TyContext<'_> {
                                 self.add_expr_diagnostic_error(
                                     file,
                                     *expr,
+                                    DiagnosticCode::CallArg,
                                     String::from(
                                         "Unpacked iterable argument cannot follow keyword arguments",
-                                    ),
-                                );
+                                    ));
                             }
                             if saw_unpacked_dict {
                                 self.add_expr_diagnostic_error(
                                     file,
                                     *expr,
+                                    DiagnosticCode::CallArg,
                                     String::from(
                                         "Unpacked iterable argument cannot follow keyword argument unpacking",
-                                    ),
-                                );
+                                    ));
                             }
                             self.infer_expr(file, *expr)
                         }
@@ -400,88 +505,38 @@ impl TyContext<'_> {
                 let args_with_ty = args.iter().zip(arg_tys.iter());
 
                 match callee_ty.kind() {
-                    TyKind::Function(def) => {
-                        let module = module(db, def.func.file(db));
-                        let params = def.func.params(db).iter().copied();
-                        let mut slots: Slots = params
-                            .clone()
-                            .map(|param| module[param].clone())
-                            .collect::<Vec<_>>()[..]
-                            .into();
-                        let errors = slots.assign_args(args, None).0;
-
-                        for error in errors {
-                            self.add_expr_diagnostic_error(file, error.expr, error.message);
-                        }
-
-                        let mut missing_params = Vec::new();
-
-                        // Validate argument types.
-                        for (param, slot) in params.zip(slots.into_inner()) {
-                            let hir_param = &module[param];
-                            let param_ty =
-                                resolve_type_ref_opt(self, hir_param.type_ref(), Some(def.stmt));
-
-                            // TODO(withered-magic): Deduplicate the following logic for
-                            // validating providers, as it's currently shared between
-                            // the handlers for `Function`s, `IntrinsicFunction`s, and
-                            // `BuiltinFunction`s.
-                            let mut validate_provider = |provider| match provider {
-                                SlotProvider::Missing => {
-                                    if !hir_param.is_optional() {
-                                        let name = hir_param.name();
-                                        if !name.is_missing() {
-                                            missing_params.push(name.clone());
-                                        }
-                                    }
-                                }
-                                SlotProvider::Single(expr, index) => {
-                                    let ty = &arg_tys[index];
-                                    if !assign_tys(db, ty, &param_ty) {
-                                        self.add_expr_diagnostic_error(file, expr, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), param_ty.display(self.db).alt()));
-                                    }
-                                }
-                                _ => {}
-                            };
-
-                            match slot {
-                                Slot::Positional { provider } | Slot::Keyword { provider, .. } => {
-                                    validate_provider(provider);
-                                }
-                                Slot::ArgsList { providers, .. }
-                                | Slot::KwargsDict { providers } => {
-                                    providers.into_iter().for_each(validate_provider);
-                                }
-                            }
-                        }
-
-                        // Emit diagnostic for missing parameters.
-                        if !missing_params.is_empty() {
-                            let mut message = String::from("Argument missing for parameter(s) ");
-                            for (i, name) in missing_params.into_iter().enumerate() {
-                                if i > 0 {
-                                    message.push_str(", ");
-                                }
-                                message.push('"');
-                                message.push_str(name.as_str());
-                                message.push('"');
-                            }
-
-                            self.add_expr_diagnostic_error(file, expr, message);
+                    TyKind::Function(def) => self.infer_function_call(
+                        file,
+                        expr,
+                        def,
+                        def.func.params(db),
+                        args,
+                        &arg_tys,
+                    ),
+                    TyKind::Subrule(subrule) => match subrule.implementation {
+                        // The implementation function's leading `subrule_ctx` parameter and its
+                        // attribute parameters are passed by Bazel, not by the caller.
+                        Some(ref def) => {
+                            let params = subrule
+                                .params(db)
+                                .map(|(_, param)| param)
+                                .collect::<Vec<_>>();
+                            self.infer_function_call(file, expr, def, &params, args, &arg_tys)
                         }
-
-                        def.func
-                            .ret_type_ref(db)
-                            .map(|type_ref| resolve_type_ref(self, &type_ref, Some(def.stmt)).0)
-                            .unwrap_or_else(|| self.unknown_ty())
-                    }
+                        None => self.unknown_ty(),
+                    },
                     TyKind::IntrinsicFunction(func, subst) => {
                         let params = func.params(db);
                         let mut slots: Slots = params[..].into();
                         let errors = slots.assign_args(args, None).0;
 
                         for error in errors {
-                            self.add_expr_diagnostic_error(file, error.expr, error.message);
+                            self.add_expr_diagnostic_error(
+                                file,
+                                error.expr,
+                                DiagnosticCode::CallArg,
+                                error.message,
+                            );
                         }
 
                         // Validate argument types.
@@ -500,6 +555,7 @@ impl TyContext<'_> {
                                         self.add_expr_diagnostic_error(
                                             file,
                                             expr,
+                                            DiagnosticCode::CallArg,
                                             format!(
                                                 "Missing expected argument of type \"{}\"",
                                                 param_ty.display(db)
@@ -510,7 +566,7 @@ impl TyContext<'_> {
                                 SlotProvider::Single(expr, index) => {
                                     let ty = &arg_tys[index];
                                     if !assign_tys(db, ty, &param_ty) {
-                                        self.add_expr_diagnostic_error(file, expr, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), param_ty.display(self.db).alt()));
+                                        self.add_expr_diagnostic_error(file, expr, DiagnosticCode::ArgType, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), param_ty.display(self.db).alt()));
                                     }
                                 }
                                 _ => {}
@@ -536,7 +592,12 @@ impl TyContext<'_> {
                         let errors = slots.assign_args(args, None).0;
 
                         for error in errors {
-                            self.add_expr_diagnostic_error(file, error.expr, error.message);
+                            self.add_expr_diagnostic_error(
+                                file,
+                                error.expr,
+                                DiagnosticCode::CallArg,
+                                error.message,
+                            );
                         }
 
                         let mut missing_params = Vec::new();
@@ -556,7 +617,7 @@ impl TyContext<'_> {
                                 SlotProvider::Single(expr, index) => {
                                     let ty = &arg_tys[index];
                                     if !assign_tys(db, ty, &param_ty) {
-                                        self.add_expr_diagnostic_error(file, expr, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), param_ty.display(self.db).alt()));
+                                        self.add_expr_diagnostic_error(file, expr, DiagnosticCode::ArgType, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), param_ty.display(self.db).alt()));
                                     }
                                 }
                                 _ => {}
@@ -585,115 +646,118 @@ impl TyContext<'_> {
                                 message.push('"');
                             }
 
-                            self.add_expr_diagnostic_error(file, expr, message);
+                            self.add_expr_diagnostic_error(
+                                file,
+                                expr,
+                                DiagnosticCode::CallArg,
+                                message,
+                            );
+                        }
+
+                        let is_global = func.parent_type(db).is_none();
+                        if is_global && func.name(db).as_str() == "aspect" {
+                            self.validate_aspect_args(file, args);
                         }
 
-                        func.maybe_unique_ret_type(self, file, expr, args_with_ty)
-                            .unwrap_or_else(|| {
-                                resolve_type_ref(self, func.ret_type_ref(db), None).0
-                            })
-                    }
-                    TyKind::Rule(rule) => {
-                        let mut slots = Slots::from_rule(db, rule);
-                        slots.assign_args(args, None);
+                        if is_global && func.name(db).as_str() == "use_repo" {
+                            self.validate_use_repo(file, expr, args);
+                        }
 
-                        let mut missing_attrs = Vec::new();
+                        if is_global && func.name(db).as_str() == "include" {
+                            self.validate_include(file, expr);
+                        }
 
-                        // Validate argument types.
-                        for ((name, attr), slot) in rule.attrs(db).zip(slots.into_inner()) {
-                            let expected_ty = attr.expected_ty();
-                            if let Slot::Keyword { provider, .. } = slot {
-                                match provider {
-                                    SlotProvider::Single(expr, index) => {
-                                        let ty = &arg_tys[index];
-                                        if !assign_tys(db, ty, &expected_ty) {
-                                            self.add_expr_diagnostic_error(file, expr, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), expected_ty.display(self.db).alt()));
-                                        }
-                                    }
-                                    SlotProvider::Missing => {
-                                        if attr.mandatory {
-                                            missing_attrs.push(name);
-                                        }
-                                    }
-                                    _ => {}
-                                }
+                        let ret_ty = if is_global && func.name(db).as_str() == "depset" {
+                            self.infer_depset_call(file, args)
+                        } else if is_global && func.name(db).as_str() == "select" {
+                            self.infer_select_call(file, args)
+                        } else {
+                            match self.infer_depset_method_call(file, *callee, *func) {
+                                Some(ty) => ty,
+                                None => func
+                                    .maybe_unique_ret_type(self, file, expr, args_with_ty)
+                                    .unwrap_or_else(|| {
+                                        resolve_type_ref(self, func.ret_type_ref(db), None).0
+                                    }),
                             }
+                        };
+
+                        if is_global
+                            && matches!(
+                                func.name(db).as_str(),
+                                "rule" | "aspect" | "repository_rule" | "tag_class"
+                            )
+                        {
+                            self.validate_attrs_dict(file, args);
                         }
 
-                        // Emit diagnostic for missing parameters.
-                        if !missing_attrs.is_empty() {
-                            let mut message = String::from("Argument missing for attribute(s) ");
-                            for (i, name) in missing_attrs.iter().enumerate() {
-                                if i > 0 {
-                                    message.push_str(", ");
-                                }
-                                message.push('"');
-                                message.push_str(name.as_str());
-                                message.push('"');
+                        if let TyKind::Rule(
+                            rule @ Rule {
+                                kind: RuleKind::Build,
+                                ..
+                            },
+                        ) = ret_ty.kind()
+                        {
+                            // The implementation function's `ctx` parameter is typed from this
+                            // call, so record the rule's type before inferring its return values.
+                            self.set_expr_type(file, expr, ret_ty.clone());
+                            self.validate_rule_impl_returns(file, args, rule);
+                            if let Some(subrules) = rule.subrules.as_deref() {
+                                self.validate_subrule_calls(file, args, subrules);
+                            }
+                        }
+
+                        if let TyKind::Subrule(subrule) = ret_ty.kind() {
+                            self.set_expr_type(file, expr, ret_ty.clone());
+                            self.validate_subrule_attrs(file, args);
+                            if let Some(subrules) = subrule.subrules.as_deref() {
+                                self.validate_subrule_calls(file, args, subrules);
                             }
+                        }
+
+                        ret_ty
+                    }
+                    TyKind::Rule(rule) => {
+                        let mut slots = Slots::from_rule(db, rule);
+                        slots.assign_args(args, None);
+                        self.validate_attr_call_args(file, expr, rule.attrs(db), slots, &arg_tys);
+                        self.none_ty()
+                    }
+                    TyKind::Macro(macro_) => {
+                        let mut slots = Slots::from_macro(db, macro_);
+                        let errors = slots.assign_args(args, None).0;
 
-                            self.add_expr_diagnostic_error(file, expr, message);
+                        for error in errors {
+                            self.add_expr_diagnostic_error(
+                                file,
+                                error.expr,
+                                DiagnosticCode::CallArg,
+                                error.message,
+                            );
                         }
 
+                        self.validate_attr_call_args(file, expr, macro_.attrs(db), slots, &arg_tys);
                         self.none_ty()
                     }
                     TyKind::Provider(provider) | TyKind::ProviderRawConstructor(_, provider) => {
                         TyKind::ProviderInstance(provider.clone()).intern()
                     }
                     TyKind::Tag(tag_class) => {
-                        // TODO(withered-magic): Much of this logic is duplicated from handling `TyKind::Rule` above.
                         let mut slots = Slots::from_tag_class(tag_class);
                         slots.assign_args(args, None);
-
-                        let mut missing_attrs = Vec::new();
-
-                        // Validate argument types.
-                        for (data, slot) in tag_class
+                        let attrs = tag_class
                             .attrs
                             .iter()
                             .flat_map(|attrs| attrs.iter())
-                            .zip(slots.into_inner())
-                        {
-                            let expected_ty = data.attr.expected_ty();
-                            if let Slot::Keyword { provider, .. } = slot {
-                                match provider {
-                                    SlotProvider::Single(expr, index) => {
-                                        let ty = &arg_tys[index];
-                                        if !assign_tys(db, ty, &expected_ty) {
-                                            self.add_expr_diagnostic_error(file, expr, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), expected_ty.display(self.db).alt()));
-                                        }
-                                    }
-                                    SlotProvider::Missing => {
-                                        if data.attr.mandatory {
-                                            missing_attrs.push(&data.name);
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-
-                        // Emit diagnostic for missing parameters.
-                        if !missing_attrs.is_empty() {
-                            let mut message = String::from("Argument missing for attribute(s) ");
-                            for (i, name) in missing_attrs.iter().enumerate() {
-                                if i > 0 {
-                                    message.push_str(", ");
-                                }
-                                message.push('"');
-                                message.push_str(name.as_str());
-                                message.push('"');
-                            }
-
-                            self.add_expr_diagnostic_error(file, expr, message);
-                        }
-
+                            .map(|data| (&data.name, &*data.attr));
+                        self.validate_attr_call_args(file, expr, attrs, slots, &arg_tys);
                         self.none_ty()
                     }
                     TyKind::Unknown | TyKind::Any | TyKind::Unbound => self.unknown_ty(),
                     _ => self.add_expr_diagnostic_warning_ty(
                         file,
                         expr,
+                        DiagnosticCode::NotCallable,
                         format!("Type \"{}\" is not callable", callee_ty.display(db).alt()),
                     ),
                 }
@@ -732,6 +796,7 @@ impl TyContext<'_> {
                         self.add_expr_diagnostic_error(
                             file,
                             expr,
+                            DiagnosticCode::ArgType,
                             "`start`, `stop`, and `step` operands must be integers or `None`",
                         )
                     }
@@ -755,6 +820,7 @@ impl TyContext<'_> {
                     _ => self.add_expr_diagnostic_warning_ty(
                         file,
                         expr,
+                        DiagnosticCode::Index,
                         format!("Cannot slice expression of type \"{}\"", lhs_ty.display(db)),
                     ),
                 }
@@ -772,6 +838,7 @@ impl TyContext<'_> {
             Err(()) => self.add_expr_diagnostic_error_ty(
                 file,
                 parent,
+                DiagnosticCode::Operator,
                 format!(
                     "Operator \"{}\" is not supported for type \"{}\"",
                     op,
@@ -806,15 +873,44 @@ impl TyContext<'_> {
         rhs: ExprId,
         op: BinaryOp,
     ) -> Ty {
-        let db = self.db;
         let lhs = self.infer_expr(file, lhs);
         let rhs = self.infer_expr(file, rhs);
+
+        // Concatenating or merging a value with a `select()` yields another `select()`, whose
+        // branches are the results of applying the operator to each of the original branches.
+        if matches!(
+            op,
+            BinaryOp::Arith(ArithOp::Add) | BinaryOp::Bitwise(BitwiseOp::Or)
+        ) && (matches!(lhs.kind(), TyKind::Select(_)) || matches!(rhs.kind(), TyKind::Select(_)))
+        {
+            let mut tys = Vec::new();
+            for lhs in lhs.select_branch_tys() {
+                for rhs in rhs.select_branch_tys() {
+                    tys.push(self.infer_binary_tys(file, parent, lhs.clone(), rhs, op));
+                }
+            }
+            return Ty::select(Ty::union(tys.into_iter()));
+        }
+
+        self.infer_binary_tys(file, parent, lhs, rhs, op)
+    }
+
+    fn infer_binary_tys(
+        &mut self,
+        file: File,
+        parent: ExprId,
+        lhs: Ty,
+        rhs: Ty,
+        op: BinaryOp,
+    ) -> Ty {
+        let db = self.db;
         let lhs_kind = lhs.kind();
         let rhs_kind = rhs.kind();
         let mut unknown = || {
             self.add_expr_diagnostic_warning_ty(
                 file,
                 parent,
+                DiagnosticCode::Operator,
                 format!(
                     "Operator \"{}\" not supported for types \"{}\" and \"{}\"",
                     op,
@@ -904,6 +1000,7 @@ impl TyContext<'_> {
                     self.add_expr_diagnostic_warning(
                         file,
                         parent,
+                        DiagnosticCode::Operator,
                         format!(
                             "Operator \"{}\" not supported for types \"{}\" and \"{}\"",
                             op,
@@ -915,13 +1012,14 @@ impl TyContext<'_> {
                 self.bool_ty()
             }
             BinaryOp::Logic(LogicOp::Or) => match (lhs_kind, rhs_kind) {
-                // TODO(withered-magic): Strip None from optional types once we implement narrowing.
                 (TyKind::Bool(Some(lhs)), TyKind::Bool(Some(rhs))) => {
                     TyKind::Bool(Some(*lhs || *rhs)).intern()
                 }
                 (TyKind::Bool(Some(false)) | TyKind::Int(Some(0)) | TyKind::None, _) => rhs,
                 (TyKind::Tuple(Tuple::Simple(tys)), _) if tys.is_empty() => rhs,
-                _ => Ty::union([lhs, rhs].into_iter()),
+                // The left-hand side is only the result if it is truthy, e.g. `None` is stripped
+                // from optional types.
+                _ => Ty::union([lhs.narrow_truthiness(db, true), rhs].into_iter()),
             },
             BinaryOp::Logic(LogicOp::And) => match (lhs_kind, rhs_kind) {
                 (TyKind::Bool(Some(lhs)), TyKind::Bool(Some(rhs))) => {
@@ -1011,6 +1109,7 @@ impl TyContext<'_> {
                             for error in errors.iter() {
                                 self.add_diagnostic_for_range(
                                     file,
+                                    DiagnosticCode::TypeComment,
                                     Severity::Error,
                                     type_ref.1,
                                     error,
@@ -1061,6 +1160,7 @@ impl TyContext<'_> {
                 self.add_expr_diagnostic_warning(
                     file,
                     source,
+                    DiagnosticCode::NotIterable,
                     format!("Type \"{}\" is not iterable", source_ty.display(db)),
                 );
                 for expr in targets.iter() {
@@ -1133,8 +1233,24 @@ impl TyContext<'_> {
                     self.infer_assign(*file, expr, source, known_ty.clone())
                 }));
 
-                if known_ty.is_some() {
-                    return known_ty;
+                if let Some(known_ty) = known_ty {
+                    // Known types can still be narrowed by conditions in the same execution scope.
+                    return Some(
+                        if self.shared_state.options.use_code_flow_analysis
+                            && def_execution_scope == curr_execution_scope
+                        {
+                            self.narrow_known_ty_from_code_flow(
+                                file,
+                                name,
+                                hir_id,
+                                curr_execution_scope,
+                                &known_ty,
+                            )
+                            .unwrap_or(known_ty)
+                        } else {
+                            known_ty
+                        },
+                    );
                 }
 
                 (def_execution_scope, effective_ty)
@@ -1210,6 +1326,41 @@ impl TyContext<'_> {
         self.infer_ref_from_flow_node(cfg, file, execution_scope, name, start_ty, *start_node)
     }
 
+    fn narrow_known_ty_from_code_flow(
+        &mut self,
+        file: File,
+        name: &Name,
+        usage: ScopeHirId,
+        execution_scope: ExecutionScopeId,
+        known_ty: &Ty,
+    ) -> Option<Ty> {
+        // Unlike with `infer_name_from_code_flow`, unreachable usages keep their known type.
+        let cfg = code_flow_graph(self.db, file).cfg(self.db);
+        let start_node = cfg.hir_to_flow_node.get(&usage)?;
+        self.infer_ref_from_flow_node(cfg, file, execution_scope, name, known_ty, *start_node)
+    }
+
+    /// Narrowing on `type(x) == "..."` only applies if `type` refers to the builtin function, and
+    /// not e.g. to a shadowing variable.
+    fn is_valid_narrowing(&self, file: File, kind: NarrowingKind) -> bool {
+        let callee = match kind {
+            NarrowingKind::TypeIs { callee, .. } => callee,
+            _ => return true,
+        };
+        let name = match &module(self.db, file)[callee] {
+            Expr::Name { name } => name,
+            _ => return false,
+        };
+        let resolver = Resolver::new_for_hir_execution_scope(self.db, file, callee.into());
+        resolver.resolve_name(name).is_none()
+            && matches!(
+                resolver
+                    .resolve_name_in_prelude_or_builtins(name)
+                    .as_deref(),
+                Some([ScopeDef::IntrinsicFunction(_), ..])
+            )
+    }
+
     /// Returning `None` here means that code-flow analysis failed and that a fallback type should
     /// be returned instead.
     fn infer_ref_from_flow_node(
@@ -1272,7 +1423,43 @@ impl TyContext<'_> {
                     }
                     Ty::union(antecedent_tys.into_iter())
                 }
-                FlowNode::Loop { .. } => Ty::unknown(), // TODO(withered-magic): Correctly handle loops.
+                FlowNode::Condition {
+                    expr,
+                    is_true,
+                    execution_scope: condition_execution_scope,
+                    antecedent,
+                } => {
+                    let module = module(self.db, file);
+                    let narrowing = match narrowing_for_condition(module, *expr) {
+                        Some(narrowing)
+                            if narrowing.name == name
+                                && execution_scope == *condition_execution_scope
+                                && self.is_valid_narrowing(file, narrowing.kind) =>
+                        {
+                            narrowing.kind
+                        }
+                        _ => {
+                            curr_node_id = *antecedent;
+                            continue;
+                        }
+                    };
+
+                    match self.infer_ref_from_flow_node(
+                        cfg,
+                        file,
+                        execution_scope,
+                        name,
+                        start_ty,
+                        *antecedent,
+                    ) {
+                        Some(antecedent_ty) => antecedent_ty.narrow(self.db, narrowing, *is_true),
+                        None => break 'outer None,
+                    }
+                }
+                // TODO(withered-magic): Correctly handle loops. For now, names with known types
+                // fall back to those types, discarding any narrowing from before the loop.
+                FlowNode::Loop { .. } if !start_ty.is_unbound() => start_ty.clone(),
+                FlowNode::Loop { .. } => Ty::unknown(),
                 FlowNode::Unreachable { .. } => Ty::never(),
             };
 
@@ -1337,12 +1524,12 @@ impl TyContext<'_> {
                         self.add_expr_diagnostic_error(
                             file,
                             root,
+                            DiagnosticCode::Assignment,
                             format!(
                                 "Expression of type \"{}\" cannot be assigned to variable of type \"{}\"",
                                 source_ty.display(self.db),
                                 expected_ty.display(self.db)
-                            ),
-                        )
+                            ))
                     }
                     self.set_expr_type(file, expr, expected_ty);
                 } else {
@@ -1384,6 +1571,7 @@ impl TyContext<'_> {
                     self.add_expr_diagnostic_error(
                         file,
                         root,
+                        DiagnosticCode::Assignment,
                         format!(
                             "Tuple size mismatch, {} on left-hand side and {} on right-hand side",
                             exprs.len(),
@@ -1401,6 +1589,7 @@ impl TyContext<'_> {
                 self.add_expr_diagnostic_warning(
                     file,
                     root,
+                    DiagnosticCode::NotIterable,
                     format!("Type \"{}\" is not iterable", source_ty.display(self.db)),
                 );
                 for expr in exprs.iter() {
@@ -1447,19 +1636,27 @@ impl TyContext<'_> {
         &mut self,
         file: File,
         expr: ExprId,
+        code: DiagnosticCode,
         message: T,
     ) {
-        self.add_expr_diagnostic_with_severity(file, expr, Severity::Warning, message)
+        self.add_expr_diagnostic_with_severity(file, expr, code, Severity::Warning, message)
     }
 
-    fn add_expr_diagnostic_error<T: Into<String>>(&mut self, file: File, expr: ExprId, message: T) {
-        self.add_expr_diagnostic_with_severity(file, expr, Severity::Error, message)
+    fn add_expr_diagnostic_error<T: Into<String>>(
+        &mut self,
+        file: File,
+        expr: ExprId,
+        code: DiagnosticCode,
+        message: T,
+    ) {
+        self.add_expr_diagnostic_with_severity(file, expr, code, Severity::Error, message)
     }
 
     fn add_expr_diagnostic_with_severity<T: Into<String>>(
         &mut self,
         file: File,
         expr: ExprId,
+        code: DiagnosticCode,
         severity: Severity,
         message: T,
     ) {
@@ -1467,16 +1664,17 @@ impl TyContext<'_> {
             Some(ptr) => ptr.syntax_node_ptr().text_range(),
             None => return,
         };
-        self.add_diagnostic_for_range(file, severity, range, message);
+        self.add_diagnostic_for_range(file, code, severity, range, message);
     }
 
     fn add_expr_diagnostic_error_ty<T: Into<String>>(
         &mut self,
         file: File,
         expr: ExprId,
+        code: DiagnosticCode,
         message: T,
     ) -> Ty {
-        self.add_expr_diagnostic_error(file, expr, message);
+        self.add_expr_diagnostic_error(file, expr, code, message);
         self.unknown_ty()
     }
 
@@ -1484,27 +1682,34 @@ impl TyContext<'_> {
         &mut self,
         file: File,
         expr: ExprId,
+        code: DiagnosticCode,
         message: T,
     ) -> Ty {
-        self.add_expr_diagnostic_warning(file, expr, message);
+        self.add_expr_diagnostic_warning(file, expr, code, message);
         self.unknown_ty()
     }
 
     fn add_diagnostic_for_range<T: Into<String>>(
         &mut self,
         file: File,
+        code: DiagnosticCode,
         severity: Severity,
         range: TextRange,
         message: T,
     ) {
-        self.cx.diagnostics.push(Diagnostic {
-            message: message.into(),
+        self.add_diagnostic(Diagnostic::new(
+            code,
             severity,
-            range: FileRange {
+            FileRange {
                 file_id: file.id(self.db),
                 range,
             },
-        });
+            message,
+        ));
+    }
+
+    fn add_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.cx.diagnostics.push(diagnostic);
     }
 
     pub(crate) fn infer_param(&mut self, file: File, param: ParamId) -> Ty {
@@ -1516,7 +1721,10 @@ impl TyContext<'_> {
             .shared_state
             .options
             .infer_ctx_attributes
-            .then(|| self.infer_param_from_rule_usage(file, param))
+            .then(|| {
+                self.infer_param_from_rule_usage(file, param)
+                    .or_else(|| self.infer_param_from_map_each_usage(file, param))
+            })
             .and_then(|ty| ty)
             .unwrap_or_else(|| {
                 let module = module(self.db, file);
@@ -1553,44 +1761,1328 @@ impl TyContext<'_> {
 
     fn infer_param_from_rule_usage(&mut self, file: File, param: ParamId) -> Option<Ty> {
         let module = module(self.db, file);
-        let name = match module[*module.param_to_def_stmt.get(&param)?] {
-            Stmt::Def { func, .. } if func.params(self.db).len() == 1 => func.name(self.db),
+        let func = match module[*module.param_to_def_stmt.get(&param)?] {
+            Stmt::Def { func, .. } => func,
+            _ => return None,
+        };
+        let params = func.params(self.db);
+        let index = params.iter().position(|other| *other == param)?;
+        let name = func.name(self.db);
+        let call_ty = self.infer_expr(file, *module.call_expr_with_impl_fn.get(&name)?);
+
+        // Symbolic macro implementations receive their attributes as keyword arguments. Only
+        // `name` and `visibility` can be typed without knowing how the attributes were declared.
+        if let TyKind::Macro(_) = call_ty.kind() {
+            return match module[param].name().as_str() {
+                "name" => Some(Ty::string()),
+                "visibility" => Some(Ty::list(
+                    resolve_builtin_type_ref(self.db, &TypeRef::from_str_opt("Label")).0,
+                )),
+                _ => None,
+            };
+        }
+
+        // Subrule implementations take a `subrule_ctx` parameter, followed by the caller's
+        // arguments. Bazel passes the subrule's attributes as keyword arguments.
+        if let TyKind::Subrule(subrule) = call_ty.kind() {
+            if index > 0 {
+                let name = module[param].name();
+                return subrule
+                    .attrs
+                    .iter()
+                    .find(|(other, _)| other == name)
+                    .map(|(_, attr)| self.subrule_attr_ty(attr));
+            }
+        }
+
+        // Rule implementations take a single `ctx` parameter, while aspect implementations take
+        // the target that the aspect is being applied to, followed by `ctx`.
+        let (ctx_name, data) = match (call_ty.kind(), params.len(), index) {
+            (TyKind::Rule(rule), 1, 0) => (
+                match rule.kind {
+                    RuleKind::Build => "ctx",
+                    RuleKind::Repository => "repository_ctx",
+                },
+                TyData::Rule(rule.clone()),
+            ),
+            (TyKind::Aspect(_), 2, 0) => return Some(Ty::target()),
+            (TyKind::Aspect(aspect), 2, 1) => ("ctx", TyData::Aspect(aspect.clone())),
+            (TyKind::Subrule(subrule), _, 0) => ("subrule_ctx", TyData::Subrule(subrule.clone())),
             _ => return None,
         };
-        match self
-            .infer_expr(file, *module.call_expr_with_impl_fn.get(&name)?)
+        match builtin_types(self.db, file.dialect(self.db))
+            .types(self.db)
+            .get(ctx_name)?
             .kind()
         {
-            TyKind::Rule(rule) => {
-                let ty = builtin_types(self.db, file.dialect(self.db))
-                    .types(self.db)
-                    .get(match rule.kind {
-                        RuleKind::Build => "ctx",
-                        RuleKind::Repository => "repository_ctx",
-                    })?;
-                match ty.kind() {
-                    TyKind::BuiltinType(ty, _) => Some(
-                        TyKind::BuiltinType(*ty, Some(TyData::Attributes(rule.attrs.clone())))
-                            .intern(),
-                    ),
-                    _ => None,
-                }
-            }
+            TyKind::BuiltinType(ty, _) => Some(TyKind::BuiltinType(*ty, Some(data)).intern()),
             _ => None,
         }
     }
 
-    fn lower_param_type_ref(
-        &mut self,
-        file: File,
-        param: ParamId,
-        type_ref: &TypeRef,
-        usage: Option<StmtId>,
-    ) -> Ty {
-        let (ty, errors) = usage.map_or_else(
-            || resolve_builtin_type_ref(self.db, type_ref),
-            |usage| resolve_type_ref(self, type_ref, Some(InFile { file, value: usage })),
-        );
+    /// Infers the type of the first parameter of a function passed as `map_each` to
+    /// `Args.add_all()` or `Args.add_joined()`, which is called with each element of the
+    /// values being added.
+    fn infer_param_from_map_each_usage(&mut self, file: File, param: ParamId) -> Option<Ty> {
+        let module = module(self.db, file);
+        let func = match module[*module.param_to_def_stmt.get(&param)?] {
+            Stmt::Def { func, .. } => func,
+            _ => return None,
+        };
+        if func.params(self.db).first() != Some(&param) {
+            return None;
+        }
+        let call_expr = *module.call_expr_with_map_each_fn.get(&func.name(self.db))?;
+        let (callee, args) = match &module[call_expr] {
+            Expr::Call { callee, args } => (*callee, args),
+            _ => return None,
+        };
+        match self.infer_expr(file, callee).kind() {
+            TyKind::BuiltinFunction(func)
+                if func.parent_type(self.db).as_deref() == Some("Args")
+                    && matches!(func.name(self.db).as_str(), "add_all" | "add_joined") => {}
+            _ => return None,
+        }
+
+        // The values are either the only positional argument, or follow the argument name.
+        let mut positional = args.iter().filter_map(|arg| match arg {
+            Argument::Simple { expr } => Some(*expr),
+            _ => None,
+        });
+        let values = args
+            .iter()
+            .find_map(|arg| match arg {
+                Argument::Keyword { name, expr } if name.as_str() == "values" => Some(*expr),
+                _ => None,
+            })
+            .or_else(|| {
+                let first = positional.next();
+                positional.next().or(first)
+            })?;
+        match self.infer_expr(file, values).kind() {
+            TyKind::Depset(ty, _) | TyKind::List(ty) => Some(ty.clone()),
+            _ => None,
+        }
+    }
+
+    /// Validates the arguments of a call to a rule, macro or tag class against the corresponding
+    /// attributes, given the slots that the arguments were assigned to.
+    fn validate_attr_call_args<'a>(
+        &mut self,
+        file: File,
+        expr: ExprId,
+        attrs: impl Iterator<Item = (&'a Name, &'a Attribute)>,
+        slots: Slots,
+        arg_tys: &[Ty],
+    ) {
+        let db = self.db;
+        let mut missing_attrs = Vec::new();
+
+        // Validate argument types.
+        for ((name, attr), slot) in attrs.zip(slots.into_inner()) {
+            let expected_ty = attr.expected_ty();
+            if let Slot::Keyword { provider, .. } = slot {
+                match provider {
+                    SlotProvider::Single(expr, index) => {
+                        let ty = &arg_tys[index];
+                        if !assign_tys(db, ty, &expected_ty) {
+                            self.add_expr_diagnostic_error(file, expr, DiagnosticCode::ArgType, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), expected_ty.display(self.db).alt()));
+                        }
+                    }
+                    SlotProvider::Missing => {
+                        if attr.mandatory {
+                            missing_attrs.push(name);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Emit diagnostic for missing parameters.
+        if !missing_attrs.is_empty() {
+            let mut message = String::from("Argument missing for attribute(s) ");
+            for (i, name) in missing_attrs.iter().enumerate() {
+                if i > 0 {
+                    message.push_str(", ");
+                }
+                message.push('"');
+                message.push_str(name.as_str());
+                message.push('"');
+            }
+
+            self.add_expr_diagnostic_error(file, expr, DiagnosticCode::CallArg, message);
+        }
+    }
+
+    /// Checks the arguments of a call to a user-defined function, and returns the function's
+    /// declared return type. Only the given parameters of the function are matched against the
+    /// arguments.
+    fn infer_function_call(
+        &mut self,
+        file: File,
+        expr: ExprId,
+        def: &FunctionDef,
+        params: &[ParamId],
+        args: &[Argument],
+        arg_tys: &[Ty],
+    ) -> Ty {
+        let db = self.db;
+        let module = module(db, def.func.file(db));
+        let params = params.iter().copied();
+        let mut slots: Slots = params
+            .clone()
+            .map(|param| module[param].clone())
+            .collect::<Vec<_>>()[..]
+            .into();
+        let errors = slots.assign_args(args, None).0;
+
+        for error in errors {
+            self.add_expr_diagnostic_error(
+                file,
+                error.expr,
+                DiagnosticCode::CallArg,
+                error.message,
+            );
+        }
+
+        let mut missing_params = Vec::new();
+
+        // Validate argument types.
+        for (param, slot) in params.zip(slots.into_inner()) {
+            let hir_param = &module[param];
+            let param_ty = resolve_type_ref_opt(self, hir_param.type_ref(), Some(def.stmt));
+
+            // TODO(withered-magic): Deduplicate the following logic for
+            // validating providers, as it's currently shared between
+            // the handlers for `Function`s, `IntrinsicFunction`s, and
+            // `BuiltinFunction`s.
+            let mut validate_provider = |provider| match provider {
+                SlotProvider::Missing => {
+                    if !hir_param.is_optional() {
+                        let name = hir_param.name();
+                        if !name.is_missing() {
+                            missing_params.push(name.clone());
+                        }
+                    }
+                }
+                SlotProvider::Single(expr, index) => {
+                    let ty = &arg_tys[index];
+                    if !assign_tys(db, ty, &param_ty) {
+                        self.add_expr_diagnostic_error(file, expr, DiagnosticCode::ArgType, format!("Argument of type \"{}\" cannot be assigned to parameter of type \"{}\"", ty.display(self.db).alt(), param_ty.display(self.db).alt()));
+                    }
+                }
+                _ => {}
+            };
+
+            match slot {
+                Slot::Positional { provider } | Slot::Keyword { provider, .. } => {
+                    validate_provider(provider);
+                }
+                Slot::ArgsList { providers, .. } | Slot::KwargsDict { providers } => {
+                    providers.into_iter().for_each(validate_provider);
+                }
+            }
+        }
+
+        // Emit diagnostic for missing parameters.
+        if !missing_params.is_empty() {
+            let mut message = String::from("Argument missing for parameter(s) ");
+            for (i, name) in missing_params.into_iter().enumerate() {
+                if i > 0 {
+                    message.push_str(", ");
+                }
+                message.push('"');
+                message.push_str(name.as_str());
+                message.push('"');
+            }
+
+            self.add_expr_diagnostic_error(file, expr, DiagnosticCode::CallArg, message);
+        }
+
+        match def.func.ret_type_ref(db) {
+            Some(type_ref) => resolve_type_ref(self, &type_ref, Some(def.stmt)).0,
+            // Calls to functions whose bodies always end in e.g. a call to `fail()` never return.
+            None if self.shared_state.options.use_code_flow_analysis
+                && code_flow_graph(db, def.stmt.file)
+                    .cfg(db)
+                    .no_return_functions
+                    .contains(&def.stmt.value) =>
+            {
+                Ty::never()
+            }
+            None => self.unknown_ty(),
+        }
+    }
+
+    fn validate_aspect_args(&mut self, file: File, args: &[Argument]) {
+        let module = module(self.db, file);
+        for arg in args.iter() {
+            let (name, expr) = match arg {
+                Argument::Keyword { name, expr } => (name, *expr),
+                _ => continue,
+            };
+            let exprs = match &module[expr] {
+                Expr::List { exprs } => exprs,
+                _ => continue,
+            };
+            match name.as_str() {
+                "attr_aspects" => {
+                    for expr in exprs.iter().copied() {
+                        match self.infer_expr(file, expr).kind() {
+                            TyKind::String(Some(s)) if s.value(self.db).as_ref() == "*" => {
+                                if exprs.len() > 1 {
+                                    self.add_expr_diagnostic_error(
+                                        file,
+                                        expr,
+                                        DiagnosticCode::ArgType,
+                                        "\"*\" must be the only element of \"attr_aspects\"",
+                                    );
+                                }
+                            }
+                            TyKind::String(_) | TyKind::Unknown | TyKind::Any => {}
+                            _ => self.add_expr_diagnostic_error(
+                                file,
+                                expr,
+                                DiagnosticCode::ArgType,
+                                "Elements of \"attr_aspects\" must be attribute names",
+                            ),
+                        }
+                    }
+                }
+
+                // `required_providers` accepts either a list of providers, or a list of lists of
+                // providers.
+                "required_providers" => {
+                    for expr in exprs.iter().copied() {
+                        match &module[expr] {
+                            Expr::List { exprs } => {
+                                for expr in exprs.iter().copied() {
+                                    self.validate_required_provider(file, expr);
+                                }
+                            }
+                            _ => self.validate_required_provider(file, expr),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the type of the value that Bazel passes to a subrule's implementation function for
+    /// one of the subrule's attributes.
+    fn subrule_attr_ty(&self, attr: &Attribute) -> Ty {
+        let builtin_ty = |name| resolve_builtin_type_ref(self.db, &TypeRef::from_str_opt(name)).0;
+        match attr.kind {
+            AttributeKind::Label if attr.executable => builtin_ty("FilesToRunProvider"),
+            AttributeKind::Label if attr.allow_single_file => builtin_ty("File"),
+            _ => attr.resolved_ty(),
+        }
+    }
+
+    /// Checks that a subrule only declares private, label-typed attributes with default values,
+    /// since callers can't pass values for them.
+    fn validate_subrule_attrs(&mut self, file: File, args: &[Argument]) {
+        let db = self.db;
+        let module = module(db, file);
+        let entries = match args.iter().find_map(|arg| match arg {
+            Argument::Keyword { name, expr } if name.as_str() == "attrs" => Some(&module[*expr]),
+            _ => None,
+        }) {
+            Some(Expr::Dict { entries }) => entries,
+            _ => return,
+        };
+
+        for entry in entries.iter() {
+            let name = match &module[entry.key] {
+                Expr::Literal {
+                    literal: Literal::String(s),
+                } => s.value(db),
+                _ => continue,
+            };
+            if !name.starts_with('_') {
+                self.add_expr_diagnostic_error(
+                    file,
+                    entry.key,
+                    DiagnosticCode::Subrule,
+                    format!(
+                        "Subrule attribute \"{}\" must be private, i.e. its name must start with \"_\"",
+                        name
+                    ),
+                );
+                continue;
+            }
+            let attr = match self.infer_expr(file, entry.value).kind() {
+                TyKind::Attribute(attr) => attr.clone(),
+                _ => continue,
+            };
+            if !matches!(attr.kind, AttributeKind::Label | AttributeKind::LabelList) {
+                self.add_expr_diagnostic_error(
+                    file,
+                    entry.value,
+                    DiagnosticCode::Subrule,
+                    format!(
+                        "Subrule attribute \"{}\" must be a label or label_list attribute",
+                        name
+                    ),
+                );
+            } else if attr.default_text_range.is_none() {
+                self.add_expr_diagnostic_error(
+                    file,
+                    entry.value,
+                    DiagnosticCode::Subrule,
+                    format!("Subrule attribute \"{}\" must have a default value", name),
+                );
+            }
+        }
+    }
+
+    /// Checks that the implementation function of a rule or subrule only calls the subrules
+    /// listed in its `subrules` argument.
+    fn validate_subrule_calls(&mut self, file: File, args: &[Argument], subrules: &[Arc<Subrule>]) {
+        let db = self.db;
+        let impl_expr = match args.iter().find_map(|arg| match arg {
+            Argument::Keyword { name, expr } if name.as_str() == "implementation" => Some(*expr),
+            _ => None,
+        }) {
+            Some(expr) => expr,
+            None => return,
+        };
+
+        // As with return values, only implementation functions defined in the current file are
+        // checked, and calls made from helper functions aren't followed.
+        let stmt = match self.infer_expr(file, impl_expr).kind() {
+            TyKind::Function(def) if def.stmt.file == file => def.stmt.value,
+            _ => return,
+        };
+        let module = module(db, file);
+        let stmts = match &module[stmt] {
+            Stmt::Def { stmts, .. } => stmts,
+            _ => return,
+        };
+        let mut calls = Vec::new();
+        collect_call_exprs(module, stmts, &mut calls);
+
+        for (call_expr, callee) in calls {
+            let subrule = match self.infer_expr(file, callee).kind() {
+                TyKind::Subrule(subrule) => subrule.clone(),
+                _ => continue,
+            };
+            if subrules
+                .iter()
+                .any(|other| other.call_expr == subrule.call_expr)
+            {
+                continue;
+            }
+            let name = match &module[callee] {
+                Expr::Name { name } => name.as_str(),
+                Expr::Dot { field, .. } => field.as_str(),
+                _ => "",
+            };
+            self.add_expr_diagnostic_error(
+                file,
+                call_expr,
+                DiagnosticCode::Subrule,
+                format!("Subrule \"{}\" is not declared in \"subrules\"", name),
+            );
+        }
+    }
+
+    /// Checks the repositories imported by a `use_repo()` call against the repositories generated
+    /// by the corresponding module extension, as recorded in `MODULE.bazel.lock`. Repositories that
+    /// are referenced in the workspace but not imported are offered as a quick fix.
+    fn validate_use_repo(&mut self, file: File, expr: ExprId, args: &[Argument]) {
+        let db = self.db;
+        let repos = match db.get_module_extension_repos() {
+            Some(repos) => repos,
+            None => return,
+        };
+        let module = module(db, file);
+        let (proxy, proxy_name) = match args.first() {
+            Some(Argument::Simple { expr }) => match &module[*expr] {
+                Expr::Name { name } => (*expr, name),
+                _ => return,
+            },
+            _ => return,
+        };
+        let (bzl_file, extension_name) = match use_extension_args(db, file, proxy, proxy_name) {
+            Some(args) => args,
+            None => return,
+        };
+        let generated = match repos.generated_repos(&bzl_file, &extension_name) {
+            Some(generated) => generated,
+            None => return,
+        };
+
+        let mut last_positional = proxy;
+        for arg in args.iter().skip(1) {
+            let arg_expr = match arg {
+                Argument::Simple { expr } => {
+                    last_positional = *expr;
+                    *expr
+                }
+                Argument::Keyword { expr, .. } => *expr,
+                _ => continue,
+            };
+            let repo = match &module[arg_expr] {
+                Expr::Literal {
+                    literal: Literal::String(s),
+                } => s.value(db),
+                _ => continue,
+            };
+            if !generated.iter().any(|name| **name == *repo) {
+                self.add_expr_diagnostic_warning(
+                    file,
+                    arg_expr,
+                    DiagnosticCode::UseRepo,
+                    format!(
+                        "Module extension \"{}\" does not generate a repository named \"{}\"",
+                        extension_name, repo
+                    ),
+                );
+            }
+        }
+
+        // Repositories may be imported across several `use_repo()` calls, possibly in different
+        // segments of the root module, so missing repositories are only reported on the last call for
+        // this extension. Each segment binds its own proxies, so calls are matched by extension, and
+        // only the repositories imported from this extension are counted.
+        let mut imported = FxHashSet::default();
+        let mut last_call = (file, expr);
+        for (segment, call_expr) in module_use_repo_calls(db, file) {
+            let segment_module = crate::module(db, segment);
+            let args = match &segment_module[call_expr] {
+                Expr::Call { args, .. } => args,
+                _ => continue,
+            };
+            let uses_extension = match args.first() {
+                Some(Argument::Simple { expr }) => match &segment_module[*expr] {
+                    Expr::Name { name } => use_extension_args(db, segment, *expr, name)
+                        .is_some_and(|(other_bzl_file, other_name)| {
+                            other_bzl_file == bzl_file && other_name == extension_name
+                        }),
+                    _ => false,
+                },
+                _ => false,
+            };
+            if !uses_extension {
+                continue;
+            }
+            last_call = (segment, call_expr);
+            for arg in args.iter().skip(1) {
+                match arg {
+                    Argument::Simple { expr } => {
+                        if let Expr::Literal {
+                            literal: Literal::String(s),
+                        } = &segment_module[*expr]
+                        {
+                            imported.insert(s.value(db));
+                        }
+                    }
+                    Argument::Keyword { name, .. } => {
+                        imported.insert(name.as_str().into());
+                    }
+                    // The imported repositories can't be determined statically.
+                    _ => return,
+                }
+            }
+        }
+        if last_call != (file, expr) {
+            return;
+        }
+
+        let missing = generated
+            .iter()
+            .filter(|repo| repos.is_referenced(repo) && !imported.contains(repo.as_str()))
+            .map(|repo| format!("\"{}\"", repo))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return;
+        }
+
+        let source_map = source_map(db, file);
+        let range = |expr: ExprId| {
+            source_map
+                .expr_map_back
+                .get(&expr)
+                .map(|ptr| ptr.syntax_node_ptr().text_range())
+        };
+        let (call_range, insert_offset) = match (range(expr), range(last_positional)) {
+            (Some(call_range), Some(arg_range)) => (call_range, arg_range.end()),
+            _ => return,
+        };
+        let file_id = file.id(db);
+        self.add_diagnostic(
+            Diagnostic::new(
+                DiagnosticCode::UseRepo,
+                Severity::Warning,
+                FileRange {
+                    file_id,
+                    range: call_range,
+                },
+                format!(
+                    "Repositories generated by module extension \"{}\" are referenced but not imported: {}",
+                    extension_name,
+                    missing.join(", ")
+                ),
+            )
+            .with_fix(Fix {
+                label: "Add missing repositories to \"use_repo\"".to_string(),
+                edits: vec![SourceEdit {
+                    range: FileRange {
+                        file_id,
+                        range: TextRange::empty(insert_offset),
+                    },
+                    new_text: missing.iter().map(|repo| format!(", {}", repo)).collect(),
+                }],
+            }),
+        );
+    }
+
+    /// Checks that the `MODULE.bazel` segment included by an `include()` call can be loaded.
+    fn validate_include(&mut self, file: File, expr: ExprId) {
+        let label = match include_label(self.db, file, expr) {
+            Some(label) => label,
+            None => return,
+        };
+        if let Err(err) = self
+            .db
+            .load_file(&label, file.dialect(self.db), file.id(self.db))
+        {
+            self.add_expr_diagnostic_warning(
+                file,
+                expr,
+                DiagnosticCode::Load,
+                format!("Could not resolve module segment \"{}\": {}", label, err),
+            );
+        }
+    }
+
+    /// Checks the values returned by a rule's implementation function. Each returned list must
+    /// contain only provider instances, must not contain the same provider twice, and must
+    /// include every provider listed in the rule's `provides` argument.
+    fn validate_rule_impl_returns(&mut self, file: File, args: &[Argument], rule: &Rule) {
+        let db = self.db;
+        let impl_expr = match args.iter().find_map(|arg| match arg {
+            Argument::Keyword { name, expr } if name.as_str() == "implementation" => Some(*expr),
+            _ => None,
+        }) {
+            Some(expr) => expr,
+            None => return,
+        };
+
+        // Only implementation functions defined in the current file are checked, since the
+        // diagnostics are reported on their return statements.
+        let stmt = match self.infer_expr(file, impl_expr).kind() {
+            TyKind::Function(def) if def.stmt.file == file => def.stmt.value,
+            _ => return,
+        };
+        let module = module(db, file);
+        let mut return_exprs = Vec::new();
+        if let Stmt::Def { stmts, .. } = &module[stmt] {
+            collect_return_exprs(module, stmts, &mut return_exprs);
+        }
+
+        for expr in return_exprs {
+            let ty = self.infer_expr(file, expr);
+            let mut returned_providers: Vec<Provider> = Vec::new();
+            match (&module[expr], ty.kind()) {
+                (Expr::List { exprs }, _) => {
+                    let mut is_complete = true;
+                    for expr in exprs.iter().copied() {
+                        let ty = self.infer_expr(file, expr);
+                        match ty.kind() {
+                            TyKind::ProviderInstance(provider) => {
+                                if returned_providers.contains(provider) {
+                                    self.add_expr_diagnostic_error(
+                                        file,
+                                        expr,
+                                        DiagnosticCode::ProviderReturn,
+                                        format!(
+                                            "Provider \"{}\" is returned more than once",
+                                            ty.display(db).alt()
+                                        ),
+                                    );
+                                } else {
+                                    returned_providers.push(provider.clone());
+                                }
+                            }
+                            TyKind::Unknown | TyKind::Any => is_complete = false,
+                            _ => self.add_expr_diagnostic_error(
+                                file,
+                                expr,
+                                DiagnosticCode::ProviderReturn,
+                                format!(
+                                    "Expected a provider instance, got \"{}\"",
+                                    ty.display(db).alt()
+                                ),
+                            ),
+                        }
+                    }
+
+                    // A list with elements of unknown type might still contain the required
+                    // providers.
+                    if !is_complete {
+                        continue;
+                    }
+                }
+                (_, TyKind::ProviderInstance(provider)) => {
+                    returned_providers.push(provider.clone());
+                }
+                (_, TyKind::None) => {}
+                _ => continue,
+            }
+
+            let missing_providers = rule
+                .provides
+                .iter()
+                .filter(|provider| !returned_providers.contains(*provider))
+                .collect::<Vec<_>>();
+            if !missing_providers.is_empty() {
+                let mut message = String::from("Missing provider(s) ");
+                for (i, provider) in missing_providers.iter().enumerate() {
+                    if i > 0 {
+                        message.push_str(", ");
+                    }
+                    message.push('"');
+                    message.push_str(provider.name(db).map_or("_", Name::as_str));
+                    message.push('"');
+                }
+                message.push_str(" required by \"provides\"");
+                self.add_expr_diagnostic_error(file, expr, DiagnosticCode::ProviderReturn, message);
+            }
+        }
+    }
+
+    /// Checks the `attr.*` calls in the `attrs` dict passed to `rule()`, `aspect()`,
+    /// `repository_rule()` or `tag_class()`. Besides dict literals, the dict can be built from
+    /// globals that are assigned once in the current file, `dict()` calls and `|` merges.
+    fn validate_attrs_dict(&mut self, file: File, args: &[Argument]) {
+        let module = module(self.db, file);
+        let attrs = match args.iter().find_map(|arg| match arg {
+            Argument::Keyword { name, expr } if name.as_str() == "attrs" => Some(*expr),
+            _ => None,
+        }) {
+            Some(attrs) => attrs,
+            None => return,
+        };
+
+        let mut values = Vec::new();
+        collect_attrs_dict_values(module, attrs, &mut FxHashSet::default(), &mut values);
+        for value in values {
+            // A dict shared by multiple rules is only checked once.
+            if !self
+                .cx
+                .validated_attr_exprs
+                .insert(FileExprId::new(file, value))
+            {
+                continue;
+            }
+            if let Expr::Call { args, .. } = &module[value] {
+                if let TyKind::Attribute(attr) = self.infer_expr(file, value).kind() {
+                    self.validate_attr_args(file, args, attr);
+                }
+            }
+        }
+    }
+
+    /// Checks the arguments of an `attr.*` call against the kind of attribute that it creates.
+    fn validate_attr_args(&mut self, file: File, args: &[Argument], attr: &Attribute) {
+        let db = self.db;
+        let keyword_arg = |name: &str| {
+            args.iter().find_map(|arg| match arg {
+                Argument::Keyword {
+                    name: arg_name,
+                    expr,
+                } if arg_name.as_str() == name => Some(*expr),
+                _ => None,
+            })
+        };
+        let is_set = |tcx: &mut Self, expr: Option<ExprId>| {
+            expr.is_some_and(|expr| !matches!(tcx.infer_expr(file, expr).kind(), TyKind::None))
+        };
+        let is_label_kind = matches!(
+            attr.kind,
+            AttributeKind::Label | AttributeKind::LabelList | AttributeKind::LabelKeyedStringDict
+        );
+
+        // Check the values allowed by `attr.int()` and `attr.string()`. The default value is
+        // only checked against them if all of them are literals.
+        let mut allowed_values = None;
+        if let Some(values) = keyword_arg("values") {
+            let expected_ty = match attr.kind {
+                AttributeKind::Int => Ty::int(),
+                _ => Ty::string(),
+            };
+            if let Expr::List { exprs } = &module(db, file)[values] {
+                let mut literal_values = Some(Vec::new());
+                for expr in exprs.iter().copied() {
+                    let ty = self.infer_expr(file, expr);
+                    if !assign_tys(db, &ty, &expected_ty) {
+                        self.add_expr_diagnostic_error(
+                            file,
+                            expr,
+                            DiagnosticCode::AttrSchema,
+                            format!(
+                                "Expected a value of type \"{}\", got \"{}\"",
+                                expected_ty.display(db).alt(),
+                                ty.display(db).alt()
+                            ),
+                        );
+                    }
+                    if !matches!(ty.kind(), TyKind::Int(Some(_)) | TyKind::String(Some(_))) {
+                        literal_values = None;
+                    } else if let Some(literal_values) = literal_values.as_mut() {
+                        literal_values.push(ty);
+                    }
+                }
+                allowed_values = literal_values;
+            }
+        }
+
+        if let Some(default) = keyword_arg("default") {
+            let default_ty = self.infer_expr(file, default);
+
+            // Label attributes also accept `Label` values, and private label attributes can have
+            // computed defaults.
+            let is_computed_default = is_label_kind
+                && match default_ty.kind() {
+                    TyKind::Function(_) => true,
+                    TyKind::BuiltinType(ty, _) => ty.name(db).as_str() == "LateBoundDefault",
+                    _ => false,
+                };
+            let expected_ty = if is_label_kind {
+                let label_ty = builtin_types(db, file.dialect(db))
+                    .types(db)
+                    .get("Label")
+                    .cloned()
+                    .unwrap_or_else(Ty::unknown);
+                let label_ty = Ty::union([Ty::string(), label_ty].into_iter());
+                match attr.kind {
+                    AttributeKind::Label => Ty::union([label_ty, Ty::none()].into_iter()),
+                    AttributeKind::LabelList => Ty::list(label_ty),
+                    _ => Ty::dict(label_ty, Ty::string(), None),
+                }
+            } else {
+                attr.expected_ty()
+            };
+            if !is_computed_default && !assign_tys(db, &default_ty, &expected_ty) {
+                self.add_expr_diagnostic_error(
+                    file,
+                    default,
+                    DiagnosticCode::AttrSchema,
+                    format!(
+                        "Default value of type \"{}\" cannot be assigned to attribute of type \"{}\"",
+                        default_ty.display(db).alt(),
+                        expected_ty.display(db).alt()
+                    ),
+                );
+            } else if let Some(allowed_values) = allowed_values {
+                if matches!(
+                    default_ty.kind(),
+                    TyKind::Int(Some(_)) | TyKind::String(Some(_))
+                ) && !allowed_values.contains(&default_ty)
+                {
+                    self.add_expr_diagnostic_error(
+                        file,
+                        default,
+                        DiagnosticCode::AttrSchema,
+                        "Default value is not one of the attribute's allowed \"values\"",
+                    );
+                }
+            }
+
+            if attr.mandatory {
+                self.add_expr_diagnostic_warning(
+                    file,
+                    default,
+                    DiagnosticCode::AttrSchema,
+                    "Default value is never used because the attribute is mandatory",
+                );
+            }
+        }
+
+        let allow_single_file = keyword_arg("allow_single_file");
+        if is_set(self, keyword_arg("allow_files")) && is_set(self, allow_single_file) {
+            if let Some(allow_single_file) = allow_single_file {
+                self.add_expr_diagnostic_error(
+                    file,
+                    allow_single_file,
+                    DiagnosticCode::AttrSchema,
+                    "\"allow_files\" and \"allow_single_file\" cannot both be specified",
+                );
+            }
+        }
+
+        if attr.executable && !is_set(self, keyword_arg("cfg")) {
+            if let Some(executable) = keyword_arg("executable") {
+                self.add_expr_diagnostic_error(
+                    file,
+                    executable,
+                    DiagnosticCode::AttrSchema,
+                    "\"cfg\" must be specified for executable attributes",
+                );
+            }
+        }
+    }
+
+    /// Infers the type of `ctx.toolchains[label]`, warning if the rule doesn't declare the toolchain
+    /// type.
+    fn infer_toolchain_index(
+        &mut self,
+        file: File,
+        expr: ExprId,
+        toolchains: &ToolchainTypes,
+        label: &str,
+    ) -> Ty {
+        if !toolchains.may_contain(self.db, file, label) {
+            self.add_expr_diagnostic_warning(
+                file,
+                expr,
+                DiagnosticCode::Index,
+                format!("Toolchain type \"{}\" is not declared by this rule", label),
+            );
+        }
+        self.infer_toolchain_info_ty(file, label)
+    }
+
+    /// Infers the type of the `ToolchainInfo` provided by the toolchains of the given type. Bazel
+    /// associates a toolchain type with the rules implementing it through the `toolchain()` targets
+    /// in the toolchain type's package, so the result is built from the keyword arguments of the
+    /// `ToolchainInfo` providers created by the implementation functions of those rules, if they
+    /// are declared in the current file.
+    fn infer_toolchain_info_ty(&mut self, file: File, label: &str) -> Ty {
+        let db = self.db;
+        let key = (file, Box::from(label));
+        if let Some(ty) = self.cx.toolchain_info_tys.get(&key) {
+            return ty.clone();
+        }
+
+        // Toolchain implementations can themselves depend on toolchains of the same type. Those
+        // see the generic `ToolchainInfo` type.
+        let generic_ty =
+            resolve_builtin_type_ref(db, &TypeRef::Name(Name::new_inline("ToolchainInfo"), None)).0;
+        self.cx
+            .toolchain_info_tys
+            .insert(key.clone(), generic_ty.clone());
+
+        let module = module(db, file);
+        let mut call_expr = None;
+        let mut fields: Vec<(Name, Vec<Ty>)> = Vec::new();
+        for stmts in toolchain_rule_impls(db, file, label) {
+            let mut calls = Vec::new();
+            collect_call_exprs(module, stmts, &mut calls);
+            for (id, callee) in calls {
+                match (&module[id], &module[callee]) {
+                    (
+                        Expr::Call { args, .. },
+                        Expr::Name { name } | Expr::Dot { field: name, .. },
+                    ) if name.as_str() == "ToolchainInfo" => {
+                        call_expr.get_or_insert(id);
+                        for arg in args.iter() {
+                            if let Argument::Keyword { name, expr } = arg {
+                                let ty = self.infer_expr(file, *expr);
+                                match fields.iter_mut().find(|(field, _)| field == name) {
+                                    Some((_, tys)) => tys.push(ty),
+                                    None => fields.push((name.clone(), vec![ty])),
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let ty = match call_expr {
+            Some(call_expr) => TyKind::Struct(Some(Struct::Inline {
+                call_expr: InFile {
+                    file,
+                    value: call_expr,
+                },
+                fields: fields
+                    .into_iter()
+                    .map(|(name, tys)| (name, Ty::union(tys.into_iter())))
+                    .collect::<Vec<_>>()
+                    .into_boxed_slice(),
+            }))
+            .intern(),
+            None => generic_ty,
+        };
+        self.cx.toolchain_info_tys.insert(key, ty.clone());
+        ty
+    }
+
+    /// Infers the type of `ctx.exec_groups[name]`, whose `toolchains` field is restricted to the
+    /// toolchain types required by the execution group.
+    fn infer_exec_group_index(
+        &mut self,
+        file: File,
+        expr: ExprId,
+        exec_groups: &[(Name, Arc<ToolchainTypes>)],
+        name: &str,
+    ) -> Option<Ty> {
+        let db = self.db;
+        let ty = builtin_types(db, file.dialect(db))
+            .types(db)
+            .get("ExecGroupContext")?;
+        let toolchains = match exec_groups
+            .iter()
+            .find(|(exec_group, _)| exec_group.as_str() == name)
+        {
+            Some((_, toolchains)) => toolchains.clone(),
+            None => {
+                self.add_expr_diagnostic_warning(
+                    file,
+                    expr,
+                    DiagnosticCode::Index,
+                    format!("Execution group \"{}\" is not declared by this rule", name),
+                );
+                Default::default()
+            }
+        };
+        match ty.kind() {
+            TyKind::BuiltinType(ty, _) => {
+                Some(TyKind::BuiltinType(*ty, Some(TyData::Toolchains(toolchains))).intern())
+            }
+            _ => None,
+        }
+    }
+
+    /// Infers the element type of a depset created with `depset()` from its `direct` and
+    /// `transitive` arguments. All elements of a depset must have the same type, so direct
+    /// elements and transitive depsets that disagree with the inferred type are reported.
+    fn infer_depset_call(&mut self, file: File, args: &[Argument]) -> Ty {
+        let module = module(self.db, file);
+        let mut direct = None;
+        let mut order = None;
+        let mut transitive = None;
+        for (index, arg) in args.iter().enumerate() {
+            match arg {
+                Argument::Simple { expr } if index == 0 => direct = Some(*expr),
+                Argument::Simple { expr } if index == 1 => order = Some(*expr),
+                Argument::Keyword { name, expr } => match name.as_str() {
+                    "direct" => direct = Some(*expr),
+                    "order" => order = Some(*expr),
+                    "transitive" => transitive = Some(*expr),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        let mut elem_ty = None;
+        if let Some(direct) = direct {
+            match &module[direct] {
+                Expr::List { exprs } => {
+                    for expr in exprs.iter().copied() {
+                        let ty = self.infer_expr(file, expr);
+                        if let Some(expected) = unify_depset_elem_ty(self.db, &mut elem_ty, &ty) {
+                            self.add_expr_diagnostic_error(
+                                file,
+                                expr,
+                                DiagnosticCode::ArgType,
+                                format!(
+                                    "Element of type \"{}\" doesn't match the depset's element type \"{}\"",
+                                    ty.display(self.db).alt(),
+                                    expected.display(self.db).alt()
+                                ),
+                            );
+                        }
+                    }
+                }
+                _ => {
+                    if let TyKind::List(ty) = self.infer_expr(file, direct).kind() {
+                        unify_depset_elem_ty(self.db, &mut elem_ty, ty);
+                    }
+                }
+            }
+        }
+
+        if let Some(transitive) = transitive {
+            match &module[transitive] {
+                Expr::List { exprs } => {
+                    for expr in exprs.iter().copied() {
+                        let ty = self.infer_expr(file, expr);
+                        let message = match ty.kind() {
+                            TyKind::Depset(elem, _) => {
+                                match unify_depset_elem_ty(self.db, &mut elem_ty, elem) {
+                                    Some(expected) => format!(
+                                        "Depset of type \"{}\" doesn't match the depset's element type \"{}\"",
+                                        ty.display(self.db).alt(),
+                                        expected.display(self.db).alt()
+                                    ),
+                                    None => continue,
+                                }
+                            }
+                            TyKind::Unknown | TyKind::Any => continue,
+                            _ => {
+                                format!("Expected a depset, got \"{}\"", ty.display(self.db).alt())
+                            }
+                        };
+                        self.add_expr_diagnostic_error(
+                            file,
+                            expr,
+                            DiagnosticCode::ArgType,
+                            message,
+                        );
+                    }
+                }
+                _ => {
+                    if let TyKind::List(ty) = self.infer_expr(file, transitive).kind() {
+                        if let TyKind::Depset(ty, _) = ty.kind() {
+                            unify_depset_elem_ty(self.db, &mut elem_ty, ty);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(order) = order {
+            if let TyKind::String(Some(s)) = self.infer_expr(file, order).kind() {
+                if !matches!(
+                    s.value(self.db).as_ref(),
+                    "default" | "postorder" | "preorder" | "topological"
+                ) {
+                    self.add_expr_diagnostic_error(
+                        file,
+                        order,
+                        DiagnosticCode::ArgType,
+                        format!(
+                            "Invalid depset order \"{}\", expected one of \"default\", \"postorder\", \"preorder\" or \"topological\"",
+                            s.value(self.db)
+                        ),
+                    );
+                }
+            }
+        }
+
+        Ty::depset(
+            elem_ty.unwrap_or_else(Ty::unknown),
+            builtin_depset_class(self.db, file.dialect(self.db)),
+        )
+    }
+
+    /// Infers the type of a `select()` call from the types of its branches. Also validates that
+    /// the keys of the select are labels.
+    fn infer_select_call(&mut self, file: File, args: &[Argument]) -> Ty {
+        let module = module(self.db, file);
+        let arg = match args.first() {
+            Some(Argument::Simple { expr } | Argument::Keyword { expr, .. }) => *expr,
+            _ => return Ty::select(Ty::unknown()),
+        };
+        let entries = match &module[arg] {
+            Expr::Dict { entries } if !entries.is_empty() => entries,
+            _ => {
+                return Ty::select(match self.infer_expr(file, arg).kind() {
+                    TyKind::Dict(_, value_ty, _) => value_ty.clone(),
+                    _ => Ty::unknown(),
+                })
+            }
+        };
+
+        for entry in entries.iter() {
+            let key_ty = self.infer_expr(file, entry.key);
+            let message = match key_ty.kind() {
+                TyKind::String(Some(s)) => {
+                    let key = s.value(self.db);
+                    match Label::parse(&key) {
+                        Ok(_) => continue,
+                        Err(err) => format!("Select key \"{}\" is not a valid label: {}", key, err),
+                    }
+                }
+                TyKind::String(None) | TyKind::Unknown | TyKind::Any => continue,
+                TyKind::BuiltinType(ty, _) if ty.name(self.db).as_str() == "Label" => continue,
+                _ => format!(
+                    "Expected a label string as select key, got \"{}\"",
+                    key_ty.display(self.db).alt()
+                ),
+            };
+            self.add_expr_diagnostic_error(file, entry.key, DiagnosticCode::ArgType, message);
+        }
+
+        let branch_tys = entries
+            .iter()
+            .map(|entry| self.infer_expr(file, entry.value))
+            .collect::<Vec<_>>();
+        Ty::select(Ty::union(branch_tys.into_iter()))
+    }
+
+    /// Returns the type of calls to methods of `depset` whose return type depends on the
+    /// depset's element type, i.e. `to_list()`.
+    fn infer_depset_method_call(
+        &mut self,
+        file: File,
+        callee: ExprId,
+        func: BuiltinFunction,
+    ) -> Option<Ty> {
+        if func.parent_type(self.db).as_deref() != Some("depset")
+            || func.name(self.db).as_str() != "to_list"
+        {
+            return None;
+        }
+        let receiver = match &module(self.db, file)[callee] {
+            Expr::Dot { expr, .. } => *expr,
+            _ => return None,
+        };
+        match self.infer_expr(file, receiver).kind() {
+            TyKind::Depset(ty, _) => Some(Ty::list(ty.clone())),
+            _ => None,
+        }
+    }
+
+    /// Infers the types of the fields of a custom provider. A field's type is taken from the
+    /// matching parameter of the provider's `init` callback if it has a type comment, and is
+    /// otherwise the union of the argument types passed for it at every construction site in the
+    /// current file and in the file declaring the provider.
+    pub(crate) fn infer_custom_provider_field_tys(
+        &mut self,
+        file: File,
+        provider: &Provider,
+    ) -> Arc<Vec<Ty>> {
+        let db = self.db;
+        let custom_provider = match provider {
+            Provider::Custom(custom_provider) => custom_provider,
+            Provider::Builtin(_) => return Default::default(),
+        };
+        let fields = match custom_provider.fields.as_ref() {
+            Some(fields) => &fields.fields,
+            None => return Default::default(),
+        };
+        let key = (file, provider.clone());
+        if let Some(field_tys) = self.cx.custom_provider_field_tys.get(&key) {
+            if self.cx.custom_providers_in_progress.contains(&key) {
+                self.cx.custom_provider_placeholder_reads += 1;
+            }
+            return field_tys.clone();
+        }
+
+        // Construction sites commonly read the provider's own fields, e.g. to merge the values
+        // from dependencies. Those reads see the fields as unknown. The types inferred from them
+        // are discarded afterwards, so that they're inferred again with the actual field types.
+        let snapshot = self.cx.snapshot();
+        let placeholder_reads = self.cx.custom_provider_placeholder_reads;
+        self.cx.custom_providers_in_progress.insert(key.clone());
+        self.cx
+            .custom_provider_field_tys
+            .insert(key.clone(), Arc::new(vec![Ty::unknown(); fields.len()]));
+
+        let mut field_tys = vec![Vec::new(); fields.len()];
+        let mut typed_by_init = vec![false; fields.len()];
+        let decl_file = custom_provider.call_expr.file;
+        if let Some(init) = custom_provider.init {
+            if let TyKind::Function(def) = self.infer_expr(decl_file, init).kind() {
+                let init_file = def.stmt.file;
+                for param in def.func.params(db).iter().copied() {
+                    let name = match &module(db, init_file)[param] {
+                        Param::Simple {
+                            name,
+                            type_ref: Some(_),
+                            ..
+                        } => name,
+                        _ => continue,
+                    };
+                    if let Some(index) = fields.iter().position(|field| &field.name == name) {
+                        field_tys[index].push(self.infer_param(init_file, param));
+                        typed_by_init[index] = true;
+                    }
+                }
+            }
+        }
+
+        let mut files = vec![decl_file];
+        if file != decl_file {
+            files.push(file);
+        }
+        for site_file in files {
+            let module = module(db, site_file);
+            for (_, expr) in module.exprs.iter() {
+                let (callee, args) = match expr {
+                    Expr::Call { callee, args } => (*callee, args),
+                    _ => continue,
+                };
+                if !matches!(&module[callee], Expr::Name { .. } | Expr::Dot { .. }) {
+                    continue;
+                }
+
+                // Calls to a provider with an `init` callback pass their arguments to the
+                // callback, so only calls to its raw constructor set the fields directly.
+                let is_construction = match self.infer_expr(site_file, callee).kind() {
+                    TyKind::Provider(other) => other == provider && custom_provider.init.is_none(),
+                    TyKind::ProviderRawConstructor(_, other) => other == provider,
+                    _ => false,
+                };
+                if !is_construction {
+                    continue;
+                }
+
+                for arg in args.iter() {
+                    if let Argument::Keyword { name, expr } = arg {
+                        if let Some(index) = fields.iter().position(|field| &field.name == name) {
+                            if !typed_by_init[index] {
+                                let ty = self.infer_expr(site_file, *expr);
+                                field_tys[index].push(ty);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let field_tys = Arc::new(
+            field_tys
+                .into_iter()
+                .map(|tys| {
+                    // Ignore unknown types from sites that read the fields being inferred, unless
+                    // they are the only types seen for a field.
+                    if tys.iter().all(|ty| ty.is_unknown()) {
+                        Ty::unknown()
+                    } else {
+                        Ty::union(tys.into_iter().filter(|ty| !ty.is_unknown()))
+                    }
+                })
+                .collect::<Vec<_>>(),
+        );
+        self.cx.custom_providers_in_progress.remove(&key);
+        if self.cx.custom_provider_placeholder_reads != placeholder_reads {
+            self.cx.rollback(snapshot);
+        }
+        self.cx
+            .custom_provider_field_tys
+            .insert(key, field_tys.clone());
+        field_tys
+    }
+
+    fn validate_required_provider(&mut self, file: File, expr: ExprId) {
+        let ty = self.infer_expr(file, expr);
+        if !matches!(
+            ty.kind(),
+            TyKind::Provider(_)
+                | TyKind::ProviderRawConstructor(_, _)
+                | TyKind::Unknown
+                | TyKind::Any
+        ) {
+            self.add_expr_diagnostic_error(
+                file,
+                expr,
+                DiagnosticCode::ArgType,
+                format!("Expected a provider, got \"{}\"", ty.display(self.db).alt()),
+            );
+        }
+    }
+
+    fn lower_param_type_ref(
+        &mut self,
+        file: File,
+        param: ParamId,
+        type_ref: &TypeRef,
+        usage: Option<StmtId>,
+    ) -> Ty {
+        let (ty, errors) = usage.map_or_else(
+            || resolve_builtin_type_ref(self.db, type_ref),
+            |usage| resolve_type_ref(self, type_ref, Some(InFile { file, value: usage })),
+        );
 
         // TODO(withered-magic): This will eventually need to handle diagnostics
         // for other places that type comments can appear.
@@ -1598,6 +3090,7 @@ impl TyContext<'_> {
             if let Some(ptr) = source_map(self.db, file).param_map_back.get(&param) {
                 self.add_diagnostic_for_range(
                     file,
+                    DiagnosticCode::TypeComment,
                     Severity::Warning,
                     ptr.syntax_node_ptr().text_range(),
                     error,
@@ -1637,6 +3130,7 @@ impl TyContext<'_> {
                         if file == loaded_file {
                             self.add_diagnostic_for_range(
                                 file,
+                                DiagnosticCode::Load,
                                 Severity::Warning,
                                 range(),
                                 "Cannot load the current file",
@@ -1660,25 +3154,37 @@ impl TyContext<'_> {
                             message.push_str(load_stmt.module(db));
                             message.push('\n');
 
-                            // Use a range here to avoid having to allocate.
-                            for i in 0..self.cx.load_resolution_stack.len() {
-                                let (file, load_stmt) = self.cx.load_resolution_stack[i];
-                                self.add_diagnostic_for_range(
-                                    file,
+                            // Each member of the cycle gets its own diagnostic, with the other members of the
+                            // cycle attached as related information.
+                            let cycle = self
+                                .cx
+                                .load_resolution_stack
+                                .iter()
+                                .copied()
+                                .chain(std::iter::once((file, *load_stmt)))
+                                .map(|(file, load_stmt)| FileRange {
+                                    file_id: file.id(db),
+                                    range: load_stmt.ptr(db).text_range(),
+                                })
+                                .collect::<Vec<_>>();
+                            for (i, range) in cycle.iter().enumerate() {
+                                let mut diagnostic = Diagnostic::new(
+                                    DiagnosticCode::CircularImport,
                                     Severity::Warning,
-                                    load_stmt.ptr(db).text_range(),
+                                    range.clone(),
                                     message.clone(),
-                                )
+                                );
+                                for (j, other) in cycle.iter().enumerate() {
+                                    if i != j {
+                                        diagnostic = diagnostic.with_related(RelatedInformation {
+                                            message: "Part of the import cycle".to_string(),
+                                            range: other.clone(),
+                                        });
+                                    }
+                                }
+                                self.add_diagnostic(diagnostic);
                             }
 
-                            // Also add the current (importing) file.
-                            self.add_diagnostic_for_range(
-                                file,
-                                Severity::Warning,
-                                load_stmt.ptr(db).text_range(),
-                                message,
-                            );
-
                             return self.unknown_ty();
                         }
 
@@ -1699,6 +3205,7 @@ impl TyContext<'_> {
                                 None => {
                                     tcx.add_diagnostic_for_range(
                                         file,
+                                        DiagnosticCode::Load,
                                         Severity::Warning,
                                         range(),
                                         format!(
@@ -1739,6 +3246,7 @@ impl TyContext<'_> {
             Err(err) => {
                 self.add_diagnostic_for_range(
                     file,
+                    DiagnosticCode::Load,
                     Severity::Warning,
                     load_stmt.ptr(self.db).text_range(),
                     format!(
@@ -1755,6 +3263,15 @@ impl TyContext<'_> {
         res
     }
 
+    /// Resolves the `MODULE.bazel` segment included by an `include()` call.
+    pub fn resolve_include(&mut self, file: File, expr: ExprId) -> Option<File> {
+        let label = include_label(self.db, file, expr)?;
+        self.db
+            .load_file(&label, file.dialect(self.db), file.id(self.db))
+            .ok()
+            .flatten()
+    }
+
     fn push_load_resolution<F, T>(&mut self, file: File, load_stmt: LoadStmt, f: F) -> T
     where
         F: FnOnce(&mut Self) -> T,
@@ -1807,6 +3324,16 @@ impl TyContext<'_> {
                     TyKind::IntrinsicFunction(func, _) => func.params(db)[..].into(),
                     TyKind::BuiltinFunction(func) => func.params(db)[..].into(),
                     TyKind::Rule(rule) => Slots::from_rule(db, rule),
+                    TyKind::Macro(macro_) => Slots::from_macro(db, macro_),
+                    TyKind::Subrule(subrule) => {
+                        let def = subrule.implementation.as_ref()?;
+                        let module = module(db, def.func.file(db));
+                        subrule
+                            .params(db)
+                            .map(|(_, param)| module[param].clone())
+                            .collect::<Vec<_>>()[..]
+                            .into()
+                    }
                     TyKind::Provider(provider) | TyKind::ProviderRawConstructor(_, provider) => {
                         Slots::from_provider(db, provider)
                     }
@@ -1860,3 +3387,338 @@ impl TyContext<'_> {
         self.types().bytes.clone()
     }
 }
+
+/// Collects the expressions returned by the given function body, excluding those of nested
+/// functions.
+fn collect_return_exprs(module: &Module, stmts: &[StmtId], exprs: &mut Vec<ExprId>) {
+    for stmt in stmts.iter() {
+        match &module[*stmt] {
+            Stmt::If {
+                if_stmts,
+                elif_or_else_stmts,
+                ..
+            } => {
+                collect_return_exprs(module, if_stmts, exprs);
+                match elif_or_else_stmts {
+                    Some(Either::Left(stmt)) => {
+                        collect_return_exprs(module, slice::from_ref(stmt), exprs)
+                    }
+                    Some(Either::Right(stmts)) => collect_return_exprs(module, stmts, exprs),
+                    None => {}
+                }
+            }
+            Stmt::For { stmts, .. } => collect_return_exprs(module, stmts, exprs),
+            Stmt::Return { expr: Some(expr) } => exprs.push(*expr),
+            _ => {}
+        }
+    }
+}
+
+/// Collects the values of the entries of the dict that the given expression evaluates to, as far as
+/// they can be determined syntactically. Names are followed to their assignment if they're assigned
+/// exactly once at the top level of the module.
+fn collect_attrs_dict_values(
+    module: &Module,
+    expr: ExprId,
+    visited: &mut FxHashSet<Name>,
+    values: &mut Vec<ExprId>,
+) {
+    match &module[expr] {
+        Expr::Dict { entries } => values.extend(entries.iter().map(|entry| entry.value)),
+        Expr::Paren { expr } => collect_attrs_dict_values(module, *expr, visited, values),
+        Expr::Binary {
+            lhs,
+            rhs,
+            op: Some(BinaryOp::Bitwise(BitwiseOp::Or)),
+        } => {
+            collect_attrs_dict_values(module, *lhs, visited, values);
+            collect_attrs_dict_values(module, *rhs, visited, values);
+        }
+        Expr::Call { callee, args } if matches!(&module[*callee], Expr::Name { name } if name.as_str() == "dict") => {
+            for arg in args.iter() {
+                match arg {
+                    Argument::Simple { expr } | Argument::UnpackedDict { expr } => {
+                        collect_attrs_dict_values(module, *expr, visited, values)
+                    }
+                    Argument::Keyword { expr, .. } => values.push(*expr),
+                    Argument::UnpackedList { .. } => {}
+                }
+            }
+        }
+        Expr::Name { name } if visited.insert(name.clone()) => {
+            let mut assigned_values =
+                module
+                    .top_level
+                    .iter()
+                    .filter_map(|stmt| match &module[*stmt] {
+                        Stmt::Assign { lhs, rhs, op, .. } => match &module[*lhs] {
+                            Expr::Name { name: lhs_name } if lhs_name == name => {
+                                Some((*rhs, op.is_none()))
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    });
+            if let (Some((rhs, true)), None) = (assigned_values.next(), assigned_values.next()) {
+                collect_attrs_dict_values(module, rhs, visited, values);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the bodies of the functions in the given file that implement the rules providing
+/// toolchains of the given type. Those rules are the kinds of the targets that the `toolchain()`
+/// targets in the toolchain type's package declare for the toolchain type.
+fn toolchain_rule_impls<'a>(db: &'a dyn Db, file: File, label: &str) -> Vec<&'a [StmtId]> {
+    let resolve_target =
+        |label: &str, from: File| match db.resolve_path(label, Dialect::Bazel, from.id(db)) {
+            Ok(Some(ResolvedPath::BuildTarget {
+                build_file, target, ..
+            })) => Some((db.get_file(build_file)?, target)),
+            _ => None,
+        };
+    let string_arg = |def: &TargetDef, name: &str| {
+        def.string_args
+            .iter()
+            .find(|(arg, _)| arg.as_str() == name)
+            .map(|(_, value)| value.clone())
+    };
+    let toolchain_type = match resolve_target(label, file) {
+        Some(toolchain_type) => toolchain_type,
+        None => return Vec::new(),
+    };
+    let build_file = toolchain_type.0;
+    let kinds = file_targets(db, build_file)
+        .targets(db)
+        .iter()
+        .filter(|def| {
+            def.kind.as_str() == "toolchain"
+                && string_arg(def, "toolchain_type")
+                    .and_then(|label| resolve_target(&label, build_file))
+                    .as_ref()
+                    == Some(&toolchain_type)
+        })
+        .filter_map(|def| {
+            let (impl_file, name) = resolve_target(&string_arg(def, "toolchain")?, build_file)?;
+            file_targets(db, impl_file)
+                .targets(db)
+                .iter()
+                .find(|target| *target.name == name)
+                .map(|target| target.kind.clone())
+        })
+        .collect::<Vec<_>>();
+    if kinds.is_empty() {
+        return Vec::new();
+    }
+
+    let module = module(db, file);
+    let impl_names = module
+        .top_level
+        .iter()
+        .filter_map(|stmt| match &module[*stmt] {
+            Stmt::Assign { lhs, rhs, .. } => match (&module[*lhs], &module[*rhs]) {
+                (Expr::Name { name }, Expr::Call { args, .. }) if kinds.contains(name) => {
+                    args.iter().find_map(|arg| match arg {
+                        Argument::Keyword { name, expr } if name.as_str() == "implementation" => {
+                            match &module[*expr] {
+                                Expr::Name { name } => Some(name),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    })
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    module
+        .top_level
+        .iter()
+        .filter_map(|stmt| match &module[*stmt] {
+            Stmt::Def { func, stmts } if impl_names.contains(&&func.name(db)) => Some(&**stmts),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collects the calls in the given statements, along with their callees. Calls in nested functions
+/// and lambdas are skipped.
+fn collect_call_exprs(module: &Module, stmts: &[StmtId], calls: &mut Vec<(ExprId, ExprId)>) {
+    fn collect_in_expr(module: &Module, expr: ExprId, calls: &mut Vec<(ExprId, ExprId)>) {
+        match &module[expr] {
+            Expr::Lambda { .. } => return,
+            Expr::Call { callee, .. } => calls.push((expr, *callee)),
+            _ => {}
+        }
+        module[expr].walk_child_exprs(|expr| collect_in_expr(module, expr, calls));
+    }
+
+    for stmt in stmts.iter() {
+        match &module[*stmt] {
+            Stmt::If {
+                test,
+                if_stmts,
+                elif_or_else_stmts,
+            } => {
+                collect_in_expr(module, *test, calls);
+                collect_call_exprs(module, if_stmts, calls);
+                match elif_or_else_stmts {
+                    Some(Either::Left(stmt)) => {
+                        collect_call_exprs(module, slice::from_ref(stmt), calls)
+                    }
+                    Some(Either::Right(stmts)) => collect_call_exprs(module, stmts, calls),
+                    None => {}
+                }
+            }
+            Stmt::For {
+                iterable, stmts, ..
+            } => {
+                collect_in_expr(module, *iterable, calls);
+                collect_call_exprs(module, stmts, calls);
+            }
+            Stmt::Return { expr: Some(expr) } | Stmt::Expr { expr } => {
+                collect_in_expr(module, *expr, calls)
+            }
+            Stmt::Assign { lhs, rhs, .. } => {
+                collect_in_expr(module, *lhs, calls);
+                collect_in_expr(module, *rhs, calls);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the `.bzl` file and extension name passed to the `use_extension()` call assigned to the
+/// given name, e.g. `deps = use_extension("//:extensions.bzl", "deps")`.
+fn use_extension_args(
+    db: &dyn Db,
+    file: File,
+    expr: ExprId,
+    name: &Name,
+) -> Option<(Box<str>, Box<str>)> {
+    let resolver = Resolver::new_for_expr(db, file, expr);
+    let (_, defs) = resolver.resolve_name(name)?;
+    let (file, source) = match defs.last()?.def {
+        ScopeDef::Variable(VariableDef {
+            file,
+            source: Some(source),
+            ..
+        }) => (*file, *source),
+        _ => return None,
+    };
+    let module = module(db, file);
+    let args = match &module[source] {
+        Expr::Call { callee, args } => match &module[*callee] {
+            Expr::Name { name } if name.as_str() == "use_extension" => args,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let mut values = args.iter().filter_map(|arg| match arg {
+        Argument::Simple { expr } => match &module[*expr] {
+            Expr::Literal {
+                literal: Literal::String(s),
+            } => Some(s.value(db)),
+            _ => None,
+        },
+        _ => None,
+    });
+    Some((values.next()?, values.next()?))
+}
+
+/// Returns the label passed to an `include()` call, e.g. `include("//bazel:deps.MODULE.bazel")`.
+fn include_label(db: &dyn Db, file: File, expr: ExprId) -> Option<Box<str>> {
+    let module = module(db, file);
+    let args = match &module[expr] {
+        Expr::Call { callee, args } => match &module[*callee] {
+            Expr::Name { name } if name.as_str() == "include" => args,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match args.first()? {
+        Argument::Simple { expr } => match &module[*expr] {
+            Expr::Literal {
+                literal: Literal::String(s),
+            } => Some(s.value(db)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the `use_repo()` calls of the module that `file` belongs to, i.e. the root `MODULE.bazel`
+/// file and the segments it includes, in the order in which Bazel evaluates them. If `file` isn't
+/// part of the root module, e.g. because it's a segment that isn't included anywhere, only its own
+/// calls are returned.
+fn module_use_repo_calls(db: &dyn Db, file: File) -> Vec<(File, ExprId)> {
+    fn collect(
+        db: &dyn Db,
+        segment: File,
+        segments: &mut FxHashSet<File>,
+        calls: &mut Vec<(File, ExprId)>,
+    ) {
+        let module = module(db, segment);
+        let mut call_exprs = Vec::new();
+        collect_call_exprs(module, &module.top_level, &mut call_exprs);
+        for (call_expr, callee) in call_exprs {
+            match &module[callee] {
+                Expr::Name { name } if name.as_str() == "use_repo" => {
+                    calls.push((segment, call_expr))
+                }
+                Expr::Name { name } if name.as_str() == "include" => {
+                    // Included segments are evaluated as if they were inlined at the call site.
+                    let included = include_label(db, segment, call_expr).and_then(|label| {
+                        db.load_file(&label, segment.dialect(db), segment.id(db))
+                            .ok()
+                            .flatten()
+                    });
+                    if let Some(included) = included {
+                        if segments.insert(included) {
+                            collect(db, included, segments, calls);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut segments = FxHashSet::default();
+    let mut calls = Vec::new();
+    if let Ok(Some(root)) = db.load_file("//:MODULE.bazel", file.dialect(db), file.id(db)) {
+        segments.insert(root);
+        collect(db, root, &mut segments, &mut calls);
+    }
+    if !segments.contains(&file) {
+        calls.clear();
+        collect(db, file, &mut segments, &mut calls);
+    }
+    calls
+}
+
+/// Unifies the element type of a depset with the type of one of its elements. The element type is
+/// widened to the element's type if it is assignable to it, e.g. when an element of type
+/// `string | Label` follows a string. Returns the depset's element type if neither type is
+/// assignable to the other.
+fn unify_depset_elem_ty(db: &dyn Db, elem_ty: &mut Option<Ty>, ty: &Ty) -> Option<Ty> {
+    let ty = ty.clone().normalize();
+    if matches!(ty.kind(), TyKind::Unknown | TyKind::Any) {
+        return None;
+    }
+    match elem_ty {
+        Some(expected) if assign_tys(db, &ty, expected) => None,
+        Some(expected) if assign_tys(db, expected, &ty) => {
+            *expected = ty;
+            None
+        }
+        Some(expected) => Some(expected.clone()),
+        None => {
+            *elem_ty = Some(ty);
+            None
+        }
+    }
+}
@@ -359,7 +359,7 @@ fail("oops", 1, False)		# "fail: oops 1 False"
 ```
 "#,
         vec![ArgsList { ty: Any.intern() }],
-        None,
+        Never,
     );
     add_function(
         "getattr",
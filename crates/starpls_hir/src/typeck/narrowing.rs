@@ -0,0 +1,137 @@
+use crate::{
+    def::codeflow::NarrowingKind,
+    typeck::{Tuple, Ty, TyKind},
+    Db,
+};
+
+impl Ty {
+    /// Narrows this type given that the specified condition is known to be either truthy or falsy.
+    pub(crate) fn narrow(&self, db: &dyn Db, kind: NarrowingKind, is_true: bool) -> Ty {
+        match kind {
+            NarrowingKind::Truthiness => self.narrow_truthiness(db, is_true),
+            NarrowingKind::IsNone { negated } => self.narrow_none(is_true != negated),
+            NarrowingKind::TypeIs {
+                type_name, negated, ..
+            } => self.narrow_type_name(&type_name.value(db), is_true != negated),
+        }
+    }
+
+    /// Narrows this type to the subset of its values that are truthy, or falsy if `is_true` is
+    /// not set.
+    pub(crate) fn narrow_truthiness(&self, db: &dyn Db, is_true: bool) -> Ty {
+        self.filter_union(|ty| match ty.truthiness(db) {
+            Some(truthiness) => truthiness == is_true,
+            None => true,
+        })
+    }
+
+    fn narrow_none(&self, is_none: bool) -> Ty {
+        if is_none {
+            self.map_union(|ty| match ty.kind() {
+                TyKind::None => Some(ty.clone()),
+                TyKind::Unknown | TyKind::Any => Some(Ty::none()),
+                _ => None,
+            })
+        } else {
+            self.filter_union(|ty| ty.kind() != &TyKind::None)
+        }
+    }
+
+    fn narrow_type_name(&self, type_name: &str, matches: bool) -> Ty {
+        // Bail out if we don't recognize the type name, since we can't say anything about which
+        // types it corresponds to.
+        let Some(narrowed_ty) = ty_for_type_name(type_name) else {
+            return self.clone();
+        };
+
+        self.map_union(|ty| match (ty.kind(), ty.matches_type_name(type_name)) {
+            (TyKind::Unknown | TyKind::Any, _) => Some(if matches {
+                narrowed_ty.clone()
+            } else {
+                ty.clone()
+            }),
+            (_, Some(is_match)) if is_match != matches => None,
+            _ => Some(ty.clone()),
+        })
+    }
+
+    /// Returns whether all values of this type are truthy (`Some(true)`), falsy (`Some(false)`),
+    /// or if the truthiness can't be determined statically (`None`).
+    fn truthiness(&self, db: &dyn Db) -> Option<bool> {
+        Some(match self.kind() {
+            TyKind::None => false,
+            TyKind::Bool(Some(value)) => *value,
+            TyKind::Int(Some(value)) => *value != 0,
+            TyKind::String(Some(value)) => !value.value(db).is_empty(),
+            TyKind::Tuple(Tuple::Simple(tys)) => !tys.is_empty(),
+            TyKind::Function(_)
+            | TyKind::IntrinsicFunction(_, _)
+            | TyKind::BuiltinFunction(_)
+            | TyKind::Struct(_)
+            | TyKind::Attribute(_)
+            | TyKind::Rule(_)
+            | TyKind::Aspect(_)
+            | TyKind::Macro(_)
+            | TyKind::Subrule(_)
+            | TyKind::Provider(_)
+            | TyKind::ProviderInstance(_)
+            | TyKind::ProviderRawConstructor(_, _)
+            | TyKind::TagClass(_)
+            | TyKind::ModuleExtension(_)
+            | TyKind::ModuleExtensionProxy(_)
+            | TyKind::Tag(_)
+            | TyKind::Target => true,
+            _ => return None,
+        })
+    }
+
+    /// Returns whether `type()` called on a value of this type would return the given type name,
+    /// or `None` if this can't be determined statically.
+    fn matches_type_name(&self, type_name: &str) -> Option<bool> {
+        let actual_type_name = match self.kind() {
+            TyKind::None => "NoneType",
+            TyKind::Bool(_) => "bool",
+            TyKind::Int(_) => "int",
+            TyKind::Float => "float",
+            TyKind::String(_) => "string",
+            TyKind::Bytes => "bytes",
+            TyKind::List(_) => "list",
+            TyKind::Tuple(_) => "tuple",
+            TyKind::Dict(_, _, _) => "dict",
+            TyKind::Range => "range",
+            TyKind::Struct(_) => "struct",
+            TyKind::Function(_) => "function",
+            _ => return None,
+        };
+        Some(actual_type_name == type_name)
+    }
+
+    fn filter_union(&self, mut f: impl FnMut(&Ty) -> bool) -> Ty {
+        self.map_union(|ty| f(ty).then(|| ty.clone()))
+    }
+
+    fn map_union(&self, mut f: impl FnMut(&Ty) -> Option<Ty>) -> Ty {
+        match self.kind() {
+            TyKind::Union(tys) => Ty::union(tys.iter().filter_map(f)),
+            _ => f(self).unwrap_or_else(Ty::never),
+        }
+    }
+}
+
+fn ty_for_type_name(type_name: &str) -> Option<Ty> {
+    Some(match type_name {
+        "NoneType" => Ty::none(),
+        "bool" => Ty::bool(),
+        "int" => Ty::int(),
+        "float" => TyKind::Float.intern(),
+        "string" => Ty::string(),
+        "bytes" => TyKind::Bytes.intern(),
+        "list" => Ty::list(Ty::unknown()),
+        "tuple" => TyKind::Tuple(Tuple::Variable(Ty::unknown())).intern(),
+        "dict" => Ty::dict(Ty::unknown(), Ty::unknown(), None),
+        "range" => TyKind::Range.intern(),
+        "struct" => TyKind::Struct(None).intern(),
+        "function" => Ty::unknown(),
+        _ => return None,
+    })
+}
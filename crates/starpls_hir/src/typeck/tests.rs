@@ -2,13 +2,14 @@ use std::{cmp::Ordering, fmt::Write};
 
 use expect_test::{expect, Expect};
 use itertools::Itertools;
-use starpls_bazel::APIContext;
+use starpls_bazel::{lockfile::ModuleExtensionRepos, APIContext};
 use starpls_common::{parse, Db as _, Dialect, FileId, FileInfo};
 use starpls_syntax::ast::AstNode;
 use starpls_test_util::FixtureType;
 
 use crate::{
     source_map, test_database::TestDatabaseBuilder, Db as _, DisplayWithDb, InferenceOptions,
+    TypeIgnores,
 };
 
 fn check_infer(input: &str, expect: Expect) {
@@ -28,10 +29,28 @@ fn check_infer_with_code_flow_analysis(input: &str, expect: Expect) {
 
 fn check_infer_with_options(input: &str, expect: Expect, options: InferenceOptions) {
     let mut builder = TestDatabaseBuilder::default();
+    builder.set_inference_options(options);
+    check_infer_with_builder(input, expect, builder)
+}
+
+fn check_infer_with_builder(input: &str, expect: Expect, mut builder: TestDatabaseBuilder) {
     builder.add_function("provider");
     builder.add_function("rule");
     builder.add_function("struct");
+    builder.add_function("aspect");
+    builder.add_function("macro");
+    builder.add_function("depset");
+    builder.add_function("select");
+    builder.add_function("exec_group");
+    builder.add_function("subrule");
     builder.add_type(FixtureType::new("File", vec![], vec![]));
+    builder.add_type(FixtureType::new("Label", vec![], vec![]));
+    builder.add_type(FixtureType::new("depset", vec![], vec!["to_list"]));
+    builder.add_type(FixtureType::new(
+        "Args",
+        vec![],
+        vec!["add_all", "add_joined"],
+    ));
     builder.add_type(FixtureType::new(
         "ctx",
         vec![
@@ -40,9 +59,29 @@ fn check_infer_with_options(input: &str, expect: Expect, options: InferenceOptio
             ("file", "struct"),
             ("files", "struct"),
             ("outputs", "struct"),
+            ("rule", "rule_attributes"),
+            ("toolchains", "ToolchainContext"),
+            ("exec_groups", "ExecGroupCollection"),
         ],
         vec![],
     ));
+    builder.add_type(FixtureType::new(
+        "subrule_ctx",
+        vec![("label", "Label"), ("toolchains", "ToolchainContext")],
+        vec![],
+    ));
+    builder.add_type(FixtureType::new("ToolchainContext", vec![], vec![]));
+    builder.add_type(FixtureType::new("ExecGroupCollection", vec![], vec![]));
+    builder.add_type(FixtureType::new(
+        "ExecGroupContext",
+        vec![("toolchains", "ToolchainContext")],
+        vec![],
+    ));
+    builder.add_type(FixtureType::new(
+        "rule_attributes",
+        vec![("attr", "struct"), ("kind", "string")],
+        vec![],
+    ));
     builder.add_type(FixtureType::new(
         "repository_ctx",
         vec![("attr", "struct")],
@@ -50,7 +89,7 @@ fn check_infer_with_options(input: &str, expect: Expect, options: InferenceOptio
     ));
     builder.add_type(FixtureType::new(
         "DefaultInfo",
-        vec![("file", "string")],
+        vec![("file", "string"), ("files", "depset of Files")],
         vec![],
     ));
     builder.add_type(FixtureType::new(
@@ -61,7 +100,12 @@ fn check_infer_with_options(input: &str, expect: Expect, options: InferenceOptio
     builder.add_type(FixtureType::new(
         "config_common",
         vec![],
-        vec!["FeatureFlagInfo"],
+        vec!["FeatureFlagInfo", "toolchain_type"],
+    ));
+    builder.add_type(FixtureType::new(
+        "platform_common",
+        vec![],
+        vec!["ToolchainInfo"],
     ));
     builder.add_function("DefaultInfo");
     builder.add_type(FixtureType::new(
@@ -70,11 +114,15 @@ fn check_infer_with_options(input: &str, expect: Expect, options: InferenceOptio
         vec![],
     ));
     builder.add_type(FixtureType::new("CcInfo", vec![], vec![]));
-    builder.add_type(FixtureType::new("attr", vec![], vec!["label_list"]));
+    builder.add_type(FixtureType::new(
+        "attr",
+        vec![],
+        vec!["int", "label", "label_list", "output", "string"],
+    ));
     builder.add_global("attr", "attr");
     builder.add_global("config_common", "config_common");
+    builder.add_global("platform_common", "platform_common");
     builder.add_global("PyInfo", "PyInfo");
-    builder.set_inference_options(options);
 
     let mut db = builder.build();
     let file_id = FileId(0);
@@ -139,7 +187,16 @@ fn check_infer_with_options(input: &str, expect: Expect, options: InferenceOptio
         });
     }
 
-    let diagnostics = db.gcx.with_tcx(&db, |tcx| tcx.diagnostics_for_file(file));
+    let mut type_ignores = TypeIgnores::new(&db, file);
+    let mut diagnostics = db
+        .gcx
+        .with_tcx(&db, |tcx| tcx.diagnostics_for_file(file))
+        .into_iter()
+        .filter(|diagnostic| !type_ignores.is_suppressed(diagnostic))
+        .collect::<Vec<_>>();
+    if db.gcx.options().report_unused_type_ignores {
+        diagnostics.extend(type_ignores.unused_ignores());
+    }
     if !diagnostics.is_empty() {
         res.push('\n');
         for diagnostic in diagnostics
@@ -314,6 +371,106 @@ res2 = 2 + "y" # type: ignore
     )
 }
 
+#[test]
+fn test_type_ignore_comment_with_codes() {
+    check_infer(
+        r#"
+res1 = 1 + "x" # type: ignore[arg-type]
+res2 = 2 + "y" # type: ignore[arg-type, operator]
+res3 = foo # type: ignore[not-callable]
+    "#,
+        expect![[r#"
+            1..5 "res1": Unknown
+            8..9 "1": Literal[1]
+            12..15 "\"x\"": Literal["x"]
+            8..15 "1 + \"x\"": Unknown
+            41..45 "res2": Unknown
+            48..49 "2": Literal[2]
+            52..55 "\"y\"": Literal["y"]
+            48..55 "2 + \"y\"": Unknown
+            91..95 "res3": Unknown
+            98..101 "foo": Unbound
+
+            8..15 Operator "+" not supported for types "Literal[1]" and "Literal["x"]"
+            98..101 "foo" is not defined
+        "#]],
+    )
+}
+
+#[test]
+fn test_file_level_type_ignore_comment() {
+    check_infer(
+        r#"
+# type: ignore[undefined-name]
+
+res1 = foo
+res2 = 1 + "x"
+    "#,
+        expect![[r#"
+            33..37 "res1": Unknown
+            40..43 "foo": Unbound
+            44..48 "res2": Unknown
+            51..52 "1": Literal[1]
+            55..58 "\"x\"": Literal["x"]
+            51..58 "1 + \"x\"": Unknown
+
+            51..58 Operator "+" not supported for types "Literal[1]" and "Literal["x"]"
+        "#]],
+    )
+}
+
+#[test]
+fn test_unused_type_ignore_comment() {
+    check_infer_with_options(
+        r#"
+res1 = 1 + "x" # type: ignore
+res2 = 2 # type: ignore
+res3 = 3 + "z" # type: ignore[undefined-name]
+    "#,
+        expect![[r#"
+            1..5 "res1": Unknown
+            8..9 "1": Literal[1]
+            12..15 "\"x\"": Literal["x"]
+            8..15 "1 + \"x\"": Unknown
+            31..35 "res2": Literal[2]
+            38..39 "2": Literal[2]
+            55..59 "res3": Unknown
+            62..63 "3": Literal[3]
+            66..69 "\"z\"": Literal["z"]
+            62..69 "3 + \"z\"": Unknown
+
+            40..54 Unused "type: ignore" comment
+            62..69 Operator "+" not supported for types "Literal[3]" and "Literal["z"]"
+            70..100 Unused "type: ignore" comment
+        "#]],
+        InferenceOptions {
+            report_unused_type_ignores: true,
+            ..Default::default()
+        },
+    )
+}
+
+#[test]
+fn test_unused_file_level_type_ignore_comment() {
+    check_infer_with_options(
+        r#"
+# type: ignore[undefined-name]
+
+res1 = 1
+    "#,
+        expect![[r#"
+            33..37 "res1": Literal[1]
+            40..41 "1": Literal[1]
+
+            1..31 Unused "type: ignore" comment
+        "#]],
+        InferenceOptions {
+            report_unused_type_ignores: true,
+            ..Default::default()
+        },
+    )
+}
+
 #[test]
 fn test_invalid_type_refs() {
     check_infer(
@@ -906,7 +1063,7 @@ infos = f(info1)
             99..120 "DataInfo(foo = \"abc\")": DataInfo
             180..185 "print": def print(*args: Any, str: string = None) -> None
             186..190 "info": DataInfo
-            186..194 "info.foo": Unknown
+            186..194 "info.foo": string
             180..195 "print(info.foo)": None
             208..212 "info": DataInfo
             207..213 "[info]": list[DataInfo]
@@ -924,6 +1081,93 @@ infos = f(info1)
     );
 }
 
+#[test]
+fn test_provider_field_types() {
+    check_infer(
+        r#"
+def _ext_info_init(name, deps):
+    # type: (string, list[string]) -> Unknown
+    return {"name": name, "deps": deps}
+
+DataInfo = provider(fields = ["files", "count"])
+ExtInfo, _new_ext_info = provider(fields = ["name", "deps", "extra"], init = _ext_info_init)
+
+def _impl(dep):
+    DataInfo(files = ["a"], count = 1)
+    DataInfo(files = dep[DataInfo].files + ["b"], count = None)
+    _new_ext_info(name = "x", deps = [], extra = 1)
+    dep[DataInfo].files
+    dep[DataInfo].count
+    dep[ExtInfo].name
+    dep[ExtInfo].deps
+    dep[ExtInfo].extra
+"#,
+        expect![[r#"
+            91..97 "\"name\"": Literal["name"]
+            99..103 "name": string
+            105..111 "\"deps\"": Literal["deps"]
+            113..117 "deps": list[string]
+            90..118 "{\"name\": name, \"deps\": deps}": dict[string, Unknown]
+            120..128 "DataInfo": Provider[DataInfo]
+            131..139 "provider": def provider(*args, **kwargs) -> Unknown
+            150..157 "\"files\"": Literal["files"]
+            159..166 "\"count\"": Literal["count"]
+            149..167 "[\"files\", \"count\"]": list[string]
+            131..168 "provider(fields = [\"files\", \"count\"])": Provider[DataInfo]
+            169..176 "ExtInfo": Provider[ExtInfo]
+            178..191 "_new_ext_info": ProviderRawConstructor
+            169..191 "ExtInfo, _new_ext_info": tuple[Provider[ExtInfo], ProviderRawConstructor]
+            194..202 "provider": def provider(*args, **kwargs) -> Unknown
+            213..219 "\"name\"": Literal["name"]
+            221..227 "\"deps\"": Literal["deps"]
+            229..236 "\"extra\"": Literal["extra"]
+            212..237 "[\"name\", \"deps\", \"extra\"]": list[string]
+            246..260 "_ext_info_init": def _ext_info_init(name: string, deps: list[string]) -> Unknown
+            194..261 "provider(fields = [\"name\", \"deps\", \"extra\"], init = _ext_info_init)": tuple[Provider[ExtInfo], ProviderRawConstructor]
+            283..291 "DataInfo": Provider[DataInfo]
+            301..304 "\"a\"": Literal["a"]
+            300..305 "[\"a\"]": list[string]
+            315..316 "1": Literal[1]
+            283..317 "DataInfo(files = [\"a\"], count = 1)": DataInfo
+            322..330 "DataInfo": Provider[DataInfo]
+            339..342 "dep": Unknown
+            343..351 "DataInfo": Provider[DataInfo]
+            339..352 "dep[DataInfo]": DataInfo
+            339..358 "dep[DataInfo].files": list[string]
+            362..365 "\"b\"": Literal["b"]
+            361..366 "[\"b\"]": list[string]
+            339..366 "dep[DataInfo].files + [\"b\"]": list[string]
+            376..380 "None": None
+            322..381 "DataInfo(files = dep[DataInfo].files + [\"b\"], count = None)": DataInfo
+            386..399 "_new_ext_info": ProviderRawConstructor
+            407..410 "\"x\"": Literal["x"]
+            419..421 "[]": list[Unknown]
+            431..432 "1": Literal[1]
+            386..433 "_new_ext_info(name = \"x\", deps = [], extra = 1)": ExtInfo
+            438..441 "dep": Unknown
+            442..450 "DataInfo": Provider[DataInfo]
+            438..451 "dep[DataInfo]": DataInfo
+            438..457 "dep[DataInfo].files": list[string]
+            462..465 "dep": Unknown
+            466..474 "DataInfo": Provider[DataInfo]
+            462..475 "dep[DataInfo]": DataInfo
+            462..481 "dep[DataInfo].count": int | None
+            486..489 "dep": Unknown
+            490..497 "ExtInfo": Provider[ExtInfo]
+            486..498 "dep[ExtInfo]": ExtInfo
+            486..503 "dep[ExtInfo].name": string
+            508..511 "dep": Unknown
+            512..519 "ExtInfo": Provider[ExtInfo]
+            508..520 "dep[ExtInfo]": ExtInfo
+            508..525 "dep[ExtInfo].deps": list[string]
+            530..533 "dep": Unknown
+            534..541 "ExtInfo": Provider[ExtInfo]
+            530..542 "dep[ExtInfo]": ExtInfo
+            530..548 "dep[ExtInfo].extra": int
+        "#]],
+    );
+}
+
 #[test]
 fn test_path_type_comments() {
     check_infer(
@@ -1298,10 +1542,10 @@ my_rule = repository_rule(
 )
 "#,
         expect![[r#"
-            26..29 "foo": File
+            26..29 "foo": Unknown
             32..35 "ctx": ctx
             32..40 "ctx.file": struct
-            32..44 "ctx.file.foo": File
+            32..44 "ctx.file.foo": Unknown
             49..53 "srcs": list[Target]
             56..59 "ctx": ctx
             56..64 "ctx.attr": struct
@@ -1328,10 +1572,320 @@ my_rule = repository_rule(
             360..377 "attr.label_list()": Attribute
             342..384 "{\n        \"srcs\": attr.label_list(),\n    }": dict[string, Attribute]
             269..387 "repository_rule(\n    implementation = _repository_rule_impl,\n    attrs = {\n        \"srcs\": attr.label_list(),\n    },\n)": repository_rule
+
+            32..44 Cannot access field "foo" of "ctx.file", the rule has no attribute with that name
         "#]],
         InferenceOptions {
             infer_ctx_attributes: true,
             use_code_flow_analysis: true,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn test_infer_ctx_files() {
+    check_infer_with_options(
+        r#"
+def _impl(ctx):
+    ctx.file.src
+    ctx.files.deps
+    ctx.executable.tool
+    ctx.outputs.out
+    ctx.outputs.report
+    ctx.file.deps
+
+my_rule = rule(
+    implementation = _impl,
+    attrs = {
+        "src": attr.label(allow_single_file = True),
+        "deps": attr.label_list(),
+        "tool": attr.label(executable = True, cfg = "exec"),
+        "out": attr.output(),
+    },
+    outputs = {"report": "{name}.txt"},
+)
+"#,
+        expect![[r#"
+            21..24 "ctx": ctx
+            21..29 "ctx.file": struct
+            21..33 "ctx.file.src": File
+            38..41 "ctx": ctx
+            38..47 "ctx.files": struct
+            38..52 "ctx.files.deps": list[File]
+            57..60 "ctx": ctx
+            57..71 "ctx.executable": struct
+            57..76 "ctx.executable.tool": File
+            81..84 "ctx": ctx
+            81..92 "ctx.outputs": struct
+            81..96 "ctx.outputs.out": File
+            101..104 "ctx": ctx
+            101..112 "ctx.outputs": struct
+            101..119 "ctx.outputs.report": File
+            124..127 "ctx": ctx
+            124..132 "ctx.file": struct
+            124..137 "ctx.file.deps": Unknown
+            139..146 "my_rule": rule
+            149..153 "rule": def rule(*args, **kwargs) -> Unknown
+            176..181 "_impl": def _impl(ctx) -> Unknown
+            205..210 "\"src\"": Literal["src"]
+            212..216 "attr": attr
+            212..222 "attr.label": def label(*args, **kwargs) -> Unknown
+            243..247 "True": Literal[True]
+            212..248 "attr.label(allow_single_file = True)": Attribute
+            258..264 "\"deps\"": Literal["deps"]
+            266..270 "attr": attr
+            266..281 "attr.label_list": def label_list(*args, **kwargs) -> Unknown
+            266..283 "attr.label_list()": Attribute
+            293..299 "\"tool\"": Literal["tool"]
+            301..305 "attr": attr
+            301..311 "attr.label": def label(*args, **kwargs) -> Unknown
+            325..329 "True": Literal[True]
+            337..343 "\"exec\"": Literal["exec"]
+            301..344 "attr.label(executable = True, cfg = \"exec\")": Attribute
+            354..359 "\"out\"": Literal["out"]
+            361..365 "attr": attr
+            361..372 "attr.output": def output(*args, **kwargs) -> Unknown
+            361..374 "attr.output()": Attribute
+            195..381 "{\n        \"src\": attr.label(allow_single_file = True),\n        \"deps\": attr.label_list(),\n        \"tool\": attr.label(executable = True, cfg = \"exec\"),\n        \"out\": attr.output(),\n    }": dict[string, Attribute]
+            398..406 "\"report\"": Literal["report"]
+            408..420 "\"{name}.txt\"": Literal["{name}.txt"]
+            397..421 "{\"report\": \"{name}.txt\"}": dict[string, string]
+            149..424 "rule(\n    implementation = _impl,\n    attrs = {\n        \"src\": attr.label(allow_single_file = True),\n        \"deps\": attr.label_list(),\n        \"tool\": attr.label(executable = True, cfg = \"exec\"),\n        \"out\": attr.output(),\n    },\n    outputs = {\"report\": \"{name}.txt\"},\n)": rule
+
+            124..137 Cannot access field "deps" of "ctx.file", which only contains attributes declared with "allow_single_file"
+        "#]],
+        InferenceOptions {
+            infer_ctx_attributes: true,
+            use_code_flow_analysis: true,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn test_infer_ctx_files_undeclared_attr() {
+    check_infer_with_options(
+        r#"
+def _impl(ctx):
+    ctx.files.srsc
+    ctx.files.toolchains
+
+my_rule = rule(
+    implementation = _impl,
+    attrs = {"srcs": attr.label_list()},
+)
+"#,
+        expect![[r#"
+            21..24 "ctx": ctx
+            21..30 "ctx.files": struct
+            21..35 "ctx.files.srsc": Unknown
+            40..43 "ctx": ctx
+            40..49 "ctx.files": struct
+            40..60 "ctx.files.toolchains": list[File]
+            62..69 "my_rule": rule
+            72..76 "rule": def rule(*args, **kwargs) -> Unknown
+            99..104 "_impl": def _impl(ctx) -> Unknown
+            119..125 "\"srcs\"": Literal["srcs"]
+            127..131 "attr": attr
+            127..142 "attr.label_list": def label_list(*args, **kwargs) -> Unknown
+            127..144 "attr.label_list()": Attribute
+            118..145 "{\"srcs\": attr.label_list()}": dict[string, Attribute]
+            72..148 "rule(\n    implementation = _impl,\n    attrs = {\"srcs\": attr.label_list()},\n)": rule
+
+            21..35 Cannot access field "srsc" of "ctx.files", the rule has no attribute with that name
+        "#]],
+        InferenceOptions {
+            infer_ctx_attributes: true,
+            use_code_flow_analysis: true,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn test_infer_ctx_files_unknown_attrs() {
+    check_infer_with_options(
+        r#"
+_ATTRS = {"src": attr.label(allow_single_file = True)}
+
+def _impl(ctx):
+    ctx.file.src
+    ctx.file.deps
+    ctx.outputs.executable
+
+my_rule = rule(
+    implementation = _impl,
+    attrs = dict(_ATTRS, deps = attr.label_list()),
+    executable = True,
+)
+"#,
+        expect![[r#"
+            1..7 "_ATTRS": dict[string, Attribute]
+            11..16 "\"src\"": Literal["src"]
+            18..22 "attr": attr
+            18..28 "attr.label": def label(*args, **kwargs) -> Unknown
+            49..53 "True": Literal[True]
+            18..54 "attr.label(allow_single_file = True)": Attribute
+            10..55 "{\"src\": attr.label(allow_single_file = True)}": dict[string, Attribute]
+            77..80 "ctx": ctx
+            77..85 "ctx.file": struct
+            77..89 "ctx.file.src": Unknown
+            94..97 "ctx": ctx
+            94..102 "ctx.file": struct
+            94..107 "ctx.file.deps": Unknown
+            112..115 "ctx": ctx
+            112..123 "ctx.outputs": struct
+            112..134 "ctx.outputs.executable": File
+            136..143 "my_rule": rule
+            146..150 "rule": def rule(*args, **kwargs) -> Unknown
+            173..178 "_impl": def _impl(ctx) -> Unknown
+            192..196 "dict": def dict(x0: dict[Unknown, Unknown] | Iterable[Iterable[Any]] = None, **kwargs) -> dict[Unknown, Unknown]
+            197..203 "_ATTRS": dict[string, Attribute]
+            212..216 "attr": attr
+            212..227 "attr.label_list": def label_list(*args, **kwargs) -> Unknown
+            212..229 "attr.label_list()": Attribute
+            192..230 "dict(_ATTRS, deps = attr.label_list())": dict[string, Attribute]
+            249..253 "True": Literal[True]
+            146..256 "rule(\n    implementation = _impl,\n    attrs = dict(_ATTRS, deps = attr.label_list()),\n    executable = True,\n)": rule
+        "#]],
+        InferenceOptions {
+            infer_ctx_attributes: true,
+            use_code_flow_analysis: true,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn test_infer_aspect_ctx() {
+    check_infer_with_options(
+        r#"
+def _aspect_impl(target, ctx):
+    target
+    ctx.attr._tool
+    ctx.rule.attr.deps
+    ctx.rule.kind
+    ctx.outputs.foo
+    return []
+
+my_aspect = aspect(
+    implementation = _aspect_impl,
+    attr_aspects = ["deps", "*"],
+    required_providers = [PyInfo, "CcInfo"],
+    attrs = {
+        "_tool": attr.label(),
+    },
+    doc = "Collects tools.",
+)
+"#,
+        expect![[r#"
+            36..42 "target": Target
+            47..50 "ctx": ctx
+            47..55 "ctx.attr": struct
+            47..61 "ctx.attr._tool": Target
+            66..69 "ctx": ctx
+            66..74 "ctx.rule": rule_attributes
+            66..79 "ctx.rule.attr": struct
+            66..84 "ctx.rule.attr.deps": Unknown
+            89..92 "ctx": ctx
+            89..97 "ctx.rule": rule_attributes
+            89..102 "ctx.rule.kind": string
+            107..110 "ctx": ctx
+            107..118 "ctx.outputs": Unknown
+            107..122 "ctx.outputs.foo": Unknown
+            134..136 "[]": list[Unknown]
+            138..147 "my_aspect": Aspect
+            150..156 "aspect": def aspect(*args, **kwargs) -> Unknown
+            179..191 "_aspect_impl": def _aspect_impl(target, ctx) -> Unknown
+            213..219 "\"deps\"": Literal["deps"]
+            221..224 "\"*\"": Literal["*"]
+            212..225 "[\"deps\", \"*\"]": list[string]
+            253..259 "PyInfo": Provider[PyInfo]
+            261..269 "\"CcInfo\"": Literal["CcInfo"]
+            252..270 "[PyInfo, \"CcInfo\"]": list[Unknown]
+            294..301 "\"_tool\"": Literal["_tool"]
+            303..307 "attr": attr
+            303..313 "attr.label": def label(*args, **kwargs) -> Unknown
+            303..315 "attr.label()": Attribute
+            284..322 "{\n        \"_tool\": attr.label(),\n    }": dict[string, Attribute]
+            334..351 "\"Collects tools.\"": Literal["Collects tools."]
+            150..354 "aspect(\n    implementation = _aspect_impl,\n    attr_aspects = [\"deps\", \"*\"],\n    required_providers = [PyInfo, \"CcInfo\"],\n    attrs = {\n        \"_tool\": attr.label(),\n    },\n    doc = \"Collects tools.\",\n)": Aspect
+
+            221..224 "*" must be the only element of "attr_aspects"
+            261..269 Expected a provider, got "Literal["CcInfo"]"
+        "#]],
+        InferenceOptions {
+            infer_ctx_attributes: true,
+            use_code_flow_analysis: true,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn test_infer_macro() {
+    check_infer_with_options(
+        r#"
+def _impl(name, visibility, deps):
+    name
+    visibility
+    deps
+
+my_macro = macro(
+    implementation = _impl,
+    attrs = {
+        "deps": attr.label_list(mandatory = True),
+        "tags": None,
+    },
+    inherit_attrs = "common",
+    doc = "Wraps a library.",
+)
+
+my_macro(name = "foo", deps = [], testonly = True, tags = [])
+my_macro(name = "bar", deps = 1)
+my_macro(name = "baz")
+"#,
+        expect![[r#"
+            40..44 "name": string
+            49..59 "visibility": list[Label]
+            64..68 "deps": Unknown
+            70..78 "my_macro": macro
+            81..86 "macro": def macro(*args, **kwargs) -> Unknown
+            109..114 "_impl": def _impl(name, visibility, deps) -> Unknown
+            138..144 "\"deps\"": Literal["deps"]
+            146..150 "attr": attr
+            146..161 "attr.label_list": def label_list(*args, **kwargs) -> Unknown
+            174..178 "True": Literal[True]
+            146..179 "attr.label_list(mandatory = True)": Attribute
+            189..195 "\"tags\"": Literal["tags"]
+            197..201 "None": None
+            128..208 "{\n        \"deps\": attr.label_list(mandatory = True),\n        \"tags\": None,\n    }": dict[string, Unknown]
+            230..238 "\"common\"": Literal["common"]
+            250..268 "\"Wraps a library.\"": Literal["Wraps a library."]
+            81..271 "macro(\n    implementation = _impl,\n    attrs = {\n        \"deps\": attr.label_list(mandatory = True),\n        \"tags\": None,\n    },\n    inherit_attrs = \"common\",\n    doc = \"Wraps a library.\",\n)": macro
+            273..281 "my_macro": macro
+            289..294 "\"foo\"": Literal["foo"]
+            303..305 "[]": list[Unknown]
+            318..322 "True": Literal[True]
+            331..333 "[]": list[Unknown]
+            273..334 "my_macro(name = \"foo\", deps = [], testonly = True, tags = [])": None
+            335..343 "my_macro": macro
+            351..356 "\"bar\"": Literal["bar"]
+            365..366 "1": Literal[1]
+            335..367 "my_macro(name = \"bar\", deps = 1)": None
+            368..376 "my_macro": macro
+            384..389 "\"baz\"": Literal["baz"]
+            368..390 "my_macro(name = \"baz\")": None
+
+            331..333 Unexpected keyword argument "tags"
+            365..366 Argument of type "Literal[1]" cannot be assigned to parameter of type "list[string]"
+            368..390 Argument missing for attribute(s) "deps"
+        "#]],
+        InferenceOptions {
+            infer_ctx_attributes: true,
+            use_code_flow_analysis: true,
+            ..Default::default()
         },
     );
 }
@@ -1443,6 +1997,797 @@ def f(info):
     );
 }
 
+#[test]
+fn test_depset() {
+    check_infer(
+        r#"
+files = depset(["a.txt", "b.txt"])
+files.to_list()
+nested = depset(direct = ["c.txt"], transitive = [files], order = "postorder")
+nested.to_list()
+bad = depset(["a", 1], transitive = [depset([1]), ["x"]], order = "random")
+DefaultInfo().files.to_list()
+
+def f(d):
+    # type: (depset[File]) -> None
+    d.to_list()
+"#,
+        expect![[r#"
+            1..6 "files": depset[string]
+            9..15 "depset": def depset(*args, **kwargs) -> Unknown
+            17..24 "\"a.txt\"": Literal["a.txt"]
+            26..33 "\"b.txt\"": Literal["b.txt"]
+            16..34 "[\"a.txt\", \"b.txt\"]": list[string]
+            9..35 "depset([\"a.txt\", \"b.txt\"])": depset[string]
+            36..41 "files": depset[string]
+            36..49 "files.to_list": def to_list(*args, **kwargs) -> Unknown
+            36..51 "files.to_list()": list[string]
+            52..58 "nested": depset[string]
+            61..67 "depset": def depset(*args, **kwargs) -> Unknown
+            78..85 "\"c.txt\"": Literal["c.txt"]
+            77..86 "[\"c.txt\"]": list[string]
+            102..107 "files": depset[string]
+            101..108 "[files]": list[depset[string]]
+            118..129 "\"postorder\"": Literal["postorder"]
+            61..130 "depset(direct = [\"c.txt\"], transitive = [files], order = \"postorder\")": depset[string]
+            131..137 "nested": depset[string]
+            131..145 "nested.to_list": def to_list(*args, **kwargs) -> Unknown
+            131..147 "nested.to_list()": list[string]
+            148..151 "bad": depset[string]
+            154..160 "depset": def depset(*args, **kwargs) -> Unknown
+            162..165 "\"a\"": Literal["a"]
+            167..168 "1": Literal[1]
+            161..169 "[\"a\", 1]": list[Unknown]
+            185..191 "depset": def depset(*args, **kwargs) -> Unknown
+            193..194 "1": Literal[1]
+            192..195 "[1]": list[int]
+            185..196 "depset([1])": depset[int]
+            199..202 "\"x\"": Literal["x"]
+            198..203 "[\"x\"]": list[string]
+            184..204 "[depset([1]), [\"x\"]]": list[Unknown]
+            214..222 "\"random\"": Literal["random"]
+            154..223 "depset([\"a\", 1], transitive = [depset([1]), [\"x\"]], order = \"random\")": depset[string]
+            224..235 "DefaultInfo": Provider[DefaultInfo]
+            224..237 "DefaultInfo()": DefaultInfo
+            224..243 "DefaultInfo().files": depset[File]
+            224..251 "DefaultInfo().files.to_list": def to_list(*args, **kwargs) -> Unknown
+            224..253 "DefaultInfo().files.to_list()": list[File]
+            304..305 "d": depset[File]
+            304..313 "d.to_list": def to_list(*args, **kwargs) -> Unknown
+            304..315 "d.to_list()": list[File]
+
+            167..168 Element of type "Literal[1]" doesn't match the depset's element type "string"
+            185..196 Depset of type "depset[int]" doesn't match the depset's element type "string"
+            198..203 Expected a depset, got "list[string]"
+            214..222 Invalid depset order "random", expected one of "default", "postorder", "preorder" or "topological"
+        "#]],
+    );
+}
+
+#[test]
+fn test_depset_map_each() {
+    check_infer_with_options(
+        r#"
+def _path(file):
+    return file
+
+def _name(src):
+    return src
+
+def f(args, files, srcs, x):
+    # type: (Args, depset[File], list[string], int | string) -> None
+    args.add_all(files, map_each = _path)
+    args.add_joined("--srcs", srcs, map_each = _name, join_with = ",")
+    depset([1, x])
+"#,
+        expect![[r#"
+            29..33 "file": File
+            62..65 "src": string
+            169..173 "args": Args
+            169..181 "args.add_all": def add_all(*args, **kwargs) -> Unknown
+            182..187 "files": depset[File]
+            200..205 "_path": def _path(file) -> Unknown
+            169..206 "args.add_all(files, map_each = _path)": Unknown
+            211..215 "args": Args
+            211..226 "args.add_joined": def add_joined(*args, **kwargs) -> Unknown
+            227..235 "\"--srcs\"": Literal["--srcs"]
+            237..241 "srcs": list[string]
+            254..259 "_name": def _name(src) -> Unknown
+            273..276 "\",\"": Literal[","]
+            211..277 "args.add_joined(\"--srcs\", srcs, map_each = _name, join_with = \",\")": Unknown
+            282..288 "depset": def depset(*args, **kwargs) -> Unknown
+            290..291 "1": Literal[1]
+            293..294 "x": int | string
+            289..295 "[1, x]": list[Unknown]
+            282..296 "depset([1, x])": depset[int | string]
+        "#]],
+        InferenceOptions {
+            infer_ctx_attributes: true,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn test_select() {
+    check_infer(
+        r#"
+srcs = ["a.cc"] + select({
+    "//conditions:default": ["b.cc"],
+    ":linux": ["c.cc"],
+})
+keys = select({"//foo:": 1, 2: 3})
+bad = ["a.cc"] + select({":x": 1})
+
+def _impl(ctx):
+    return None
+
+my_rule = rule(
+    implementation = _impl,
+    attrs = {"srcs": attr.label_list()},
+)
+
+my_rule(name = "a", srcs = srcs)
+my_rule(name = "b", srcs = select({":x": [1]}))
+"#,
+        expect![[r#"
+            1..5 "srcs": Select[list[string]]
+            9..15 "\"a.cc\"": Literal["a.cc"]
+            8..16 "[\"a.cc\"]": list[string]
+            19..25 "select": def select(*args, **kwargs) -> Unknown
+            32..54 "\"//conditions:default\"": Literal["//conditions:default"]
+            57..63 "\"b.cc\"": Literal["b.cc"]
+            56..64 "[\"b.cc\"]": list[string]
+            70..78 "\":linux\"": Literal[":linux"]
+            81..87 "\"c.cc\"": Literal["c.cc"]
+            80..88 "[\"c.cc\"]": list[string]
+            26..91 "{\n    \"//conditions:default\": [\"b.cc\"],\n    \":linux\": [\"c.cc\"],\n}": dict[string, list[string]]
+            19..92 "select({\n    \"//conditions:default\": [\"b.cc\"],\n    \":linux\": [\"c.cc\"],\n})": Select[list[string]]
+            8..92 "[\"a.cc\"] + select({\n    \"//conditions:default\": [\"b.cc\"],\n    \":linux\": [\"c.cc\"],\n})": Select[list[string]]
+            93..97 "keys": Select[int]
+            100..106 "select": def select(*args, **kwargs) -> Unknown
+            108..116 "\"//foo:\"": Literal["//foo:"]
+            118..119 "1": Literal[1]
+            121..122 "2": Literal[2]
+            124..125 "3": Literal[3]
+            107..126 "{\"//foo:\": 1, 2: 3}": dict[string | int, int]
+            100..127 "select({\"//foo:\": 1, 2: 3})": Select[int]
+            128..131 "bad": Select[Unknown]
+            135..141 "\"a.cc\"": Literal["a.cc"]
+            134..142 "[\"a.cc\"]": list[string]
+            145..151 "select": def select(*args, **kwargs) -> Unknown
+            153..157 "\":x\"": Literal[":x"]
+            159..160 "1": Literal[1]
+            152..161 "{\":x\": 1}": dict[string, int]
+            145..162 "select({\":x\": 1})": Select[int]
+            134..162 "[\"a.cc\"] + select({\":x\": 1})": Select[Unknown]
+            191..195 "None": None
+            197..204 "my_rule": rule
+            207..211 "rule": def rule(*args, **kwargs) -> Unknown
+            234..239 "_impl": def _impl(ctx) -> Unknown
+            254..260 "\"srcs\"": Literal["srcs"]
+            262..266 "attr": attr
+            262..277 "attr.label_list": def label_list(*args, **kwargs) -> Unknown
+            262..279 "attr.label_list()": Attribute
+            253..280 "{\"srcs\": attr.label_list()}": dict[string, Attribute]
+            207..283 "rule(\n    implementation = _impl,\n    attrs = {\"srcs\": attr.label_list()},\n)": rule
+            285..292 "my_rule": rule
+            300..303 "\"a\"": Literal["a"]
+            312..316 "srcs": Select[list[string]]
+            285..317 "my_rule(name = \"a\", srcs = srcs)": None
+            318..325 "my_rule": rule
+            333..336 "\"b\"": Literal["b"]
+            345..351 "select": def select(*args, **kwargs) -> Unknown
+            353..357 "\":x\"": Literal[":x"]
+            360..361 "1": Literal[1]
+            359..362 "[1]": list[int]
+            352..363 "{\":x\": [1]}": dict[string, list[int]]
+            345..364 "select({\":x\": [1]})": Select[list[int]]
+            318..365 "my_rule(name = \"b\", srcs = select({\":x\": [1]}))": None
+
+            108..116 Select key "//foo:" is not a valid label: empty target
+            121..122 Expected a label string as select key, got "Literal[2]"
+            134..162 Operator "+" not supported for types "list[string]" and "int"
+            345..364 Argument of type "Select[list[int]]" cannot be assigned to parameter of type "list[string]"
+        "#]],
+    );
+}
+
+#[test]
+fn test_toolchains() {
+    let mut builder = TestDatabaseBuilder::default();
+    builder.set_inference_options(InferenceOptions {
+        infer_ctx_attributes: true,
+        ..Default::default()
+    });
+    builder.add_file(
+        "//cc:BUILD.bazel",
+        r#"
+toolchain(
+    name = "gcc_linux",
+    toolchain = ":gcc",
+    toolchain_type = ":toolchain_type",
+)
+
+gcc_toolchain(name = "gcc")
+"#,
+    );
+    check_infer_with_builder(
+        r#"
+def _toolchain_impl(ctx):
+    return [platform_common.ToolchainInfo(compiler = "gcc")]
+
+def _impl(ctx):
+    ctx.toolchains["//cc:toolchain_type"].compiler
+    ctx.toolchains["//py:toolchain_type"]
+    ctx.exec_groups["link"].toolchains["//ld:toolchain_type"]
+
+my_rule = rule(
+    implementation = _impl,
+    toolchains = ["//cc:toolchain_type"],
+    exec_groups = {
+        "link": exec_group(toolchains = [config_common.toolchain_type("//ld:toolchain_type")]),
+    },
+)
+
+TOOLCHAINS = ["//cc:toolchain_type"]
+
+def _other_impl(ctx):
+    ctx.toolchains["//py:toolchain_type"]
+
+other_rule = rule(implementation = _other_impl, toolchains = TOOLCHAINS)
+gcc_toolchain = rule(implementation = _toolchain_impl)
+"#,
+        expect![[r#"
+            39..54 "platform_common": platform_common
+            39..68 "platform_common.ToolchainInfo": def ToolchainInfo(*args, **kwargs) -> Unknown
+            80..85 "\"gcc\"": Literal["gcc"]
+            39..86 "platform_common.ToolchainInfo(compiler = \"gcc\")": Unknown
+            38..87 "[platform_common.ToolchainInfo(compiler = \"gcc\")]": list[Unknown]
+            109..112 "ctx": ctx
+            109..123 "ctx.toolchains": ToolchainContext
+            124..145 "\"//cc:toolchain_type\"": Literal["//cc:toolchain_type"]
+            109..146 "ctx.toolchains[\"//cc:toolchain_type\"]": struct
+            109..155 "ctx.toolchains[\"//cc:toolchain_type\"].compiler": string
+            160..163 "ctx": ctx
+            160..174 "ctx.toolchains": ToolchainContext
+            175..196 "\"//py:toolchain_type\"": Literal["//py:toolchain_type"]
+            160..197 "ctx.toolchains[\"//py:toolchain_type\"]": Unknown
+            202..205 "ctx": ctx
+            202..217 "ctx.exec_groups": ExecGroupCollection
+            218..224 "\"link\"": Literal["link"]
+            202..225 "ctx.exec_groups[\"link\"]": ExecGroupContext
+            202..236 "ctx.exec_groups[\"link\"].toolchains": ToolchainContext
+            237..258 "\"//ld:toolchain_type\"": Literal["//ld:toolchain_type"]
+            202..259 "ctx.exec_groups[\"link\"].toolchains[\"//ld:toolchain_type\"]": Unknown
+            261..268 "my_rule": rule
+            271..275 "rule": def rule(*args, **kwargs) -> Unknown
+            298..303 "_impl": def _impl(ctx) -> Unknown
+            323..344 "\"//cc:toolchain_type\"": Literal["//cc:toolchain_type"]
+            322..345 "[\"//cc:toolchain_type\"]": list[string]
+            375..381 "\"link\"": Literal["link"]
+            383..393 "exec_group": def exec_group(*args, **kwargs) -> Unknown
+            408..421 "config_common": config_common
+            408..436 "config_common.toolchain_type": def toolchain_type(*args, **kwargs) -> Unknown
+            437..458 "\"//ld:toolchain_type\"": Literal["//ld:toolchain_type"]
+            408..459 "config_common.toolchain_type(\"//ld:toolchain_type\")": Unknown
+            407..460 "[config_common.toolchain_type(\"//ld:toolchain_type\")]": list[Unknown]
+            383..461 "exec_group(toolchains = [config_common.toolchain_type(\"//ld:toolchain_type\")])": Unknown
+            365..468 "{\n        \"link\": exec_group(toolchains = [config_common.toolchain_type(\"//ld:toolchain_type\")]),\n    }": dict[string, Unknown]
+            271..471 "rule(\n    implementation = _impl,\n    toolchains = [\"//cc:toolchain_type\"],\n    exec_groups = {\n        \"link\": exec_group(toolchains = [config_common.toolchain_type(\"//ld:toolchain_type\")]),\n    },\n)": rule
+            473..483 "TOOLCHAINS": list[string]
+            487..508 "\"//cc:toolchain_type\"": Literal["//cc:toolchain_type"]
+            486..509 "[\"//cc:toolchain_type\"]": list[string]
+            537..540 "ctx": ctx
+            537..551 "ctx.toolchains": ToolchainContext
+            552..573 "\"//py:toolchain_type\"": Literal["//py:toolchain_type"]
+            537..574 "ctx.toolchains[\"//py:toolchain_type\"]": Unknown
+            576..586 "other_rule": rule
+            589..593 "rule": def rule(*args, **kwargs) -> Unknown
+            611..622 "_other_impl": def _other_impl(ctx) -> Unknown
+            637..647 "TOOLCHAINS": list[string]
+            589..648 "rule(implementation = _other_impl, toolchains = TOOLCHAINS)": rule
+            649..662 "gcc_toolchain": rule
+            665..669 "rule": def rule(*args, **kwargs) -> Unknown
+            687..702 "_toolchain_impl": def _toolchain_impl(ctx) -> Unknown
+            665..703 "rule(implementation = _toolchain_impl)": rule
+
+            160..197 Toolchain type "//py:toolchain_type" is not declared by this rule
+        "#]],
+        builder,
+    );
+}
+
+#[test]
+fn test_toolchain_type_labels() {
+    let mut builder = TestDatabaseBuilder::default();
+    builder.set_inference_options(InferenceOptions {
+        infer_ctx_attributes: true,
+        ..Default::default()
+    });
+    builder.add_function("Label");
+    check_infer_with_builder(
+        r#"
+def _impl(ctx):
+    ctx.toolchains["@//cc:toolchain_type"]
+    ctx.toolchains["@@//py"]
+    ctx.toolchains["//go:toolchain_type"]
+
+my_rule = rule(
+    implementation = _impl,
+    toolchains = [Label("//cc:toolchain_type"), Label("//py:py"), Label("@rules_go//go:toolchain_type")],
+)
+"#,
+        expect![[r#"
+            21..24 "ctx": ctx
+            21..35 "ctx.toolchains": ToolchainContext
+            36..58 "\"@//cc:toolchain_type\"": Literal["@//cc:toolchain_type"]
+            21..59 "ctx.toolchains[\"@//cc:toolchain_type\"]": Unknown
+            64..67 "ctx": ctx
+            64..78 "ctx.toolchains": ToolchainContext
+            79..87 "\"@@//py\"": Literal["@@//py"]
+            64..88 "ctx.toolchains[\"@@//py\"]": Unknown
+            93..96 "ctx": ctx
+            93..107 "ctx.toolchains": ToolchainContext
+            108..129 "\"//go:toolchain_type\"": Literal["//go:toolchain_type"]
+            93..130 "ctx.toolchains[\"//go:toolchain_type\"]": Unknown
+            132..139 "my_rule": rule
+            142..146 "rule": def rule(*args, **kwargs) -> Unknown
+            169..174 "_impl": def _impl(ctx) -> Unknown
+            194..199 "Label": def Label(*args, **kwargs) -> Unknown
+            200..221 "\"//cc:toolchain_type\"": Literal["//cc:toolchain_type"]
+            194..222 "Label(\"//cc:toolchain_type\")": Unknown
+            224..229 "Label": def Label(*args, **kwargs) -> Unknown
+            230..239 "\"//py:py\"": Literal["//py:py"]
+            224..240 "Label(\"//py:py\")": Unknown
+            242..247 "Label": def Label(*args, **kwargs) -> Unknown
+            248..278 "\"@rules_go//go:toolchain_type\"": Literal["@rules_go//go:toolchain_type"]
+            242..279 "Label(\"@rules_go//go:toolchain_type\")": Unknown
+            193..280 "[Label(\"//cc:toolchain_type\"), Label(\"//py:py\"), Label(\"@rules_go//go:toolchain_type\")]": list[Unknown]
+            142..283 "rule(\n    implementation = _impl,\n    toolchains = [Label(\"//cc:toolchain_type\"), Label(\"//py:py\"), Label(\"@rules_go//go:toolchain_type\")],\n)": rule
+
+            93..130 Toolchain type "//go:toolchain_type" is not declared by this rule
+        "#]],
+        builder,
+    );
+}
+
+#[test]
+fn test_rule_provides() {
+    check_infer(
+        r#"
+FooInfo = provider()
+BarInfo = provider()
+
+def _impl(ctx):
+    return [FooInfo(), FooInfo(), "oops"]
+
+def _other_impl(ctx):
+    if True:
+        return None
+    return [FooInfo(), BarInfo()]
+
+a = rule(implementation = _impl, provides = [FooInfo, BarInfo])
+b = rule(implementation = _other_impl, provides = [FooInfo])
+"#,
+        expect![[r#"
+            1..8 "FooInfo": Provider[FooInfo]
+            11..19 "provider": def provider(*args, **kwargs) -> Unknown
+            11..21 "provider()": Provider[FooInfo]
+            22..29 "BarInfo": Provider[BarInfo]
+            32..40 "provider": def provider(*args, **kwargs) -> Unknown
+            32..42 "provider()": Provider[BarInfo]
+            72..79 "FooInfo": Provider[FooInfo]
+            72..81 "FooInfo()": FooInfo
+            83..90 "FooInfo": Provider[FooInfo]
+            83..92 "FooInfo()": FooInfo
+            94..100 "\"oops\"": Literal["oops"]
+            71..101 "[FooInfo(), FooInfo(), \"oops\"]": list[Unknown]
+            132..136 "True": Literal[True]
+            153..157 "None": None
+            170..177 "FooInfo": Provider[FooInfo]
+            170..179 "FooInfo()": FooInfo
+            181..188 "BarInfo": Provider[BarInfo]
+            181..190 "BarInfo()": BarInfo
+            169..191 "[FooInfo(), BarInfo()]": list[Unknown]
+            193..194 "a": rule
+            197..201 "rule": def rule(*args, **kwargs) -> Unknown
+            219..224 "_impl": def _impl(ctx) -> Unknown
+            238..245 "FooInfo": Provider[FooInfo]
+            247..254 "BarInfo": Provider[BarInfo]
+            237..255 "[FooInfo, BarInfo]": list[Unknown]
+            197..256 "rule(implementation = _impl, provides = [FooInfo, BarInfo])": rule
+            257..258 "b": rule
+            261..265 "rule": def rule(*args, **kwargs) -> Unknown
+            283..294 "_other_impl": def _other_impl(ctx) -> Unknown
+            308..315 "FooInfo": Provider[FooInfo]
+            307..316 "[FooInfo]": list[Provider[FooInfo]]
+            261..317 "rule(implementation = _other_impl, provides = [FooInfo])": rule
+
+            71..101 Missing provider(s) "BarInfo" required by "provides"
+            83..92 Provider "FooInfo" is returned more than once
+            94..100 Expected a provider instance, got "Literal["oops"]"
+            153..157 Missing provider(s) "FooInfo" required by "provides"
+        "#]],
+    );
+}
+
+#[test]
+fn test_attr_schema() {
+    check_infer(
+        r#"
+unchecked = {"a": attr.string(default = 3)}
+
+my_rule = rule(attrs = {
+    "a": attr.string(default = 3),
+    "b": attr.label(allow_files = True, allow_single_file = True),
+    "c": attr.string(values = ["x", 1], default = "y"),
+    "d": attr.int(mandatory = True, default = 1),
+    "e": attr.label(executable = True),
+    "f": attr.label_list(default = ["//a", "//b"]),
+})
+
+_COMMON = {"g": attr.string(default = 3)}
+_ATTRS = dict(_COMMON, h = attr.string(default = 4), **{"i": attr.int(mandatory = True, default = 1)})
+
+other_rule = rule(attrs = _ATTRS | {"j": attr.label(executable = True)})
+third_rule = rule(attrs = _ATTRS)
+"#,
+        expect![[r#"
+            1..10 "unchecked": dict[string, Attribute]
+            14..17 "\"a\"": Literal["a"]
+            19..23 "attr": attr
+            19..30 "attr.string": def string(*args, **kwargs) -> Unknown
+            41..42 "3": Literal[3]
+            19..43 "attr.string(default = 3)": Attribute
+            13..44 "{\"a\": attr.string(default = 3)}": dict[string, Attribute]
+            46..53 "my_rule": rule
+            56..60 "rule": def rule(*args, **kwargs) -> Unknown
+            75..78 "\"a\"": Literal["a"]
+            80..84 "attr": attr
+            80..91 "attr.string": def string(*args, **kwargs) -> Unknown
+            102..103 "3": Literal[3]
+            80..104 "attr.string(default = 3)": Attribute
+            110..113 "\"b\"": Literal["b"]
+            115..119 "attr": attr
+            115..125 "attr.label": def label(*args, **kwargs) -> Unknown
+            140..144 "True": Literal[True]
+            166..170 "True": Literal[True]
+            115..171 "attr.label(allow_files = True, allow_single_file = True)": Attribute
+            177..180 "\"c\"": Literal["c"]
+            182..186 "attr": attr
+            182..193 "attr.string": def string(*args, **kwargs) -> Unknown
+            204..207 "\"x\"": Literal["x"]
+            209..210 "1": Literal[1]
+            203..211 "[\"x\", 1]": list[Unknown]
+            223..226 "\"y\"": Literal["y"]
+            182..227 "attr.string(values = [\"x\", 1], default = \"y\")": Attribute
+            233..236 "\"d\"": Literal["d"]
+            238..242 "attr": attr
+            238..246 "attr.int": def int(*args, **kwargs) -> Unknown
+            259..263 "True": Literal[True]
+            275..276 "1": Literal[1]
+            238..277 "attr.int(mandatory = True, default = 1)": Attribute
+            283..286 "\"e\"": Literal["e"]
+            288..292 "attr": attr
+            288..298 "attr.label": def label(*args, **kwargs) -> Unknown
+            312..316 "True": Literal[True]
+            288..317 "attr.label(executable = True)": Attribute
+            323..326 "\"f\"": Literal["f"]
+            328..332 "attr": attr
+            328..343 "attr.label_list": def label_list(*args, **kwargs) -> Unknown
+            355..360 "\"//a\"": Literal["//a"]
+            362..367 "\"//b\"": Literal["//b"]
+            354..368 "[\"//a\", \"//b\"]": list[string]
+            328..369 "attr.label_list(default = [\"//a\", \"//b\"])": Attribute
+            69..372 "{\n    \"a\": attr.string(default = 3),\n    \"b\": attr.label(allow_files = True, allow_single_file = True),\n    \"c\": attr.string(values = [\"x\", 1], default = \"y\"),\n    \"d\": attr.int(mandatory = True, default = 1),\n    \"e\": attr.label(executable = True),\n    \"f\": attr.label_list(default = [\"//a\", \"//b\"]),\n}": dict[string, Attribute]
+            56..373 "rule(attrs = {\n    \"a\": attr.string(default = 3),\n    \"b\": attr.label(allow_files = True, allow_single_file = True),\n    \"c\": attr.string(values = [\"x\", 1], default = \"y\"),\n    \"d\": attr.int(mandatory = True, default = 1),\n    \"e\": attr.label(executable = True),\n    \"f\": attr.label_list(default = [\"//a\", \"//b\"]),\n})": rule
+            375..382 "_COMMON": dict[string, Attribute]
+            386..389 "\"g\"": Literal["g"]
+            391..395 "attr": attr
+            391..402 "attr.string": def string(*args, **kwargs) -> Unknown
+            413..414 "3": Literal[3]
+            391..415 "attr.string(default = 3)": Attribute
+            385..416 "{\"g\": attr.string(default = 3)}": dict[string, Attribute]
+            417..423 "_ATTRS": dict[string, Attribute]
+            426..430 "dict": def dict(x0: dict[Unknown, Unknown] | Iterable[Iterable[Any]] = None, **kwargs) -> dict[Unknown, Unknown]
+            431..438 "_COMMON": dict[string, Attribute]
+            444..448 "attr": attr
+            444..455 "attr.string": def string(*args, **kwargs) -> Unknown
+            466..467 "4": Literal[4]
+            444..468 "attr.string(default = 4)": Attribute
+            473..476 "\"i\"": Literal["i"]
+            478..482 "attr": attr
+            478..486 "attr.int": def int(*args, **kwargs) -> Unknown
+            499..503 "True": Literal[True]
+            515..516 "1": Literal[1]
+            478..517 "attr.int(mandatory = True, default = 1)": Attribute
+            472..518 "{\"i\": attr.int(mandatory = True, default = 1)}": dict[string, Attribute]
+            426..519 "dict(_COMMON, h = attr.string(default = 4), **{\"i\": attr.int(mandatory = True, default = 1)})": dict[string, Attribute]
+            521..531 "other_rule": rule
+            534..538 "rule": def rule(*args, **kwargs) -> Unknown
+            547..553 "_ATTRS": dict[string, Attribute]
+            557..560 "\"j\"": Literal["j"]
+            562..566 "attr": attr
+            562..572 "attr.label": def label(*args, **kwargs) -> Unknown
+            586..590 "True": Literal[True]
+            562..591 "attr.label(executable = True)": Attribute
+            556..592 "{\"j\": attr.label(executable = True)}": dict[string, Attribute]
+            547..592 "_ATTRS | {\"j\": attr.label(executable = True)}": dict[string, Attribute | Attribute]
+            534..593 "rule(attrs = _ATTRS | {\"j\": attr.label(executable = True)})": rule
+            594..604 "third_rule": rule
+            607..611 "rule": def rule(*args, **kwargs) -> Unknown
+            620..626 "_ATTRS": dict[string, Attribute]
+            607..627 "rule(attrs = _ATTRS)": rule
+
+            102..103 Default value of type "Literal[3]" cannot be assigned to attribute of type "string"
+            166..170 "allow_files" and "allow_single_file" cannot both be specified
+            209..210 Expected a value of type "string", got "Literal[1]"
+            223..226 Default value is not one of the attribute's allowed "values"
+            275..276 Default value is never used because the attribute is mandatory
+            312..316 "cfg" must be specified for executable attributes
+            413..414 Default value of type "Literal[3]" cannot be assigned to attribute of type "string"
+            466..467 Default value of type "Literal[4]" cannot be assigned to attribute of type "string"
+            515..516 Default value is never used because the attribute is mandatory
+            586..590 "cfg" must be specified for executable attributes
+        "#]],
+    );
+}
+
+#[test]
+fn test_subrules() {
+    check_infer_with_options(
+        r#"
+def _compile_impl(subrule_ctx, srcs, _compiler):
+    subrule_ctx.toolchains["//cc:toolchain_type"]
+    subrule_ctx.toolchains["//py:toolchain_type"]
+    _compiler
+    return srcs
+
+_compile = subrule(
+    implementation = _compile_impl,
+    attrs = {"_compiler": attr.label(default = "//tools:cc", allow_single_file = True)},
+    toolchains = ["//cc:toolchain_type"],
+)
+
+def _link_impl(subrule_ctx):
+    return None
+
+_link = subrule(
+    implementation = _link_impl,
+    attrs = {"deps": attr.label_list(), "_opt": attr.string(default = "O2"), "_dep": attr.label()},
+)
+
+def _impl(ctx):
+    _compile(["a.cc"])
+    _compile(_compiler = "x")
+    _link()
+
+my_rule = rule(
+    implementation = _impl,
+    subrules = [_compile],
+)
+
+SUBRULES = [_compile]
+
+def _other_impl(ctx):
+    _link()
+
+other_rule = rule(implementation = _other_impl, subrules = SUBRULES)
+"#,
+        expect![[r#"
+            54..65 "subrule_ctx": subrule_ctx
+            54..76 "subrule_ctx.toolchains": ToolchainContext
+            77..98 "\"//cc:toolchain_type\"": Literal["//cc:toolchain_type"]
+            54..99 "subrule_ctx.toolchains[\"//cc:toolchain_type\"]": Unknown
+            104..115 "subrule_ctx": subrule_ctx
+            104..126 "subrule_ctx.toolchains": ToolchainContext
+            127..148 "\"//py:toolchain_type\"": Literal["//py:toolchain_type"]
+            104..149 "subrule_ctx.toolchains[\"//py:toolchain_type\"]": Unknown
+            154..163 "_compiler": File
+            175..179 "srcs": Unknown
+            181..189 "_compile": Subrule
+            192..199 "subrule": def subrule(*args, **kwargs) -> Unknown
+            222..235 "_compile_impl": def _compile_impl(subrule_ctx, srcs, _compiler) -> Unknown
+            250..261 "\"_compiler\"": Literal["_compiler"]
+            263..267 "attr": attr
+            263..273 "attr.label": def label(*args, **kwargs) -> Unknown
+            284..296 "\"//tools:cc\"": Literal["//tools:cc"]
+            318..322 "True": Literal[True]
+            263..323 "attr.label(default = \"//tools:cc\", allow_single_file = True)": Attribute
+            249..324 "{\"_compiler\": attr.label(default = \"//tools:cc\", allow_single_file = True)}": dict[string, Attribute]
+            344..365 "\"//cc:toolchain_type\"": Literal["//cc:toolchain_type"]
+            343..366 "[\"//cc:toolchain_type\"]": list[string]
+            192..369 "subrule(\n    implementation = _compile_impl,\n    attrs = {\"_compiler\": attr.label(default = \"//tools:cc\", allow_single_file = True)},\n    toolchains = [\"//cc:toolchain_type\"],\n)": Subrule
+            411..415 "None": None
+            417..422 "_link": Subrule
+            425..432 "subrule": def subrule(*args, **kwargs) -> Unknown
+            455..465 "_link_impl": def _link_impl(subrule_ctx) -> Unknown
+            480..486 "\"deps\"": Literal["deps"]
+            488..492 "attr": attr
+            488..503 "attr.label_list": def label_list(*args, **kwargs) -> Unknown
+            488..505 "attr.label_list()": Attribute
+            507..513 "\"_opt\"": Literal["_opt"]
+            515..519 "attr": attr
+            515..526 "attr.string": def string(*args, **kwargs) -> Unknown
+            537..541 "\"O2\"": Literal["O2"]
+            515..542 "attr.string(default = \"O2\")": Attribute
+            544..550 "\"_dep\"": Literal["_dep"]
+            552..556 "attr": attr
+            552..562 "attr.label": def label(*args, **kwargs) -> Unknown
+            552..564 "attr.label()": Attribute
+            479..565 "{\"deps\": attr.label_list(), \"_opt\": attr.string(default = \"O2\"), \"_dep\": attr.label()}": dict[string, Attribute]
+            425..568 "subrule(\n    implementation = _link_impl,\n    attrs = {\"deps\": attr.label_list(), \"_opt\": attr.string(default = \"O2\"), \"_dep\": attr.label()},\n)": Subrule
+            590..598 "_compile": Subrule
+            600..606 "\"a.cc\"": Literal["a.cc"]
+            599..607 "[\"a.cc\"]": list[string]
+            590..608 "_compile([\"a.cc\"])": Unknown
+            613..621 "_compile": Subrule
+            634..637 "\"x\"": Literal["x"]
+            613..638 "_compile(_compiler = \"x\")": Unknown
+            643..648 "_link": Subrule
+            643..650 "_link()": Unknown
+            652..659 "my_rule": rule
+            662..666 "rule": def rule(*args, **kwargs) -> Unknown
+            689..694 "_impl": def _impl(ctx) -> Unknown
+            712..720 "_compile": Subrule
+            711..721 "[_compile]": list[Subrule]
+            662..724 "rule(\n    implementation = _impl,\n    subrules = [_compile],\n)": rule
+            726..734 "SUBRULES": list[Subrule]
+            738..746 "_compile": Subrule
+            737..747 "[_compile]": list[Subrule]
+            775..780 "_link": Subrule
+            775..782 "_link()": Unknown
+            784..794 "other_rule": rule
+            797..801 "rule": def rule(*args, **kwargs) -> Unknown
+            819..830 "_other_impl": def _other_impl(ctx) -> Unknown
+            843..851 "SUBRULES": list[Subrule]
+            797..852 "rule(implementation = _other_impl, subrules = SUBRULES)": rule
+
+            104..149 Toolchain type "//py:toolchain_type" is not declared by this rule
+            480..486 Subrule attribute "deps" must be private, i.e. its name must start with "_"
+            515..542 Subrule attribute "_opt" must be a label or label_list attribute
+            552..564 Subrule attribute "_dep" must have a default value
+            613..638 Argument missing for parameter(s) "srcs"
+            634..637 Unexpected keyword argument "_compiler"
+            643..650 Subrule "_link" is not declared in "subrules"
+        "#]],
+        InferenceOptions {
+            infer_ctx_attributes: true,
+            ..Default::default()
+        },
+    );
+}
+
+#[test]
+fn test_use_repo() {
+    let mut repos = ModuleExtensionRepos::from_lockfile(
+        r#"{
+  "moduleExtensions": {
+    "//:extensions.bzl%deps": {
+      "general": {
+        "generatedRepoSpecs": {
+          "tool_config": {},
+          "tool_linux": {},
+          "tool_macos": {}
+        }
+      }
+    }
+  }
+}"#,
+    )
+    .unwrap();
+    repos.set_referenced_repos(
+        ["tool_config", "tool_linux", "macos"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+    );
+    let mut builder = TestDatabaseBuilder::default();
+    builder.add_function("use_extension");
+    builder.add_function("use_repo");
+    builder.set_module_extension_repos(repos);
+    check_infer_with_builder(
+        r#"
+deps = use_extension("//:extensions.bzl", "deps")
+use_repo(deps, "tool_linux", "tool_windows")
+use_repo(deps, macos = "tool_macos")
+
+pip = use_extension("@rules_python//python/extensions:pip.bzl", "pip")
+use_repo(pip, "pypi", "tool_config")
+"#,
+        expect![[r#"
+            1..5 "deps": Unknown
+            8..21 "use_extension": def use_extension(*args, **kwargs) -> Unknown
+            22..41 "\"//:extensions.bzl\"": Literal["//:extensions.bzl"]
+            43..49 "\"deps\"": Literal["deps"]
+            8..50 "use_extension(\"//:extensions.bzl\", \"deps\")": Unknown
+            51..59 "use_repo": def use_repo(*args, **kwargs) -> Unknown
+            60..64 "deps": Unknown
+            66..78 "\"tool_linux\"": Literal["tool_linux"]
+            80..94 "\"tool_windows\"": Literal["tool_windows"]
+            51..95 "use_repo(deps, \"tool_linux\", \"tool_windows\")": Unknown
+            96..104 "use_repo": def use_repo(*args, **kwargs) -> Unknown
+            105..109 "deps": Unknown
+            119..131 "\"tool_macos\"": Literal["tool_macos"]
+            96..132 "use_repo(deps, macos = \"tool_macos\")": Unknown
+            134..137 "pip": Unknown
+            140..153 "use_extension": def use_extension(*args, **kwargs) -> Unknown
+            154..196 "\"@rules_python//python/extensions:pip.bzl\"": Literal["@rules_python//python/extensions:pip.bzl"]
+            198..203 "\"pip\"": Literal["pip"]
+            140..204 "use_extension(\"@rules_python//python/extensions:pip.bzl\", \"pip\")": Unknown
+            205..213 "use_repo": def use_repo(*args, **kwargs) -> Unknown
+            214..217 "pip": Unknown
+            219..225 "\"pypi\"": Literal["pypi"]
+            227..240 "\"tool_config\"": Literal["tool_config"]
+            205..241 "use_repo(pip, \"pypi\", \"tool_config\")": Unknown
+
+            80..94 Module extension "deps" does not generate a repository named "tool_windows"
+            96..132 Repositories generated by module extension "deps" are referenced but not imported: "tool_config"
+        "#]],
+        builder,
+    );
+}
+
+#[test]
+fn test_use_repo_in_module_segment() {
+    let mut repos = ModuleExtensionRepos::from_lockfile(
+        r#"{
+  "moduleExtensions": {
+    "//:extensions.bzl%deps": {
+      "generatedRepoSpecs": {
+        "tool_config": {},
+        "tool_linux": {},
+        "tool_macos": {}
+      }
+    }
+  }
+}"#,
+    )
+    .unwrap();
+    repos.set_referenced_repos(
+        ["tool_config", "tool_linux", "tool_macos"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+    );
+    let mut builder = TestDatabaseBuilder::default();
+    builder.add_function("include");
+    builder.add_function("use_extension");
+    builder.add_function("use_repo");
+    builder.set_module_extension_repos(repos);
+    builder.set_file_path("//:deps.MODULE.bazel");
+    builder.add_file(
+        "//:MODULE.bazel",
+        r#"
+deps = use_extension("//:extensions.bzl", "deps")
+use_repo(deps, "tool_linux")
+
+include("//:deps.MODULE.bazel")
+"#,
+    );
+    check_infer_with_builder(
+        r#"
+deps = use_extension("//:extensions.bzl", "deps")
+use_repo(deps, "tool_config")
+"#,
+        expect![[r#"
+            1..5 "deps": Unknown
+            8..21 "use_extension": def use_extension(*args, **kwargs) -> Unknown
+            22..41 "\"//:extensions.bzl\"": Literal["//:extensions.bzl"]
+            43..49 "\"deps\"": Literal["deps"]
+            8..50 "use_extension(\"//:extensions.bzl\", \"deps\")": Unknown
+            51..59 "use_repo": def use_repo(*args, **kwargs) -> Unknown
+            60..64 "deps": Unknown
+            66..79 "\"tool_config\"": Literal["tool_config"]
+            51..80 "use_repo(deps, \"tool_config\")": Unknown
+
+            51..80 Repositories generated by module extension "deps" are referenced but not imported: "tool_macos"
+        "#]],
+        builder,
+    );
+}
+
 #[test]
 fn test_tuple_type_comments() {
     check_infer(
@@ -1633,6 +2978,81 @@ def f():
     );
 }
 
+#[test]
+fn test_narrowing() {
+    check_infer_with_code_flow_analysis(
+        r#"
+def f(x, y):
+    # type: (string | None, int | string | None) -> None
+    if x != None:
+        x
+    else:
+        x
+    x
+    if x:
+        x
+    x or 1
+    x if x == None else x
+    if type(y) == "string" and y:
+        y
+    else:
+        y
+"#,
+        expect![[r#"
+            78..79 "x": string | None
+            83..87 "None": None
+            78..87 "x != None": bool
+            97..98 "x": string
+            117..118 "x": None
+            123..124 "x": string | None
+            132..133 "x": string | None
+            143..144 "x": string
+            149..150 "x": string | None
+            154..155 "1": Literal[1]
+            149..155 "x or 1": string | int
+            160..161 "x": None
+            165..166 "x": string | None
+            170..174 "None": None
+            165..174 "x == None": bool
+            180..181 "x": string
+            160..181 "x if x == None else x": None | string
+            189..193 "type": def type(x0: Any) -> string
+            194..195 "y": int | string | None
+            189..196 "type(y)": string
+            200..208 "\"string\"": Literal["string"]
+            189..208 "type(y) == \"string\"": bool
+            213..214 "y": string
+            189..214 "type(y) == \"string\" and y": bool | string
+            224..225 "y": string
+            244..245 "y": int | None | string
+        "#]],
+    );
+}
+
+#[test]
+fn test_narrowing_shadowed_type() {
+    check_infer_with_code_flow_analysis(
+        r#"
+def type(x):
+    # type: (Any) -> string
+    pass
+
+def f(y):
+    # type: (int | string) -> None
+    if type(y) == "string":
+        y
+"#,
+        expect![[r#"
+            104..108 "type": def type(x: Any) -> string
+            109..110 "y": int | string
+            104..111 "type(y)": string
+            115..123 "\"string\"": Literal["string"]
+            104..123 "type(y) == \"string\"": bool
+            133..134 "y": int | string
+        "#]],
+    );
+}
+
 #[test]
 fn test_possibly_unbound() {
     check_infer_with_code_flow_analysis(
@@ -1711,6 +3131,55 @@ def h():
             255..256 "y": Literal["one"]
             259..264 "\"one\"": Literal["one"]
             291..292 "y": Never
+
+            54..69 Code is unreachable
+            127..142 Code is unreachable
+            291..292 Code is unreachable
+        "#]],
+    );
+}
+
+#[test]
+fn test_no_return_calls() {
+    check_infer_with_code_flow_analysis(
+        r#"
+def f(x):
+    # type: (string | None) -> string
+    if not x:
+        fail("x is required")
+    return x
+
+def g(x):
+    # type: (string | None) -> None
+    if x == None:
+        _fail("x is required")
+    x
+    return
+    x
+
+def _fail(msg):
+    fail(msg)
+"#,
+        expect![[r#"
+            60..61 "x": string | None
+            56..61 "not x": bool
+            71..75 "fail": def fail(*args: Any) -> Never
+            76..91 "\"x is required\"": Literal["x is required"]
+            71..92 "fail(\"x is required\")": Never
+            104..105 "x": string
+            160..161 "x": string | None
+            165..169 "None": None
+            160..169 "x == None": bool
+            179..184 "_fail": def _fail(msg) -> Unknown
+            185..200 "\"x is required\"": Literal["x is required"]
+            179..201 "_fail(\"x is required\")": Never
+            206..207 "x": string
+            223..224 "x": string | None
+            246..250 "fail": def fail(*args: Any) -> Never
+            251..254 "msg": Unknown
+            246..255 "fail(msg)": Never
+
+            223..224 Code is unreachable
         "#]],
     );
 }
@@ -1,7 +1,8 @@
 //! Partially replicates the "completions" API in the LSP specification.
 
 use rustc_hash::FxHashMap;
-use starpls_common::{parse, FileId, LoadItemCandidateKind};
+use starpls_bazel::APIContext;
+use starpls_common::{parse, Db as _, FileId, LoadItemCandidate, LoadItemCandidateKind};
 use starpls_hir::{Db, Name, Param, ScopeDef, Semantics, Type};
 use starpls_syntax::{
     ast::{self, AstNode, AstToken},
@@ -10,7 +11,10 @@ use starpls_syntax::{
     SyntaxNode, TextRange, TextSize,
 };
 
-use crate::FilePosition;
+use crate::{
+    util::{bazel_dep_arg, keyword_arg_for_string, BazelDepArg},
+    Database, FilePosition, ResolvedPath,
+};
 
 const COMPLETION_MARKER: &str = "__STARPLS_COMPLETION_MARKER";
 
@@ -69,6 +73,7 @@ pub enum CompletionItemKind {
     File,
     Folder,
     Constant,
+    Target,
 }
 
 #[repr(u16)]
@@ -113,6 +118,22 @@ enum StringContext {
         file_id: FileId,
         lhs: ast::Expression,
     },
+    BazelDep {
+        text: ast::String,
+        keyword: String,
+        module_name: Option<String>,
+    },
+    Target {
+        file_id: FileId,
+        text: ast::String,
+    },
+    /// A string passed to a keyword argument, which may be a label-valued attribute like `deps`.
+    LabelAttribute {
+        file_id: FileId,
+        text: ast::String,
+        call: ast::CallExpr,
+        keyword: String,
+    },
 }
 
 struct CompletionContext {
@@ -120,7 +141,7 @@ struct CompletionContext {
 }
 
 pub(crate) fn completions(
-    db: &dyn Db,
+    db: &Database,
     pos: FilePosition,
     trigger_character: Option<String>,
 ) -> Option<Vec<CompletionItem>> {
@@ -214,52 +235,9 @@ pub(crate) fn completions(
             }
         }
         CompletionAnalysis::String(StringContext::LoadModule { file_id, text }) => {
-            let (value, offset) = text.value_and_offset()?;
-            let token_start = text.syntax().text_range().start() + TextSize::from(offset);
-            for candidate in db.list_load_candidates(&value, file_id).ok()?? {
-                let start = TextSize::from(
-                    value
-                        .rfind(&['/', ':', '@'])
-                        .map(|start| {
-                            if candidate.replace_trailing_slash {
-                                start
-                            } else {
-                                start + 1
-                            }
-                        })
-                        .unwrap_or(0) as u32,
-                );
-                let end = TextSize::from(value.len() as u32);
-                let (edit, filter_text) = if candidate.replace_trailing_slash {
-                    (
-                        Edit::InsertReplaceEdit(InsertReplaceEdit {
-                            new_text: candidate.path.clone(),
-                            insert: TextRange::new(token_start + start, token_start + end),
-                            replace: TextRange::new(token_start + start, token_start + end),
-                        }),
-                        Some("/".to_string()),
-                    )
-                } else {
-                    (
-                        Edit::TextEdit(TextEdit {
-                            range: TextRange::new(token_start + start, token_start + end),
-                            new_text: candidate.path.clone(),
-                        }),
-                        None,
-                    )
-                };
-
-                items.push(CompletionItem {
-                    label: candidate.path,
-                    kind: match candidate.kind {
-                        LoadItemCandidateKind::Directory => CompletionItemKind::Folder,
-                        LoadItemCandidateKind::File => CompletionItemKind::File,
-                    },
-                    mode: Some(CompletionMode::TextEdit(edit)),
-                    relevance: CompletionRelevance::VariableOrKeyword,
-                    filter_text,
-                });
-            }
+            let (value, _) = text.value_and_offset()?;
+            let candidates = db.list_load_candidates(&value, file_id).ok()??;
+            add_path_candidates(&mut items, &text, candidates)?;
         }
         CompletionAnalysis::String(StringContext::LoadItem { file_id, load_stmt }) => {
             let sema = Semantics::new(db);
@@ -303,12 +281,202 @@ pub(crate) fn completions(
                 });
             }
         }
+        CompletionAnalysis::String(StringContext::BazelDep {
+            text,
+            keyword,
+            module_name,
+        }) => {
+            let registry = db.bazel_registry.as_ref()?;
+            let (value, offset) = text.value_and_offset()?;
+            let start = text.syntax().text_range().start() + TextSize::from(offset);
+            let range = TextRange::new(start, start + TextSize::from(value.len() as u32));
+            let mut add_item = |label: &str, kind| {
+                items.push(CompletionItem {
+                    label: label.to_string(),
+                    kind,
+                    mode: Some(CompletionMode::TextEdit(Edit::TextEdit(TextEdit {
+                        range,
+                        new_text: label.to_string(),
+                    }))),
+                    relevance: CompletionRelevance::VariableOrKeyword,
+                    filter_text: None,
+                })
+            };
+
+            match keyword.as_str() {
+                "name" => {
+                    for name in registry.module_names() {
+                        add_item(name, CompletionItemKind::Module);
+                    }
+                }
+                "version" => {
+                    for version in registry.versions(module_name.as_deref()?) {
+                        add_item(version, CompletionItemKind::Constant);
+                    }
+                }
+                _ => {}
+            }
+        }
+        CompletionAnalysis::String(StringContext::Target { file_id, text }) => {
+            add_targets(db, file_id, &text, &mut items)?;
+        }
+        CompletionAnalysis::String(StringContext::LabelAttribute {
+            file_id,
+            text,
+            call,
+            keyword,
+        }) => {
+            let sema = Semantics::new(db);
+            let file = db.get_file(file_id)?;
+            let value = text.value()?;
+            let allowed_files = sema.resolve_call_expr(file, &call).and_then(|callable| {
+                callable
+                    .params(db)
+                    .into_iter()
+                    .find_map(|(param, _)| match param.name(db) {
+                        Some(name) if name.as_str() == keyword => param.allowed_files(db),
+                        _ => None,
+                    })
+            });
+
+            match allowed_files {
+                Some(allowed_files) => {
+                    if let Ok(Some(candidates)) =
+                        db.list_label_candidates(&value, file_id, &allowed_files)
+                    {
+                        add_path_candidates(&mut items, &text, candidates)?;
+                    }
+                    // Targets in the current package can be referred to by name alone, e.g. `"foo"`.
+                    if value.contains(':')
+                        || (!value.starts_with(['/', '@']) && !value.contains('/'))
+                    {
+                        add_targets(db, file_id, &text, &mut items);
+                    }
+                }
+                None if is_target_label(&value) => {
+                    add_targets(db, file_id, &text, &mut items)?;
+                }
+                None => {}
+            }
+        }
         _ => {}
     }
 
     Some(items)
 }
 
+/// Adds the candidates for completing a load path or label, replacing the text after the last
+/// separator in the string.
+fn add_path_candidates(
+    items: &mut Vec<CompletionItem>,
+    text: &ast::String,
+    candidates: Vec<LoadItemCandidate>,
+) -> Option<()> {
+    let (value, offset) = text.value_and_offset()?;
+    let token_start = text.syntax().text_range().start() + TextSize::from(offset);
+    for candidate in candidates {
+        let start = TextSize::from(
+            value
+                .rfind(&['/', ':', '@'])
+                .map(|start| {
+                    if candidate.replace_trailing_slash {
+                        start
+                    } else {
+                        start + 1
+                    }
+                })
+                .unwrap_or(0) as u32,
+        );
+        let end = TextSize::from(value.len() as u32);
+        let (edit, filter_text) = if candidate.replace_trailing_slash {
+            (
+                Edit::InsertReplaceEdit(InsertReplaceEdit {
+                    new_text: candidate.path.clone(),
+                    insert: TextRange::new(token_start + start, token_start + end),
+                    replace: TextRange::new(token_start + start, token_start + end),
+                }),
+                Some("/".to_string()),
+            )
+        } else {
+            (
+                Edit::TextEdit(TextEdit {
+                    range: TextRange::new(token_start + start, token_start + end),
+                    new_text: candidate.path.clone(),
+                }),
+                None,
+            )
+        };
+
+        items.push(CompletionItem {
+            label: candidate.path,
+            kind: match candidate.kind {
+                LoadItemCandidateKind::Directory => CompletionItemKind::Folder,
+                LoadItemCandidateKind::File => CompletionItemKind::File,
+            },
+            mode: Some(CompletionMode::TextEdit(edit)),
+            relevance: CompletionRelevance::VariableOrKeyword,
+            filter_text,
+        });
+    }
+    Some(())
+}
+
+/// Adds the targets of the package named by a label, replacing the text after the label's colon.
+/// Labels without a colon name a target in the current package.
+fn add_targets(
+    db: &Database,
+    file_id: FileId,
+    text: &ast::String,
+    items: &mut Vec<CompletionItem>,
+) -> Option<()> {
+    let (value, offset) = text.value_and_offset()?;
+    let file = db.get_file(file_id)?;
+    let (build_file, start) = match value.rfind(':') {
+        None => (file, 0),
+        Some(0) => (file, 1),
+        Some(colon) => {
+            // Resolve a placeholder target in the label's package to find its BUILD file.
+            match db
+                .resolve_path(
+                    &format!("{}:__pkg__", &value[..colon]),
+                    file.dialect(db),
+                    file_id,
+                )
+                .ok()??
+            {
+                ResolvedPath::BuildTarget { build_file, .. } => {
+                    (db.get_file(build_file)?, colon + 1)
+                }
+                _ => return None,
+            }
+        }
+    };
+
+    let token_start = text.syntax().text_range().start() + TextSize::from(offset);
+    let range = TextRange::new(
+        token_start + TextSize::from(start as u32),
+        token_start + TextSize::from(value.len() as u32),
+    );
+    for target in Semantics::new(db).targets(build_file) {
+        items.push(CompletionItem {
+            label: target.name().to_string(),
+            kind: CompletionItemKind::Target,
+            mode: Some(CompletionMode::TextEdit(Edit::TextEdit(TextEdit {
+                range,
+                new_text: target.name().to_string(),
+            }))),
+            relevance: CompletionRelevance::VariableOrKeyword,
+            filter_text: None,
+        });
+    }
+    Some(())
+}
+
+/// Whether the string looks like a label naming a target, e.g. `":foo"` or `"//pkg:foo"`.
+fn is_target_label(value: &str) -> bool {
+    value.starts_with(':') || (value.starts_with(['/', '@']) && value.contains(':'))
+}
+
 pub(crate) fn add_globals(items: &mut Vec<CompletionItem>) {
     let add_global = &mut |global: &'static str| {
         items.push(CompletionItem {
@@ -350,7 +518,12 @@ fn add_keywords(items: &mut Vec<CompletionItem>, is_in_def: bool, is_in_for: boo
     }
 }
 
-fn maybe_str_context(file_id: FileId, root: &SyntaxNode, pos: TextSize) -> Option<StringContext> {
+fn maybe_str_context(
+    file_id: FileId,
+    api_context: Option<APIContext>,
+    root: &SyntaxNode,
+    pos: TextSize,
+) -> Option<StringContext> {
     let token = root.token_at_offset(pos).right_biased()?;
     let text = ast::String::cast(token.clone())?;
     let parent = token.parent()?;
@@ -361,6 +534,37 @@ fn maybe_str_context(file_id: FileId, root: &SyntaxNode, pos: TextSize) -> Optio
         let load_stmt = ast::LoadStmt::cast(parent.parent()?)?;
         return Some(StringContext::LoadItem { file_id, load_stmt });
     } else if let Some(expr) = ast::LiteralExpr::cast(parent) {
+        if api_context == Some(APIContext::Module) {
+            if let Some(BazelDepArg {
+                keyword,
+                module_name,
+                ..
+            }) = bazel_dep_arg(&expr)
+            {
+                return Some(StringContext::BazelDep {
+                    text,
+                    keyword,
+                    module_name,
+                });
+            }
+        }
+
+        if api_context == Some(APIContext::Build) {
+            if let Some((call, keyword)) = keyword_arg_for_string(&expr) {
+                return Some(StringContext::LabelAttribute {
+                    file_id,
+                    text,
+                    call,
+                    keyword,
+                });
+            }
+
+            // Complete the targets of a package in labels like `":foo"` and `"//pkg:foo"`.
+            if is_target_label(&text.value()?) {
+                return Some(StringContext::Target { file_id, text });
+            }
+        }
+
         if let Some(index_expr) = ast::IndexExpr::cast(expr.syntax().parent()?) {
             if index_expr.index() == Some(ast::Expression::Literal(expr)) {
                 return Some(StringContext::DictKey {
@@ -385,7 +589,7 @@ impl CompletionContext {
         let file = db.get_file(file_id)?;
         let parse = parse(db, file);
 
-        if let Some(cx) = maybe_str_context(file_id, &parse.syntax(db), pos) {
+        if let Some(cx) = maybe_str_context(file_id, file.api_context(db), &parse.syntax(db), pos) {
             return Some(CompletionContext {
                 analysis: CompletionAnalysis::String(cx),
             });
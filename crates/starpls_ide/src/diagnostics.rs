@@ -1,7 +1,12 @@
-use starpls_common::{Db, Diagnostic, FileId};
-use starpls_hir::diagnostics_for_file;
+use starpls_bazel::{visibility, APIContext, Label};
+use starpls_common::{
+    parse, Db, Diagnostic, DiagnosticCode, Dialect, File, FileId, FileRange, Navigation,
+    RelatedInformation, Severity,
+};
+use starpls_hir::{diagnostics_for_file, Semantics, TypeIgnores};
+use starpls_syntax::ast::{self, AstNode, AstToken};
 
-use crate::Database;
+use crate::{util::glob_args, Database, ResolvedPath};
 
 pub(crate) fn diagnostics(db: &Database, file_id: FileId) -> Vec<Diagnostic> {
     let file = match db.get_file(file_id) {
@@ -16,10 +21,252 @@ pub(crate) fn diagnostics(db: &Database, file_id: FileId) -> Vec<Diagnostic> {
         tcx.diagnostics_for_file(file)
     });
 
-    // Limit the amount of syntax errors we send, as this many syntax errors probably means something
-    // is really wrong with the file being analyzed.
-    diagnostics_for_file(db, file)
-        .take(128)
-        .chain(diagnostics)
+    // Apply `# type: ignore` comments to the diagnostics from every source. Only then limit the
+    // amount of syntax errors we send, as this many syntax errors probably means something is
+    // really wrong with the file being analyzed.
+    let mut type_ignores = TypeIgnores::new(db, file);
+    let mut result = diagnostics_for_file(db, file)
+        .filter(|diagnostic| !type_ignores.is_suppressed(diagnostic))
+        .collect::<Vec<_>>();
+    result.truncate(128);
+    result.extend(
+        diagnostics
+            .into_iter()
+            .chain(glob_diagnostics(db, file))
+            .chain(visibility_diagnostics(db, file))
+            .filter(|diagnostic| !type_ignores.is_suppressed(diagnostic)),
+    );
+    if db.gcx.options().report_unused_type_ignores {
+        result.extend(type_ignores.unused_ignores());
+    }
+    result
+}
+
+/// Reports the `glob()` calls in a BUILD file that match no files, or whose patterns would match
+/// files in subpackages.
+fn glob_diagnostics(db: &Database, file: File) -> Vec<Diagnostic> {
+    if file.api_context(db) != Some(APIContext::Build) {
+        return Vec::new();
+    }
+
+    let mut diagnostics = Vec::new();
+    for call in parse(db, file)
+        .syntax(db)
+        .descendants()
+        .filter_map(ast::CallExpr::cast)
+    {
+        let args = match glob_args(&call) {
+            Some(args) => args,
+            None => continue,
+        };
+        let range = FileRange {
+            file_id: file.id(db),
+            range: call.syntax().text_range(),
+        };
+        let result = match db.loader.glob(
+            file.id(db),
+            &args.include,
+            &args.exclude,
+            args.exclude_directories,
+        ) {
+            Ok(Some(result)) => result,
+            Ok(None) => continue,
+            Err(err) => {
+                diagnostics.push(Diagnostic::new(
+                    DiagnosticCode::Glob,
+                    Severity::Error,
+                    range,
+                    format!("Error evaluating glob: {}", err),
+                ));
+                continue;
+            }
+        };
+
+        if result.matches.is_empty() && args.allow_empty != Some(true) {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticCode::Glob,
+                Severity::Warning,
+                range.clone(),
+                "Glob doesn't match any files, pass \"allow_empty = True\" if this is expected",
+            ));
+        }
+        if !result.subpackages.is_empty() {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticCode::Glob,
+                Severity::Warning,
+                range,
+                format!(
+                    "Glob doesn't match files in subpackages: {}",
+                    result
+                        .subpackages
+                        .iter()
+                        .map(|subpackage| format!("\"{}\"", subpackage))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Reports the labels in the `deps` of a BUILD file's targets that refer to targets in other
+/// packages that aren't visible to the BUILD file's package.
+fn visibility_diagnostics(db: &Database, file: File) -> Vec<Diagnostic> {
+    if file.api_context(db) != Some(APIContext::Build) {
+        return Vec::new();
+    }
+    let from_package = match db.loader.package_name(file.id(db)) {
+        Some(package) => package,
+        None => return Vec::new(),
+    };
+
+    let mut strings = Vec::new();
+    for call in parse(db, file)
+        .syntax(db)
+        .descendants()
+        .filter_map(ast::CallExpr::cast)
+    {
+        let deps = call
+            .arguments()
+            .into_iter()
+            .flat_map(|args| args.arguments())
+            .find_map(|arg| match arg {
+                ast::Argument::Keyword(arg) if arg.name()?.name()?.text() == "deps" => arg.expr(),
+                _ => None,
+            });
+        if let Some(deps) = deps {
+            collect_label_strings(deps, &mut strings);
+        }
+    }
+
+    strings
+        .iter()
+        .filter_map(|text| check_dep_visibility(db, file.id(db), &from_package, text))
         .collect()
 }
+
+/// Collects the string literals in a list of labels, including lists that are concatenated and
+/// the values of `select()` calls.
+fn collect_label_strings(expr: ast::Expression, strings: &mut Vec<ast::String>) {
+    match expr {
+        ast::Expression::List(list) => {
+            strings.extend(list.elements().filter_map(|element| match element {
+                ast::Expression::Literal(lit) => match lit.kind() {
+                    ast::LiteralKind::String(text) => Some(text),
+                    _ => None,
+                },
+                _ => None,
+            }))
+        }
+        ast::Expression::Binary(binary) => {
+            for expr in [binary.lhs(), binary.rhs()].into_iter().flatten() {
+                collect_label_strings(expr, strings);
+            }
+        }
+        ast::Expression::Paren(paren) => {
+            if let Some(expr) = paren.expr() {
+                collect_label_strings(expr, strings);
+            }
+        }
+        ast::Expression::Call(call) => {
+            match call.callee() {
+                Some(ast::Expression::Name(name_ref))
+                    if name_ref.name().is_some_and(|name| name.text() == "select") => {}
+                _ => return,
+            }
+            let dict = call
+                .arguments()
+                .and_then(|args| args.arguments().next())
+                .and_then(|arg| match arg {
+                    ast::Argument::Simple(arg) => match arg.expr()? {
+                        ast::Expression::Dict(dict) => Some(dict),
+                        _ => None,
+                    },
+                    _ => None,
+                });
+            for value in dict
+                .into_iter()
+                .flat_map(|dict| dict.entries())
+                .filter_map(|entry| entry.value())
+            {
+                collect_label_strings(value, strings);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_dep_visibility(
+    db: &Database,
+    file_id: FileId,
+    from_package: &str,
+    text: &ast::String,
+) -> Option<Diagnostic> {
+    let value = text.value()?;
+    let label = Label::parse(&value).ok()?;
+
+    // Targets are always visible within their own package. Labels in other repositories aren't
+    // checked, since that would require fetching them.
+    if !label.repo().is_empty() || label.is_relative() || label.package() == from_package {
+        return None;
+    }
+
+    let resolve_target = |label: &str| match db.resolve_path(label, Dialect::Bazel, file_id) {
+        Ok(Some(ResolvedPath::BuildTarget {
+            build_file, target, ..
+        })) => Some((db.get_file(build_file)?, target)),
+        _ => None,
+    };
+    let sema = Semantics::new(db);
+    let (build_file, name) = resolve_target(&value)?;
+    let target = sema.target(build_file, &name)?;
+    let target_visibility = target.visibility(db)?;
+    if visibility::is_visible(
+        target_visibility.labels(),
+        label.package(),
+        from_package,
+        |group| {
+            let (file, name) = resolve_target(group)?;
+            sema.package_group(file, &name)
+        },
+    ) {
+        return None;
+    }
+
+    let visibility_range = target_visibility.range(db);
+    let mut diagnostic = Diagnostic::new(
+        DiagnosticCode::Visibility,
+        Severity::Error,
+        FileRange {
+            file_id,
+            range: text.syntax().text_range(),
+        },
+        format!(
+            "Target \"{}\" is not visible from package \"//{}\"",
+            value, from_package
+        ),
+    );
+    if let Some(range) = visibility_range {
+        diagnostic = diagnostic.with_related(RelatedInformation {
+            message: format!("The visibility of \"{}\" is declared here", value),
+            range: FileRange {
+                file_id: build_file.id(db),
+                range,
+            },
+        });
+    }
+
+    // Targets without a declared visibility are private, so jump to the target itself.
+    Some(
+        diagnostic.with_navigation(Navigation {
+            label: format!("Go to the visibility of \"{}\"", value),
+            target: FileRange {
+                file_id: build_file.id(db),
+                range: visibility_range
+                    .or_else(|| target.name_range(db))
+                    .unwrap_or_default(),
+            },
+        }),
+    )
+}
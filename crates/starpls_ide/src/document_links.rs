@@ -0,0 +1,132 @@
+//! Partially replicates the "documentLink" API in the LSP specification.
+
+use starpls_bazel::Label;
+use starpls_common::{parse, Db, Dialect, FileId};
+use starpls_hir::Semantics;
+use starpls_syntax::{
+    ast::{self, AstNode, AstToken},
+    TextRange, TextSize,
+};
+
+use crate::{Database, LocationLink, ResolvedPath};
+
+/// A label in a Bazel file. The location that the label points to is resolved lazily with
+/// [`resolve_document_link`], since resolving labels may require loading BUILD files or fetching
+/// external repositories.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DocumentLink {
+    /// The range of the label, excluding its quotes.
+    pub range: TextRange,
+    pub label: String,
+}
+
+pub(crate) fn document_links(db: &Database, file_id: FileId) -> Option<Vec<DocumentLink>> {
+    let file = db.get_file(file_id)?;
+    if file.dialect(db) != Dialect::Bazel {
+        return None;
+    }
+
+    let links = parse(db, file)
+        .syntax(db)
+        .descendants_with_tokens()
+        .filter_map(|element| ast::String::cast(element.into_token()?))
+        .filter_map(|text| {
+            let (value, offset) = text.value_and_offset()?;
+            if !value.starts_with(['/', ':', '@']) || Label::parse(&value).is_err() {
+                return None;
+            }
+            let start = text.syntax().text_range().start() + TextSize::from(offset);
+            Some(DocumentLink {
+                range: TextRange::new(start, start + TextSize::from(value.len() as u32)),
+                label: value.to_string(),
+            })
+        })
+        .collect();
+    Some(links)
+}
+
+/// Resolves a label to either its source file or the definition of its target in a BUILD file.
+pub(crate) fn resolve_document_link(
+    db: &Database,
+    file_id: FileId,
+    label: &str,
+) -> Option<LocationLink> {
+    match db.resolve_path(label, Dialect::Bazel, file_id).ok()?? {
+        ResolvedPath::Source { path } => {
+            path.try_exists().ok()?.then_some(LocationLink::External {
+                origin_selection_range: None,
+                target_path: path,
+            })
+        }
+        ResolvedPath::BuildTarget {
+            build_file: build_file_id,
+            target,
+            ..
+        } => {
+            let build_file = db.get_file(build_file_id)?;
+            let (target_range, target_selection_range) = Semantics::new(db)
+                .target(build_file, &target)
+                .and_then(|target| {
+                    let range = target.call_expr(db)?.syntax().text_range();
+                    Some((range, target.name_range(db).unwrap_or(range)))
+                })
+                // Fall back to the start of the BUILD file if the target can't be found.
+                .unwrap_or_default();
+            Some(LocationLink::Local {
+                origin_selection_range: None,
+                target_range,
+                target_selection_range,
+                target_file_id: build_file_id,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+    use starpls_bazel::APIContext;
+    use starpls_common::{Dialect, FileInfo};
+
+    use crate::AnalysisSnapshot;
+
+    fn check(input: &str, expect: Expect) {
+        let (snap, file_id) = AnalysisSnapshot::from_single_file(
+            input,
+            Dialect::Bazel,
+            Some(FileInfo::Bazel {
+                api_context: APIContext::Build,
+                is_external: false,
+            }),
+        );
+        let mut actual = String::new();
+        for link in snap.document_links(file_id).unwrap().unwrap() {
+            actual.push_str(&format!("{:?} {}\n", link.range, link.label));
+        }
+        expect.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_labels() {
+        check(
+            r#"
+load("//tools:defs.bzl", "cc_library")
+
+cc_library(
+    name = "lib",
+    srcs = ["lib.cc"],
+    deps = [":missing", "//foo:bar", "@repo//pkg:target", "@@canonical//:target"],
+    visibility = [Label("//visibility:public")],
+)
+"#,
+            expect![[r#"
+                7..23 //tools:defs.bzl
+                107..115 :missing
+                119..128 //foo:bar
+                132..149 @repo//pkg:target
+                153..173 @@canonical//:target
+                202..221 //visibility:public
+            "#]],
+        );
+    }
+}
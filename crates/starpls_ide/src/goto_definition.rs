@@ -1,3 +1,4 @@
+use starpls_bazel::APIContext;
 use starpls_common::{parse as parse_query, Db};
 use starpls_hir::{Name, ScopeDef, Semantics};
 use starpls_syntax::{
@@ -5,7 +6,10 @@ use starpls_syntax::{
     T,
 };
 
-use crate::{util::pick_best_token, Database, FilePosition, LocationLink, ResolvedPath};
+use crate::{
+    util::{bazel_dep_arg, include_call, pick_best_token, BazelDepArg},
+    Database, FilePosition, LocationLink, ResolvedPath,
+};
 
 pub(crate) fn goto_definition(
     db: &Database,
@@ -135,6 +139,45 @@ pub(crate) fn goto_definition(
     }
 
     if let Some(lit) = ast::LiteralExpr::cast(parent) {
+        if file.api_context(db) == Some(APIContext::Module) {
+            // Navigate from an `include()` call to the included segment.
+            if let Some(call) = include_call(&lit) {
+                let segment = sema.resolve_include(file, &call)?;
+                return Some(vec![LocationLink::Local {
+                    origin_selection_range: Some(token.text_range()),
+                    target_range: Default::default(),
+                    target_selection_range: Default::default(),
+                    target_file_id: segment.id(db),
+                }]);
+            }
+
+            // Navigate from a `bazel_dep()` call to the dependency's fetched `MODULE.bazel` file.
+            if let Some(BazelDepArg {
+                keyword,
+                repo_name: Some(repo_name),
+                ..
+            }) = bazel_dep_arg(&lit)
+            {
+                if keyword != "name" && keyword != "repo_name" {
+                    return None;
+                }
+                let path = db
+                    .resolve_path(
+                        &format!("@{}//:MODULE.bazel", repo_name),
+                        file.dialect(db),
+                        file_id,
+                    )
+                    .ok()??;
+                return match path {
+                    ResolvedPath::Source { path } => Some(vec![LocationLink::External {
+                        origin_selection_range: Some(token.text_range()),
+                        target_path: path,
+                    }]),
+                    _ => None,
+                };
+            }
+        }
+
         let value = match lit.kind() {
             ast::LiteralKind::String(s) => s.value()?,
             _ => return None,
@@ -153,42 +196,12 @@ pub(crate) fn goto_definition(
                 ..
             } => {
                 let build_file = db.get_file(build_file_id)?;
-                let parse = parse_query(db, build_file).syntax(db);
-                let call_expr = parse
-                    .children()
-                    .filter_map(ast::CallExpr::cast)
-                    .find(|expr| {
-                        expr.arguments()
-                            .into_iter()
-                            .flat_map(|args| args.arguments())
-                            .any(|arg| match arg {
-                                ast::Argument::Keyword(arg) => {
-                                    arg.name()
-                                        .and_then(|name| name.name())
-                                        .map(|name| name.text() == "name")
-                                        .unwrap_or_default()
-                                        && arg
-                                            .expr()
-                                            .and_then(|expr| match expr {
-                                                ast::Expression::Literal(expr) => Some(expr),
-                                                _ => None,
-                                            })
-                                            .and_then(|expr| match expr.kind() {
-                                                ast::LiteralKind::String(s) => {
-                                                    s.value().map(|value| *value == target)
-                                                }
-                                                _ => None,
-                                            })
-                                            .unwrap_or_default()
-                                }
-                                _ => false,
-                            })
-                    })?;
-                let range = call_expr.syntax().text_range();
+                let target = sema.target(build_file, &target)?;
+                let target_range = target.call_expr(db)?.syntax().text_range();
                 Some(vec![LocationLink::Local {
                     origin_selection_range: Some(token.text_range()),
-                    target_range: range,
-                    target_selection_range: range,
+                    target_range,
+                    target_selection_range: target.name_range(db).unwrap_or(target_range),
                     target_file_id: build_file_id,
                 }])
             }
@@ -200,11 +213,13 @@ pub(crate) fn goto_definition(
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use starpls_bazel::APIContext;
-    use starpls_common::{Dialect, FileInfo};
+    use starpls_common::{Dialect, FileId, FileInfo};
     use starpls_test_util::parse_fixture;
 
-    use crate::{AnalysisSnapshot, FilePosition, LocationLink};
+    use crate::{Analysis, AnalysisSnapshot, FilePosition, LocationLink};
 
     fn check_goto_definition(fixture: &str) {
         let (contents, pos, expected) = parse_fixture(fixture);
@@ -290,4 +305,56 @@ info.fo$0o
 "#,
         )
     }
+
+    fn check_goto_bazel_dep(fixture: &str, expected_path: &str) {
+        let (contents, pos, _) = parse_fixture(fixture);
+        let info = Some(FileInfo::Bazel {
+            api_context: APIContext::Module,
+            is_external: false,
+        });
+        let analysis = Analysis::from_files(&[
+            ("MODULE.bazel", Dialect::Bazel, info.clone(), &contents),
+            (
+                "@rules_cc//:MODULE.bazel",
+                Dialect::Bazel,
+                info,
+                r#"module(name = "rules_cc", version = "0.0.9")"#,
+            ),
+        ]);
+        let actual = analysis
+            .snapshot()
+            .goto_definition(FilePosition {
+                file_id: FileId(0),
+                pos,
+            })
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|loc| match loc {
+                LocationLink::External { target_path, .. } => target_path,
+                _ => panic!("expected external location"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(actual, vec![PathBuf::from(expected_path)]);
+    }
+
+    #[test]
+    fn test_bazel_dep_name() {
+        check_goto_bazel_dep(
+            r#"
+bazel_dep(name = "rules$0_cc", version = "0.0.9")
+"#,
+            "@rules_cc//:MODULE.bazel",
+        )
+    }
+
+    #[test]
+    fn test_bazel_dep_repo_name() {
+        check_goto_bazel_dep(
+            r#"
+bazel_dep(name = "rules_cc_fork", version = "0.0.9", repo_name = "rules$0_cc")
+"#,
+            "@rules_cc//:MODULE.bazel",
+        )
+    }
 }
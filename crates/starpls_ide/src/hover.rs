@@ -1,7 +1,8 @@
 use std::fmt::Write;
 
-use starpls_common::{parse, Db as _};
-use starpls_hir::{DisplayWithDb, Semantics, Type};
+use starpls_bazel::APIContext;
+use starpls_common::{parse, Db as _, File, ResolvedPath};
+use starpls_hir::{DisplayWithDb, Field, Semantics, Type};
 use starpls_syntax::{
     ast::{self, AstNode},
     SyntaxKind::*,
@@ -9,7 +10,7 @@ use starpls_syntax::{
 };
 
 use crate::{
-    util::{pick_best_token, unindent_doc},
+    util::{bazel_dep_arg, glob_args, include_call, pick_best_token, unindent_doc, BazelDepArg},
     Database, FilePosition,
 };
 
@@ -64,7 +65,16 @@ pub(crate) fn hover(db: &Database, FilePosition { file_id, pos }: FilePosition)
     let parent = token.parent()?;
     if let Some(expr) = ast::NameRef::cast(parent.clone()) {
         let ty = sema.type_of_expr(file, &expr.clone().into())?;
-        return Some(format_for_name(db, expr.name()?.text(), &ty).into());
+        let mut text = format_for_name(db, expr.name()?.text(), &ty);
+        if ty.is_provider() {
+            format_provider_fields(db, &sema.provider_fields(file, &ty), &mut text);
+        }
+        if file.api_context(db) == Some(APIContext::Build) {
+            if let Some(call) = expr.syntax().parent().and_then(ast::CallExpr::cast) {
+                format_glob_matches(db, file, &call, &mut text);
+            }
+        }
+        return Some(text.into());
     } else if let Some(name) = ast::Name::cast(parent.clone()) {
         let parent = name.syntax().parent()?;
         let name_token = name.name()?;
@@ -162,7 +172,7 @@ pub(crate) fn hover(db: &Database, FilePosition { file_id, pos }: FilePosition)
         let load_item = sema.resolve_load_item(file, &load_item)?;
         let def = sema.def_for_load_item(&load_item)?;
         return Some(format_for_name(db, load_item.name(db).as_str(), &def.value.ty(db)).into());
-    } else if let Some(load_module) = ast::LoadModule::cast(parent) {
+    } else if let Some(load_module) = ast::LoadModule::cast(parent.clone()) {
         let load_stmt = ast::LoadStmt::cast(load_module.syntax().parent()?)?;
         let loaded_file = sema.resolve_load_stmt(file, &load_stmt)?;
         let parsed = parse(db, loaded_file);
@@ -172,11 +182,245 @@ pub(crate) fn hover(db: &Database, FilePosition { file_id, pos }: FilePosition)
             text.push('\n');
         }
         return Some(text.into());
+    } else if let Some(lit) = ast::LiteralExpr::cast(parent) {
+        if file.api_context(db) == Some(APIContext::Module) {
+            if let Some(call) = include_call(&lit) {
+                let segment = sema.resolve_include(file, &call)?;
+                return Some(hover_for_module_segment(db, segment, token.text()));
+            }
+            if let Some(BazelDepArg {
+                keyword,
+                module_name: Some(module_name),
+                repo_name: Some(repo_name),
+            }) = bazel_dep_arg(&lit)
+            {
+                if keyword == "name" || keyword == "repo_name" {
+                    return hover_for_bazel_dep(db, file, &module_name, &repo_name);
+                }
+            }
+        }
+        return hover_for_target(db, file, &lit);
     }
 
     None
 }
 
+/// Formats the BUILD target referred to by a label, e.g. `"//foo:bar"`, along with the attributes
+/// that it's declared with.
+fn hover_for_target(db: &Database, file: File, lit: &ast::LiteralExpr) -> Option<Hover> {
+    let label = match lit.kind() {
+        ast::LiteralKind::String(s) => s.value()?,
+        _ => return None,
+    };
+    if !label.starts_with([':', '/', '@']) {
+        return None;
+    }
+    let (build_file, name) = match db
+        .resolve_path(&label, file.dialect(db), file.id(db))
+        .ok()??
+    {
+        ResolvedPath::BuildTarget {
+            build_file, target, ..
+        } => (db.get_file(build_file)?, target),
+        _ => return None,
+    };
+    let target = Semantics::new(db).target(build_file, &name)?;
+
+    let mut text = format!("```python\n(target) {}\n```\n", label);
+    write!(text, "Kind: `{}`", target.kind()).unwrap();
+    if let Some(generator) = target.generator_function() {
+        write!(text, " (generated by macro `{}`)", generator).unwrap();
+    }
+    text.push('\n');
+
+    let attrs = target
+        .call_expr(db)
+        .and_then(|call| call.arguments())
+        .into_iter()
+        .flat_map(|args| args.arguments())
+        .filter_map(|arg| match arg {
+            ast::Argument::Keyword(kwarg) => {
+                let name = kwarg.name()?.name()?;
+                (name.text() != "name").then(|| {
+                    let value = kwarg.expr().map(|expr| expr.syntax().text().to_string());
+                    (name.text().to_string(), value.unwrap_or_default())
+                })
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if !attrs.is_empty() {
+        text.push_str("\nAttributes:\n");
+        for (name, value) in attrs {
+            // Collapse multi-line values, e.g. lists, onto a single line.
+            let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+            writeln!(text, "- `{} = {}`", name, value).unwrap();
+        }
+    }
+
+    Some(text.into())
+}
+
+/// Formats the module depended on by a `bazel_dep()` call. The resolved version and dependencies are
+/// taken from `MODULE.bazel.lock` if possible, and otherwise from the dependency's fetched
+/// `MODULE.bazel` file, which also declares its compatibility level.
+fn hover_for_bazel_dep(
+    db: &Database,
+    file: File,
+    module_name: &str,
+    repo_name: &str,
+) -> Option<Hover> {
+    // The dependency's `MODULE.bazel` file is loaded through the database, so that it is only read
+    // and parsed once.
+    let module_file = db
+        .load_file(
+            &format!("@{}//:MODULE.bazel", repo_name),
+            file.dialect(db),
+            file.id(db),
+        )
+        .ok()
+        .flatten()
+        .map(|module_file| ModuleFile::from_module(&parse(db, module_file).tree(db)));
+    let resolved = db
+        .resolved_modules
+        .as_ref()
+        .and_then(|modules| modules.get(module_name));
+    if module_file.is_none() && resolved.is_none() {
+        return None;
+    }
+
+    let mut text = format!("```python\n(module) {}", module_name);
+    let version = resolved
+        .map(|module| module.version.clone())
+        .or_else(|| module_file.as_ref().and_then(|file| file.version.clone()));
+    if let Some(version) = version.filter(|version| !version.is_empty()) {
+        text.push('@');
+        text.push_str(&version);
+    }
+    text.push_str("\n```\n");
+
+    if let Some(module_file) = &module_file {
+        writeln!(
+            text,
+            "Compatibility level: {}",
+            module_file.compatibility_level
+        )
+        .unwrap();
+    }
+
+    let deps = match (resolved, module_file) {
+        (Some(module), _) => module.deps.clone(),
+        (None, Some(module_file)) => module_file.deps,
+        (None, None) => Vec::new(),
+    };
+    if !deps.is_empty() {
+        text.push_str("\nDependencies:\n");
+        for dep in deps {
+            writeln!(text, "- `{}`", dep).unwrap();
+        }
+    }
+
+    Some(text.into())
+}
+
+/// Formats a `MODULE.bazel` segment included by an `include()` call, along with the dependencies it
+/// declares.
+fn hover_for_module_segment(db: &Database, segment: File, label: &str) -> Hover {
+    let mut text = format!("```python\n(module segment) {}\n```\n", label);
+    if let Some(doc) = parse(db, segment)
+        .tree(db)
+        .doc()
+        .and_then(|doc| doc.value())
+    {
+        text.push_str(&unindent_doc(&doc));
+        text.push('\n');
+    }
+
+    let deps = ModuleFile::parse(segment.contents(db)).deps;
+    if !deps.is_empty() {
+        text.push_str("\nDependencies:\n");
+        for dep in deps {
+            writeln!(text, "- `{}`", dep).unwrap();
+        }
+    }
+
+    text.into()
+}
+
+/// The parts of a `MODULE.bazel` file that are relevant to its dependents.
+struct ModuleFile {
+    version: Option<String>,
+    compatibility_level: u64,
+    deps: Vec<String>,
+}
+
+impl ModuleFile {
+    fn from_module(module: &ast::Module) -> Self {
+        let mut module_file = ModuleFile {
+            version: None,
+            compatibility_level: 0,
+            deps: Vec::new(),
+        };
+
+        for stmt in module.statements() {
+            let call = match stmt {
+                ast::Statement::Expr(ast::Expression::Call(call)) => call,
+                _ => continue,
+            };
+            let callee = match call.callee() {
+                Some(ast::Expression::Name(name_ref)) => name_ref.name(),
+                _ => None,
+            };
+            match callee.as_ref().map(|callee| callee.text()) {
+                Some("module") => {
+                    if let Some(ast::LiteralKind::String(s)) = keyword_literal(&call, "version") {
+                        module_file.version = s.value().map(String::from);
+                    }
+                    if let Some(ast::LiteralKind::Int(level)) =
+                        keyword_literal(&call, "compatibility_level")
+                    {
+                        module_file.compatibility_level = level.value().unwrap_or_default();
+                    }
+                }
+                Some("bazel_dep") => {
+                    // Development dependencies are ignored when the module isn't the root module.
+                    if let Some(ast::LiteralKind::Bool(true)) =
+                        keyword_literal(&call, "dev_dependency")
+                    {
+                        continue;
+                    }
+                    let string_value = |name| match keyword_literal(&call, name) {
+                        Some(ast::LiteralKind::String(s)) => s.value(),
+                        _ => None,
+                    };
+                    if let Some(name) = string_value("name") {
+                        module_file.deps.push(match string_value("version") {
+                            Some(version) => format!("{}@{}", name, version),
+                            None => name.to_string(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        module_file.deps.sort();
+        module_file
+    }
+}
+
+fn keyword_literal(call: &ast::CallExpr, name: &str) -> Option<ast::LiteralKind> {
+    call.arguments()?.arguments().find_map(|arg| match arg {
+        ast::Argument::Keyword(kwarg) if kwarg.name()?.name()?.text() == name => {
+            match kwarg.expr()? {
+                ast::Expression::Literal(lit) => Some(lit.kind()),
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
 fn format_for_name(db: &Database, name: &str, ty: &Type) -> String {
     let mut text = String::from("```python\n");
 
@@ -199,3 +443,201 @@ fn format_for_name(db: &Database, name: &str, ty: &Type) -> String {
 
     text
 }
+
+/// The maximum number of files to list when hovering over a `glob()` call.
+const MAX_GLOB_MATCHES: usize = 50;
+
+/// Evaluates a `glob()` call in a BUILD file and lists the files that it matches.
+fn format_glob_matches(db: &Database, file: File, call: &ast::CallExpr, text: &mut String) {
+    let args = match glob_args(call) {
+        Some(args) => args,
+        None => return,
+    };
+    let result = match db.loader.glob(
+        file.id(db),
+        &args.include,
+        &args.exclude,
+        args.exclude_directories,
+    ) {
+        Ok(Some(result)) => result,
+        _ => return,
+    };
+
+    match result.matches.len() {
+        0 => text.push_str("\nMatches no files.\n"),
+        1 => text.push_str("\nMatches 1 file:\n"),
+        len => writeln!(text, "\nMatches {} files:", len).unwrap(),
+    }
+    for path in result.matches.iter().take(MAX_GLOB_MATCHES) {
+        writeln!(text, "- `{}`", path).unwrap();
+    }
+    if result.matches.len() > MAX_GLOB_MATCHES {
+        writeln!(
+            text,
+            "- ... and {} more",
+            result.matches.len() - MAX_GLOB_MATCHES
+        )
+        .unwrap();
+    }
+}
+
+fn format_provider_fields(db: &Database, fields: &[(Field, Type)], text: &mut String) {
+    if fields.is_empty() {
+        return;
+    }
+
+    text.push_str("\nFields:\n");
+    for (field, ty) in fields {
+        write!(text, "- `{}: {}`", field.name(db).as_str(), ty.display(db)).unwrap();
+        let doc = field.doc(db);
+        if !doc.is_empty() {
+            text.push_str(" - ");
+            text.push_str(&doc);
+        }
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+    use starpls_bazel::{lockfile::ResolvedModules, APIContext};
+    use starpls_common::{Dialect, FileId, FileInfo};
+    use starpls_test_util::parse_fixture;
+
+    use crate::{Analysis, FilePosition};
+
+    const RULES_CC_MODULE: &str = r#"
+module(name = "rules_cc", version = "0.0.9", compatibility_level = 1)
+
+bazel_dep(name = "platforms", version = "0.0.7")
+bazel_dep(name = "stardoc", version = "0.5.6", dev_dependency = True)
+"#;
+
+    fn check(fixture: &str, expect: Expect) {
+        let (contents, pos, _) = parse_fixture(fixture);
+        let analysis = Analysis::from_files(&[(
+            "defs.bzl",
+            Dialect::Bazel,
+            Some(FileInfo::Bazel {
+                api_context: APIContext::Bzl,
+                is_external: false,
+            }),
+            &contents,
+        )]);
+        let hover = analysis
+            .snapshot()
+            .hover(FilePosition {
+                file_id: FileId(0),
+                pos,
+            })
+            .unwrap()
+            .unwrap();
+        expect.assert_eq(&hover.contents.value);
+    }
+
+    fn check_bazel_dep(fixture: &str, modules: Option<ResolvedModules>, expect: Expect) {
+        let (contents, pos, _) = parse_fixture(fixture);
+        let info = Some(FileInfo::Bazel {
+            api_context: APIContext::Module,
+            is_external: false,
+        });
+        let mut analysis = Analysis::from_files(&[
+            ("MODULE.bazel", Dialect::Bazel, info.clone(), &contents),
+            (
+                "@rules_cc//:MODULE.bazel",
+                Dialect::Bazel,
+                info,
+                RULES_CC_MODULE,
+            ),
+        ]);
+        if let Some(modules) = modules {
+            analysis.set_resolved_modules(modules);
+        }
+        let hover = analysis
+            .snapshot()
+            .hover(FilePosition {
+                file_id: FileId(0),
+                pos,
+            })
+            .unwrap()
+            .unwrap();
+        expect.assert_eq(&hover.contents.value);
+    }
+
+    #[test]
+    fn test_provider_fields() {
+        check(
+            r#"
+DataInfo = provider(fields = {"files": "The data files.", "count": "The number of files."})
+
+def _impl(dep):
+    Data$0Info(files = ["a"], count = 1)
+    return [DataInfo(files = dep[DataInfo].files + ["b"], count = 2)]
+"#,
+            expect![[r#"
+                ```python
+                (variable) DataInfo: Provider[DataInfo]
+                ```
+
+                Fields:
+                - `files: list[string]` - The data files.
+                - `count: int` - The number of files.
+            "#]],
+        );
+    }
+
+    #[test]
+    fn test_bazel_dep_from_module_file() {
+        check_bazel_dep(
+            r#"
+bazel_dep(name = "rules$0_cc", version = "0.0.9")
+"#,
+            None,
+            expect![[r#"
+                ```python
+                (module) rules_cc@0.0.9
+                ```
+                Compatibility level: 1
+
+                Dependencies:
+                - `platforms@0.0.7`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn test_bazel_dep_from_lockfile() {
+        let modules = ResolvedModules::from_lockfile(
+            r#"{
+  "moduleDepGraph": {
+    "rules_cc@0.0.10": {
+      "name": "rules_cc",
+      "version": "0.0.10",
+      "deps": {
+        "platforms": "platforms@0.0.8",
+        "bazel_tools": "bazel_tools@_"
+      }
+    }
+  }
+}"#,
+        )
+        .unwrap();
+        check_bazel_dep(
+            r#"
+bazel_dep(name = "rules$0_cc", version = "0.0.9")
+"#,
+            Some(modules),
+            expect![[r#"
+                ```python
+                (module) rules_cc@0.0.10
+                ```
+                Compatibility level: 1
+
+                Dependencies:
+                - `bazel_tools@_`
+                - `platforms@0.0.8`
+            "#]],
+        );
+    }
+}
@@ -3,9 +3,14 @@ use std::{fmt::Debug, panic, path::PathBuf, sync::Arc};
 use dashmap::{mapref::entry::Entry, DashMap};
 use rustc_hash::FxHashMap;
 use salsa::ParallelDatabase;
-use starpls_bazel::{APIContext, Builtins};
+use starpls_bazel::{
+    glob::GlobResult,
+    lockfile::{ModuleExtensionRepos, ResolvedModules},
+    registry::BazelRegistry,
+    APIContext, Builtins,
+};
 use starpls_common::{
-    Db, Diagnostic, Dialect, File, FileId, FileInfo, LoadItemCandidate, ResolvedPath,
+    AllowedFiles, Db, Diagnostic, Dialect, File, FileId, FileInfo, LoadItemCandidate, ResolvedPath,
 };
 use starpls_hir::{BuiltinDefs, Db as _, GlobalContext};
 pub use starpls_hir::{Cancelled, InferenceOptions};
@@ -16,6 +21,7 @@ pub use crate::{
     completions::{
         CompletionItem, CompletionItemKind, CompletionMode, Edit, InsertReplaceEdit, TextEdit,
     },
+    document_links::DocumentLink,
     document_symbols::{DocumentSymbol, SymbolKind, SymbolTag},
     hover::{Hover, Markup},
     signature_help::{ParameterInfo, SignatureHelp, SignatureInfo},
@@ -23,6 +29,7 @@ pub use crate::{
 
 mod completions;
 mod diagnostics;
+mod document_links;
 mod document_symbols;
 mod goto_definition;
 mod hover;
@@ -42,6 +49,9 @@ pub(crate) struct Database {
     loader: Arc<dyn FileLoader>,
     gcx: Arc<GlobalContext>,
     prelude_file: Option<FileId>,
+    module_extension_repos: Option<Arc<ModuleExtensionRepos>>,
+    resolved_modules: Option<Arc<ResolvedModules>>,
+    bazel_registry: Option<Arc<BazelRegistry>>,
 }
 
 impl Database {
@@ -76,6 +86,9 @@ impl salsa::ParallelDatabase for Database {
             loader: self.loader.clone(),
             storage: self.storage.snapshot(),
             prelude_file: self.prelude_file,
+            module_extension_repos: self.module_extension_repos.clone(),
+            resolved_modules: self.resolved_modules.clone(),
+            bazel_registry: self.bazel_registry.clone(),
         })
     }
 }
@@ -137,6 +150,20 @@ impl starpls_common::Db for Database {
         self.loader.list_load_candidates(path, dialect, from)
     }
 
+    fn list_label_candidates(
+        &self,
+        path: &str,
+        from: FileId,
+        allowed_files: &AllowedFiles,
+    ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>> {
+        let dialect = match self.get_file(from) {
+            Some(file) => file.dialect(self),
+            None => return Ok(None),
+        };
+        self.loader
+            .list_label_candidates(path, dialect, from, allowed_files)
+    }
+
     fn resolve_path(
         &self,
         path: &str,
@@ -203,6 +230,14 @@ impl starpls_hir::Db for Database {
         self.prelude_file
     }
 
+    fn set_module_extension_repos(&mut self, repos: Arc<ModuleExtensionRepos>) {
+        self.module_extension_repos = Some(repos)
+    }
+
+    fn get_module_extension_repos(&self) -> Option<Arc<ModuleExtensionRepos>> {
+        self.module_extension_repos.clone()
+    }
+
     fn gcx(&self) -> &GlobalContext {
         &self.gcx
     }
@@ -266,6 +301,9 @@ impl Analysis {
                 storage: Default::default(),
                 loader,
                 prelude_file: None,
+                module_extension_repos: None,
+                resolved_modules: None,
+                bazel_registry: None,
             },
         }
     }
@@ -287,23 +325,32 @@ impl Analysis {
     pub fn set_bazel_prelude_file(&mut self, file_id: FileId) {
         self.db.set_bazel_prelude_file(file_id);
     }
-}
 
-pub struct AnalysisSnapshot {
-    db: salsa::Snapshot<Database>,
-}
+    pub fn set_module_extension_repos(&mut self, repos: ModuleExtensionRepos) {
+        // Cached diagnostics may depend on the previous set of repositories, so clear them.
+        let gcx = self.db.gcx.clone();
+        let _guard = gcx.cancel();
+        self.db.set_module_extension_repos(Arc::new(repos));
+    }
 
-impl AnalysisSnapshot {
-    pub fn from_single_file(
-        contents: &str,
-        dialect: Dialect,
-        info: Option<FileInfo>,
-    ) -> (Self, FileId) {
+    pub fn set_resolved_modules(&mut self, modules: ResolvedModules) {
+        self.db.resolved_modules = Some(Arc::new(modules));
+    }
+
+    pub fn set_bazel_registry(&mut self, registry: BazelRegistry) {
+        self.db.bazel_registry = Some(Arc::new(registry));
+    }
+
+    /// Creates an [`Analysis`] from a static set of files, keyed by path. Files are assigned
+    /// sequential [`FileId`]s in the given order.
+    pub(crate) fn from_files(files: &[(&str, Dialect, Option<FileInfo>, &str)]) -> Self {
         let mut file_set = FxHashMap::default();
-        let file_id = FileId(0);
-        file_set.insert("main.star".to_string(), (file_id, contents.to_string()));
         let mut change = Change::default();
-        change.create_file(file_id, dialect, info, contents.to_string());
+        for (index, (path, dialect, info, contents)) in files.iter().enumerate() {
+            let file_id = FileId(index as u32);
+            file_set.insert(path.to_string(), (file_id, contents.to_string()));
+            change.create_file(file_id, *dialect, info.clone(), contents.to_string());
+        }
         let mut analysis = Analysis::new(
             Arc::new(SimpleFileLoader::from_file_set(file_set)),
             Default::default(),
@@ -311,14 +358,33 @@ impl AnalysisSnapshot {
         analysis.db.set_builtin_defs(
             Dialect::Bazel,
             make_test_builtins(
-                vec!["provider".to_string(), "struct".to_string()],
+                vec![
+                    "aspect".to_string(),
+                    "provider".to_string(),
+                    "struct".to_string(),
+                ],
                 vec![],
                 vec![],
             ),
             Builtins::default(),
         );
         analysis.apply_change(change);
-        (analysis.snapshot(), file_id)
+        analysis
+    }
+}
+
+pub struct AnalysisSnapshot {
+    db: salsa::Snapshot<Database>,
+}
+
+impl AnalysisSnapshot {
+    pub fn from_single_file(
+        contents: &str,
+        dialect: Dialect,
+        info: Option<FileInfo>,
+    ) -> (Self, FileId) {
+        let analysis = Analysis::from_files(&[("main.star", dialect, info, contents)]);
+        (analysis.snapshot(), FileId(0))
     }
 
     pub fn completion(
@@ -333,6 +399,18 @@ impl AnalysisSnapshot {
         self.query(|db| diagnostics::diagnostics(db, file_id))
     }
 
+    pub fn document_links(&self, file_id: FileId) -> Cancellable<Option<Vec<DocumentLink>>> {
+        self.query(|db| document_links::document_links(db, file_id))
+    }
+
+    pub fn resolve_document_link(
+        &self,
+        file_id: FileId,
+        label: &str,
+    ) -> Cancellable<Option<LocationLink>> {
+        self.query(|db| document_links::resolve_document_link(db, file_id, label))
+    }
+
     pub fn document_symbols(&self, file_id: FileId) -> Cancellable<Option<Vec<DocumentSymbol>>> {
         self.query(|db| document_symbols::document_symbols(db, file_id))
     }
@@ -424,6 +502,29 @@ pub trait FileLoader: Send + Sync + 'static {
         dialect: Dialect,
         from: FileId,
     ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>>;
+
+    /// Returns a list of packages and source files that can complete the label `path` in a
+    /// label-valued attribute.
+    fn list_label_candidates(
+        &self,
+        path: &str,
+        dialect: Dialect,
+        from: FileId,
+        allowed_files: &AllowedFiles,
+    ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>>;
+
+    /// Evaluates a `glob()` call in the package of the given BUILD file.
+    fn glob(
+        &self,
+        from: FileId,
+        include: &[String],
+        exclude: &[String],
+        exclude_directories: bool,
+    ) -> anyhow::Result<Option<GlobResult>>;
+
+    /// Returns the name of the main repository package that contains the given BUILD file,
+    /// e.g. `foo/bar` for `foo/bar/BUILD.bazel`.
+    fn package_name(&self, file_id: FileId) -> Option<String>;
 }
 
 /// [`FileLoader`] that looks up files by path from a hash map.
@@ -465,12 +566,39 @@ impl FileLoader for SimpleFileLoader {
         Ok(None)
     }
 
-    fn resolve_path(
+    fn list_label_candidates(
         &self,
         _path: &str,
         _dialect: Dialect,
         _from: FileId,
-    ) -> anyhow::Result<Option<ResolvedPath>> {
+        _allowed_files: &AllowedFiles,
+    ) -> anyhow::Result<Option<Vec<LoadItemCandidate>>> {
+        Ok(None)
+    }
+
+    fn glob(
+        &self,
+        _from: FileId,
+        _include: &[String],
+        _exclude: &[String],
+        _exclude_directories: bool,
+    ) -> anyhow::Result<Option<GlobResult>> {
         Ok(None)
     }
+
+    fn package_name(&self, _file_id: FileId) -> Option<String> {
+        None
+    }
+
+    fn resolve_path(
+        &self,
+        path: &str,
+        _dialect: Dialect,
+        _from: FileId,
+    ) -> anyhow::Result<Option<ResolvedPath>> {
+        Ok(self
+            .file_set
+            .contains_key(path)
+            .then(|| ResolvedPath::Source { path: path.into() }))
+    }
 }
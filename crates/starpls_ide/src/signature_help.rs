@@ -103,7 +103,7 @@ pub(crate) fn signature_help(
     label.push_str(func.name(db).as_str());
     label.push('(');
 
-    let is_rule_or_tag = func.is_rule() || func.is_tag();
+    let is_rule_or_tag = func.is_rule() || func.is_macro() || func.is_tag();
     if is_rule_or_tag {
         label.push('*');
     }
@@ -162,3 +162,65 @@ pub(crate) fn signature_help(
         }],
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use expect_test::{expect, Expect};
+    use starpls_bazel::APIContext;
+    use starpls_common::{Dialect, FileInfo};
+    use starpls_test_util::parse_fixture;
+
+    use crate::{AnalysisSnapshot, FilePosition};
+
+    fn check(fixture: &str, expect: Expect) {
+        let (contents, pos, _) = parse_fixture(fixture);
+        let (snap, file_id) = AnalysisSnapshot::from_single_file(
+            &contents,
+            Dialect::Bazel,
+            Some(FileInfo::Bazel {
+                api_context: APIContext::Bzl,
+                is_external: false,
+            }),
+        );
+        let mut actual = String::new();
+        if let Some(help) = snap.signature_help(FilePosition { file_id, pos }).unwrap() {
+            for signature in help.signatures {
+                actual.push_str(&format!(
+                    "{} {:?}\n",
+                    signature.label, signature.active_parameter
+                ));
+            }
+        }
+        expect.assert_eq(&actual);
+    }
+
+    #[test]
+    fn test_function() {
+        check(
+            r#"
+def f(x, y = 1):
+    pass
+
+f(1, $0)
+"#,
+            expect![[r#"
+                def f(x, y = 1) -> Unknown Some(1)
+            "#]],
+        );
+    }
+
+    #[test]
+    fn test_aspect() {
+        check(
+            r#"
+def _impl(target, ctx):
+    pass
+
+my_aspect = aspect(implementation = _i$0mpl)
+"#,
+            expect![[r#"
+                def aspect(*args, **kwargs) -> Unknown Some(1)
+            "#]],
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use starpls_syntax::{SyntaxKind, SyntaxToken, TokenAtOffset};
+use starpls_syntax::{
+    ast::{self, AstNode},
+    SyntaxKind, SyntaxToken, TokenAtOffset,
+};
 
 pub(crate) fn pick_best_token(
     tokens: TokenAtOffset<SyntaxToken>,
@@ -31,3 +34,155 @@ pub(crate) fn unindent_doc(doc: &str) -> String {
         .collect::<Vec<_>>()
         .join("\n")
 }
+
+/// A keyword argument to a `bazel_dep()` call in a `MODULE.bazel` file.
+pub(crate) struct BazelDepArg {
+    /// The name of the keyword argument, e.g. `name` or `version`.
+    pub(crate) keyword: String,
+    /// The name of the module being depended on.
+    pub(crate) module_name: Option<String>,
+    /// The apparent repository name of the dependency, i.e. `repo_name` if specified, otherwise the
+    /// module name.
+    pub(crate) repo_name: Option<String>,
+}
+
+/// Determines whether the given string literal is passed as a keyword argument to `bazel_dep()`.
+pub(crate) fn bazel_dep_arg(lit: &ast::LiteralExpr) -> Option<BazelDepArg> {
+    let kwarg = ast::KeywordArgument::cast(lit.syntax().parent()?)?;
+    let call = kwarg
+        .syntax()
+        .parent()
+        .and_then(|args| args.parent())
+        .and_then(ast::CallExpr::cast)?;
+    match call.callee()? {
+        ast::Expression::Name(name_ref) if name_ref.name()?.text() == "bazel_dep" => {}
+        _ => return None,
+    }
+
+    let string_arg = |name: &str| {
+        call.arguments()?.arguments().find_map(|arg| match arg {
+            ast::Argument::Keyword(kwarg) if kwarg.name()?.name()?.text() == name => {
+                match kwarg.expr()? {
+                    ast::Expression::Literal(lit) => match lit.kind() {
+                        ast::LiteralKind::String(s) => s.value().map(String::from),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+    };
+    let module_name = string_arg("name");
+    Some(BazelDepArg {
+        keyword: kwarg.name()?.name()?.text().to_string(),
+        repo_name: string_arg("repo_name").or_else(|| module_name.clone()),
+        module_name,
+    })
+}
+
+/// Returns the `include()` call in a `MODULE.bazel` file that the given string literal is passed to,
+/// e.g. `include("//bazel:deps.MODULE.bazel")`.
+pub(crate) fn include_call(lit: &ast::LiteralExpr) -> Option<ast::CallExpr> {
+    let arg = ast::SimpleArgument::cast(lit.syntax().parent()?)?;
+    let call = arg
+        .syntax()
+        .parent()
+        .and_then(|args| args.parent())
+        .and_then(ast::CallExpr::cast)?;
+    match call.callee()? {
+        ast::Expression::Name(name_ref) if name_ref.name()?.text() == "include" => Some(call),
+        _ => None,
+    }
+}
+
+/// Returns the call and keyword argument that the given string literal is passed to, either
+/// directly or as an element of a list or key of a dict, e.g. `deps` for `"//foo"` in
+/// `cc_library(deps = ["//foo"])`.
+pub(crate) fn keyword_arg_for_string(lit: &ast::LiteralExpr) -> Option<(ast::CallExpr, String)> {
+    let mut parent = lit.syntax().parent()?;
+    if ast::ListExpr::can_cast(parent.kind()) {
+        parent = parent.parent()?;
+    } else if let Some(entry) = ast::DictEntry::cast(parent.clone()) {
+        if entry.key()?.syntax() != lit.syntax() {
+            return None;
+        }
+        parent = entry.syntax().parent()?.parent()?;
+    }
+
+    let kwarg = ast::KeywordArgument::cast(parent)?;
+    let call = kwarg
+        .syntax()
+        .parent()
+        .and_then(|args| args.parent())
+        .and_then(ast::CallExpr::cast)?;
+    Some((call, kwarg.name()?.name()?.text().to_string()))
+}
+
+/// The arguments of a `glob()` call, if they can be determined statically.
+pub(crate) struct GlobArgs {
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+    pub(crate) exclude_directories: bool,
+    pub(crate) allow_empty: Option<bool>,
+}
+
+/// Returns the arguments of the given call if it's a call to `glob()` whose arguments are literals,
+/// e.g. `glob(["**/*.cc"], exclude = ["*_test.cc"])`.
+pub(crate) fn glob_args(call: &ast::CallExpr) -> Option<GlobArgs> {
+    match call.callee()? {
+        ast::Expression::Name(name_ref) if name_ref.name()?.text() == "glob" => {}
+        _ => return None,
+    }
+
+    let strings = |expr: ast::Expression| match expr {
+        ast::Expression::List(list) => list
+            .elements()
+            .map(|element| match element {
+                ast::Expression::Literal(lit) => match lit.kind() {
+                    ast::LiteralKind::String(s) => s.value().map(String::from),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>(),
+        _ => None,
+    };
+    let literal = |expr: ast::Expression| match expr {
+        ast::Expression::Literal(lit) => Some(lit.kind()),
+        _ => None,
+    };
+
+    let mut args = GlobArgs {
+        include: Vec::new(),
+        exclude: Vec::new(),
+        exclude_directories: true,
+        allow_empty: None,
+    };
+    for (index, arg) in call.arguments()?.arguments().enumerate() {
+        let (name, expr) = match arg {
+            ast::Argument::Simple(arg) => (
+                ["include", "exclude", "exclude_directories", "allow_empty"]
+                    .get(index)?
+                    .to_string(),
+                arg.expr()?,
+            ),
+            ast::Argument::Keyword(arg) => (arg.name()?.name()?.text().to_string(), arg.expr()?),
+            _ => return None,
+        };
+        match name.as_str() {
+            "include" => args.include = strings(expr)?,
+            "exclude" => args.exclude = strings(expr)?,
+            "exclude_directories" => match literal(expr)? {
+                ast::LiteralKind::Int(value) => args.exclude_directories = value.value()? != 0,
+                _ => return None,
+            },
+            "allow_empty" => match literal(expr)? {
+                ast::LiteralKind::Bool(value) => args.allow_empty = Some(value),
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+    Some(args)
+}
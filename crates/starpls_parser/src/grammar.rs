@@ -26,6 +26,9 @@ pub(crate) fn type_comment_body(p: &mut Parser) {
         T![ignore] => {
             let m = p.start();
             p.bump(T![ignore]);
+            if p.at(T!['[']) {
+                ignore_codes(p);
+            }
             m.complete(p, IGNORE_TYPE);
         }
         T!['('] => {
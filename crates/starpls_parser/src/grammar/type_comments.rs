@@ -28,6 +28,46 @@ pub(crate) fn types(p: &mut Parser, stop: Option<SyntaxKind>) {
     }
 }
 
+/// Parses the bracketed list of diagnostic codes in `# type: ignore[arg-type, undefined-name]`.
+pub(crate) fn ignore_codes(p: &mut Parser) {
+    p.bump(T!['[']);
+    while !p.at(EOF) && !p.at(T![']']) {
+        if !at_ignore_code_segment(p, 0) {
+            p.error_recover_until("Expected diagnostic code", IGNORE_CODE_RECOVERY);
+            if !p.eat(T![,]) {
+                break;
+            }
+            continue;
+        }
+        ignore_code(p);
+        if !p.eat(T![,]) {
+            break;
+        }
+    }
+    if !p.eat(T![']']) {
+        p.error_recover_until("Expected \"]\"", EMPTY);
+    }
+}
+
+const IGNORE_CODE_RECOVERY: SyntaxKindSet = SyntaxKindSet::new(&[T![,], T![']']]);
+
+fn ignore_code(p: &mut Parser) {
+    // Diagnostic codes are kebab-case, so they are lexed as a sequence of identifiers (or keywords,
+    // e.g. the `not` in `not-callable`) separated by `-`.
+    let m = p.start();
+    p.bump_any();
+    while p.at(T![-]) && at_ignore_code_segment(p, 1) {
+        p.bump(T![-]);
+        p.bump_any();
+    }
+    m.complete(p, IGNORE_CODE);
+}
+
+fn at_ignore_code_segment(p: &Parser, n: usize) -> bool {
+    let kind = p.nth(n);
+    kind == T![ident] || kind.is_keyword()
+}
+
 pub(crate) fn parameter_types(p: &mut Parser) {
     parameter_type(p);
     while p.at(T![,]) && PARAMETER_TYPE_START.contains(p.nth(1)) {
@@ -139,6 +139,7 @@ pub enum SyntaxKind {
     PATH_SEGMENT,
 
     IGNORE_TYPE,
+    IGNORE_CODE,                // arg-type in `# type: ignore[arg-type]`
     FUNCTION_TYPE,              // e.g. (int, int) -> int
     PARAMETER_TYPES,            // the (int, int) in the signature above
     SIMPLE_PARAMETER_TYPE,      // int
@@ -12,7 +12,7 @@ pub use rowan::{
 use crate::{
     StarlarkLanguage,
     SyntaxKind::{self, *},
-    SyntaxNode, SyntaxNodeChildren, SyntaxToken, TextSize, T,
+    SyntaxNode, SyntaxNodeChildren, SyntaxToken, T,
 };
 
 pub type SyntaxNodePtr = rowan::ast::SyntaxNodePtr<StarlarkLanguage>;
@@ -178,11 +178,8 @@ impl Module {
             })
     }
 
-    pub fn type_ignore_comment_positions(&self) -> impl Iterator<Item = TextSize> {
-        self.syntax()
-            .descendants()
-            .filter_map(IgnoreType::cast)
-            .map(|node| node.syntax().text_range().start())
+    pub fn type_ignore_comments(&self) -> impl Iterator<Item = IgnoreType> {
+        self.syntax().descendants().filter_map(IgnoreType::cast)
     }
 }
 
@@ -1424,6 +1421,29 @@ ast_node! {
 
 ast_node! {
     IgnoreType => IGNORE_TYPE
+    children codes -> IgnoreCode;
+}
+
+impl IgnoreType {
+    /// Whether the comment has a bracketed list of codes, e.g. `# type: ignore[arg-type]`.
+    pub fn has_codes(&self) -> bool {
+        token(self.syntax(), T!['[']).is_some()
+    }
+}
+
+ast_node! {
+    IgnoreCode => IGNORE_CODE
+}
+
+impl IgnoreCode {
+    pub fn text(&self) -> String {
+        self.syntax()
+            .children_with_tokens()
+            .filter_map(|element| element.into_token())
+            .filter(|token| !token.kind().is_trivia_token())
+            .map(|token| token.text().to_string())
+            .collect()
+    }
 }
 
 ast_node! {